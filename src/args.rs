@@ -0,0 +1,590 @@
+use std::path::PathBuf;
+
+use color_eyre::{eyre::eyre, Result};
+use glam::Vec3;
+
+use compaster::{Guides, RenderResolution, RendererOptions, RepairOptions, TestScene};
+
+use crate::hud::{LengthUnit, TimeUnit};
+
+/// Printed for `--help` and after an invalid argument.
+pub const USAGE: &str = "\
+Usage: compaster [OPTIONS] [MODEL]
+
+Renders MODEL, a glTF or OBJ file, or the embedded Suzanne with a compute
+shader rasterizer.
+
+Scene:
+  --scene NAME                  Test scene: thin, huge, coplanar, tiny,
+                                t-junction, shared-edge or fan
+  --tab MODEL                   Open a model in a tab of its own, repeatable
+  --recent INDEX                Load a recent model, see --list-recent
+  --list-recent                 Print the recent models and exit
+  --fresh                       Don't restore the last session
+  --no-repair                   Load models without repairing them
+  --environment HDR             Equirectangular image as sky and lighting
+  --shading-snippet WGSL        File defining `custom_shade`, hot reloaded
+  --marker X,Y,Z                Mark a point over the model, repeatable
+
+Camera:
+  --eye X,Y,Z                   Camera position
+  --target X,Y,Z                Point the camera looks at, origin by default
+  --fov DEGREES                 Vertical field of view, 0 to 180
+  --bookmark SLOT               Start from the view stored under a number
+                                key, 1 to 9
+
+Rendering:
+  --clear-color R,G,B           Linear color the color buffer is cleared to
+  --msaa 1|4                    Samples of the present pass and the lines
+  --render-scale FACTOR         Internal resolution, 0.5 to 2 of the window
+  --render-size WIDTHxHEIGHT    Internal resolution in pixels
+  --dynamic-resolution          Adjust the render scale to the frame rate
+  --chunk-size TRIANGLES        Raster in chunks of this many triangles
+  --chunk-dispatches            Dispatch the chunks in a single submission
+  --workgroup-size INVOCATIONS  Invocations per workgroup, 256 by default
+  --guides off|thirds|safe|all  Framing guides shown from the start
+  --presentation-aspect W:H     Letterbox of the presentation mode
+  --presentation-turntable      Spin the model in the presentation mode
+
+Device:
+  --backend NAME                vulkan, dx12, dx11, metal or gl
+  --adapter INDEX|NAME          Adapter to use, listed when none matches
+  --vsync                       Present on the vertical blank
+  --low-power                   Integrated GPU, capped frame rate
+  --assets-dir DIR              Read shaders and models from DIR
+  --trace DIR                   Record a wgpu API trace into DIR
+
+Window:
+  --width PIXELS                Window width, 1280 by default
+  --height PIXELS               Window height, 720 by default
+  --locale JSON                 Translations of the printed text
+  --time-unit ms|fps            Unit of the frame times
+  --length-unit m|ft            Unit of the lengths
+
+Without a window:
+  --output PNG                  Render a single frame into PNG and exit
+  --size WIDTHxHEIGHT           Size of --output and of the bench frames
+  --check-coverage              Report double hits and cracks and exit
+  --bench FRAMES                Time the stages of FRAMES frames and exit
+  --bench-output JSON           Write the bench timings into JSON
+  --baseline JSON               Fail when slower than these timings
+  --fail-threshold PERCENT      Slowdown the baseline allows, 5% by default
+
+  -h, --help                    Print this help
+";
+
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Print the usage and exit
+    pub help: bool,
+    /// glTF model to load instead of the embedded Suzanne
+    pub model: Option<PathBuf>,
+    /// Index of the recent model to load, see `--list-recent`
+    pub recent: Option<usize>,
+    /// Print the recent models and their thumbnails and exit
+    pub list_recent: bool,
+    pub scene: Option<TestScene>,
+    /// Camera position, replaces the default turntable view
+    pub eye: Option<Vec3>,
+    /// Point the camera looks at, origin by default
+    pub target: Option<Vec3>,
+    /// Points of interest marked over the model, `--marker` can be repeated
+    pub markers: Vec<Vec3>,
+    /// Models opened in tabs of their own next to the main one, `--tab` can
+    /// be repeated
+    pub tabs: Vec<PathBuf>,
+    /// Vertical field of view in degrees
+    pub fov: Option<f32>,
+    /// Slot of the bookmarked view to start from, see `Bookmarks`
+    pub bookmark: Option<u8>,
+    /// Don't restore the last session
+    pub fresh: bool,
+    /// Load the models as they are, without dropping invalid triangles or
+    /// fixing their winding
+    pub no_repair: bool,
+    /// Triangles rasterized per chunk, splits the raster to keep huge scenes stable
+    pub chunk_size: Option<u32>,
+    /// Dispatch the chunks in a single submission instead of one each
+    pub chunk_dispatches: bool,
+    /// Width over height the presentation mode letterboxes the image to
+    pub presentation_aspect: Option<f32>,
+    /// Spin the model in the presentation mode
+    pub presentation_turntable: bool,
+    /// Framing guides shown from the start
+    pub guides: Option<Guides>,
+    /// Samples per pixel of the present pass and the debug lines, 4 by default
+    pub msaa: Option<u32>,
+    /// Render a single frame, report double hits and cracks of the raster and exit
+    pub check_coverage: bool,
+    /// Render a single frame without a window into this PNG and exit
+    pub output: Option<PathBuf>,
+    /// Size of the `--output` image and of the bench frames, the window size by default
+    pub size: Option<(u32, u32)>,
+    /// Time the stages of this many frames without a window and exit
+    pub bench: Option<usize>,
+    /// JSON the bench timings are written to, the baseline of later runs
+    pub bench_output: Option<PathBuf>,
+    /// Bench timings the run is compared with, it fails on a regression
+    pub baseline: Option<PathBuf>,
+    /// Fraction a stage may get slower than the baseline, 5% by default
+    pub fail_threshold: f32,
+    /// Internal resolution as a factor of the window size, 0.5 to 2
+    pub render_scale: Option<f32>,
+    /// Internal resolution in pixels whatever the window size
+    pub render_size: Option<(u32, u32)>,
+    /// Adjust the render scale to hold the frame rate
+    pub dynamic_resolution: bool,
+    /// Window size in logical pixels, 1280x720 by default
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Backend the adapter is picked from, the primary ones by default
+    pub backend: Option<wgpu::Backends>,
+    /// Index or part of the name of the adapter, they are listed when none matches
+    pub adapter: Option<String>,
+    /// Present on the vertical blank instead of immediately
+    pub vsync: bool,
+    /// Prefer the integrated GPU, cap the frame rate and skip unchanged frames
+    pub low_power: bool,
+    /// Linear `r,g,b` the compute raster clears the color buffer to
+    pub clear_color: Option<Vec3>,
+    /// Equirectangular Radiance HDR image shown as the sky and lighting the model
+    pub environment: Option<PathBuf>,
+    /// WGSL file defining `custom_shade`, recompiled when it changes
+    pub shading_snippet: Option<PathBuf>,
+    /// Invocations per workgroup of the compute passes, lowered to fit the
+    /// adapter and rounded down to a power of two. 256 by default.
+    pub workgroup_size: Option<u32>,
+    /// Shaders and default model read from `shaders/` and `models/` of this
+    /// directory instead of the embedded ones, the missing files stay embedded
+    pub assets_dir: Option<PathBuf>,
+    /// Directory wgpu records an API trace into, see `RendererOptions::trace_path`
+    pub trace: Option<PathBuf>,
+    /// JSON translations of the printed text
+    pub locale: Option<PathBuf>,
+    /// Frame times in milliseconds or frames per second
+    pub time_unit: TimeUnit,
+    /// Lengths in meters or feet
+    pub length_unit: LengthUnit,
+}
+
+impl Args {
+    /// Parses the command line, an error ends with the usage.
+    pub fn parse() -> Result<Self> {
+        Self::parse_from(std::env::args().skip(1)).map_err(|err| eyre!("{}\n\n{}", err, USAGE))
+    }
+
+    fn parse_from(mut iter: impl Iterator<Item = String>) -> Result<Self> {
+        let mut args = Self {
+            fail_threshold: 0.05,
+            ..Self::default()
+        };
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--help" | "-h" => args.help = true,
+                "--scene" => {
+                    let name = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--scene` expects a scene name"))?;
+                    args.scene = Some(name.parse()?);
+                }
+                "--fresh" => args.fresh = true,
+                "--no-repair" => args.no_repair = true,
+                "--list-recent" => args.list_recent = true,
+                "--recent" => {
+                    let index = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--recent` expects an index"))?;
+                    args.recent = Some(
+                        index
+                            .parse()
+                            .map_err(|_| eyre!("Invalid recent model index `{}`", index))?,
+                    );
+                }
+                "--check-coverage" => args.check_coverage = true,
+                "--low-power" => args.low_power = true,
+                "--vsync" => args.vsync = true,
+                "--dynamic-resolution" => args.dynamic_resolution = true,
+                "--width" | "--height" => {
+                    let length = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`{}` expects a size in pixels", arg))?;
+                    let length = Some(
+                        length
+                            .parse()
+                            .ok()
+                            .filter(|&length| length > 0)
+                            .ok_or_else(|| eyre!("Invalid window size `{}`", length))?,
+                    );
+                    match arg.as_str() {
+                        "--width" => args.width = length,
+                        _ => args.height = length,
+                    }
+                }
+                "--backend" => {
+                    let backend = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--backend` expects a backend name"))?;
+                    args.backend = Some(parse_backend(&backend)?);
+                }
+                "--adapter" => {
+                    let adapter = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--adapter` expects an index or a name"))?;
+                    args.adapter = Some(adapter);
+                }
+                "--chunk-dispatches" => args.chunk_dispatches = true,
+                "--chunk-size" => {
+                    let size = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--chunk-size` expects a triangle count"))?;
+                    args.chunk_size = Some(
+                        size.parse()
+                            .ok()
+                            .filter(|&size| size > 0)
+                            .ok_or_else(|| eyre!("Invalid chunk size `{}`", size))?,
+                    );
+                }
+                "--workgroup-size" => {
+                    let size = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--workgroup-size` expects an invocation count"))?;
+                    args.workgroup_size = Some(
+                        size.parse()
+                            .ok()
+                            .filter(|&size| size > 0)
+                            .ok_or_else(|| eyre!("Invalid workgroup size `{}`", size))?,
+                    );
+                }
+                "--msaa" => {
+                    let samples = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--msaa` expects a sample count"))?;
+                    // The only counts wgpu guarantees for every format
+                    args.msaa = Some(
+                        samples
+                            .parse()
+                            .ok()
+                            .filter(|samples| [1, 4].contains(samples))
+                            .ok_or_else(|| {
+                                eyre!("Invalid sample count `{}`, expected 1 or 4", samples)
+                            })?,
+                    );
+                }
+                "--output" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--output` expects a PNG path"))?;
+                    args.output = Some(path.into());
+                }
+                "--shading-snippet" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--shading-snippet` expects a WGSL path"))?;
+                    args.shading_snippet = Some(path.into());
+                }
+                "--tab" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--tab` expects a model path"))?;
+                    args.tabs.push(path.into());
+                }
+                "--environment" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--environment` expects an HDR image path"))?;
+                    args.environment = Some(path.into());
+                }
+                "--assets-dir" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--assets-dir` expects a directory"))?;
+                    args.assets_dir = Some(path.into());
+                }
+                "--trace" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--trace` expects a directory"))?;
+                    args.trace = Some(path.into());
+                }
+                "--locale" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--locale` expects a JSON path"))?;
+                    args.locale = Some(path.into());
+                }
+                "--time-unit" => {
+                    let unit = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--time-unit` expects ms or fps"))?;
+                    args.time_unit = unit.parse()?;
+                }
+                "--length-unit" => {
+                    let unit = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--length-unit` expects m or ft"))?;
+                    args.length_unit = unit.parse()?;
+                }
+                "--bench" => {
+                    let frames = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--bench` expects a number of frames"))?;
+                    args.bench = Some(
+                        frames
+                            .parse()
+                            .ok()
+                            .filter(|&frames| frames > 0)
+                            .ok_or_else(|| eyre!("Invalid number of frames `{}`", frames))?,
+                    );
+                }
+                "--bench-output" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--bench-output` expects a JSON path"))?;
+                    args.bench_output = Some(path.into());
+                }
+                "--baseline" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--baseline` expects a JSON path"))?;
+                    args.baseline = Some(path.into());
+                }
+                "--fail-threshold" => {
+                    let threshold = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--fail-threshold` expects a percentage"))?;
+                    args.fail_threshold = parse_percentage(&threshold)?;
+                }
+                "--size" => {
+                    let size = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--size` expects `widthxheight`"))?;
+                    args.size = Some(parse_size(&size)?);
+                }
+                "--render-scale" => {
+                    let scale = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--render-scale` expects a factor"))?;
+                    args.render_scale = Some(
+                        scale
+                            .parse()
+                            .ok()
+                            .filter(|scale| (0.5..=2.0).contains(scale))
+                            .ok_or_else(|| {
+                                eyre!("Invalid render scale `{}`, expected 0.5 to 2", scale)
+                            })?,
+                    );
+                }
+                "--render-size" => {
+                    let size = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--render-size` expects `widthxheight`"))?;
+                    args.render_size = Some(parse_size(&size)?);
+                }
+                "--presentation-turntable" => args.presentation_turntable = true,
+                "--presentation-aspect" => {
+                    let aspect = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--presentation-aspect` expects `width:height`"))?;
+                    args.presentation_aspect = Some(parse_aspect(&aspect)?);
+                }
+                "--guides" => {
+                    let guides = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--guides` expects off, thirds, safe or all"))?;
+                    args.guides = Some(guides.parse()?);
+                }
+                "--eye" => args.eye = Some(parse_vec3(&arg, iter.next())?),
+                "--target" => args.target = Some(parse_vec3(&arg, iter.next())?),
+                "--marker" => args.markers.push(parse_vec3(&arg, iter.next())?),
+                "--clear-color" => args.clear_color = Some(parse_vec3(&arg, iter.next())?),
+                "--fov" => {
+                    let fov = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--fov` expects an angle in degrees"))?;
+                    args.fov = Some(
+                        fov.parse()
+                            .ok()
+                            .filter(|fov| *fov > 0.0 && *fov < 180.0)
+                            .ok_or_else(|| {
+                                eyre!("Invalid field of view `{}`, expected 0 to 180 degrees", fov)
+                            })?,
+                    );
+                }
+                "--bookmark" => {
+                    let slot = iter
+                        .next()
+                        .ok_or_else(|| eyre!("`--bookmark` expects a slot"))?;
+                    args.bookmark = Some(
+                        slot.parse()
+                            .ok()
+                            .filter(|slot| (1..=9).contains(slot))
+                            .ok_or_else(|| eyre!("Invalid bookmark `{}`, expected 1 to 9", slot))?,
+                    );
+                }
+                _ if !arg.starts_with('-') && args.model.is_none() => {
+                    args.model = Some(arg.into());
+                }
+                _ => return Err(eyre!("Unknown argument `{}`", arg)),
+            }
+        }
+        if args.bookmark.is_some() && (args.eye.is_some() || args.target.is_some()) {
+            return Err(eyre!(
+                "`--bookmark` can't be combined with `--eye` or `--target`"
+            ));
+        }
+        Ok(args)
+    }
+
+    pub fn renderer_options(&self) -> RendererOptions {
+        let defaults = RendererOptions::default();
+        RendererOptions {
+            sample_count: self.msaa.unwrap_or(defaults.sample_count),
+            power_preference: match self.low_power {
+                true => wgpu::PowerPreference::LowPower,
+                false => wgpu::PowerPreference::HighPerformance,
+            },
+            backends: self.backend.unwrap_or(defaults.backends),
+            adapter: self.adapter.clone(),
+            vsync: self.vsync,
+            workgroup_size: self.workgroup_size,
+            assets_dir: self.assets_dir.clone(),
+            trace_path: self.trace.clone(),
+        }
+    }
+
+    pub fn repair_options(&self) -> RepairOptions {
+        match self.no_repair {
+            true => RepairOptions {
+                remove_invalid: false,
+                remove_degenerate: false,
+                fix_winding: false,
+            },
+            false => RepairOptions::default(),
+        }
+    }
+
+    /// Size of the window, also the default size of the `--output` image.
+    pub fn window_size(&self) -> (u32, u32) {
+        (self.width.unwrap_or(1280), self.height.unwrap_or(720))
+    }
+
+    /// Internal resolution of the compute raster, `--render-size` wins over
+    /// `--render-scale`.
+    pub fn render_resolution(&self) -> Option<RenderResolution> {
+        match (self.render_size, self.render_scale) {
+            (Some((width, height)), _) => Some(RenderResolution::Fixed(width, height)),
+            (None, Some(scale)) => Some(RenderResolution::Scaled(scale)),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether the camera was specified on the command line.
+    pub fn has_camera(&self) -> bool {
+        self.eye.is_some() || self.target.is_some() || self.fov.is_some() || self.bookmark.is_some()
+    }
+}
+
+/// Parses a percentage like `5%` into a fraction, the percent sign is optional.
+fn parse_percentage(value: &str) -> Result<f32> {
+    value
+        .trim_end_matches('%')
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|percent| percent.is_finite() && *percent >= 0.0)
+        .map(|percent| percent / 100.0)
+        .ok_or_else(|| eyre!("Invalid percentage `{}`, expected like `5%`", value))
+}
+
+/// Parses a `width:height` ratio like `16:9`.
+fn parse_aspect(value: &str) -> Result<f32> {
+    let invalid = || eyre!("Invalid aspect ratio `{}`, expected `width:height`", value);
+    let (width, height) = value.split_once(':').ok_or_else(invalid)?;
+    let width: f32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: f32 = height.trim().parse().map_err(|_| invalid())?;
+    if width <= 0.0 || height <= 0.0 || !(width / height).is_finite() {
+        return Err(invalid());
+    }
+    Ok(width / height)
+}
+
+/// Parses a backend name like `vulkan`.
+fn parse_backend(value: &str) -> Result<wgpu::Backends> {
+    match value.to_lowercase().as_str() {
+        "vulkan" => Ok(wgpu::Backends::VULKAN),
+        "dx12" => Ok(wgpu::Backends::DX12),
+        "dx11" => Ok(wgpu::Backends::DX11),
+        "metal" => Ok(wgpu::Backends::METAL),
+        "gl" => Ok(wgpu::Backends::GL),
+        _ => Err(eyre!(
+            "Invalid backend `{}`, expected vulkan, dx12, dx11, metal or gl",
+            value
+        )),
+    }
+}
+
+/// Parses an image size like `1920x1080`.
+fn parse_size(value: &str) -> Result<(u32, u32)> {
+    let invalid = || eyre!("Invalid size `{}`, expected `widthxheight`", value);
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+/// Parses `x,y,z` given to `flag`.
+fn parse_vec3(flag: &str, value: Option<String>) -> Result<Vec3> {
+    let value = value.ok_or_else(|| eyre!("`{}` expects `x,y,z`", flag))?;
+    let components = value
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|c| c.len() == 3 && c.iter().all(|c| c.is_finite()))
+        .ok_or_else(|| {
+            eyre!(
+                "Invalid vector `{}` for `{}`, expected `x,y,z`",
+                value,
+                flag
+            )
+        })?;
+    Ok(Vec3::from_slice(&components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse(&["--help"]).unwrap().help);
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(!parse(&[]).unwrap().help);
+    }
+
+    #[test]
+    fn vectors_are_finite() {
+        let eye = parse(&["--eye", "1, 2,3"]).unwrap().eye;
+        assert_eq!(eye, Some(Vec3::new(1.0, 2.0, 3.0)));
+        for vector in ["nan,0,1", "0,inf,1", "0,0,-inf", "1,2", "1,2,3,4"] {
+            assert!(parse(&["--eye", vector]).is_err(), "{}", vector);
+        }
+    }
+
+    #[test]
+    fn bookmark_conflicts_with_the_camera() {
+        assert_eq!(parse(&["--bookmark", "3"]).unwrap().bookmark, Some(3));
+        assert!(parse(&["--bookmark", "0"]).is_err());
+        assert!(parse(&["--bookmark", "10"]).is_err());
+        assert!(parse(&["--bookmark", "3", "--eye", "0,1,2"]).is_err());
+        assert!(parse(&["--target", "0,1,2", "--bookmark", "3"]).is_err());
+        assert!(parse(&["--bookmark", "3", "--fov", "60"]).is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::stats::Timings;
+
+/// Stage timings of a bench run, see `Renderer::time_frame`. Written with
+/// `--bench-output` and read back as the `--baseline` of later runs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub model: String,
+    pub frames: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Milliseconds by stage
+    pub stages: BTreeMap<String, Timings>,
+}
+
+impl BenchReport {
+    /// `times` are the milliseconds of every frame by stage.
+    pub fn new(
+        model: String,
+        (width, height): (u32, u32),
+        times: &BTreeMap<String, Vec<f32>>,
+    ) -> Self {
+        Self {
+            model,
+            frames: times.values().map(Vec::len).max().unwrap_or_default(),
+            width,
+            height,
+            stages: times
+                .iter()
+                .map(|(stage, times)| (stage.clone(), Timings::new(times)))
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read the baseline `{}`", path.display()))?;
+        serde_json::from_str(&json)
+            .wrap_err_with(|| format!("Invalid baseline `{}`", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Failed to write `{}`", path.display()))
+    }
+
+    /// Stages whose median got slower than in `baseline` by more than
+    /// `threshold`, a fraction. Stages missing from either side are skipped.
+    pub fn regressions(&self, baseline: &BenchReport, threshold: f32) -> Vec<Regression> {
+        self.stages
+            .iter()
+            .filter_map(|(stage, timings)| {
+                let before = baseline.stages.get(stage)?.p50;
+                let change = timings.p50 / before - 1.0;
+                (change > threshold).then(|| Regression {
+                    stage: stage.clone(),
+                    baseline: before,
+                    current: timings.p50,
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames of {} at {}x{}, in milliseconds",
+            self.frames, self.model, self.width, self.height
+        )?;
+        writeln!(f, "{:<10}{:>10}{:>10}{:>10}", "stage", "p50", "p95", "max")?;
+        for (stage, timings) in &self.stages {
+            writeln!(
+                f,
+                "{:<10}{:>10.3}{:>10.3}{:>10.3}",
+                stage, timings.p50, timings.p95, timings.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Median of a stage slower than its baseline.
+#[derive(Debug)]
+pub struct Regression {
+    pub stage: String,
+    pub baseline: f32,
+    pub current: f32,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.3}ms -> {:.3}ms (+{:.1}%)",
+            self.stage,
+            self.baseline,
+            self.current,
+            (self.current / self.baseline - 1.0) * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Report whose stages took `times` milliseconds on every frame.
+    fn report(times: &[(&str, f32)]) -> BenchReport {
+        let times = times
+            .iter()
+            .map(|&(stage, time)| (stage.to_string(), vec![time; 5]))
+            .collect();
+        BenchReport::new("test".to_string(), (64, 64), &times)
+    }
+
+    #[test]
+    fn slower_than_the_threshold_regresses() {
+        let baseline = report(&[("Raster", 10.0), ("Present", 2.0)]);
+        let current = report(&[("Raster", 10.4), ("Present", 2.2)]);
+        let regressions = current.regressions(&baseline, 0.05);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].stage, "Present");
+        assert_eq!(
+            (regressions[0].baseline, regressions[0].current),
+            (2.0, 2.2)
+        );
+
+        assert!(current.regressions(&baseline, 0.15).is_empty());
+    }
+
+    #[test]
+    fn faster_or_missing_stages_pass() {
+        let baseline = report(&[("Raster", 10.0), ("Frame", 12.0)]);
+        let current = report(&[("Raster", 8.0), ("Present", 50.0)]);
+        assert!(current.regressions(&baseline, 0.0).is_empty());
+    }
+
+    #[test]
+    fn compares_the_medians() {
+        let baseline = report(&[("Raster", 10.0)]);
+        // A single outlier frame doesn't move the median
+        let times = [("Raster".to_string(), vec![10.0, 10.0, 90.0, 10.0, 10.0])];
+        let current = BenchReport::new("test".to_string(), (64, 64), &times.into());
+        assert_eq!(current.frames, 5);
+        assert!(current.regressions(&baseline, 0.05).is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use color_eyre::{eyre::eyre, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::session::CameraState;
+
+/// Views stored under the number keys, kept next to the executable so that
+/// every build of the app shares them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bookmarks {
+    views: BTreeMap<u8, CameraState>,
+}
+
+impl Bookmarks {
+    /// Missing or unreadable bookmarks start out empty.
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Ok(path) => path,
+            Err(_) => return Self::default(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                eprintln!("Ignoring bookmarks `{}`: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn get(&self, slot: u8) -> Option<CameraState> {
+        self.views.get(&slot).copied()
+    }
+
+    /// Stores `camera` under `slot` and saves all of the bookmarks.
+    pub fn set(&mut self, slot: u8, camera: CameraState) -> Result<PathBuf> {
+        self.views.insert(slot, camera);
+        let path = Self::path()?;
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    fn path() -> Result<PathBuf> {
+        let exe = std::env::current_exe()?;
+        let dir = exe
+            .parent()
+            .ok_or_else(|| eyre!("Unable to locate the directory of the executable"))?;
+        Ok(dir.join("bookmarks.json"))
+    }
+}
@@ -1,16 +1,28 @@
+use color_eyre::{eyre::eyre, Result};
 use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     pub view_position: [f32; 4],
     pub view_proj: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub projection: u32,
+    pub fov: f32,
+    _padding: [u32; 2],
 }
 
 impl CameraUniform {
-    pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_position = [camera.eye.x, camera.eye.y, camera.eye.z, 1.0];
-        self.view_proj = camera.build_view_projection_matrix().to_cols_array_2d();
+    /// The matrices map the object space of the mesh, placed in the world by
+    /// `model`. The eye is given in object space as well.
+    pub fn update_view_proj(&mut self, camera: &Camera, model: Mat4) {
+        let eye = model.inverse().transform_point3(camera.eye);
+        self.view_position = [eye.x, eye.y, eye.z, 1.0];
+        self.view_proj = (camera.build_view_projection_matrix() * model).to_cols_array_2d();
+        self.view = (camera.build_view_matrix() * model).to_cols_array_2d();
+        self.projection = camera.projection as u32;
+        self.fov = camera.fov();
     }
 }
 
@@ -19,6 +31,38 @@ impl Default for CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            view: Mat4::IDENTITY.to_cols_array_2d(),
+            projection: Projection::Perspective as u32,
+            fov: Projection::Perspective.fov(),
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Non-linear projections are applied per vertex by the compute rasterizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Projection {
+    Perspective,
+    /// Equidistant fisheye
+    Fisheye,
+    /// Full 360 panorama
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn next(self) -> Self {
+        match self {
+            Projection::Perspective => Projection::Fisheye,
+            Projection::Fisheye => Projection::Equirectangular,
+            Projection::Equirectangular => Projection::Perspective,
+        }
+    }
+
+    pub fn fov(self) -> f32 {
+        match self {
+            Projection::Perspective => Camera::FOVY,
+            Projection::Fisheye => std::f32::consts::PI,
+            Projection::Equirectangular => std::f32::consts::TAU,
         }
     }
 }
@@ -32,6 +76,9 @@ pub struct Camera {
     pub yaw: f32,
     pub up: Vec3,
     pub aspect: f32,
+    /// Vertical field of view of the perspective projection, in radians
+    pub fovy: f32,
+    pub projection: Projection,
 }
 
 impl Camera {
@@ -49,15 +96,59 @@ impl Camera {
             target,
             up: Self::UP,
             aspect,
+            fovy: Self::FOVY,
+            projection: Projection::Perspective,
         };
         camera.update();
         camera
     }
 
+    /// Orbit camera placed at `eye` and looking at `target`. The distance
+    /// and the pitch are clamped like the ones of the orbit, so an eye too
+    /// far, too close or right above the target is moved into range.
+    pub fn look_at(eye: Vec3, target: Vec3, aspect: f32) -> Result<Self> {
+        let offset = eye - target;
+        let distance = offset.length();
+        if !distance.is_normal() {
+            return Err(eyre!(
+                "The eye {} is at the target {}, there's no direction to look in",
+                eye,
+                target
+            ));
+        }
+        let pitch = (offset.y / distance).clamp(-1.0, 1.0).asin();
+        let yaw = offset.x.atan2(offset.z);
+        let mut camera = Self::new(distance, 0.0, yaw, target, aspect);
+        camera.set_zoom(distance);
+        camera.set_pitch(pitch);
+        Ok(camera)
+    }
+
+    /// Field of view of the active projection, in radians.
+    pub fn fov(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective => self.fovy,
+            projection => projection.fov(),
+        }
+    }
+
+    /// Half of the horizontal field of view of the active projection, in
+    /// radians. Wider views are capped to the half space in front.
+    pub fn half_horizontal_fov(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective => ((self.fovy * 0.5).tan() * self.aspect).atan(),
+            projection => (projection.fov() * 0.5).min(std::f32::consts::FRAC_PI_2),
+        }
+    }
+
+    pub fn build_view_matrix(&self) -> Mat4 {
+        Mat4::look_at_rh(self.eye, self.target, self.up)
+    }
+
     pub fn build_view_projection_matrix(&self) -> Mat4 {
-        let view = Mat4::look_at_rh(self.eye, self.target, self.up);
+        let view = self.build_view_matrix();
         // let view = view * Mat4::from_translation(glam::vec3(4., 3., -10.));
-        let proj = Mat4::perspective_rh(Self::FOVY, self.aspect, Self::ZNEAR, Self::ZFAR);
+        let proj = Mat4::perspective_rh(self.fovy, self.aspect, Self::ZNEAR, Self::ZFAR);
         proj * view
     }
 
@@ -91,13 +182,89 @@ impl Camera {
         self.set_yaw(self.yaw + delta);
     }
 
+    /// Turns the view about the eye instead of orbiting the target, which
+    /// stays ahead at the same distance.
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        let eye = self.eye;
+        self.set_yaw(self.yaw + yaw);
+        self.set_pitch(self.pitch + pitch);
+        self.translate(eye - self.eye);
+    }
+
+    /// Moves the eye and the target together.
+    pub fn translate(&mut self, offset: Vec3) {
+        self.target += offset;
+        self.update();
+    }
+
+    /// Slides the target and the eye across the view, by `dx` and `dy` times
+    /// the distance to the target so that the pan follows the cursor at any
+    /// zoom. Positive `dx` moves the view content right, positive `dy` down.
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let forward = self.forward();
+        let right = forward.cross(self.up).normalize_or_zero();
+        let up = right.cross(forward);
+        self.translate((up * dy - right * dx) * self.zoom);
+    }
+
+    /// Unit vector the camera looks along.
+    pub fn forward(&self) -> Vec3 {
+        (self.target - self.eye).normalize_or_zero()
+    }
+
     fn update(&mut self) {
         let pitch_cos = self.pitch.cos();
-        self.eye = self.zoom
-            * Vec3::new(
-                self.yaw.sin() * pitch_cos,
-                self.pitch.sin(),
-                self.yaw.cos() * pitch_cos,
-            );
+        self.eye = self.target
+            + self.zoom
+                * Vec3::new(
+                    self.yaw.sin() * pitch_cos,
+                    self.pitch.sin(),
+                    self.yaw.cos() * pitch_cos,
+                );
+    }
+}
+
+/// First-person movement of the camera. The held keys are integrated every
+/// frame, with the velocity eased towards the one they ask for.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FlyController {
+    pub forward: bool,
+    pub back: bool,
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    /// Moves `FlyController::FAST_FACTOR` times faster
+    pub fast: bool,
+    velocity: Vec3,
+}
+
+impl FlyController {
+    /// Units per second.
+    const SPEED: f32 = 1.5;
+    const FAST_FACTOR: f32 = 4.0;
+    /// Inverse of the time the velocity takes to get most of the way to
+    /// the one of the held keys, in seconds.
+    const RESPONSE: f32 = 12.0;
+
+    /// Moves `camera` by the time `dt` since the last frame, in seconds.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        let forward = camera.forward();
+        let right = forward.cross(camera.up).normalize_or_zero();
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = forward * axis(self.forward, self.back)
+            + right * axis(self.right, self.left)
+            + camera.up * axis(self.up, self.down);
+        let speed = match self.fast {
+            true => Self::SPEED * Self::FAST_FACTOR,
+            false => Self::SPEED,
+        };
+        let target = direction.normalize_or_zero() * speed;
+
+        // Frame rate independent easing
+        self.velocity += (target - self.velocity) * (1.0 - (-Self::RESPONSE * dt).exp());
+        if self.velocity.length_squared() > 1e-8 {
+            camera.translate(self.velocity * dt);
+        }
     }
 }
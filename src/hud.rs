@@ -0,0 +1,143 @@
+use std::{collections::HashMap, fmt::Display, fs, path::Path, str::FromStr};
+
+use color_eyre::{
+    eyre::{eyre, Report, WrapErr},
+    Result,
+};
+use glam::Vec3;
+
+/// How durations are shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    #[default]
+    Milliseconds,
+    /// The rate the duration would be repeated at
+    FramesPerSecond,
+}
+
+impl FromStr for TimeUnit {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ms" => Ok(TimeUnit::Milliseconds),
+            "fps" => Ok(TimeUnit::FramesPerSecond),
+            _ => Err(eyre!("Unknown time unit `{}`, expected ms or fps", s)),
+        }
+    }
+}
+
+/// How lengths in the space of the mesh are shown, glTF models are in meters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    #[default]
+    Meters,
+    Feet,
+}
+
+impl FromStr for LengthUnit {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m" => Ok(LengthUnit::Meters),
+            "ft" => Ok(LengthUnit::Feet),
+            _ => Err(eyre!("Unknown length unit `{}`, expected m or ft", s)),
+        }
+    }
+}
+
+const FEET_PER_METER: f32 = 3.28084;
+
+/// Text printed for the user as the settings change. The English text
+/// doubles as the key of its translation, a locale is a JSON object of the
+/// texts it translates and the rest stays in English. `{}` in a text is
+/// replaced by its arguments in order.
+#[derive(Debug, Default)]
+pub struct Hud {
+    translations: HashMap<String, String>,
+    pub time_unit: TimeUnit,
+    pub length_unit: LengthUnit,
+}
+
+impl Hud {
+    pub fn new(
+        locale: Option<&Path>,
+        time_unit: TimeUnit,
+        length_unit: LengthUnit,
+    ) -> Result<Self> {
+        let translations = match locale {
+            Some(path) => {
+                let json = fs::read_to_string(path)
+                    .wrap_err_with(|| format!("Failed to read the locale `{}`", path.display()))?;
+                serde_json::from_str(&json)
+                    .wrap_err_with(|| format!("Invalid locale `{}`", path.display()))?
+            }
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            translations,
+            time_unit,
+            length_unit,
+        })
+    }
+
+    /// Translation of `english`, itself when there's none.
+    pub fn text<'a>(&'a self, english: &'a str) -> &'a str {
+        self.translations
+            .get(english)
+            .map_or(english, String::as_str)
+    }
+
+    /// Translation of `english` with its `{}` replaced by `args`.
+    pub fn format(&self, english: &str, args: &[&dyn Display]) -> String {
+        let mut parts = self.text(english).split("{}");
+        let mut text = parts.next().unwrap_or_default().to_owned();
+        for (index, part) in parts.enumerate() {
+            if let Some(arg) = args.get(index) {
+                text.push_str(&arg.to_string());
+            }
+            text.push_str(part);
+        }
+        text
+    }
+
+    pub fn print(&self, english: &str, args: &[&dyn Display]) {
+        println!("{}", self.format(english, args));
+    }
+
+    pub fn error(&self, english: &str, args: &[&dyn Display]) {
+        eprintln!("{}", self.format(english, args));
+    }
+
+    /// Prints the new value of a setting.
+    pub fn status(&self, label: &str, value: impl Display) {
+        println!("{}: {}", self.text(label), value);
+    }
+
+    /// Prints whether a setting was turned on or off.
+    pub fn switch(&self, label: &str, on: bool) {
+        self.status(label, self.text(if on { "on" } else { "off" }));
+    }
+
+    pub fn duration(&self, seconds: f32) -> String {
+        match self.time_unit {
+            TimeUnit::Milliseconds => format!("{:.2}ms", seconds * 1000.0),
+            TimeUnit::FramesPerSecond => format!("{:.1} FPS", 1.0 / seconds.max(1e-6)),
+        }
+    }
+
+    pub fn length(&self, meters: f32) -> String {
+        match self.length_unit {
+            LengthUnit::Meters => format!("{:.3}m", meters),
+            LengthUnit::Feet => format!("{:.3}ft", meters * FEET_PER_METER),
+        }
+    }
+
+    pub fn position(&self, position: Vec3) -> String {
+        let [x, y, z] = position
+            .to_array()
+            .map(|coordinate| self.length(coordinate));
+        format!("({}, {}, {})", x, y, z)
+    }
+}
@@ -0,0 +1,18 @@
+//! Software rasterizer running in compute shaders, the hardware pipeline is
+//! only used to present the result and to validate it.
+//!
+//! [`Renderer`] draws a [`Mesh`] into a window surface, the `compaster` binary
+//! is an example of driving it from a winit event loop.
+
+mod camera;
+mod state;
+
+pub use camera::{Camera, FlyController, Projection};
+pub use image::RgbaImage;
+pub use state::{
+    load_model, process_gltf_model, render_on_cpu, Analysis, Billboard, CompareMode, Coverage,
+    DebugView, Distortion, GraphPass, Guides, Hit, Light, LightKind, Mesh, NoAdapter,
+    OccupancyReport, PassOccupancy, Presentation, RasterMode, RenderError, RenderGraph,
+    RenderResolution, Renderer, RendererOptions, RepairOptions, RepairReport, SceneState, Settings,
+    Sprite, TestScene, TraversalStats,
+};
@@ -1,58 +1,243 @@
-mod camera;
-mod state;
+mod args;
+mod bench;
+mod bookmarks;
+mod hud;
+mod session;
+mod stats;
+mod thumbnails;
 
-use camera::Camera;
-use glam::vec3;
-use state::State;
+use args::Args;
+use bench::BenchReport;
+use bookmarks::Bookmarks;
+use compaster::{
+    load_model, process_gltf_model, render_on_cpu, Billboard, Camera, DebugView, FlyController,
+    Light, Mesh, NoAdapter, Presentation, RenderError, RenderResolution, Renderer, Sprite,
+    TestScene,
+};
+use glam::Vec3;
+use hud::Hud;
+use session::{CameraState, Session};
+use stats::StatsRecorder;
 
-use std::time::{Duration, Instant};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
     event::{
-        DeviceEvent, ElementState, Event, KeyboardInput, MouseScrollDelta, VirtualKeyCode,
-        WindowEvent,
+        DeviceEvent, ElementState, Event, KeyboardInput, ModifiersState, MouseButton,
+        MouseScrollDelta, VirtualKeyCode, WindowEvent,
     },
     event_loop::{ControlFlow, EventLoop},
+    window::{Fullscreen, Window},
 };
 
+/// Frames per second the window is redrawn at.
+const FRAME_RATE: f64 = 60.0;
+/// Frame rate cap of `--low-power`, still responsive enough to orbit the camera.
+const LOW_POWER_FRAME_RATE: f64 = 20.0;
+
+/// Frames between two GPU time samples of the dynamic resolution, each one
+/// stalls the CPU until the GPU is done.
+const DYNAMIC_RESOLUTION_INTERVAL: u32 = 5;
+/// GPU time samples averaged before the dynamic resolution adjusts the render
+/// scale.
+const DYNAMIC_RESOLUTION_SAMPLES: u32 = 6;
+/// Fraction of the frame budget the dynamic resolution aims for, the scale is
+/// kept while the GPU time stays between it and the budget.
+const DYNAMIC_RESOLUTION_LOAD: f32 = 0.8;
+/// Range of the render scale picked by the dynamic resolution.
+const DYNAMIC_RESOLUTION_SCALES: (f32, f32) = (0.5, 1.0);
+
+/// Frames rendered before the bench mode starts timing, while the driver
+/// settles.
+const BENCH_WARMUP_FRAMES: usize = 10;
+
+/// Longest time step of the fly camera, in seconds.
+const MAX_FLY_STEP: f32 = 0.1;
+
+/// Distance the arrow keys move a light by, in the units of the mesh.
+const LIGHT_STEP: f32 = 0.1;
+
+/// Times in a row the GPU device is recreated after a loss before the viewer
+/// gives up, a frame rendered in between starts the count over.
+const MAX_DEVICE_RECREATIONS: u32 = 3;
+
+const TITLE: &str = "WGPU - Compute Raster";
+
+/// The window title doubles as the HUD of the debug view.
+fn title(debug_view: DebugView) -> String {
+    format!("{} - {:?}", TITLE, debug_view)
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     color_eyre::install()?;
+    let args = Args::parse()?;
+    if args.help {
+        print!("{}", args::USAGE);
+        return Ok(());
+    }
+    let hud = Hud::new(args.locale.as_deref(), args.time_unit, args.length_unit)?;
+    if let Some(output) = &args.output {
+        return render_image(&args, &hud, output);
+    }
+    if let Some(frames) = args.bench {
+        return bench(&args, &hud, frames);
+    }
+    if args.list_recent {
+        list_recent(&hud);
+        return Ok(());
+    }
 
     let event_loop = EventLoop::new();
     let window = winit::window::WindowBuilder::new()
-        .with_title("WGPU - Compute Raster")
-        .with_inner_size(winit::dpi::LogicalSize::new(1280.0, 720.0))
+        .with_title(TITLE)
+        .with_inner_size({
+            let (width, height) = args.window_size();
+            winit::dpi::LogicalSize::new(width, height)
+        })
         .build(&event_loop)?;
     let PhysicalSize { width, height } = window.inner_size();
 
-    let mut state = pollster::block_on({
-        let camera = Camera::new(
-            1.5,
-            0.5,
-            1.25,
-            vec3(0.0, 0.0, 0.0),
-            width as f32 / height as f32,
-        );
-        State::new(&window, width, height, camera)
+    let session = match args.fresh {
+        true => Session::default(),
+        false => Session::load(),
+    };
+    let recent_model = match args.recent {
+        Some(index) => Some(
+            session
+                .recent
+                .get(index)
+                .cloned()
+                .ok_or_else(|| eyre!("No recent model {}, see `--list-recent`", index))?,
+        ),
+        None => None,
+    };
+    // Explicit model or scene on the command line replace the restored ones
+    let (scene, model) = match (args.scene, args.model.as_ref().or(recent_model.as_ref())) {
+        (None, None) => (
+            session.scene(),
+            session.model.clone().filter(|path| path.exists()),
+        ),
+        (scene, model) => (scene, model.cloned()),
+    };
+    let recent = session.recent_with(model.as_deref());
+
+    let mut meshes = load_tab_meshes(scene, model.as_deref(), &args)?.into_iter();
+    let mut renderer = pollster::block_on({
+        let camera = initial_camera(&args, session.camera, width as f32 / height as f32)?;
+        let mesh = meshes.next().expect("The first tab is always loaded");
+        Renderer::new(
+            &window,
+            width,
+            height,
+            camera,
+            mesh,
+            &args.renderer_options(),
+        )
+    })
+    .map_err(|err| match err.downcast_ref() {
+        // There's no CPU present, softbuffer isn't a dependency
+        Some(NoAdapter) => {
+            err.wrap_err("A window needs an adapter, `--output` renders on the CPU without one")
+        }
+        _ => err,
     })?;
+    if let Some(settings) = &session.settings {
+        renderer.apply_settings(settings);
+    }
+    if let Some(color) = args.clear_color {
+        renderer.set_clear_color(color.to_array());
+    }
+    if let Some(path) = &args.environment {
+        renderer.load_environment(path)?;
+    }
+    if let Some(path) = &args.shading_snippet {
+        renderer.set_shading_snippet(path);
+    }
+    window.set_title(&title(renderer.settings().debug_view));
+    if args.has_camera() {
+        renderer.turntable = false;
+    }
+    renderer.dirty_tracking = args.low_power;
+    renderer.presentation = Presentation {
+        turntable: args.presentation_turntable,
+        aspect: args.presentation_aspect,
+    };
+    if args.chunk_size.is_some() {
+        renderer.set_raster_chunks(args.chunk_size, !args.chunk_dispatches);
+    }
+    if let Some(resolution) = args.render_resolution() {
+        renderer.set_render_resolution(resolution);
+    }
+    if let Some(guides) = args.guides {
+        renderer.set_guides(guides);
+    }
+    renderer.set_billboards(&markers(&args));
+    // Names of the tabs, the first one is the model the session restores
+    let mut tabs = vec![mesh_name(scene, model.as_deref())];
+    for (path, mesh) in args.tabs.iter().zip(meshes) {
+        let camera = initial_camera(&args, None, width as f32 / height as f32)?;
+        renderer.open_tab(mesh, camera);
+        tabs.push(path.display().to_string());
+    }
+    renderer.switch_tab(0);
+
+    if args.check_coverage {
+        // Checked from the camera as given, not spun by the turntable
+        renderer.turntable = false;
+        renderer.update(0.0);
+        let coverage = renderer.check_coverage()?;
+        hud.status("Coverage", coverage);
+        if !coverage.is_watertight() {
+            return Err(eyre!("The raster isn't watertight"));
+        }
+        return Ok(());
+    }
+    if !recent.is_empty() {
+        thumbnails::spawn(recent.clone(), args.renderer_options());
+    }
 
     let mut mouse_dragged = false;
+    let mut mouse_panning = false;
+    let mut cursor = PhysicalPosition::new(0.0, 0.0);
+    // First-person movement replacing the orbit, `None` while orbiting
+    let mut fly: Option<FlyController> = None;
+    let mut modifiers = ModifiersState::empty();
+    let mut bookmarks = Bookmarks::load();
+    let mut window_mode = WindowMode::Windowed;
+    // Recreations since the last frame rendered
+    let mut device_recreations = 0;
     let rotate_speed = 0.0025;
     let zoom_speed = 0.002;
+    let pan_speed = 0.0015;
 
     let mut last_update_inst = Instant::now();
     let mut last_frame_inst = Instant::now();
     let mut frame_counter = FrameCounter::new();
+    let mut stats = StatsRecorder::new();
+    for name in &tabs {
+        stats.model_loaded(name.clone());
+    }
     let time = Instant::now();
+    let target_frametime = Duration::from_secs_f64(match args.low_power {
+        true => 1.0 / LOW_POWER_FRAME_RATE,
+        false => 1.0 / FRAME_RATE,
+    });
+    let mut dynamic_resolution = args
+        .dynamic_resolution
+        .then(|| DynamicResolution::new(target_frametime, renderer.render_resolution()));
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
         match event {
             Event::RedrawEventsCleared => {
-                let target_frametime = Duration::from_secs_f64(1.0 / 60.0);
                 let time_since_last_frame = last_update_inst.elapsed();
                 if time_since_last_frame >= target_frametime {
                     window.request_redraw();
@@ -77,12 +262,356 @@ fn main() -> Result<()> {
                             ..
                         },
                     ..
-                } => *control_flow = ControlFlow::Exit,
+                } => {
+                    renderer.switch_tab(0);
+                    if let Err(err) =
+                        Session::new(model.clone(), scene, &renderer, recent.clone()).save()
+                    {
+                        hud.error("Failed to save the session: {}", &[&err]);
+                    }
+                    match stats.save() {
+                        Ok(path) => {
+                            hud.print("Saved the session statistics to {}", &[&path.display()])
+                        }
+                        Err(err) => hud.error("Failed to save the session statistics: {}", &[&err]),
+                    }
+                    *control_flow = ControlFlow::Exit;
+                }
+                // Held down rather than pressed, they take over the toggles
+                // of the same keys in the fly mode
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state,
+                            ..
+                        },
+                    ..
+                } if fly.as_mut().and_then(|fly| fly_key(fly, *key)).is_some() => {
+                    if let Some(held) = fly.as_mut().and_then(|fly| fly_key(fly, *key)) {
+                        *held = *state == ElementState::Pressed;
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => match key {
+                    VirtualKeyCode::Q => {
+                        fly = match fly {
+                            Some(_) => None,
+                            None => Some(FlyController::default()),
+                        };
+                        hud.switch("Fly camera", fly.is_some());
+                    }
+                    VirtualKeyCode::Z if modifiers.ctrl() => {
+                        let undone = renderer.undo();
+                        hud.status("Undo", if undone { "done" } else { "nothing to undo" });
+                    }
+                    VirtualKeyCode::Y if modifiers.ctrl() => {
+                        let redone = renderer.redo();
+                        hud.status("Redo", if redone { "done" } else { "nothing to redo" });
+                    }
+                    VirtualKeyCode::P => {
+                        renderer.camera.projection = renderer.camera.projection.next();
+                        hud.status(
+                            "Projection",
+                            format_args!("{:?}", renderer.camera.projection),
+                        );
+                    }
+                    VirtualKeyCode::T => {
+                        renderer.raster_mode = renderer.raster_mode.next();
+                        hud.status("Raster mode", format_args!("{:?}", renderer.raster_mode));
+                    }
+                    VirtualKeyCode::LBracket => {
+                        window.set_title(&title(renderer.prev_debug_view()));
+                    }
+                    VirtualKeyCode::RBracket => {
+                        window.set_title(&title(renderer.next_debug_view()));
+                    }
+                    VirtualKeyCode::B if modifiers.alt() => {
+                        let bloom = renderer.toggle_bloom();
+                        hud.switch("Bloom", bloom);
+                    }
+                    VirtualKeyCode::B if modifiers.shift() => {
+                        let sky = renderer.cycle_sky();
+                        hud.status("Sky", format_args!("{:?}", sky));
+                    }
+                    VirtualKeyCode::B => {
+                        let background = renderer.cycle_background();
+                        hud.status("Background", format_args!("{:?}", background));
+                    }
+                    VirtualKeyCode::A => {
+                        let accumulate = renderer.toggle_accumulate();
+                        hud.switch("Accumulation", accumulate);
+                    }
+                    VirtualKeyCode::F => {
+                        let culling = renderer.toggle_frustum_culling();
+                        hud.switch("Frustum culling", culling);
+                    }
+                    VirtualKeyCode::G if modifiers.shift() => {
+                        let guides = renderer.cycle_guides();
+                        hud.status("Framing guides", format_args!("{:?}", guides));
+                    }
+                    VirtualKeyCode::G => {
+                        let lines = renderer.toggle_debug_lines();
+                        hud.switch("Debug lines", lines);
+                    }
+                    VirtualKeyCode::K => {
+                        let level = renderer.cycle_bvh_level();
+                        let (visible, stats) = renderer.visible_objects();
+                        hud.print(
+                            "BVH level: {}, {} visible objects, {}",
+                            &[
+                                &format_args!("{:?}", level),
+                                &visible.len(),
+                                &format_args!("{:?}", stats),
+                            ],
+                        );
+                    }
+                    VirtualKeyCode::R => {
+                        let graph = renderer.render_graph();
+                        let path = Path::new("render_graph.dot");
+                        print!("{}", graph);
+                        match fs::write(path, graph.to_dot()) {
+                            Ok(()) => hud.print(
+                                "Wrote the render graph of {} passes to {}",
+                                &[&graph.passes().len(), &path.display()],
+                            ),
+                            Err(err) => hud.error("Failed to write the render graph: {}", &[&err]),
+                        }
+                    }
+                    VirtualKeyCode::U => {
+                        let report = renderer.occupancy();
+                        hud.print(
+                            "Workgroup occupancy, at most {} invocations per workgroup:",
+                            &[&report.max_invocations_per_workgroup],
+                        );
+                        print!("{}", report);
+                    }
+                    VirtualKeyCode::X => {
+                        let texture = renderer.toggle_texture_output();
+                        hud.switch("Texture output", texture);
+                    }
+                    VirtualKeyCode::N => {
+                        let samples = renderer.toggle_msaa();
+                        hud.status("MSAA samples", samples);
+                    }
+                    VirtualKeyCode::E => {
+                        let analysis = renderer.cycle_analysis();
+                        hud.status("Analysis", format_args!("{:?}", analysis));
+                    }
+                    VirtualKeyCode::I if modifiers.shift() => {
+                        let ibl = renderer.toggle_ibl();
+                        hud.switch("Environment lighting", ibl);
+                    }
+                    VirtualKeyCode::I => {
+                        let tracking = renderer.toggle_dirty_tracking();
+                        hud.switch("Skip unchanged frames", tracking);
+                    }
+                    VirtualKeyCode::Tab if modifiers.ctrl() => {
+                        let tab = renderer.next_tab();
+                        hud.print(
+                            "Tab {} of {}: {}",
+                            &[&(tab + 1), &renderer.tab_count(), &tabs[tab]],
+                        );
+                    }
+                    VirtualKeyCode::Tab => {
+                        let presenting = renderer.toggle_presentation();
+                        hud.switch("Presentation mode", presenting);
+                    }
+                    VirtualKeyCode::H => {
+                        let compare = renderer.cycle_compare();
+                        hud.status("Hardware raster compare", format_args!("{:?}", compare));
+                    }
+                    VirtualKeyCode::C => {
+                        let culling = renderer.toggle_meshlet_culling();
+                        hud.switch("Meshlet culling", culling);
+                    }
+                    VirtualKeyCode::O => {
+                        let culling = renderer.toggle_occlusion_culling();
+                        hud.switch("Occlusion culling", culling);
+                    }
+                    VirtualKeyCode::M => {
+                        let minimap = renderer.toggle_minimap();
+                        hud.switch("Minimap", minimap);
+                    }
+                    VirtualKeyCode::V => {
+                        let mode = renderer.cycle_present_mode();
+                        hud.status("Present mode", format_args!("{:?}", mode));
+                    }
+                    VirtualKeyCode::L if modifiers.shift() => {
+                        let light = Light::point(renderer.eye(), [1.0; 3], 1.0);
+                        match renderer.add_light(light) {
+                            Ok(index) => hud.print(
+                                "Added light {} at {}",
+                                &[&index, &hud.position(light.position)],
+                            ),
+                            Err(err) => hud.error("{}", &[&err]),
+                        }
+                    }
+                    VirtualKeyCode::L if modifiers.ctrl() => {
+                        match renderer.lights().len().checked_sub(1) {
+                            Some(index) => {
+                                renderer.remove_light(index);
+                                hud.print("Removed light {}", &[&index]);
+                            }
+                            None => hud.print("No lights to remove", &[]),
+                        }
+                    }
+                    VirtualKeyCode::L if modifiers.alt() => {
+                        let shadows = renderer.toggle_shadows();
+                        hud.switch("Shadows", shadows);
+                    }
+                    VirtualKeyCode::Left
+                    | VirtualKeyCode::Right
+                    | VirtualKeyCode::Up
+                    | VirtualKeyCode::Down
+                    | VirtualKeyCode::PageUp
+                    | VirtualKeyCode::PageDown => {
+                        if let Some(index) = renderer.lights().len().checked_sub(1) {
+                            renderer.move_light(index, light_step(*key));
+                            let position = renderer.lights()[index].position;
+                            hud.status("Light position", hud.position(position));
+                        }
+                    }
+                    VirtualKeyCode::L => {
+                        let distortion = renderer.cycle_distortion();
+                        hud.status("Lens distortion", format_args!("{:?}", distortion));
+                    }
+                    #[cfg(feature = "renderdoc")]
+                    VirtualKeyCode::F9 => {
+                        renderer.capture_frame();
+                        hud.print("Capturing the next frame", &[]);
+                    }
+                    VirtualKeyCode::F11 => {
+                        window_mode = window_mode.next().apply(&window);
+                        hud.status("Window mode", format_args!("{:?}", window_mode));
+                        // Resized follows, though not on every platform
+                        let PhysicalSize { width, height } = window.inner_size();
+                        renderer.resize(width, height);
+                    }
+                    VirtualKeyCode::Minus | VirtualKeyCode::Equals => {
+                        let up = *key == VirtualKeyCode::Equals;
+                        let resolution = renderer.step_render_scale(up);
+                        hud.status("Render resolution", format_args!("{:?}", resolution));
+                        if dynamic_resolution.take().is_some() {
+                            hud.switch("Dynamic resolution", false);
+                        }
+                    }
+                    VirtualKeyCode::D => {
+                        dynamic_resolution = match dynamic_resolution {
+                            Some(_) => None,
+                            None => Some(DynamicResolution::new(
+                                target_frametime,
+                                renderer.render_resolution(),
+                            )),
+                        };
+                        hud.switch("Dynamic resolution", dynamic_resolution.is_some());
+                    }
+                    VirtualKeyCode::Comma | VirtualKeyCode::Period
+                        if modifiers.alt() && modifiers.shift() =>
+                    {
+                        let delta = if *key == VirtualKeyCode::Period {
+                            0.1
+                        } else {
+                            -0.1
+                        };
+                        let threshold = renderer.add_bloom_threshold(delta);
+                        hud.status("Bloom threshold", format_args!("{:.1}", threshold));
+                    }
+                    VirtualKeyCode::Comma | VirtualKeyCode::Period if modifiers.alt() => {
+                        let delta = if *key == VirtualKeyCode::Period {
+                            0.02
+                        } else {
+                            -0.02
+                        };
+                        let intensity = renderer.add_bloom_intensity(delta);
+                        hud.status("Bloom intensity", format_args!("{:.2}", intensity));
+                    }
+                    VirtualKeyCode::Comma | VirtualKeyCode::Period if modifiers.shift() => {
+                        let stops = if *key == VirtualKeyCode::Period {
+                            0.5
+                        } else {
+                            -0.5
+                        };
+                        let exposure = renderer.add_exposure(stops);
+                        hud.status("Exposure", format_args!("{:+.1} EV", exposure));
+                    }
+                    VirtualKeyCode::J => {
+                        let tonemap = renderer.cycle_tonemap();
+                        hud.status("Tonemap", format_args!("{:?}", tonemap));
+                    }
+                    VirtualKeyCode::Comma | VirtualKeyCode::Period => {
+                        let delta = if *key == VirtualKeyCode::Period {
+                            0.1
+                        } else {
+                            -0.1
+                        };
+                        let strength = renderer.add_distortion_strength(delta);
+                        hud.status("Lens distortion strength", format_args!("{:.1}", strength));
+                    }
+                    key => match bookmark_slot(*key) {
+                        Some(slot) if modifiers.ctrl() => {
+                            match bookmarks.set(slot, CameraState::new(&renderer.camera)) {
+                                Ok(path) => {
+                                    hud.print("Stored view {} in {}", &[&slot, &path.display()])
+                                }
+                                Err(err) => hud.error("Failed to save the bookmarks: {}", &[&err]),
+                            }
+                        }
+                        Some(slot) => match bookmarks.get(slot) {
+                            Some(view) => {
+                                renderer.set_camera(view.camera(renderer.camera.aspect));
+                                hud.print("Recalled view {}", &[&slot]);
+                            }
+                            None => hud.print("No view stored under {}", &[&slot]),
+                        },
+                        None => {}
+                    },
+                },
+                WindowEvent::ModifiersChanged(state) => {
+                    modifiers = *state;
+                }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Middle,
+                    state,
+                    ..
+                } => {
+                    mouse_panning = *state == ElementState::Pressed;
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    cursor = *position;
+                }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Right,
+                    state: ElementState::Pressed,
+                    ..
+                } => {
+                    let (hit, stats) = renderer.pick(cursor.x as f32, cursor.y as f32);
+                    match hit {
+                        Some(hit) => hud.print(
+                            "Picked object {} triangle {} at {}, {} away, {}",
+                            &[
+                                &hit.object,
+                                &hit.triangle,
+                                &hud.position(hit.position),
+                                &hud.length(hit.distance),
+                                &format_args!("{:?}", stats),
+                            ],
+                        ),
+                        None => hud.print("Picked nothing, {}", &[&format_args!("{:?}", stats)]),
+                    }
+                }
                 WindowEvent::Resized(size) => {
-                    state.resize(size.width, size.height);
+                    renderer.resize(size.width, size.height);
                 }
                 WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                    state.resize(new_inner_size.width, new_inner_size.height);
+                    renderer.resize(new_inner_size.width, new_inner_size.height);
                 }
                 _ => {}
             },
@@ -106,29 +635,93 @@ fn main() -> Result<()> {
                             *scroll as f32
                         }
                     };
-                    state.camera.add_zoom(scroll_amount * zoom_speed);
+                    renderer.camera.add_zoom(scroll_amount * zoom_speed);
                 }
                 DeviceEvent::MouseMotion { delta } => {
-                    if mouse_dragged {
-                        state.camera.add_yaw(-delta.0 as f32 * rotate_speed);
-                        state.camera.add_pitch(delta.1 as f32 * rotate_speed);
+                    if mouse_panning {
+                        renderer
+                            .camera
+                            .pan(delta.0 as f32 * pan_speed, delta.1 as f32 * pan_speed);
+                    }
+                    let (yaw, pitch) = (-delta.0 as f32, delta.1 as f32);
+                    match (mouse_dragged, fly.is_some()) {
+                        (true, true) => renderer
+                            .camera
+                            .look(yaw * rotate_speed, pitch * rotate_speed),
+                        (true, false) => {
+                            renderer.camera.add_yaw(yaw * rotate_speed);
+                            renderer.camera.add_pitch(pitch * rotate_speed);
+                        }
+                        (false, _) => {}
                     }
                 }
                 _ => (),
             },
 
             Event::RedrawRequested(_) => {
-                frame_counter.record(&mut last_frame_inst);
-                state.update(time.elapsed().as_secs_f32());
-                match state.render() {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost) => {
-                        state.resize(state.width, state.height);
-                        window.request_redraw();
+                let frame_time = last_frame_inst.elapsed();
+                if let Some(fly) = &mut fly {
+                    // Capped to not jump after a stall
+                    let dt = frame_time.as_secs_f32().min(MAX_FLY_STEP);
+                    fly.update(&mut renderer.camera, dt);
+                }
+                if dynamic_resolution
+                    .as_mut()
+                    .is_some_and(DynamicResolution::wants_sample)
+                {
+                    renderer.sample_gpu_time();
+                }
+                let render_start = Instant::now();
+                renderer.update(time.elapsed().as_secs_f32());
+                let update_time = render_start.elapsed();
+                let rendered = renderer.render_to_surface();
+                let render_time = render_start.elapsed();
+                frame_counter.record(&hud, &mut last_frame_inst, render_time);
+                stats.record(
+                    frame_time,
+                    update_time,
+                    render_time - update_time,
+                    renderer.buffer_size(),
+                );
+                let gpu_time = renderer.take_gpu_time();
+                if let Some(scale) = dynamic_resolution
+                    .as_mut()
+                    .zip(gpu_time)
+                    .and_then(|(controller, gpu_time)| controller.record(gpu_time))
+                {
+                    renderer.set_render_resolution(RenderResolution::Scaled(scale));
+                }
+                match rendered {
+                    Ok(_) => device_recreations = 0,
+                    Err(RenderError::Surface(wgpu::SurfaceError::OutOfMemory)) => {
+                        *control_flow = ControlFlow::Exit
+                    }
+                    Err(RenderError::DeviceLost)
+                        if device_recreations == MAX_DEVICE_RECREATIONS =>
+                    {
+                        hud.error(
+                            "The GPU device was lost again after {} recreations, giving up",
+                            &[&device_recreations],
+                        );
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    Err(RenderError::DeviceLost) => {
+                        device_recreations += 1;
+                        hud.error("The GPU device was lost, recreating it", &[]);
+                        let recreated =
+                            load_tab_meshes(scene, model.as_deref(), &args).and_then(|meshes| {
+                                pollster::block_on(renderer.recreate_gpu_resources(&window, meshes))
+                            });
+                        match recreated {
+                            Ok(()) => window.request_redraw(),
+                            Err(err) => {
+                                hud.error("Failed to recreate the GPU device: {}", &[&err]);
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
                     }
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     Err(e) => {
-                        eprintln!("{:?}", e);
+                        hud.error("{}", &[&e]);
                         window.request_redraw();
                     }
                 }
@@ -138,9 +731,281 @@ fn main() -> Result<()> {
     });
 }
 
+/// Camera given on the command line or by `--bookmark`, otherwise the one of
+/// the last session.
+fn initial_camera(args: &Args, session: Option<CameraState>, aspect: f32) -> Result<Camera> {
+    let bookmark = match args.bookmark {
+        Some(slot) => Some(
+            Bookmarks::load()
+                .get(slot)
+                .ok_or_else(|| eyre!("No view stored under bookmark {}", slot))?,
+        ),
+        None => None,
+    };
+    let mut camera = match (args.eye, bookmark, session) {
+        (Some(eye), _, _) => Camera::look_at(eye, args.target.unwrap_or(Vec3::ZERO), aspect)?,
+        (None, Some(view), _) => view.camera(aspect),
+        (None, None, Some(camera)) if !args.has_camera() => camera.camera(aspect),
+        (None, None, _) => Camera::new(1.5, 0.5, 1.25, args.target.unwrap_or(Vec3::ZERO), aspect),
+    };
+    if let Some(fov) = args.fov {
+        camera.fovy = fov.to_radians();
+    }
+    Ok(camera)
+}
+
+/// Movement of the fly camera held by `key`: WASD, space and control to
+/// go up and down and shift to speed up.
+fn fly_key(fly: &mut FlyController, key: VirtualKeyCode) -> Option<&mut bool> {
+    match key {
+        VirtualKeyCode::W => Some(&mut fly.forward),
+        VirtualKeyCode::S => Some(&mut fly.back),
+        VirtualKeyCode::A => Some(&mut fly.left),
+        VirtualKeyCode::D => Some(&mut fly.right),
+        VirtualKeyCode::Space => Some(&mut fly.up),
+        VirtualKeyCode::LControl => Some(&mut fly.down),
+        VirtualKeyCode::LShift => Some(&mut fly.fast),
+        _ => None,
+    }
+}
+
+/// Offset of the last light moved by the arrow keys over the ground plane
+/// and by page up and down vertically.
+fn light_step(key: VirtualKeyCode) -> Vec3 {
+    let direction = match key {
+        VirtualKeyCode::Left => Vec3::NEG_X,
+        VirtualKeyCode::Right => Vec3::X,
+        VirtualKeyCode::Up => Vec3::NEG_Z,
+        VirtualKeyCode::Down => Vec3::Z,
+        VirtualKeyCode::PageUp => Vec3::Y,
+        VirtualKeyCode::PageDown => Vec3::NEG_Y,
+        _ => Vec3::ZERO,
+    };
+    direction * LIGHT_STEP
+}
+
+/// Bookmark stored with control and recalled by the number keys 1 to 9.
+fn bookmark_slot(key: VirtualKeyCode) -> Option<u8> {
+    use VirtualKeyCode::*;
+    [Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9]
+        .iter()
+        .position(|&slot| slot == key)
+        .map(|index| index as u8 + 1)
+}
+
+/// Red dots at the points of `--marker`.
+fn markers(args: &Args) -> Vec<Billboard> {
+    args.markers
+        .iter()
+        .map(|&position| Billboard::new(position, Sprite::Dot, [1.0, 0.1, 0.1]))
+        .collect()
+}
+
+/// Test scene or glTF model, Suzanne without either. The assets directory can
+/// replace the embedded Suzanne.
+fn load_mesh(scene: Option<TestScene>, model: Option<&Path>, args: &Args) -> Result<Mesh> {
+    let default_model = args
+        .assets_dir
+        .as_ref()
+        .map(|dir| dir.join("models").join("suzanne.glb"))
+        .filter(|path| path.exists());
+    Ok(match (scene, model.or(default_model.as_deref())) {
+        (Some(scene), _) => scene.mesh(),
+        (None, Some(path)) => load_model(path, args.repair_options())?,
+        (None, None) => process_gltf_model(),
+    })
+}
+
+/// Meshes of the tabs in order, the one of `load_mesh` followed by the
+/// `--tab` models. Loaded again when the GPU device is recreated.
+fn load_tab_meshes(
+    scene: Option<TestScene>,
+    model: Option<&Path>,
+    args: &Args,
+) -> Result<Vec<Mesh>> {
+    let mut meshes = vec![load_mesh(scene, model, args)?];
+    for path in &args.tabs {
+        meshes.push(load_model(path, args.repair_options())?);
+    }
+    Ok(meshes)
+}
+
+/// Name of the mesh `load_mesh` loads, for the session statistics.
+fn mesh_name(scene: Option<TestScene>, model: Option<&Path>) -> String {
+    match (scene, model) {
+        (Some(scene), _) => scene.name().to_owned(),
+        (None, Some(path)) => path.display().to_string(),
+        (None, None) => "Suzanne".to_owned(),
+    }
+}
+
+/// Recent models by index for `--recent`, with their thumbnail when it's rendered.
+fn list_recent(hud: &Hud) {
+    for (index, model) in Session::load().recent.iter().enumerate() {
+        hud.print("{}: {}", &[&index, &model.display()]);
+        if let Ok(thumbnail) = thumbnails::thumbnail_path(model) {
+            if thumbnail.exists() {
+                hud.print("   {}", &[&thumbnail.display()]);
+            }
+        }
+    }
+}
+
+/// Renders a single frame without a window, for golden images and batch
+/// rendering on machines without a display. The session isn't restored.
+/// Without any adapter the frame is rasterized on the CPU, slowly and with
+/// the default settings only.
+fn render_image(args: &Args, hud: &Hud, output: &Path) -> Result<()> {
+    let (width, height) = args.size.unwrap_or_else(|| args.window_size());
+    let image = match headless_renderer(args, width, height) {
+        Ok(mut renderer) => {
+            renderer.update(0.0);
+            renderer.render_to_image(width, height)?
+        }
+        Err(err) if matches!(err.downcast_ref(), Some(NoAdapter)) => {
+            hud.error("No adapter found, rendering on the CPU", &[]);
+            let camera = initial_camera(args, None, width as f32 / height as f32)?;
+            let mesh = load_mesh(args.scene, args.model.as_deref(), args)?;
+            render_on_cpu(&mesh, &camera, width, height)
+        }
+        Err(err) => return Err(err),
+    };
+    image.save(output)?;
+    hud.print("Saved {}", &[&output.display()]);
+    Ok(())
+}
+
+/// Times the stages of `frames` frames without a window, after a few to
+/// warm up. Fails when a stage got slower than in `--baseline` by more
+/// than `--fail-threshold`, to gate changes of the raster in automation.
+fn bench(args: &Args, hud: &Hud, frames: usize) -> Result<()> {
+    let (width, height) = args.size.unwrap_or_else(|| args.window_size());
+    let mut renderer = headless_renderer(args, width, height)?;
+    let mut times: BTreeMap<String, Vec<f32>> = BTreeMap::new();
+    for frame in 0..BENCH_WARMUP_FRAMES + frames {
+        renderer.update(frame as f32 / FRAME_RATE as f32);
+        let stages = renderer.time_frame();
+        if frame >= BENCH_WARMUP_FRAMES {
+            for (stage, ms) in stages {
+                times.entry(stage.to_owned()).or_default().push(ms);
+            }
+        }
+    }
+    let model = mesh_name(args.scene, args.model.as_deref());
+    let report = BenchReport::new(model, (width, height), &times);
+    print!("{}", report);
+    if let Some(path) = &args.bench_output {
+        report.save(path)?;
+        hud.print("Saved {}", &[&path.display()]);
+    }
+    if let Some(path) = &args.baseline {
+        let baseline = BenchReport::load(path)?;
+        let threshold = args.fail_threshold * 100.0;
+        let regressions = report.regressions(&baseline, args.fail_threshold);
+        for regression in &regressions {
+            hud.error("Regressed {}", &[regression]);
+        }
+        if !regressions.is_empty() {
+            return Err(eyre!(
+                "{} stages got slower than `{}` by more than {}%",
+                regressions.len(),
+                path.display(),
+                threshold
+            ));
+        }
+        hud.print(
+            "No stage got slower than {} by more than {}%",
+            &[&path.display(), &threshold],
+        );
+    }
+    Ok(())
+}
+
+/// Renderer without a window set up from the command line, the session
+/// isn't restored.
+fn headless_renderer(args: &Args, width: u32, height: u32) -> Result<Renderer> {
+    let camera = initial_camera(args, None, width as f32 / height as f32)?;
+    let mesh = load_mesh(args.scene, args.model.as_deref(), args)?;
+    let mut renderer = pollster::block_on(Renderer::new_headless(
+        width,
+        height,
+        camera,
+        mesh,
+        &args.renderer_options(),
+    ))?;
+    if args.has_camera() {
+        renderer.turntable = false;
+    }
+    if let Some(color) = args.clear_color {
+        renderer.set_clear_color(color.to_array());
+    }
+    if let Some(path) = &args.environment {
+        renderer.load_environment(path)?;
+    }
+    if let Some(path) = &args.shading_snippet {
+        renderer.set_shading_snippet(path);
+    }
+    if args.chunk_size.is_some() {
+        renderer.set_raster_chunks(args.chunk_size, !args.chunk_dispatches);
+    }
+    if let Some(resolution) = args.render_resolution() {
+        renderer.set_render_resolution(resolution);
+    }
+    renderer.set_billboards(&markers(args));
+    Ok(renderer)
+}
+
+/// Cycled through with F11.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WindowMode {
+    Windowed,
+    Borderless,
+    /// Takes over the display at its native resolution
+    Exclusive,
+}
+
+impl WindowMode {
+    fn next(self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+
+    /// Exclusive fullscreen uses the largest video mode of the current
+    /// monitor, with the highest refresh rate. It falls back to borderless
+    /// without any video mode. Returns the mode that was applied.
+    fn apply(self, window: &Window) -> Self {
+        let fullscreen = match self {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(None)),
+            WindowMode::Exclusive => {
+                let video_mode = window.current_monitor().and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let PhysicalSize { width, height } = mode.size();
+                        (width * height, mode.refresh_rate(), mode.bit_depth())
+                    })
+                });
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                        return WindowMode::Borderless;
+                    }
+                }
+            }
+        };
+        window.set_fullscreen(fullscreen);
+        self
+    }
+}
+
 struct FrameCounter {
     frame_count: u32,
     accum_time: f32,
+    /// Of `update` and `render_to_surface`, without the wait for the next redraw
+    accum_render_time: f32,
 }
 
 impl FrameCounter {
@@ -148,21 +1013,149 @@ impl FrameCounter {
         Self {
             frame_count: 0,
             accum_time: 0.,
+            accum_render_time: 0.,
         }
     }
 
-    fn record(&mut self, current_instant: &mut Instant) -> f32 /* dt */ {
+    fn record(&mut self, hud: &Hud, current_instant: &mut Instant, render_time: Duration) -> f32 /* dt */
+    {
         self.accum_time += current_instant.elapsed().as_secs_f32();
+        self.accum_render_time += render_time.as_secs_f32();
         *current_instant = Instant::now();
         self.frame_count += 1;
         if self.frame_count == 100 {
-            println!(
-                "Avg frame time {}ms",
-                self.accum_time * 1000.0 / self.frame_count as f32
+            hud.print(
+                "Avg frame time {}, render {}",
+                &[
+                    &hud.duration(self.accum_time / self.frame_count as f32),
+                    &hud.duration(self.accum_render_time / self.frame_count as f32),
+                ],
             );
             self.accum_time = 0.0;
+            self.accum_render_time = 0.0;
             self.frame_count = 0;
         }
         self.accum_time
     }
 }
+
+/// Lowers the render scale while the GPU takes longer than the frame budget
+/// and raises it back when there's headroom. Fed with samples of
+/// `Renderer::sample_gpu_time`, the time of `render_to_surface` waits on the
+/// vsync when acquiring the surface texture and would track the refresh
+/// rate instead.
+struct DynamicResolution {
+    budget: Duration,
+    scale: f32,
+    frame_count: u32,
+    sample_count: u32,
+    accum_time: Duration,
+}
+
+impl DynamicResolution {
+    /// Starts from `resolution`, a fixed one is replaced by a scale of 1.
+    fn new(budget: Duration, resolution: RenderResolution) -> Self {
+        let scale = match resolution {
+            RenderResolution::Scaled(scale) => scale,
+            RenderResolution::Fixed(..) => 1.0,
+        };
+        Self {
+            budget,
+            scale,
+            frame_count: 0,
+            sample_count: 0,
+            accum_time: Duration::ZERO,
+        }
+    }
+
+    /// Called once per frame, whether the frame should sample the GPU time.
+    fn wants_sample(&mut self) -> bool {
+        self.frame_count = (self.frame_count + 1) % DYNAMIC_RESOLUTION_INTERVAL;
+        self.frame_count == 0
+    }
+
+    /// Returns the scale to render at once it changed, every
+    /// `DYNAMIC_RESOLUTION_SAMPLES` at most.
+    fn record(&mut self, gpu_time: Duration) -> Option<f32> {
+        self.accum_time += gpu_time;
+        self.sample_count += 1;
+        if self.sample_count < DYNAMIC_RESOLUTION_SAMPLES {
+            return None;
+        }
+        let load =
+            self.accum_time.as_secs_f32() / self.sample_count as f32 / self.budget.as_secs_f32();
+        self.accum_time = Duration::ZERO;
+        self.sample_count = 0;
+        if (DYNAMIC_RESOLUTION_LOAD..=1.0).contains(&load) {
+            return None;
+        }
+
+        // The GPU time follows the pixel count, the square of the scale.
+        // Rounded to steps of 5% so that small changes don't reallocate.
+        let (min, max) = DYNAMIC_RESOLUTION_SCALES;
+        let scale = self.scale * (DYNAMIC_RESOLUTION_LOAD / load.max(1e-3)).sqrt();
+        let scale = ((scale * 20.0).round() / 20.0).clamp(min, max);
+        if scale == self.scale {
+            return None;
+        }
+        self.scale = scale;
+        Some(scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Duration = Duration::from_millis(16);
+
+    /// Feeds a window of samples of `gpu_time`, returns the scale it picked.
+    fn window(controller: &mut DynamicResolution, gpu_time: Duration) -> Option<f32> {
+        (0..DYNAMIC_RESOLUTION_SAMPLES)
+            .filter_map(|_| controller.record(gpu_time))
+            .last()
+    }
+
+    #[test]
+    fn samples_every_interval() {
+        let mut controller = DynamicResolution::new(BUDGET, RenderResolution::Scaled(1.0));
+        let sampled: Vec<bool> = (0..2 * DYNAMIC_RESOLUTION_INTERVAL)
+            .map(|_| controller.wants_sample())
+            .collect();
+        assert_eq!(sampled.iter().filter(|&&sampled| sampled).count(), 2);
+        assert!(sampled[DYNAMIC_RESOLUTION_INTERVAL as usize - 1]);
+    }
+
+    #[test]
+    fn keeps_the_scale_within_the_load() {
+        let mut controller = DynamicResolution::new(BUDGET, RenderResolution::Scaled(0.75));
+        assert_eq!(window(&mut controller, BUDGET.mul_f32(0.9)), None);
+        assert_eq!(controller.scale, 0.75);
+    }
+
+    #[test]
+    fn clamps_the_scale() {
+        let (min, max) = DYNAMIC_RESOLUTION_SCALES;
+        let mut controller = DynamicResolution::new(BUDGET, RenderResolution::Fixed(640, 480));
+        assert_eq!(controller.scale, 1.0);
+        assert_eq!(window(&mut controller, BUDGET * 10), Some(min));
+        assert_eq!(window(&mut controller, BUDGET * 10), None);
+        assert_eq!(window(&mut controller, BUDGET / 100), Some(max));
+        assert_eq!(window(&mut controller, BUDGET / 100), None);
+    }
+
+    #[test]
+    fn converges_on_the_load() {
+        // GPU time following the pixel count, twice the budget at full scale
+        let gpu_time = |scale: f32| BUDGET.mul_f32(2.0 * scale * scale);
+        let mut controller = DynamicResolution::new(BUDGET, RenderResolution::Scaled(1.0));
+        for _ in 0..10 {
+            let time = gpu_time(controller.scale);
+            window(&mut controller, time);
+        }
+        let load = gpu_time(controller.scale).as_secs_f32() / BUDGET.as_secs_f32();
+        assert!((DYNAMIC_RESOLUTION_LOAD..=1.0).contains(&load), "{}", load);
+        let time = gpu_time(controller.scale);
+        assert_eq!(window(&mut controller, time), None);
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use compaster::{Camera, Projection, Renderer, Settings, TestScene};
+
+/// What was open when the app was last closed, restored on the next start.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub model: Option<PathBuf>,
+    pub scene: Option<String>,
+    pub camera: Option<CameraState>,
+    pub settings: Option<Settings>,
+    /// Models opened last, the most recent first
+    #[serde(default)]
+    pub recent: Vec<PathBuf>,
+}
+
+/// Length of the list of recent models.
+const RECENT_MODELS: usize = 8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CameraState {
+    zoom: f32,
+    pitch: f32,
+    yaw: f32,
+    target: [f32; 3],
+    fovy: f32,
+    projection: Projection,
+}
+
+impl CameraState {
+    pub fn new(camera: &Camera) -> Self {
+        Self {
+            zoom: camera.zoom,
+            pitch: camera.pitch,
+            yaw: camera.yaw,
+            target: camera.target.to_array(),
+            fovy: camera.fovy,
+            projection: camera.projection,
+        }
+    }
+
+    pub fn camera(&self, aspect: f32) -> Camera {
+        let target = Vec3::from(self.target);
+        let mut camera = Camera::new(self.zoom, self.pitch, self.yaw, target, aspect);
+        camera.fovy = self.fovy;
+        camera.projection = self.projection;
+        camera
+    }
+}
+
+impl Session {
+    pub fn new(
+        model: Option<PathBuf>,
+        scene: Option<TestScene>,
+        renderer: &Renderer,
+        recent: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            model: model.map(|path| path.canonicalize().unwrap_or(path)),
+            scene: scene.map(|scene| scene.name().to_owned()),
+            camera: Some(CameraState::new(&renderer.camera)),
+            settings: Some(renderer.settings()),
+            recent,
+        }
+    }
+
+    /// The recent models with `model` moved to the front, those that no
+    /// longer exist are dropped.
+    pub fn recent_with(&self, model: Option<&Path>) -> Vec<PathBuf> {
+        let model = model.map(|path| path.canonicalize().unwrap_or_else(|_| path.to_owned()));
+        model
+            .iter()
+            .chain(
+                self.recent
+                    .iter()
+                    .filter(|&path| Some(path) != model.as_ref()),
+            )
+            .filter(|path| path.exists())
+            .take(RECENT_MODELS)
+            .cloned()
+            .collect()
+    }
+
+    pub fn scene(&self) -> Option<TestScene> {
+        self.scene.as_deref().and_then(|name| name.parse().ok())
+    }
+
+    /// Missing or unreadable sessions start from scratch.
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Ok(path) => path,
+            Err(_) => return Self::default(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                eprintln!("Ignoring session `{}`: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn path() -> Result<PathBuf> {
+        config_dir().map(|dir| dir.join("session.json"))
+    }
+}
+
+/// Per-user config directory of the app.
+pub fn config_dir() -> Result<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or_else(|| eyre!("Unable to locate the config directory"))?;
+    Ok(base.join("compaster"))
+}
@@ -1,31 +1,379 @@
-use std::f32::consts::PI;
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    num::NonZeroU32,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use color_eyre::Result;
-use glam::{vec3, Mat4};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use glam::{vec2, Mat4, Vec2, Vec3};
+use image::RgbaImage;
 use raw_window_handle::HasRawWindowHandle;
+use serde::{Deserialize, Serialize};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     SurfaceConfiguration, TextureFormat,
 };
 
+mod accumulate_pass;
+mod analysis_pass;
+mod billboard_pass;
+mod bloom_pass;
+mod bvh;
+mod compact_pass;
+mod compare_pass;
+mod cpu_raster;
+mod cull_pass;
+mod history;
+mod hiz_pass;
+mod ibl_pass;
+mod lights;
+mod line_pass;
+mod minimap_pass;
+mod occupancy;
+mod preprocess;
 mod present_pass;
 mod raster_pass;
+mod reference_pass;
+mod render_graph;
+mod repair;
+mod resolve_pass;
+mod scan_pass;
+mod scenes;
+mod shaders;
+mod shadow_map;
+mod sky_pass;
+mod tabs;
 mod util;
 
-use util::{create_color_buffer, dispatch_size, Uniform, Vertex};
+pub use analysis_pass::Analysis;
+pub use billboard_pass::{Billboard, Sprite};
+pub use bloom_pass::Bloom;
+pub use bvh::{Hit, TraversalStats};
+pub use compare_pass::CompareMode;
+pub use cpu_raster::render_on_cpu;
+pub use lights::{Light, LightKind};
+pub use line_pass::Guides;
+pub use occupancy::{OccupancyReport, PassOccupancy};
+pub use present_pass::{Distortion, Tonemap};
+pub use raster_pass::Coverage;
+pub use raster_pass::{DebugView, RasterMode};
+use render_graph::{Frame, Node, Pass, Stage, Surface, Targets};
+pub use render_graph::{GraphPass, RenderGraph};
+pub use repair::{RepairOptions, RepairReport};
+pub use scenes::TestScene;
+pub use sky_pass::Sky;
+pub use util::{load_model, process_gltf_model, Mesh};
+
+use util::{
+    create_accumulation_buffer, create_buffer_streamed, create_color_buffer, create_depth_buffer,
+    create_depth_target, create_msaa_target, debug_group, drive, halton, map_read, Uniform,
+    WorkgroupSize,
+};
+
+use accumulate_pass::{AccumulateBindings, AccumulatePass, AccumulateUniform};
+use analysis_pass::{AnalysisBindings, AnalysisPass};
+use billboard_pass::{BillboardBindings, BillboardNode, BillboardPass};
+use bloom_pass::{BloomBindings, BloomPass, BloomUniform};
+use bvh::Bvh;
+use history::History;
+use ibl_pass::{IblPass, IblUniform};
+use tabs::Tab;
 
-use present_pass::{PresentBindings, PresentPass};
-use raster_pass::{RasterBindings, RasterPass};
+use compare_pass::{CompareBindings, ComparePass, CompareUniform};
+use cull_pass::{CullBindings, CullPass, CullUniform, Occlusion};
+use hiz_pass::{HiZBindings, HiZPass};
+use lights::{create_light_buffer, light_instances, MAX_LIGHTS};
+use line_pass::{
+    debug_lines, framing_guides, DebugDraw, LineBindings, LineNode, LinePass, LineStyle,
+};
+use minimap_pass::{overview_bindings, MinimapBindings, MinimapPass, MinimapUniform, Overview};
+use present_pass::{PresentBindings, PresentPass, PresentUniform};
+use raster_pass::{RasterBindings, RasterChunks, RasterNode, RasterPass, RasterUniform, TileBins};
+use reference_pass::{ReferenceBindings, ReferencePass};
+use resolve_pass::{ResolveBindings, ResolvePass};
+use shaders::Shaders;
+use shadow_map::{ShadowBindings, ShadowMap, ShadowNode};
+use sky_pass::{Environment, SkyBindings, SkyPass, SkyUniform};
 
 use crate::{
-    camera::{Camera, CameraUniform},
-    state::{raster_pass::ClearPass, util::process_gltf_model},
+    camera::{Camera, CameraUniform, Projection},
+    state::raster_pass::{ClearBindings, ClearPass, ClearUniform},
 };
 
-pub struct State {
+/// Toggles that can be changed at runtime, persisted across sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub debug_view: DebugView,
+    pub distortion: Distortion,
+    pub distortion_strength: f32,
+    pub raster_mode: RasterMode,
+    pub turntable: bool,
+    pub background: [f32; 3],
+    pub frustum_culling: bool,
+    pub occlusion_culling: bool,
+    pub meshlet_culling: bool,
+    pub accumulate: bool,
+    pub compare: CompareMode,
+    pub debug_lines: bool,
+    pub texture_output: bool,
+    pub analysis: Analysis,
+    pub minimap: bool,
+    #[serde(default)]
+    pub shadows: bool,
+    /// In stops
+    #[serde(default)]
+    pub exposure: f32,
+    #[serde(default)]
+    pub tonemap: Tonemap,
+    #[serde(default)]
+    pub bloom: Bloom,
+    #[serde(default)]
+    pub sky: Sky,
+    /// Ambient light from the environment map
+    #[serde(default)]
+    pub ibl: bool,
+    /// Linear color the compute raster clears to, black by default
+    #[serde(default)]
+    pub clear_color: [f32; 3],
+}
+
+/// Arrangement of the scene, snapshotted whole by the undo history.
+#[derive(Debug, Clone)]
+pub struct SceneState {
+    pub transform: Mat4,
+    pub billboards: Vec<Billboard>,
+}
+
+/// What the presentation mode changes besides hiding the debug views.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Presentation {
+    /// Spin the model while presenting
+    pub turntable: bool,
+    /// Letterbox the image to this width over height ratio
+    pub aspect: Option<f32>,
+}
+
+/// Size of the color buffer the compute raster draws into, the present pass
+/// scales it into the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderResolution {
+    /// Factor of the surface size, clamped to `RENDER_SCALES`. Above 1 the
+    /// present pass averages the pixels shown by each fragment.
+    Scaled(f32),
+    /// Width and height in pixels whatever the surface size, letterboxed into it
+    Fixed(u32, u32),
+}
+
+impl Default for RenderResolution {
+    fn default() -> Self {
+        Self::Scaled(1.0)
+    }
+}
+
+/// How the renderer picks the GPU and sets up the surface, fixed for its lifetime.
+#[derive(Debug, Clone)]
+pub struct RendererOptions {
+    /// Samples per pixel of the present pass and the debug lines
+    pub sample_count: u32,
+    pub power_preference: wgpu::PowerPreference,
+    /// Backends the adapter is picked from
+    pub backends: wgpu::Backends,
+    /// Index in the list of adapters or part of the name of the adapter to
+    /// use, picked by `power_preference` otherwise
+    pub adapter: Option<String>,
+    /// Present on the vertical blank instead of immediately, until
+    /// `Renderer::set_present_mode` changes it
+    pub vsync: bool,
+    /// Invocations per workgroup of the compute passes, see `WorkgroupSize`
+    pub workgroup_size: Option<u32>,
+    /// Directory whose `shaders` replace the embedded ones, see `Shaders::new`
+    pub assets_dir: Option<PathBuf>,
+    /// Directory wgpu records an API trace of the session into, created when
+    /// missing. Only written when wgpu is built with its `trace` feature.
+    pub trace_path: Option<PathBuf>,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self {
+            sample_count: 4,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            backends: wgpu::Backends::PRIMARY,
+            adapter: None,
+            vsync: false,
+            workgroup_size: None,
+            assets_dir: None,
+            trace_path: None,
+        }
+    }
+}
+
+/// Why `Renderer::render_to_surface` didn't present a frame.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The surface texture couldn't be acquired, even after reconfiguring
+    /// a lost or outdated surface
+    Surface(wgpu::SurfaceError),
+    /// The GPU was reset or removed, only `Renderer::recreate_gpu_resources`
+    /// recovers
+    DeviceLost,
+    /// Raised by the commands of the frame, which is dropped
+    Validation(wgpu::Error),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Surface(error) => write!(f, "Surface error: {}", error),
+            Self::DeviceLost => write!(f, "The GPU device was lost"),
+            Self::Validation(error) => write!(f, "Validation error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// No adapter at all, hardware or software, only `render_on_cpu` is left.
+#[derive(Debug)]
+pub struct NoAdapter;
+
+impl fmt::Display for NoAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No compatible adapter found, hardware or software")
+    }
+}
+
+impl std::error::Error for NoAdapter {}
+
+impl From<wgpu::SurfaceError> for RenderError {
+    fn from(error: wgpu::SurfaceError) -> Self {
+        Self::Surface(error)
+    }
+}
+
+/// Whether `error` comes from a lost device. wgpu 0.13 has no callback for
+/// the loss, it only shows up in the errors of the calls made afterwards.
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    error.to_string().contains("device is lost")
+}
+
+/// Whether the panic of a wgpu call reports a lost device, as the submit of
+/// one does.
+fn panic_is_device_lost(payload: &(dyn Any + Send)) -> bool {
+    let message = match payload.downcast_ref::<String>() {
+        Some(message) => message.as_str(),
+        None => payload.downcast_ref::<&str>().copied().unwrap_or_default(),
+    };
+    message.contains("device is lost")
+}
+
+/// Background colors cycled through at runtime, white is the default.
+const BACKGROUNDS: [[f32; 3]; 4] = [[1.0; 3], [0.0; 3], [0.18; 3], [0.0, 1.0, 0.0]];
+
+/// Exposure range in stops either way.
+const MAX_EXPOSURE: f32 = 8.0;
+
+/// How long the window size has to stay unchanged before the color buffer
+/// is reallocated, avoids reallocating on every event of a drag resize.
+const RESIZE_SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Present modes cycled through at runtime, from vsync to tearing.
+const PRESENT_MODES: [wgpu::PresentMode; 3] = [
+    wgpu::PresentMode::Fifo,
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+];
+
+/// Factors of the surface size stepped through at runtime, from the cheapest
+/// upscaled resolution to 2x2 supersampling.
+const RENDER_SCALES: [f32; 5] = [0.5, 0.75, 1.0, 1.5, 2.0];
+
+/// Triangles a single raster dispatch may cover before the watchdog splits the
+/// work across submissions, keeps huge meshes below the driver timeout (TDR).
+const TDR_TRIANGLE_BUDGET: u32 = 1 << 20;
+
+/// Frames slower than this are taken as a sign of dispatches getting close to
+/// the driver timeout, 2 seconds by default on Windows.
+const TDR_FRAME_BUDGET: Duration = Duration::from_millis(500);
+
+/// The watchdog doesn't split the work into chunks smaller than this.
+const MIN_CHUNK_SIZE: u32 = 1 << 12;
+
+/// Direction towards the sun, the light the renderer starts with and the
+/// one of the overview.
+const LIGHT_DIR: Vec3 = Vec3::new(0.5, 1.0, -0.3);
+
+fn sun() -> Light {
+    Light::directional(-LIGHT_DIR, [1.0; 3], 1.0)
+}
+
+/// Target format of `render_to_image`, the layout of `RgbaImage`. Not sRGB,
+/// the present pass encodes into it.
+const IMAGE_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// `mode` when the surface supports it, otherwise the other mode without
+/// vsync and finally Fifo, which is supported everywhere.
+fn supported_present_mode(
+    supported: &[wgpu::PresentMode],
+    mode: wgpu::PresentMode,
+) -> wgpu::PresentMode {
+    use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+    let candidates = match mode {
+        Immediate => [Immediate, Mailbox],
+        Mailbox => [Mailbox, Immediate],
+        _ => [mode; 2],
+    };
+    candidates
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(Fifo)
+}
+
+/// An 8 bit BGRA or RGBA format of the surface, sRGB ones first so that the
+/// hardware encodes the linear output of the present pass. Images are read
+/// back from targets of the surface format, see `render_to_image`.
+fn surface_format(supported: &[TextureFormat]) -> TextureFormat {
+    use TextureFormat::{Bgra8Unorm, Bgra8UnormSrgb, Rgba8Unorm, Rgba8UnormSrgb};
+    [Bgra8UnormSrgb, Rgba8UnormSrgb, Bgra8Unorm, Rgba8Unorm]
+        .into_iter()
+        .find(|format| supported.contains(format))
+        .unwrap_or(Bgra8Unorm)
+}
+
+/// Chunks of the raster for a mesh, `None` unless it risks the driver timeout.
+fn default_chunk_size(triangle_count: u32) -> Option<u32> {
+    (triangle_count > TDR_TRIANGLE_BUDGET).then(|| {
+        eprintln!(
+            "{} triangles risk exceeding the driver timeout, splitting the raster into chunks of {}",
+            triangle_count, TDR_TRIANGLE_BUDGET
+        );
+        TDR_TRIANGLE_BUDGET
+    })
+}
+
+/// Compute rasterizer drawing a mesh into a surface.
+pub struct Renderer {
     device: wgpu::Device,
-    surface: wgpu::Surface,
+    /// Set once an error reports the device lost, see `recreate_gpu_resources`
+    device_lost: Arc<AtomicBool>,
+    /// Of the device and the surface, reused when they are recreated
+    options: RendererOptions,
+    /// `None` for a headless renderer
+    surface: Option<wgpu::Surface>,
     surface_config: wgpu::SurfaceConfiguration,
+    /// Supported by the surface, empty for a headless renderer
+    present_modes: Vec<wgpu::PresentMode>,
     queue: wgpu::Queue,
 
     pub width: u32,
@@ -37,36 +385,307 @@ pub struct State {
 
     screen_uniform: wgpu::Buffer,
     output_buffer: wgpu::Buffer,
+    depth_buffer: wgpu::Buffer,
+    accumulation_buffer: wgpu::Buffer,
 
-    vertices: Vec<Vertex>,
-    #[allow(dead_code)]
+    triangle_count: u32,
     vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    #[allow(dead_code)]
+    material_buffer: wgpu::Buffer,
+    #[allow(dead_code)]
+    triangle_buffer: wgpu::Buffer,
+    work_queue: wgpu::Buffer,
+    cull_pass: CullPass,
+    cull_bindings: CullBindings,
+    cull_uniform: wgpu::Buffer,
+    frustum_culling: bool,
+    hiz_pass: HiZPass,
+    hiz_bindings: HiZBindings,
+    occlusion_uniform: wgpu::Buffer,
+    /// Culls the triangles hidden after the depth pass, not done by the tiled mode
+    occlusion_culling: bool,
+    /// Culls whole meshlets by their bounds and normal cone first
+    meshlet_culling: bool,
+    pub raster_mode: RasterMode,
+    tile_bins: TileBins,
+    /// Split the raster when there's more than one
+    raster_chunks: RasterChunks,
+    /// Every chunk gets a submission of its own instead of a dispatch
+    chunk_submits: bool,
+    /// Places the mesh in the world, in front of the camera
+    pub transform: Mat4,
+    /// Spins the mesh about the vertical axis through its center
+    pub turntable: bool,
+    /// Of the bounds of the mesh, the turntable spins about it
+    mesh_center: Vec3,
 
     raster_pass: RasterPass,
     raster_bindings: RasterBindings,
+    raster_uniform: wgpu::Buffer,
+    debug_view: DebugView,
+    /// Shared by the tabs, the first `MAX_LIGHTS` are uploaded
+    lights: Vec<Light>,
+    light_buffer: wgpu::Buffer,
+    /// Hard shadows of the first light when it is directional
+    shadows: bool,
+    shadow_map: ShadowMap,
+    shadow_bindings: ShadowBindings,
 
     present_pass: PresentPass,
     present_bindings: PresentBindings,
+    present_uniform: wgpu::Buffer,
+    distortion: Distortion,
+    distortion_strength: f32,
+    /// Of the HDR color buffer in stops, applied before the tonemap
+    exposure: f32,
+    tonemap: Tonemap,
+    /// Shown by the uncovered pixels while the sky is solid
+    background: [f32; 3],
+    sky: Sky,
+    sky_pass: SkyPass,
+    sky_bindings: SkyBindings,
+    sky_uniform: wgpu::Buffer,
+    /// Empty until `load_environment`
+    environment: Environment,
+    /// Of the loaded environment, loaded again with the device
+    environment_path: Option<PathBuf>,
+    /// Lights the shading with the prefiltered environment in place of the
+    /// constant ambient, once one is loaded
+    ibl: bool,
+    ibl_pass: IblPass,
+    ibl_uniform: wgpu::Buffer,
+
+    billboard_pass: BillboardPass,
+    /// Markers over the mesh, hidden by the triangles in front of them
+    billboard_bindings: BillboardBindings,
+    /// Kept for the scene snapshots
+    billboards: Vec<Billboard>,
+    /// Scene states before the edits
+    history: History<SceneState>,
+    /// Meshes open next to the one on screen, the slot of the current tab is
+    /// empty while its mesh lives in the fields above
+    tabs: Vec<Option<Tab>>,
+    /// Index of the tab on screen
+    tab: usize,
+
+    accumulate_pass: AccumulatePass,
+    accumulate_bindings: AccumulateBindings,
+    /// Glow added by the present pass, applied to the image before the tonemap
+    bloom: Bloom,
+    bloom_pass: BloomPass,
+    bloom_bindings: BloomBindings,
+    bloom_uniform: wgpu::Buffer,
+    accumulate_uniform: wgpu::Buffer,
+    /// Averages jittered frames while nothing changes
+    accumulate: bool,
+    accumulated_frames: u32,
+    /// What the accumulated frames were rendered with, `None` forces a restart
+    accumulation_key: Option<(Settings, CameraUniform)>,
+    /// Skips the raster while neither the view nor a setting changes, the
+    /// color buffer of the last change is presented again
+    pub dirty_tracking: bool,
+    /// The color buffer is outdated, always set without dirty tracking
+    dirty: bool,
+    /// The next frame is recorded into a capture of the attached RenderDoc
+    capture_pending: bool,
+    /// The next frame waits for the GPU after its submit, see `sample_gpu_time`
+    gpu_time_pending: bool,
+    /// Time the GPU took for the last sampled frame, until it is taken
+    gpu_time: Option<Duration>,
+
+    resolve_pass: ResolvePass,
+    resolve_bindings: ResolveBindings,
+    /// Presents a filtered storage texture instead of reading the buffers
+    texture_output: bool,
+    analysis_pass: AnalysisPass,
+    analysis_bindings: AnalysisBindings,
+    analysis: Analysis,
+
+    reference_pass: ReferencePass,
+    reference_bindings: ReferenceBindings,
+    compare_pass: ComparePass,
+    compare_bindings: CompareBindings,
+    compare_uniform: wgpu::Buffer,
+    /// Validates the compute raster against the hardware raster
+    compare: CompareMode,
+
+    line_pass: LinePass,
+    line_bindings: LineBindings,
+    /// Lines of the mesh, the view frustum is added when they are turned on
+    debug_draw: DebugDraw,
+    /// Axes and bounds of the mesh over the image
+    debug_lines: bool,
+    /// Framing guides over the image, not persisted
+    guides: Guides,
+    guide_bindings: LineBindings,
+    /// Identity, the guides are given in screen space
+    guide_camera: wgpu::Buffer,
+    /// Objects of the mesh, for culling and picking on the CPU
+    bvh: Bvh,
+    /// Depth of the BVH nodes drawn with the debug lines
+    bvh_level: Option<u32>,
+    minimap_pass: MinimapPass,
+    minimap_bindings: MinimapBindings,
+    minimap_uniform: wgpu::Buffer,
+    /// Bounds of the mesh seen from above
+    overview: Overview,
+    /// Draws the overview through the reference pass, once per mesh
+    overview_bindings: ReferenceBindings,
+    /// Top-down overview of the mesh in the corner, with the camera marked
+    minimap: bool,
+    /// Samples per pixel of the present pass, resolved into the surface
+    sample_count: u32,
+    msaa_target: Option<wgpu::TextureView>,
+    /// Depth of the present pass, the debug lines are tested against it
+    depth_target: wgpu::TextureView,
+
+    /// Of the color buffer, independent of the surface size
+    render_resolution: RenderResolution,
+    pub presentation: Presentation,
+    /// Settings to restore when leaving the presentation mode, `Some` while presenting
+    presenting: Option<Settings>,
 
     clear_pass: ClearPass,
+    clear_bindings: ClearBindings,
+    clear_uniform: wgpu::Buffer,
+    /// Linear color of the uncovered pixels of the color buffer, added to the
+    /// background but through the exposure and the bloom
+    clear_color: [f32; 3],
+    /// Sources of the passes that are rebuilt when edited on disk
+    shaders: Shaders,
+    /// Of the passes over flat buffers, see `WorkgroupSize`
+    workgroup_size: WorkgroupSize,
+    resize_pending: Option<Instant>,
+    /// Of the last update, the view is updated again when an image is resized
+    time: f32,
 }
 
-impl State {
-    pub async fn new<W>(window: &W, width: u32, height: u32, camera: Camera) -> Result<Self>
+impl Renderer {
+    pub async fn new<W>(
+        window: &W,
+        width: u32,
+        height: u32,
+        camera: Camera,
+        mesh: Mesh,
+        options: &RendererOptions,
+    ) -> Result<Self>
     where
         W: HasRawWindowHandle,
     {
-        let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+        let instance = wgpu::Instance::new(options.backends);
         let surface = unsafe { instance.create_surface(window) };
+        Self::with_surface(
+            &instance,
+            Some(surface),
+            width,
+            height,
+            camera,
+            mesh,
+            options,
+        )
+        .await
+    }
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .unwrap();
+    /// Renderer without a window, frames are only drawn by `render_to_image`.
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        camera: Camera,
+        mesh: Mesh,
+        options: &RendererOptions,
+    ) -> Result<Self> {
+        let instance = wgpu::Instance::new(options.backends);
+        Self::with_surface(&instance, None, width, height, camera, mesh, options).await
+    }
+
+    /// The adapter named by `options.adapter`, `None` when it's not set.
+    fn select_adapter(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+        options: &RendererOptions,
+    ) -> Result<Option<wgpu::Adapter>> {
+        let selected = match &options.adapter {
+            Some(selected) => selected,
+            None => return Ok(None),
+        };
+        let adapters = instance
+            .enumerate_adapters(options.backends)
+            .filter(|adapter| surface.is_none_or(|surface| adapter.is_surface_supported(surface)))
+            .collect::<Vec<_>>();
+        let names = || {
+            adapters
+                .iter()
+                .enumerate()
+                .map(|(i, adapter)| {
+                    let info = adapter.get_info();
+                    format!("\n  {}: {} ({:?})", i, info.name, info.backend)
+                })
+                .collect::<String>()
+        };
+        let position = match selected.parse::<usize>() {
+            Ok(index) => (index < adapters.len()).then_some(index),
+            Err(_) => {
+                let selected = selected.to_lowercase();
+                adapters
+                    .iter()
+                    .position(|adapter| adapter.get_info().name.to_lowercase().contains(&selected))
+            }
+        };
+        match position {
+            Some(index) => Ok(adapters.into_iter().nth(index)),
+            None => Err(eyre!(
+                "No adapter matching `{}`, available adapters:{}",
+                selected,
+                names()
+            )),
+        }
+    }
+
+    async fn with_surface(
+        instance: &wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        width: u32,
+        height: u32,
+        camera: Camera,
+        mesh: Mesh,
+        options: &RendererOptions,
+    ) -> Result<Self> {
+        let RendererOptions {
+            sample_count,
+            power_preference,
+            vsync,
+            workgroup_size,
+            ..
+        } = *options;
+        let selected = Self::select_adapter(instance, surface.as_ref(), options)?;
+        let requested = match selected {
+            Some(adapter) => Some(adapter),
+            None => {
+                instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference,
+                        force_fallback_adapter: false,
+                        compatible_surface: surface.as_ref(),
+                    })
+                    .await
+            }
+        };
+        let adapter = match requested {
+            Some(adapter) => adapter,
+            None => {
+                // No hardware adapter, try a software one (lavapipe, WARP, ...)
+                eprintln!("No suitable GPU adapter found, falling back to a software adapter");
+                instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference: wgpu::PowerPreference::LowPower,
+                        force_fallback_adapter: true,
+                        compatible_surface: surface.as_ref(),
+                    })
+                    .await
+                    .ok_or(NoAdapter)?
+            }
+        };
 
         let device_info = adapter.get_info();
         println!("Backend: {:?}", device_info.backend);
@@ -74,13 +693,20 @@ impl State {
         println!("Device Type: {:?}", device_info.device_type);
 
         let limits = adapter.limits();
+        let workgroup_size = WorkgroupSize::new(&limits, workgroup_size);
+        println!("Workgroup Size: {}", workgroup_size.get());
         let features = adapter.features();
-        let format = surface
-            .get_supported_formats(&adapter)
-            .into_iter()
-            .find(|&x| x == TextureFormat::Bgra8Unorm)
-            .unwrap_or(TextureFormat::Bgra8Unorm);
+        let format = match &surface {
+            Some(surface) => surface_format(&surface.get_supported_formats(&adapter)),
+            None => IMAGE_FORMAT,
+        };
+        println!("Surface Format: {:?}", format);
 
+        if let Some(path) = &options.trace_path {
+            std::fs::create_dir_all(path).wrap_err_with(|| {
+                format!("Failed to create the trace directory `{}`", path.display())
+            })?;
+        }
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
@@ -88,31 +714,59 @@ impl State {
                     features,
                     limits,
                 },
-                None,
+                options.trace_path.as_deref(),
             )
             .await?;
+        let device_lost = Arc::new(AtomicBool::new(false));
+        device.on_uncaptured_error({
+            let device_lost = device_lost.clone();
+            move |error| {
+                if is_device_lost(&error) {
+                    device_lost.store(true, Ordering::Relaxed);
+                }
+                eprintln!("wgpu error: {}", error);
+            }
+        });
 
+        let present_modes = match &surface {
+            Some(surface) => surface.get_supported_modes(&adapter),
+            None => vec![],
+        };
+        let present_mode = match vsync {
+            true => wgpu::PresentMode::Fifo,
+            false => supported_present_mode(&present_modes, wgpu::PresentMode::Immediate),
+        };
         let surface_config = SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format,
             width,
             height,
-            present_mode: wgpu::PresentMode::Immediate,
+            present_mode,
         };
+        if surface.is_some() {
+            println!("Present Mode: {:?}", present_mode);
+        }
 
-        surface.configure(&device, &surface_config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_config);
+        }
 
         let mut camera_uniform = CameraUniform::default();
-        camera_uniform.update_view_proj(&camera);
+        camera_uniform.update_view_proj(&camera, Mat4::IDENTITY);
         let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer"),
             contents: bytemuck::bytes_of(&camera_uniform),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let present_pass = PresentPass::new(&device, format);
-        let raster_pass = RasterPass::new(&device);
-        let clear_pass = ClearPass::new(&device);
+        let shaders = Shaders::new(options.assets_dir.as_deref());
+        let present_pass = PresentPass::new(&device, &shaders.present(), format, sample_count);
+        let line_pass = LinePass::new(&device, &shaders.line(), format, sample_count);
+        let minimap_pass = MinimapPass::new(&device, &shaders, format, sample_count);
+        let msaa_target = create_msaa_target(&device, format, width, height, sample_count);
+        let depth_target = create_depth_target(&device, width, height, sample_count);
+        let raster_pass = RasterPass::new(&device, &shaders, workgroup_size);
+        let clear_pass = ClearPass::new(&device, &shaders.raster(), workgroup_size);
 
         let screen_uniform = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Screen Uniform Buffer"),
@@ -121,31 +775,369 @@ impl State {
         });
 
         let output_buffer = create_color_buffer(&device, width, height);
+        let depth_buffer = create_depth_buffer(&device, width, height);
+        let clear_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Clear Uniform Buffer"),
+            contents: bytemuck::bytes_of(&ClearUniform::new([0.0; 3])),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let clear_bindings = ClearBindings::new(
+            &device,
+            &clear_pass,
+            &output_buffer,
+            &depth_buffer,
+            &clear_uniform,
+        );
+        let accumulation_buffer = create_accumulation_buffer(&device, width, height);
 
         // vec2 pos, float col
         // let vertices = Vec::from([v!(-1., -1., 0.), v!(-1., 1., 0.), v!(1., -1., 0.)]);
-        let vertices = process_gltf_model();
-        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::STORAGE,
+        let triangle_count = mesh.triangle_count();
+        let debug_draw = debug_lines(&mesh);
+        let bvh = Bvh::new(&mesh);
+        let overview = Overview::new(&mesh);
+        let mesh_center = mesh.center();
+        let bounds = mesh.bounds();
+        let Mesh {
+            vertices,
+            indices,
+            materials,
+            triangles,
+            meshlets,
+        } = mesh;
+        let vertex_buffer = create_buffer_streamed(
+            &device,
+            &queue,
+            "Vertex Buffer",
+            bytemuck::cast_slice(&vertices),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+        );
+        let index_buffer = create_buffer_streamed(
+            &device,
+            &queue,
+            "Index Buffer",
+            bytemuck::cast_slice(&indices),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDEX,
+        );
+        let material_buffer = create_buffer_streamed(
+            &device,
+            &queue,
+            "Material Buffer",
+            bytemuck::cast_slice(&materials),
+            wgpu::BufferUsages::STORAGE,
+        );
+        let triangle_buffer = create_buffer_streamed(
+            &device,
+            &queue,
+            "Triangle Info Buffer",
+            bytemuck::cast_slice(&triangles),
+            wgpu::BufferUsages::STORAGE,
+        );
+
+        let distortion = Distortion::None;
+        let distortion_strength = 0.5;
+        let present_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Present Uniform Buffer"),
+            contents: bytemuck::bytes_of(&PresentUniform::new(
+                distortion,
+                distortion_strength,
+                width,
+                height,
+                BACKGROUNDS[0],
+                false,
+                Analysis::Off,
+                1.0,
+                Tonemap::None,
+                !format.describe().srgb,
+                false,
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let debug_view = DebugView::Lambert;
+        let lights = vec![sun()];
+        let light_buffer = create_light_buffer(&device, &lights);
+        let raster_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Raster Uniform Buffer"),
+            contents: bytemuck::bytes_of(&RasterUniform::new(
+                debug_view,
+                lights.len(),
+                Vec2::ZERO,
+                false,
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let work_queue = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Work Queue Buffer"),
+            size: std::mem::size_of::<[u32; 2]>() as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let hiz_pass = HiZPass::new(&device, &shaders, workgroup_size);
+        let hiz_bindings = HiZBindings::new(&device, &hiz_pass, &depth_buffer, width, height);
+        let hiz_levels = hiz_bindings.level_count();
+
+        let frustum_culling = true;
+        let occlusion_culling = true;
+        let meshlet_culling = true;
+        let cull_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Cull Uniform Buffer"),
+            contents: bytemuck::bytes_of(&CullUniform::new(
+                frustum_culling,
+                false,
+                meshlet_culling,
+                hiz_levels,
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let occlusion_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Occlusion Uniform Buffer"),
+            contents: bytemuck::bytes_of(&CullUniform::new(
+                frustum_culling,
+                true,
+                meshlet_culling,
+                hiz_levels,
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let cull_pass = CullPass::new(&device, &shaders, workgroup_size);
+        let cull_bindings = CullBindings::new(
+            &device,
+            &cull_pass,
+            &vertex_buffer,
+            &index_buffer,
+            hiz_bindings.pyramid(),
+            &camera_buffer,
+            &screen_uniform,
+            &cull_uniform,
+            &occlusion_uniform,
+            &meshlets,
+            triangle_count,
+        );
 
-        let present_bindings =
-            PresentBindings::new(&device, &present_pass, &output_buffer, &screen_uniform);
+        let bloom = Bloom::default();
+        let bloom_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Bloom Uniform Buffer"),
+            contents: bytemuck::bytes_of(&BloomUniform::new(bloom)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bloom_pass = BloomPass::new(&device, &shaders, workgroup_size);
+        let bloom_bindings = BloomBindings::new(
+            &device,
+            &bloom_pass,
+            &output_buffer,
+            &bloom_uniform,
+            width,
+            height,
+        );
+        let environment = Environment::empty(&device);
+        let sky_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Sky Uniform Buffer"),
+            contents: bytemuck::bytes_of(&SkyUniform::new(Sky::Solid, &camera, &environment)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sky_pass = SkyPass::new(&device, &shaders, workgroup_size);
+        let sky_bindings = SkyBindings::new(
+            &device,
+            &sky_pass,
+            &output_buffer,
+            &depth_buffer,
+            &environment,
+            &screen_uniform,
+            &sky_uniform,
+        );
+        let ibl_pass = IblPass::new(&device, &shaders, workgroup_size);
+        let ibl_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Ibl Uniform Buffer"),
+            contents: bytemuck::bytes_of(&IblUniform::new(Mat4::IDENTITY, false)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let resolve_pass = ResolvePass::new(&device, &shaders, workgroup_size);
+        let resolve_bindings = ResolveBindings::new(
+            &device,
+            &resolve_pass,
+            &output_buffer,
+            &depth_buffer,
+            &accumulation_buffer,
+            bloom_bindings.output(),
+            &screen_uniform,
+            &present_uniform,
+            width,
+            height,
+        );
+        let analysis_pass = AnalysisPass::new(&device, &shaders, workgroup_size);
+        let analysis_bindings = AnalysisBindings::new(
+            &device,
+            &analysis_pass,
+            &output_buffer,
+            &depth_buffer,
+            &accumulation_buffer,
+            &screen_uniform,
+            &present_uniform,
+        );
+        let present_bindings = PresentBindings::new(
+            &device,
+            &present_pass,
+            &output_buffer,
+            &depth_buffer,
+            &accumulation_buffer,
+            analysis_bindings.histogram(),
+            bloom_bindings.output(),
+            resolve_bindings.output_view(),
+            &screen_uniform,
+            &present_uniform,
+        );
+        let line_bindings = LineBindings::new(
+            &device,
+            &line_pass,
+            &camera_buffer,
+            &screen_uniform,
+            &present_uniform,
+            debug_draw.vertices(),
+        );
+        let guide_camera = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Guide Camera Buffer"),
+            contents: bytemuck::bytes_of(&CameraUniform::default()),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let guide_bindings = LineBindings::new(
+            &device,
+            &line_pass,
+            &guide_camera,
+            &screen_uniform,
+            &present_uniform,
+            &[],
+        );
+        let accumulate_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Accumulate Uniform Buffer"),
+            contents: bytemuck::bytes_of(&AccumulateUniform::new(BACKGROUNDS[0], 0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let billboard_pass = BillboardPass::new(&device, &shaders, workgroup_size);
+        let billboard_bindings = BillboardBindings::new(
+            &device,
+            &billboard_pass,
+            &output_buffer,
+            &depth_buffer,
+            &camera_buffer,
+            &screen_uniform,
+            &[],
+        );
+        let accumulate_pass = AccumulatePass::new(&device, &shaders, workgroup_size);
+        let accumulate_bindings = AccumulateBindings::new(
+            &device,
+            &accumulate_pass,
+            &output_buffer,
+            &depth_buffer,
+            &accumulation_buffer,
+            &screen_uniform,
+            &accumulate_uniform,
+        );
+        let reference_pass = ReferencePass::new(&device, &shaders);
+        let reference_bindings = ReferenceBindings::new(
+            &device,
+            &reference_pass,
+            &materials,
+            &triangles,
+            &camera_buffer,
+            &screen_uniform,
+            &raster_uniform,
+            &light_buffer,
+            width,
+            height,
+        );
+        let overview_bindings = overview_bindings(
+            &device,
+            &reference_pass,
+            &materials,
+            &triangles,
+            &overview,
+            sun(),
+        );
+        let minimap_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Minimap Uniform Buffer"),
+            contents: bytemuck::bytes_of(&MinimapUniform::new(
+                &overview,
+                camera.build_view_matrix(),
+                camera.half_horizontal_fov(),
+            )),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let minimap_bindings = MinimapBindings::new(
+            &device,
+            &minimap_pass,
+            &present_uniform,
+            &minimap_uniform,
+            &overview_bindings,
+        );
+        let compare = CompareMode::Off;
+        let compare_uniform = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Compare Uniform Buffer"),
+            contents: bytemuck::bytes_of(&CompareUniform::new(compare)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let compare_pass = ComparePass::new(&device, &shaders, workgroup_size);
+        let compare_bindings = CompareBindings::new(
+            &device,
+            &compare_pass,
+            &output_buffer,
+            &depth_buffer,
+            reference_bindings.color_view(),
+            &screen_uniform,
+            &compare_uniform,
+        );
+        let tile_bins = TileBins::new(&device, &raster_pass, width, height, triangle_count);
+        let raster_chunks =
+            RasterChunks::new(&device, triangle_count, default_chunk_size(triangle_count));
+        let shadow_map = ShadowMap::new(&device, &clear_pass);
         let raster_bindings = RasterBindings::new(
             &device,
             &raster_pass,
             &output_buffer,
+            &depth_buffer,
             &vertex_buffer,
+            &index_buffer,
+            &material_buffer,
+            &triangle_buffer,
+            &work_queue,
+            &cull_bindings,
             &screen_uniform,
+            &raster_uniform,
+            &tile_bins,
+            &raster_chunks,
             &camera_buffer,
+            &light_buffer,
+            shadow_map.depth_buffer(),
+            shadow_map.camera_buffer(),
+            ibl_pass.buffer(),
+            &ibl_uniform,
+        );
+        let shadow_bindings = ShadowBindings::new(
+            &device,
+            &cull_pass,
+            &raster_pass,
+            &shadow_map,
+            &vertex_buffer,
+            &index_buffer,
+            &material_buffer,
+            &triangle_buffer,
+            &work_queue,
+            &light_buffer,
+            &meshlets,
+            triangle_count,
+            bounds,
         );
+        shadow_map.write_camera(&queue, &lights, &shadow_bindings);
 
-        Ok(Self {
+        let renderer = Self {
             device,
+            device_lost,
+            options: options.clone(),
             surface,
             surface_config,
+            present_modes,
             queue,
 
             width,
@@ -157,118 +1149,1758 @@ impl State {
 
             screen_uniform,
             output_buffer,
+            depth_buffer,
+            accumulation_buffer,
 
-            vertices,
+            triangle_count,
             vertex_buffer,
+            index_buffer,
+            material_buffer,
+            triangle_buffer,
+            work_queue,
+            cull_pass,
+            cull_bindings,
+            cull_uniform,
+            frustum_culling,
+            hiz_pass,
+            hiz_bindings,
+            occlusion_uniform,
+            occlusion_culling,
+            meshlet_culling,
+            raster_mode: RasterMode::Triangles,
+            tile_bins,
+            raster_chunks,
+            chunk_submits: true,
 
             raster_pass,
             raster_bindings,
+            raster_uniform,
+            debug_view,
+            lights,
+            light_buffer,
+            shadows: false,
+            shadow_map,
+            shadow_bindings,
 
             present_pass,
             present_bindings,
+            present_uniform,
+            distortion,
+            distortion_strength,
+            exposure: 0.0,
+            tonemap: Tonemap::None,
+            background: BACKGROUNDS[0],
+            sky: Sky::Solid,
+            sky_pass,
+            sky_bindings,
+            sky_uniform,
+            environment,
+            environment_path: None,
+            ibl: false,
+            ibl_pass,
+            ibl_uniform,
+
+            billboard_pass,
+            billboard_bindings,
+            billboards: Vec::new(),
+            history: History::default(),
+            tabs: vec![None],
+            tab: 0,
+            accumulate_pass,
+            accumulate_bindings,
+            bloom,
+            bloom_pass,
+            bloom_bindings,
+            bloom_uniform,
+            accumulate_uniform,
+            accumulate: false,
+            accumulated_frames: 0,
+            accumulation_key: None,
+            dirty_tracking: false,
+            dirty: true,
+            capture_pending: false,
+            gpu_time_pending: false,
+            gpu_time: None,
+            resolve_pass,
+            resolve_bindings,
+            texture_output: false,
+            analysis_pass,
+            analysis_bindings,
+            analysis: Analysis::Off,
+
+            reference_pass,
+            reference_bindings,
+            compare_pass,
+            compare_bindings,
+            compare_uniform,
+            compare,
+
+            line_pass,
+            line_bindings,
+            debug_draw,
+            debug_lines: false,
+            guides: Guides::Off,
+            guide_bindings,
+            guide_camera,
+            bvh,
+            bvh_level: None,
+            minimap_pass,
+            minimap_bindings,
+            minimap_uniform,
+            overview,
+            overview_bindings,
+            minimap: false,
+            sample_count,
+            msaa_target,
+            depth_target,
+
+            render_resolution: RenderResolution::default(),
+            presentation: Presentation::default(),
+            presenting: None,
 
             clear_pass,
-        })
+            clear_bindings,
+            clear_uniform,
+            clear_color: [0.0; 3],
+            shaders,
+            workgroup_size,
+            resize_pending: None,
+            time: 0.0,
+            transform: Mat4::IDENTITY,
+            turntable: true,
+            mesh_center,
+        };
+        renderer.render_overview();
+        Ok(renderer)
     }
 
     pub fn update(&mut self, t: f32) {
-        self.camera_uniform.update_view_proj(&self.camera);
-        let view = Mat4::from_translation(vec3(5., 3., -6.));
-        let model = Mat4::from_rotation_x(PI / 2.);
-        let model = Mat4::from_rotation_y(t) * model;
-        // let model = Mat4::from_rotation_y(PI / 2. + t) * model;
-        let view = view * model;
-        // let proj = Mat4::perspective_rh((2. * PI) / 5., 1., 1.0, 100.0);
-        let proj =
-            Mat4::perspective_rh((PI) / 2., self.width as f32 / self.height as f32, 0.1, 30.0);
-        let res = proj * view;
-        // println!("{}", &res);
-        self.camera_uniform.view_position = [4., 3., -10., 1.];
-        self.camera_uniform.view_proj = res.to_cols_array_2d();
+        self.time = t;
+        self.reload_shaders();
+        if let Some(resized_at) = self.resize_pending {
+            if resized_at.elapsed() >= RESIZE_SETTLE_TIME {
+                self.resize_buffers();
+                self.resize_pending = None;
+            }
+        }
+
+        let model = self.model_transform();
+        self.camera_uniform.update_view_proj(&self.camera, model);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::bytes_of(&self.camera_uniform),
         );
+        if self.minimap {
+            self.write_minimap_uniform();
+        }
+        if self.sky != Sky::Solid {
+            self.write_sky_uniform();
+        }
+        if self.ibl {
+            self.write_ibl_uniform();
+        }
+        self.advance_accumulation();
+        // Accumulation keeps adding jittered frames of the same view
+        self.dirty = !self.dirty_tracking || self.accumulate || self.accumulated_frames == 0;
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.width = width;
-        self.height = height;
-        self.surface_config.width = width;
-        self.surface_config.height = height;
-        self.surface.configure(&self.device, &self.surface_config);
-        self.queue.write_buffer(
-            &self.screen_uniform,
-            0,
-            bytemuck::bytes_of(&Uniform::new(width as _, height as _)),
+    /// Rebuilds the passes of the shaders modified on disk. A shader failing
+    /// validation is reported and the pass keeps the last good one.
+    fn reload_shaders(&mut self) {
+        let changes = self.shaders.poll();
+        let mut results = Vec::new();
+        if changes.raster || changes.snippet {
+            let source = self.shaders.raster();
+            let result = self
+                .raster_pass
+                .reload(&self.device, &source)
+                .and_then(|_| self.clear_pass.reload(&self.device, &source));
+            let shader = match (changes.snippet, &self.shaders.snippet) {
+                (true, Some(snippet)) => snippet,
+                _ => &self.shaders.raster,
+            };
+            results.push((shader, result));
+        }
+        if changes.present {
+            results.push((
+                &self.shaders.present,
+                self.present_pass
+                    .reload(&self.device, &self.shaders.present()),
+            ));
+        }
+        if changes.line {
+            results.push((
+                &self.shaders.line,
+                self.line_pass.reload(&self.device, &self.shaders.line()),
+            ));
+        }
+        for (shader, result) in &results {
+            match result {
+                Ok(()) => println!("Reloaded {}", shader.name()),
+                Err(err) => eprintln!("Failed to reload {}: {}", shader.name(), err),
+            }
+        }
+        if !results.is_empty() {
+            // Redraw even if nothing else changed
+            self.accumulation_key = None;
+        }
+    }
+
+    /// Halves the raster chunks whenever a frame takes long enough to
+    /// risk a device lost from the driver timeout. Acquiring the next surface
+    /// texture waits on the GPU, so the time of `render` follows the GPU.
+    fn watch_frame_time(&mut self, elapsed: Duration) {
+        if elapsed < TDR_FRAME_BUDGET || self.resize_pending.is_some() {
+            return;
+        }
+        let size = self.raster_chunks.size() / 2;
+        if size < MIN_CHUNK_SIZE {
+            return;
+        }
+        eprintln!(
+            "Frame took {:?}, splitting the raster into chunks of {} triangles",
+            elapsed, size
         );
+        self.set_raster_chunks(Some(size), true);
+    }
 
-        self.output_buffer = create_color_buffer(&self.device, width, height);
-        self.present_bindings.update_color_buffer(
+    /// Splits the raster into chunks of `size` triangles, trading latency for
+    /// stability on extreme scenes. With `separate_submits` every chunk is
+    /// submitted on its own, otherwise they are dispatches of the same pass.
+    pub fn set_raster_chunks(&mut self, size: Option<u32>, separate_submits: bool) {
+        self.chunk_submits = separate_submits;
+        self.raster_chunks = RasterChunks::new(&self.device, self.triangle_count, size);
+        self.raster_bindings.update_uniform(
             &self.device,
-            &self.present_pass,
-            &self.output_buffer,
+            &self.raster_pass,
+            &self.screen_uniform,
+            &self.raster_uniform,
+            &self.tile_bins,
+            &self.raster_chunks,
         );
-        self.raster_bindings.update_color_buffer(
+    }
+
+    /// Replaces the markers drawn over the mesh, positioned in its space.
+    pub fn set_billboards(&mut self, billboards: &[Billboard]) {
+        self.billboard_bindings = BillboardBindings::new(
             &self.device,
-            &self.raster_pass,
+            &self.billboard_pass,
             &self.output_buffer,
+            &self.depth_buffer,
+            &self.camera_buffer,
+            &self.screen_uniform,
+            billboards,
         );
+        self.billboards = billboards.to_vec();
+        self.accumulation_key = None;
     }
 
-    pub fn render(&self) -> Result<(), wgpu::SurfaceError> {
-        let frame = self.surface.get_current_texture()?;
-        let view = &frame.texture.create_view(&Default::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Encoder"),
-            });
+    pub fn scene_state(&self) -> SceneState {
+        SceneState {
+            transform: self.transform,
+            billboards: self.billboards.clone(),
+        }
+    }
 
-        {
-            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Compute Pass"),
-            });
+    pub fn set_scene_state(&mut self, state: SceneState) {
+        self.transform = state.transform;
+        self.set_billboards(&state.billboards);
+    }
 
-            self.clear_pass.record(
-                &mut cpass,
-                &self.raster_bindings,
-                dispatch_size(self.width * self.height),
-            );
+    /// Snapshots the scene ahead of an edit of the transform or the
+    /// billboards, so that it can be undone.
+    pub fn record_edit(&mut self) {
+        self.history.record(self.scene_state());
+    }
 
-            self.raster_pass.record(
-                &mut cpass,
-                &self.raster_bindings,
-                dispatch_size(self.vertices.len() as u32 / 3),
-            );
+    /// Restores the scene from before the last edit. Returns whether there
+    /// was one to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo(self.scene_state()) {
+            Some(state) => {
+                self.set_scene_state(state);
+                true
+            }
+            None => false,
         }
+    }
 
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 1.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-            self.present_pass.record(&mut rpass, &self.present_bindings);
+    /// Reapplies the last undone edit. Returns whether there was one.
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo(self.scene_state()) {
+            Some(state) => {
+                self.set_scene_state(state);
+                true
+            }
+            None => false,
         }
+    }
 
-        self.queue.submit(Some(encoder.finish()));
-        frame.present();
+    /// Replaces the camera, the turntable keeps spinning the mesh in front of it.
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
 
-        Ok(())
+    /// Restarts the accumulation whenever the view or a setting changed
+    /// since the previous frame.
+    fn advance_accumulation(&mut self) {
+        let key = (self.settings(), self.camera_uniform);
+        let unchanged = self.accumulation_key.is_some_and(|(settings, view)| {
+            settings == key.0 && bytemuck::bytes_of(&view) == bytemuck::bytes_of(&key.1)
+        });
+        self.accumulated_frames = if unchanged {
+            self.accumulated_frames.saturating_add(1)
+        } else {
+            0
+        };
+        self.accumulation_key = Some(key);
+
+        if self.accumulate {
+            self.write_raster_uniform();
+            self.queue.write_buffer(
+                &self.accumulate_uniform,
+                0,
+                bytemuck::bytes_of(&AccumulateUniform::new(
+                    self.background_color(),
+                    self.accumulated_frames,
+                )),
+            );
+        }
+    }
+
+    /// Subpixel offset of the current frame, a Halton (2, 3) sequence while accumulating.
+    fn jitter(&self) -> Vec2 {
+        if !self.accumulate {
+            return Vec2::ZERO;
+        }
+        let index = self.accumulated_frames + 1;
+        vec2(halton(index, 2), halton(index, 3)) - 0.5
+    }
+
+    /// Places the mesh in the world at the current time.
+    fn model_transform(&self) -> Mat4 {
+        match self.turntable {
+            true => self.transform * self.turntable_transform(self.time),
+            false => self.transform,
+        }
+    }
+
+    /// Rotation of the mesh about the vertical axis through its center, a
+    /// radian per second.
+    fn turntable_transform(&self, t: f32) -> Mat4 {
+        Mat4::from_translation(self.mesh_center)
+            * Mat4::from_rotation_y(t)
+            * Mat4::from_translation(-self.mesh_center)
+    }
+
+    /// The presentation mode is left out, it's not persisted.
+    pub fn settings(&self) -> Settings {
+        let current = (
+            self.turntable,
+            self.compare,
+            self.debug_lines,
+            self.analysis,
+            self.minimap,
+        );
+        let (turntable, compare, debug_lines, analysis, minimap) =
+            self.presenting.map_or(current, |saved| {
+                (
+                    saved.turntable,
+                    saved.compare,
+                    saved.debug_lines,
+                    saved.analysis,
+                    saved.minimap,
+                )
+            });
+        Settings {
+            debug_view: self.debug_view,
+            distortion: self.distortion,
+            distortion_strength: self.distortion_strength,
+            raster_mode: self.raster_mode,
+            turntable,
+            background: self.background,
+            frustum_culling: self.frustum_culling,
+            occlusion_culling: self.occlusion_culling,
+            meshlet_culling: self.meshlet_culling,
+            accumulate: self.accumulate,
+            compare,
+            debug_lines,
+            texture_output: self.texture_output,
+            analysis,
+            minimap,
+            shadows: self.shadows,
+            exposure: self.exposure,
+            tonemap: self.tonemap,
+            bloom: self.bloom,
+            sky: self.sky,
+            ibl: self.ibl,
+            clear_color: self.clear_color,
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.debug_view = settings.debug_view;
+        self.distortion = settings.distortion;
+        self.distortion_strength = settings.distortion_strength.clamp(0.0, 2.0);
+        self.raster_mode = settings.raster_mode;
+        self.turntable = settings.turntable;
+        self.background = settings.background;
+        self.frustum_culling = settings.frustum_culling;
+        self.occlusion_culling = settings.occlusion_culling;
+        self.meshlet_culling = settings.meshlet_culling;
+        self.accumulate = settings.accumulate;
+        self.compare = settings.compare;
+        self.debug_lines = settings.debug_lines;
+        self.texture_output = settings.texture_output;
+        self.analysis = settings.analysis;
+        self.minimap = settings.minimap;
+        self.shadows = settings.shadows;
+        self.exposure = settings.exposure.clamp(-MAX_EXPOSURE, MAX_EXPOSURE);
+        self.tonemap = settings.tonemap;
+        self.bloom = settings.bloom;
+        self.sky = match settings.sky {
+            Sky::Environment if self.environment_path.is_none() => Sky::Solid,
+            sky => sky,
+        };
+        self.ibl = settings.ibl && self.environment_path.is_some();
+        self.clear_color = settings.clear_color;
+        if self.debug_lines {
+            self.update_debug_lines();
+        }
+        self.write_raster_uniform();
+        self.write_compare_uniform();
+        self.write_cull_uniform();
+        self.write_present_uniform();
+        self.write_bloom_uniform();
+        self.write_sky_uniform();
+        self.write_ibl_uniform();
+        self.write_clear_uniform();
+    }
+
+    pub fn next_debug_view(&mut self) -> DebugView {
+        self.set_debug_view(self.debug_view.next())
+    }
+
+    pub fn prev_debug_view(&mut self) -> DebugView {
+        self.set_debug_view(self.debug_view.prev())
+    }
+
+    fn set_debug_view(&mut self, debug_view: DebugView) -> DebugView {
+        self.debug_view = debug_view;
+        self.write_raster_uniform();
+        self.write_clear_uniform();
+        self.debug_view
+    }
+
+    fn write_raster_uniform(&self) {
+        self.queue.write_buffer(
+            &self.raster_uniform,
+            0,
+            bytemuck::bytes_of(&RasterUniform::new(
+                self.debug_view,
+                self.lights.len(),
+                self.jitter(),
+                self.shadows,
+            )),
+        );
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Adds a light to the scene, returns its index.
+    pub fn add_light(&mut self, light: Light) -> Result<usize> {
+        if self.lights.len() == MAX_LIGHTS {
+            return Err(eyre!("At most {} lights are supported", MAX_LIGHTS));
+        }
+        self.lights.push(light);
+        self.write_lights();
+        Ok(self.lights.len() - 1)
+    }
+
+    pub fn set_light(&mut self, index: usize, light: Light) {
+        self.lights[index] = light;
+        self.write_lights();
+    }
+
+    pub fn remove_light(&mut self, index: usize) -> Light {
+        let light = self.lights.remove(index);
+        self.write_lights();
+        light
+    }
+
+    /// Moves the light at `index` by `offset` in the object space of the mesh.
+    pub fn move_light(&mut self, index: usize, offset: Vec3) {
+        self.lights[index].position += offset;
+        self.write_lights();
+    }
+
+    fn write_lights(&mut self) {
+        self.queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&light_instances(&self.lights)),
+        );
+        self.write_raster_uniform();
+        self.shadow_map
+            .write_camera(&self.queue, &self.lights, &self.shadow_bindings);
+        // Redraw even if nothing else changed
+        self.accumulation_key = None;
+    }
+
+    /// Position of the eye in the object space of the mesh, where the
+    /// lights are placed.
+    pub fn eye(&self) -> Vec3 {
+        Vec3::from_slice(&self.camera_uniform.view_position[..3])
+    }
+
+    pub fn toggle_shadows(&mut self) -> bool {
+        self.shadows = !self.shadows;
+        self.write_raster_uniform();
+        self.shadows
+    }
+
+    /// Shadows are on and the first light casts them.
+    fn casts_shadows(&self) -> bool {
+        self.shadows
+            && self
+                .lights
+                .first()
+                .is_some_and(|light| light.kind == LightKind::Directional)
+    }
+
+    pub fn toggle_accumulate(&mut self) -> bool {
+        self.accumulate = !self.accumulate;
+        self.write_raster_uniform();
+        self.write_present_uniform();
+        self.accumulate
+    }
+
+    pub fn toggle_texture_output(&mut self) -> bool {
+        self.texture_output = !self.texture_output;
+        self.texture_output
+    }
+
+    pub fn cycle_analysis(&mut self) -> Analysis {
+        self.analysis = self.analysis.next();
+        self.write_present_uniform();
+        self.analysis
+    }
+
+    /// Experimental, shades the `DebugView::Custom` with the `custom_shade`
+    /// defined by the WGSL file at `path`. It's given the attributes of the
+    /// fragment as in raster.wgsl and recompiled whenever the file changes.
+    pub fn set_shading_snippet(&mut self, path: impl Into<PathBuf>) -> DebugView {
+        self.shaders.watch_snippet(path.into());
+        self.set_debug_view(DebugView::Custom)
+    }
+
+    pub fn toggle_dirty_tracking(&mut self) -> bool {
+        self.dirty_tracking = !self.dirty_tracking;
+        self.dirty_tracking
+    }
+
+    pub fn toggle_frustum_culling(&mut self) -> bool {
+        self.frustum_culling = !self.frustum_culling;
+        self.write_cull_uniform();
+        self.frustum_culling
+    }
+
+    pub fn toggle_occlusion_culling(&mut self) -> bool {
+        self.occlusion_culling = !self.occlusion_culling;
+        self.occlusion_culling
+    }
+
+    /// Clean output for demo capture: hides the debug views and applies
+    /// `presentation`, restoring everything when toggled off.
+    pub fn toggle_presentation(&mut self) -> bool {
+        match self.presenting.take() {
+            Some(saved) => {
+                self.turntable = saved.turntable;
+                self.compare = saved.compare;
+                self.debug_lines = saved.debug_lines;
+                self.analysis = saved.analysis;
+                self.minimap = saved.minimap;
+            }
+            None => {
+                self.presenting = Some(self.settings());
+                self.turntable |= self.presentation.turntable;
+                self.compare = CompareMode::Off;
+                self.debug_lines = false;
+                self.analysis = Analysis::Off;
+                self.minimap = false;
+            }
+        }
+        self.write_compare_uniform();
+        self.write_present_uniform();
+        self.resize_buffers();
+        self.presenting.is_some()
+    }
+
+    pub fn toggle_debug_lines(&mut self) -> bool {
+        self.debug_lines = !self.debug_lines;
+        if self.debug_lines {
+            self.update_debug_lines();
+        }
+        self.debug_lines
+    }
+
+    pub fn cycle_guides(&mut self) -> Guides {
+        self.set_guides(self.guides.next());
+        self.guides
+    }
+
+    pub fn set_guides(&mut self, guides: Guides) {
+        self.guides = guides;
+        self.update_guides();
+    }
+
+    /// The guides follow the aspect of the render resolution.
+    fn update_guides(&mut self) {
+        let draw = framing_guides(self.guides, self.width as f32 / self.height as f32);
+        self.guide_bindings = LineBindings::new(
+            &self.device,
+            &self.line_pass,
+            &self.guide_camera,
+            &self.screen_uniform,
+            &self.present_uniform,
+            draw.vertices(),
+        );
+    }
+
+    /// Shows the nodes at the next depth of the BVH with the debug lines,
+    /// they are hidden again after the leaves. Returns the depth shown.
+    pub fn cycle_bvh_level(&mut self) -> Option<u32> {
+        self.bvh_level = match self.bvh_level {
+            None => Some(0),
+            Some(level) if level < self.bvh.depth() => Some(level + 1),
+            Some(_) => None,
+        };
+        if self.bvh_level.is_some() {
+            self.debug_lines = true;
+        }
+        if self.debug_lines {
+            self.update_debug_lines();
+        }
+        self.bvh_level
+    }
+
+    /// Objects of the mesh within the view frustum, culled through the BVH.
+    pub fn visible_objects(&self) -> (Vec<u32>, TraversalStats) {
+        self.bvh
+            .cull(Mat4::from_cols_array_2d(&self.camera_uniform.view_proj))
+    }
+
+    /// Closest triangle under the surface position `x`, `y` in pixels, the
+    /// lens distortion is ignored. Only the perspective projection is picked.
+    pub fn pick(&self, x: f32, y: f32) -> (Option<Hit>, TraversalStats) {
+        if self.camera.projection != Projection::Perspective {
+            return (None, TraversalStats::default());
+        }
+        // Inverse of the letterboxing in present.wgsl
+        let (width, height) = self.surface_size();
+        let surface = Vec2::new(width as f32, height as f32);
+        let dims = Vec2::new(self.width as f32, self.height as f32);
+        let scale = (surface / dims).min_element();
+        let position = (Vec2::new(x, y) - 0.5 * (surface - dims * scale)) / scale;
+        // The raster maps NDC [0, 1] over the color buffer
+        let ndc = position / dims;
+
+        let inverse = Mat4::from_cols_array_2d(&self.camera_uniform.view_proj).inverse();
+        let near = inverse.project_point3(ndc.extend(0.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        self.bvh.pick(near, (far - near).normalize())
+    }
+
+    /// Adds the frustum of the current view and the BVH nodes to the lines,
+    /// the frustum stays in place as the camera moves on.
+    fn update_debug_lines(&mut self) {
+        let mut draw = self.debug_draw.clone();
+        let view_proj = Mat4::from_cols_array_2d(&self.camera_uniform.view_proj);
+        draw.frustum(view_proj, [1.0, 0.5, 0.0], LineStyle::Dashed);
+        if let Some(level) = self.bvh_level {
+            self.bvh.draw_level(&mut draw, level);
+        }
+        self.line_bindings = LineBindings::new(
+            &self.device,
+            &self.line_pass,
+            &self.camera_buffer,
+            &self.screen_uniform,
+            &self.present_uniform,
+            draw.vertices(),
+        );
+    }
+
+    /// Top-down overview of the mesh in the top right corner, with the
+    /// position and the field of view of the camera.
+    pub fn toggle_minimap(&mut self) -> bool {
+        self.minimap = !self.minimap;
+        self.write_minimap_uniform();
+        self.minimap
+    }
+
+    fn write_minimap_uniform(&self) {
+        self.queue.write_buffer(
+            &self.minimap_uniform,
+            0,
+            bytemuck::bytes_of(&MinimapUniform::new(
+                &self.overview,
+                Mat4::from_cols_array_2d(&self.camera_uniform.view),
+                self.camera.half_horizontal_fov(),
+            )),
+        );
+    }
+
+    /// Draws the overview of the mesh, it only changes along with the mesh.
+    fn render_overview(&self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Overview Encoder"),
+            });
+        self.reference_pass.record(
+            &mut encoder,
+            &self.overview_bindings,
+            &self.vertex_buffer,
+            &self.index_buffer,
+        );
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    pub fn cycle_compare(&mut self) -> CompareMode {
+        self.compare = self.compare.next();
+        self.write_compare_uniform();
+        self.compare
+    }
+
+    fn write_compare_uniform(&self) {
+        self.queue.write_buffer(
+            &self.compare_uniform,
+            0,
+            bytemuck::bytes_of(&CompareUniform::new(self.compare)),
+        );
+    }
+
+    pub fn toggle_meshlet_culling(&mut self) -> bool {
+        self.meshlet_culling = !self.meshlet_culling;
+        self.write_cull_uniform();
+        self.meshlet_culling
+    }
+
+    fn write_cull_uniform(&self) {
+        let hiz_levels = self.hiz_bindings.level_count();
+        let uniform = |occlusion| {
+            CullUniform::new(
+                self.frustum_culling,
+                occlusion,
+                self.meshlet_culling,
+                hiz_levels,
+            )
+        };
+        self.queue
+            .write_buffer(&self.cull_uniform, 0, bytemuck::bytes_of(&uniform(false)));
+        self.queue.write_buffer(
+            &self.occlusion_uniform,
+            0,
+            bytemuck::bytes_of(&uniform(true)),
+        );
+    }
+
+    pub fn cycle_background(&mut self) -> [f32; 3] {
+        let current = BACKGROUNDS.iter().position(|&c| c == self.background);
+        self.background = BACKGROUNDS[current.map_or(0, |i| (i + 1) % BACKGROUNDS.len())];
+        self.write_present_uniform();
+        self.background
+    }
+
+    /// Steps from the solid background to the gradient and the environment
+    /// map, when one is loaded.
+    pub fn cycle_sky(&mut self) -> Sky {
+        self.sky = self.sky.next(self.environment_path.is_some());
+        self.write_present_uniform();
+        self.write_sky_uniform();
+        self.sky
+    }
+
+    /// Loads an equirectangular environment map, a Radiance `.hdr` image,
+    /// shows it as the sky and lights the mesh with it.
+    pub fn load_environment(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.environment = Environment::load(&self.device, path.as_ref())?;
+        self.environment_path = Some(path.as_ref().to_owned());
+        self.ibl_pass
+            .prefilter(&self.device, &self.queue, &self.environment);
+        self.ibl = true;
+        self.sky_bindings = SkyBindings::new(
+            &self.device,
+            &self.sky_pass,
+            &self.output_buffer,
+            &self.depth_buffer,
+            &self.environment,
+            &self.screen_uniform,
+            &self.sky_uniform,
+        );
+        self.sky = Sky::Environment;
+        self.write_present_uniform();
+        self.write_sky_uniform();
+        self.write_ibl_uniform();
+        Ok(())
+    }
+
+    /// Switches the ambient light between the constant one and the
+    /// environment, stays off until one is loaded.
+    pub fn toggle_ibl(&mut self) -> bool {
+        self.ibl = !self.ibl && self.environment_path.is_some();
+        self.write_ibl_uniform();
+        self.ibl
+    }
+
+    /// Black is cleared with `clear_buffer` instead of the compute clear. The
+    /// compute clear still runs every frame, it resets the depth to `u32::MAX`
+    /// which `clear_buffer` can't.
+    pub fn set_clear_color(&mut self, color: [f32; 3]) {
+        self.clear_color = color;
+        self.write_clear_uniform();
+    }
+
+    /// The overdraw view counts the fragments from zero, whatever the clear color.
+    fn effective_clear_color(&self) -> [f32; 3] {
+        match self.debug_view {
+            DebugView::Overdraw => [0.0; 3],
+            _ => self.clear_color,
+        }
+    }
+
+    fn write_clear_uniform(&self) {
+        self.queue.write_buffer(
+            &self.clear_uniform,
+            0,
+            bytemuck::bytes_of(&ClearUniform::new(self.effective_clear_color())),
+        );
+    }
+
+    /// The background behind the sky is black, it's added to the uncovered pixels.
+    fn background_color(&self) -> [f32; 3] {
+        match self.sky {
+            Sky::Solid => self.background,
+            Sky::Gradient | Sky::Environment => [0.0; 3],
+        }
+    }
+
+    fn write_sky_uniform(&self) {
+        self.queue.write_buffer(
+            &self.sky_uniform,
+            0,
+            bytemuck::bytes_of(&SkyUniform::new(self.sky, &self.camera, &self.environment)),
+        );
+    }
+
+    fn write_ibl_uniform(&self) {
+        self.queue.write_buffer(
+            &self.ibl_uniform,
+            0,
+            bytemuck::bytes_of(&IblUniform::new(self.model_transform(), self.ibl)),
+        );
+    }
+
+    pub fn cycle_distortion(&mut self) -> Distortion {
+        self.distortion = self.distortion.next();
+        self.write_present_uniform();
+        self.distortion
+    }
+
+    pub fn add_distortion_strength(&mut self, delta: f32) -> f32 {
+        self.distortion_strength = (self.distortion_strength + delta).clamp(0.0, 2.0);
+        self.write_present_uniform();
+        self.distortion_strength
+    }
+
+    pub fn cycle_tonemap(&mut self) -> Tonemap {
+        self.tonemap = self.tonemap.next();
+        self.write_present_uniform();
+        self.tonemap
+    }
+
+    /// Returns the exposure in stops.
+    pub fn add_exposure(&mut self, stops: f32) -> f32 {
+        self.exposure = (self.exposure + stops).clamp(-MAX_EXPOSURE, MAX_EXPOSURE);
+        self.write_present_uniform();
+        self.exposure
+    }
+
+    pub fn toggle_bloom(&mut self) -> bool {
+        self.bloom.enabled = !self.bloom.enabled;
+        self.write_present_uniform();
+        self.bloom.enabled
+    }
+
+    pub fn add_bloom_intensity(&mut self, delta: f32) -> f32 {
+        self.bloom.intensity = (self.bloom.intensity + delta).clamp(0.0, 1.0);
+        self.write_bloom_uniform();
+        self.bloom.intensity
+    }
+
+    pub fn add_bloom_threshold(&mut self, delta: f32) -> f32 {
+        self.bloom.threshold = (self.bloom.threshold + delta).max(0.0);
+        self.write_bloom_uniform();
+        self.bloom.threshold
+    }
+
+    fn write_bloom_uniform(&self) {
+        self.queue.write_buffer(
+            &self.bloom_uniform,
+            0,
+            bytemuck::bytes_of(&BloomUniform::new(self.bloom)),
+        );
+    }
+
+    fn write_present_uniform(&self) {
+        self.queue.write_buffer(
+            &self.present_uniform,
+            0,
+            bytemuck::bytes_of(&PresentUniform::new(
+                self.distortion,
+                self.distortion_strength,
+                self.surface_config.width,
+                self.surface_config.height,
+                self.background_color(),
+                self.accumulate,
+                self.analysis,
+                self.exposure.exp2(),
+                self.tonemap,
+                !self.surface_config.format.describe().srgb,
+                self.bloom.enabled,
+            )),
+        );
+    }
+
+    pub fn surface_size(&self) -> (u32, u32) {
+        (self.surface_config.width, self.surface_config.height)
+    }
+
+    /// Reconfigures the surface right away, the color buffer is only
+    /// reallocated once the size settles. Until then the last frame is
+    /// presented scaled into the new surface.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
+        self.update_render_targets();
+        self.write_present_uniform();
+        self.resize_pending = Some(Instant::now());
+    }
+
+    /// Switches the surface to the next of Fifo, Mailbox and Immediate it
+    /// supports. Returns the mode in use.
+    pub fn cycle_present_mode(&mut self) -> wgpu::PresentMode {
+        let current = PRESENT_MODES
+            .iter()
+            .position(|&mode| mode == self.surface_config.present_mode)
+            .unwrap_or(0);
+        let next = (1..=PRESENT_MODES.len())
+            .map(|i| PRESENT_MODES[(current + i) % PRESENT_MODES.len()])
+            .find(|mode| self.present_modes.contains(mode))
+            .unwrap_or(wgpu::PresentMode::Fifo);
+        self.set_present_mode(next)
+    }
+
+    /// Reconfigures the surface to present with `mode`, or with the closest
+    /// mode it supports. Returns the mode in use.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> wgpu::PresentMode {
+        self.surface_config.present_mode = supported_present_mode(&self.present_modes, mode);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
+        self.surface_config.present_mode
+    }
+
+    /// Switches the present pass between 1 and 4 samples per pixel.
+    pub fn toggle_msaa(&mut self) -> u32 {
+        self.sample_count = if self.sample_count == 1 { 4 } else { 1 };
+        self.update_render_targets();
+        self.sample_count
+    }
+
+    /// Recreates the targets of the present pass for the current surface, the
+    /// pipelines are only rebuilt when the format or the sample count changed.
+    fn update_render_targets(&mut self) {
+        let SurfaceConfiguration {
+            format,
+            width,
+            height,
+            ..
+        } = self.surface_config;
+        self.msaa_target =
+            create_msaa_target(&self.device, format, width, height, self.sample_count);
+        self.depth_target = create_depth_target(&self.device, width, height, self.sample_count);
+        self.present_pass
+            .update_target(&self.device, format, self.sample_count);
+        self.line_pass
+            .update_target(&self.device, format, self.sample_count);
+        self.minimap_pass
+            .update_target(&self.device, format, self.sample_count);
+    }
+
+    /// Size of the color buffer, letterboxed into the surface while presenting
+    /// and scaled by the render resolution.
+    fn render_size(&self) -> (u32, u32) {
+        let scale = match self.render_resolution {
+            RenderResolution::Fixed(width, height) => return (width, height),
+            RenderResolution::Scaled(scale) => scale,
+        };
+        let (width, height) = self.surface_size();
+        let aspect = self.presenting.and(self.presentation.aspect);
+        let (width, height) = match aspect {
+            Some(aspect) if width as f32 / height as f32 > aspect => {
+                (((height as f32 * aspect) as u32).max(1), height)
+            }
+            Some(aspect) => (width, ((width as f32 / aspect) as u32).max(1)),
+            None => (width, height),
+        };
+        let scaled = |length: u32| ((length as f32 * scale).round() as u32).max(1);
+        (scaled(width), scaled(height))
+    }
+
+    /// Size of the color buffer in use, lags behind a resize until it settles.
+    pub fn buffer_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn render_resolution(&self) -> RenderResolution {
+        self.render_resolution
+    }
+
+    /// Reallocates the color buffer at `resolution`, independent of the
+    /// window size. Returns the resolution in use, the scale is clamped.
+    pub fn set_render_resolution(&mut self, resolution: RenderResolution) -> RenderResolution {
+        let (min, max) = (RENDER_SCALES[0], RENDER_SCALES[RENDER_SCALES.len() - 1]);
+        self.render_resolution = match resolution {
+            RenderResolution::Scaled(scale) => RenderResolution::Scaled(scale.clamp(min, max)),
+            RenderResolution::Fixed(width, height) => {
+                RenderResolution::Fixed(width.max(1), height.max(1))
+            }
+        };
+        self.resize_buffers();
+        self.render_resolution
+    }
+
+    /// Steps the scale of the render resolution up or down, a fixed
+    /// resolution goes back to the surface size.
+    pub fn step_render_scale(&mut self, up: bool) -> RenderResolution {
+        let scale = match self.render_resolution {
+            RenderResolution::Scaled(current) if up => RENDER_SCALES
+                .into_iter()
+                .find(|&scale| scale > current)
+                .unwrap_or(current),
+            RenderResolution::Scaled(current) => RENDER_SCALES
+                .into_iter()
+                .rev()
+                .find(|&scale| scale < current)
+                .unwrap_or(current),
+            RenderResolution::Fixed(..) => 1.0,
+        };
+        self.set_render_resolution(RenderResolution::Scaled(scale))
+    }
+
+    fn resize_buffers(&mut self) {
+        let (width, height) = self.render_size();
+        self.width = width;
+        self.height = height;
+        self.camera.aspect = width as f32 / height as f32;
+        self.queue.write_buffer(
+            &self.screen_uniform,
+            0,
+            bytemuck::bytes_of(&Uniform::new(width as _, height as _)),
+        );
+
+        self.output_buffer = create_color_buffer(&self.device, width, height);
+        self.depth_buffer = create_depth_buffer(&self.device, width, height);
+        self.accumulation_buffer = create_accumulation_buffer(&self.device, width, height);
+        self.accumulation_key = None;
+        self.rebind_passes();
+        if self.guides != Guides::Off {
+            self.update_guides();
+        }
+        self.write_cull_uniform();
+    }
+
+    /// Rebuilds the bindings of every pass of the graph against the buffers
+    /// of the current size, whether the pass runs with the current settings
+    /// or not. In the order of `frame_passes`, so that a pass binding the
+    /// output of another one comes after it.
+    fn rebind_passes(&mut self) {
+        let device = &self.device;
+        let targets = Targets {
+            color_buffer: &self.output_buffer,
+            depth_buffer: &self.depth_buffer,
+            accumulation_buffer: &self.accumulation_buffer,
+            width: self.width,
+            height: self.height,
+        };
+
+        self.clear_bindings = ClearBindings::new(
+            device,
+            &self.clear_pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            &self.clear_uniform,
+        );
+        self.billboard_bindings
+            .rebind(device, &self.billboard_pass, &targets);
+        self.tile_bins = TileBins::new(
+            device,
+            &self.raster_pass,
+            targets.width,
+            targets.height,
+            self.triangle_count,
+        );
+        self.raster_bindings
+            .rebind(device, &self.raster_pass, &targets);
+        self.raster_bindings.update_uniform(
+            device,
+            &self.raster_pass,
+            &self.screen_uniform,
+            &self.raster_uniform,
+            &self.tile_bins,
+            &self.raster_chunks,
+        );
+        self.hiz_bindings = HiZBindings::new(
+            device,
+            &self.hiz_pass,
+            targets.depth_buffer,
+            targets.width,
+            targets.height,
+        );
+        self.cull_bindings.update_hiz_pyramid(
+            device,
+            &self.cull_pass,
+            &self.vertex_buffer,
+            &self.index_buffer,
+            self.hiz_bindings.pyramid(),
+        );
+
+        self.reference_bindings.rebind(device, &targets);
+        self.compare_bindings.rebind(
+            device,
+            &self.compare_pass,
+            &targets,
+            self.reference_bindings.color_view(),
+        );
+        self.sky_bindings = SkyBindings::new(
+            device,
+            &self.sky_pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            &self.environment,
+            &self.screen_uniform,
+            &self.sky_uniform,
+        );
+        self.accumulate_bindings
+            .rebind(device, &self.accumulate_pass, &targets);
+        self.bloom_bindings = BloomBindings::new(
+            device,
+            &self.bloom_pass,
+            targets.color_buffer,
+            &self.bloom_uniform,
+            targets.width,
+            targets.height,
+        );
+        self.analysis_bindings
+            .rebind(device, &self.analysis_pass, &targets);
+        self.resolve_bindings.rebind(
+            device,
+            &self.resolve_pass,
+            &targets,
+            self.bloom_bindings.output(),
+        );
+        self.present_bindings.rebind(
+            device,
+            &self.present_pass,
+            &targets,
+            self.analysis_bindings.histogram(),
+            self.bloom_bindings.output(),
+            self.resolve_bindings.output_view(),
+        );
+    }
+
+    fn occlusion(&self) -> Option<Occlusion<'_>> {
+        self.occlusion_culling.then_some(Occlusion {
+            hiz_pass: &self.hiz_pass,
+            hiz_bindings: &self.hiz_bindings,
+            cull_pass: &self.cull_pass,
+        })
+    }
+
+    /// Renders the current view with the overdraw view and reads back the
+    /// fragments written per pixel, validates the fill rule on scenes like
+    /// `TestScene::SharedEdge` and `TestScene::Fan`.
+    pub fn check_coverage(&mut self) -> Result<Coverage> {
+        let debug_view = std::mem::replace(&mut self.debug_view, DebugView::Overdraw);
+        self.write_raster_uniform();
+        self.write_clear_uniform();
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Coverage Encoder"),
+            });
+        self.record_passes(&mut encoder, &self.frame(None), |stage| {
+            stage == Stage::Raster
+        });
+        self.queue.submit(Some(encoder.finish()));
+        let colors = self.read_buffer(&self.output_buffer);
+        self.device.poll(wgpu::Maintain::Wait);
+        let colors = pollster::block_on(colors)?;
+        let coverage = Coverage::new(&colors, self.width, self.height);
+
+        self.debug_view = debug_view;
+        self.write_raster_uniform();
+        self.write_clear_uniform();
+        // The color buffer holds the overdraw now
+        self.accumulation_key = None;
+        Ok(coverage)
+    }
+
+    /// Linear colors of the last frame in row-major order, before the
+    /// tonemap. Pixels without any fragment hold the clear color of
+    /// `set_clear_color`. The copy is submitted right away, awaiting polls the
+    /// device until it's done without blocking.
+    pub fn read_color_buffer(&self) -> impl Future<Output = Result<Vec<[f32; 3]>>> + '_ {
+        let words = self.read_buffer(&self.output_buffer);
+        async move {
+            let colors = words
+                .await?
+                .into_iter()
+                .map(|color| {
+                    // RGB9E5, see color.wgsl
+                    let scale = 2f32.powi((color >> 27) as i32 - 15 - 9);
+                    [color, color >> 9, color >> 18].map(|channel| (channel & 511) as f32 * scale)
+                })
+                .collect();
+            Ok(colors)
+        }
+    }
+
+    /// NDC depth of the last frame in row-major order, `f32::INFINITY` where
+    /// no fragment was written. Awaited like `read_color_buffer`.
+    pub fn read_depth_buffer(&self) -> impl Future<Output = Result<Vec<f32>>> + '_ {
+        let words = self.read_buffer(&self.depth_buffer);
+        async move {
+            let depths = words
+                .await?
+                .into_iter()
+                .map(|bits| match bits {
+                    u32::MAX => f32::INFINITY,
+                    bits => f32::from_bits(bits),
+                })
+                .collect();
+            Ok(depths)
+        }
+    }
+
+    /// Copies `buffer`, holding a word per pixel, into a mappable one. Whole
+    /// words always meet `wgpu::COPY_BUFFER_ALIGNMENT`. The future drives the
+    /// device, a caller blocking on it should wait on the device first.
+    fn read_buffer(&self, buffer: &wgpu::Buffer) -> impl Future<Output = Result<Vec<u32>>> + '_ {
+        let size = std::mem::size_of::<u32>() as u64 * (self.width * self.height) as u64;
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let mapped = map_read(readback.slice(..));
+        async move {
+            drive(&self.device, mapped).await?;
+            let words = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+            readback.unmap();
+            Ok(words)
+        }
+    }
+
+    /// Records the passes of the frame, presenting into `view`, a target of
+    /// the surface format.
+    fn record_frame(&self, view: &wgpu::TextureView) -> wgpu::CommandEncoder {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Encoder"),
+            });
+        let [r, g, b] = self.background_color().map(f64::from);
+        let frame = self.frame(Some(Surface {
+            view,
+            msaa_target: self.msaa_target.as_ref(),
+            depth_target: &self.depth_target,
+            background: wgpu::Color { r, g, b, a: 1.0 },
+        }));
+        // Keep presenting the previous frame while the size isn't settled
+        let redraw = self.resize_pending.is_none() && self.dirty;
+        self.record_passes(&mut encoder, &frame, |stage| {
+            redraw || stage == Stage::Present
+        });
+        encoder
+    }
+
+    /// Records the passes of `frame_passes` in the stages `stages` accepts,
+    /// each in a debug group of its own.
+    fn record_passes(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &Frame,
+        stages: impl Fn(Stage) -> bool,
+    ) {
+        for pass in self.frame_passes() {
+            if stages(pass.stage()) {
+                debug_group(encoder, pass.name(), |encoder| pass.record(encoder, frame));
+            }
+        }
+    }
+
+    /// What the passes of the frame are recorded with, without a surface
+    /// when the frame is only described or doesn't present.
+    fn frame<'a>(&'a self, surface: Option<Surface<'a>>) -> Frame<'a> {
+        Frame {
+            device: &self.device,
+            queue: &self.queue,
+            vertex_buffer: &self.vertex_buffer,
+            index_buffer: &self.index_buffer,
+            color_buffer: &self.output_buffer,
+            clear_color: self.effective_clear_color(),
+            width: self.width,
+            height: self.height,
+            workgroup_size: self.workgroup_size,
+            triangle_count: self.triangle_count,
+            bloom: self.bloom.enabled,
+            texture_output: self.texture_output,
+            surface,
+        }
+    }
+
+    /// Passes of the frame with the current settings, in the order they are
+    /// recorded. Recording, the render graph and the occupancy all go
+    /// through this list.
+    fn frame_passes(&self) -> Vec<Box<dyn Pass + '_>> {
+        let mut passes: Vec<Box<dyn Pass + '_>> = vec![];
+        if self.casts_shadows() {
+            passes.push(Box::new(ShadowNode {
+                shadow_map: &self.shadow_map,
+                bindings: &self.shadow_bindings,
+                cull_pass: &self.cull_pass,
+                clear_pass: &self.clear_pass,
+                raster_pass: &self.raster_pass,
+            }));
+        }
+        passes.push(Box::new(Node {
+            pass: &self.cull_pass,
+            bindings: &self.cull_bindings,
+        }));
+        passes.push(Box::new(Node {
+            pass: &self.clear_pass,
+            bindings: &self.clear_bindings,
+        }));
+        // They would count as fragments of the overdraw view
+        let billboards = self.debug_view != DebugView::Overdraw;
+        let billboard = |depth_pass| {
+            Box::new(BillboardNode {
+                pass: &self.billboard_pass,
+                bindings: &self.billboard_bindings,
+                billboards: &self.billboards,
+                depth_pass,
+            })
+        };
+        if billboards {
+            passes.push(billboard(true));
+        }
+        passes.push(Box::new(RasterNode {
+            pass: &self.raster_pass,
+            bindings: &self.raster_bindings,
+            mode: self.raster_mode,
+            cull: &self.cull_bindings,
+            occlusion: self.occlusion(),
+            chunks: &self.raster_chunks,
+            submit_chunks: self.chunk_submits,
+            tile_bins: &self.tile_bins,
+            work_queue: &self.work_queue,
+            shadows: self.casts_shadows(),
+            ibl: self.ibl,
+        }));
+        if billboards {
+            passes.push(billboard(false));
+        }
+
+        // The reference only covers the perspective projection
+        if self.compare != CompareMode::Off && self.camera.projection == Projection::Perspective {
+            passes.push(Box::new(Node {
+                pass: &self.reference_pass,
+                bindings: &self.reference_bindings,
+            }));
+            passes.push(Box::new(Node {
+                pass: &self.compare_pass,
+                bindings: &self.compare_bindings,
+            }));
+        }
+        if self.sky != Sky::Solid {
+            passes.push(Box::new(Node {
+                pass: &self.sky_pass,
+                bindings: &self.sky_bindings,
+            }));
+        }
+        if self.accumulate {
+            passes.push(Box::new(Node {
+                pass: &self.accumulate_pass,
+                bindings: &self.accumulate_bindings,
+            }));
+        }
+        if self.bloom.enabled {
+            passes.push(Box::new(Node {
+                pass: &self.bloom_pass,
+                bindings: &self.bloom_bindings,
+            }));
+        }
+        if self.analysis == Analysis::Histogram {
+            passes.push(Box::new(Node {
+                pass: &self.analysis_pass,
+                bindings: &self.analysis_bindings,
+            }));
+        }
+        if self.texture_output {
+            passes.push(Box::new(Node {
+                pass: &self.resolve_pass,
+                bindings: &self.resolve_bindings,
+            }));
+        }
+
+        passes.push(Box::new(Node {
+            pass: &self.present_pass,
+            bindings: &self.present_bindings,
+        }));
+        if self.debug_lines && self.camera.projection == Projection::Perspective {
+            passes.push(Box::new(LineNode {
+                pass: &self.line_pass,
+                bindings: &self.line_bindings,
+                guides: false,
+            }));
+        }
+        if self.guides != Guides::Off {
+            passes.push(Box::new(LineNode {
+                pass: &self.line_pass,
+                bindings: &self.guide_bindings,
+                guides: true,
+            }));
+        }
+        if self.minimap {
+            passes.push(Box::new(Node {
+                pass: &self.minimap_pass,
+                bindings: &self.minimap_bindings,
+            }));
+        }
+        passes
+    }
+
+    /// Passes `record_frame` records with the current settings and what they
+    /// go through, a frame skipped by the dirty tracking only presents.
+    pub fn render_graph(&self) -> RenderGraph {
+        let frame = self.frame(None);
+        let mut graph = RenderGraph::default();
+        for pass in self.frame_passes() {
+            graph.add(pass.as_ref(), &frame);
+        }
+        graph
+    }
+
+    /// How well the compute passes of a frame with the current settings fill
+    /// their workgroups, see `OccupancyReport`.
+    pub fn occupancy(&self) -> OccupancyReport {
+        let frame = self.frame(None);
+        OccupancyReport {
+            max_invocations_per_workgroup: self
+                .device
+                .limits()
+                .max_compute_invocations_per_workgroup,
+            passes: self
+                .frame_passes()
+                .iter()
+                .flat_map(|pass| pass.occupancy(&frame))
+                .collect(),
+        }
+    }
+
+    /// Captures the next frame with RenderDoc when the application runs
+    /// under it, the whole frame is rendered even if nothing changed. Does
+    /// nothing otherwise.
+    pub fn capture_frame(&mut self) {
+        self.capture_pending = true;
+    }
+
+    /// Waits for the GPU to finish the next frame after submitting it, the
+    /// time from the submit is then returned by `take_gpu_time`. Unlike the
+    /// time of `render_to_surface` it doesn't include acquiring the surface
+    /// texture, which follows the vsync rather than the GPU. The wait stalls
+    /// the CPU, sample every few frames only. A frame that skips the raster
+    /// is not sampled.
+    pub fn sample_gpu_time(&mut self) {
+        self.gpu_time_pending = true;
+    }
+
+    /// GPU time of the last frame sampled with `sample_gpu_time`, once.
+    pub fn take_gpu_time(&mut self) -> Option<Duration> {
+        self.gpu_time.take()
+    }
+
+    /// Fails with `SurfaceError::Lost` for a headless renderer. A lost or
+    /// outdated surface is reconfigured and acquired again first, a lost
+    /// device fails every frame until `recreate_gpu_resources`.
+    pub fn render_to_surface(&mut self) -> Result<(), RenderError> {
+        if self.device_lost.load(Ordering::Relaxed) {
+            return Err(RenderError::DeviceLost);
+        }
+        let start = Instant::now();
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let presented = self.present_frame();
+        let error = pollster::block_on(self.device.pop_error_scope());
+        if error.as_ref().is_some_and(is_device_lost) {
+            self.device_lost.store(true, Ordering::Relaxed);
+        }
+        if self.device_lost.load(Ordering::Relaxed) {
+            return Err(RenderError::DeviceLost);
+        }
+        if let Some(error) = error {
+            return Err(RenderError::Validation(error));
+        }
+        presented?;
+
+        self.watch_frame_time(start.elapsed());
+        Ok(())
+    }
+
+    fn present_frame(&mut self) -> Result<(), RenderError> {
+        let surface = match &self.surface {
+            Some(surface) => surface,
+            None => return Err(wgpu::SurfaceError::Lost.into()),
+        };
+        let frame = match surface.get_current_texture() {
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                surface.configure(&self.device, &self.surface_config);
+                surface.get_current_texture()?
+            }
+            frame => frame?,
+        };
+        let capture = std::mem::take(&mut self.capture_pending);
+        if capture {
+            self.dirty = true;
+            self.device.start_capture();
+        }
+        let sample_gpu_time = std::mem::take(&mut self.gpu_time_pending) && self.dirty;
+        let view = &frame.texture.create_view(&Default::default());
+        let encoder = self.record_frame(view);
+        // wgpu 0.13 panics instead of failing the submit and the present of
+        // a lost device, only that panic is caught
+        let (device, queue) = (&self.device, &self.queue);
+        let submitted = panic::catch_unwind(AssertUnwindSafe(move || {
+            let start = Instant::now();
+            queue.submit(Some(encoder.finish()));
+            let gpu_time = sample_gpu_time.then(|| {
+                device.poll(wgpu::Maintain::Wait);
+                start.elapsed()
+            });
+            frame.present();
+            gpu_time
+        }));
+        if capture {
+            self.device.stop_capture();
+        }
+        match submitted {
+            Ok(gpu_time) => {
+                self.gpu_time = gpu_time.or(self.gpu_time);
+                Ok(())
+            }
+            Err(payload) => {
+                if !panic_is_device_lost(&*payload) && !self.device_lost.load(Ordering::Relaxed) {
+                    panic::resume_unwind(payload);
+                }
+                self.device_lost.store(true, Ordering::Relaxed);
+                Err(RenderError::DeviceLost)
+            }
+        }
+    }
+
+    /// Creates the device, the surface of `window` and every pass and buffer
+    /// again, once `render_to_surface` reported the device lost. `meshes`
+    /// are those of the tabs in order, the renderer keeps no copy of them.
+    /// The settings, the lights, the environment and the cameras and
+    /// arrangements of the tabs carry over, the undo history and the
+    /// presentation mode don't.
+    pub async fn recreate_gpu_resources<W>(&mut self, window: &W, meshes: Vec<Mesh>) -> Result<()>
+    where
+        W: HasRawWindowHandle,
+    {
+        if meshes.len() != self.tabs.len() {
+            return Err(eyre!(
+                "Expected the meshes of {} tabs, got {}",
+                self.tabs.len(),
+                meshes.len()
+            ));
+        }
+        let (width, height) = self.surface_size();
+        let mut tabs = meshes.into_iter().zip(self.tab_states());
+        let (mesh, (camera, scene)) = tabs.next().expect("A renderer has at least one tab");
+        let mut renderer = Self::new(window, width, height, camera, mesh, &self.options).await?;
+        renderer.set_scene_state(scene);
+        for (mesh, (camera, scene)) in tabs {
+            renderer.open_tab(mesh, camera);
+            renderer.set_scene_state(scene);
+        }
+        renderer.switch_tab(self.tab);
+
+        if let Some(path) = &self.environment_path {
+            renderer.load_environment(path)?;
+        }
+        if let Some(snippet) = &self.shaders.snippet {
+            renderer.set_shading_snippet(snippet.path());
+        }
+        renderer.lights = std::mem::take(&mut self.lights);
+        renderer.write_lights();
+        renderer.apply_settings(&self.settings());
+        if self.raster_chunks.count() > 1 {
+            renderer.set_raster_chunks(Some(self.raster_chunks.size()), self.chunk_submits);
+        }
+        if renderer.sample_count != self.sample_count {
+            renderer.toggle_msaa();
+        }
+        renderer.set_present_mode(self.surface_config.present_mode);
+        renderer.set_render_resolution(self.render_resolution);
+        renderer.set_guides(self.guides);
+        renderer.dirty_tracking = self.dirty_tracking;
+        renderer.presentation = self.presentation;
+        renderer.time = self.time;
+        *self = renderer;
+        Ok(())
+    }
+
+    /// Renders a frame into an image of `width` by `height`, which the
+    /// renderer is resized to first. Also works for a windowed renderer,
+    /// the surface is then resized along.
+    pub fn render_to_image(&mut self, width: u32, height: u32) -> Result<RgbaImage> {
+        if (width, height) != self.surface_size() {
+            self.resize(width, height);
+            self.resize_buffers();
+            self.resize_pending = None;
+            self.update(self.time);
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Image Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let mut encoder = self.record_frame(&texture.create_view(&Default::default()));
+
+        // Rows of a texture copy are padded to `COPY_BYTES_PER_ROW_ALIGNMENT`
+        let row_size = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_size = row_size.div_ceil(align) * align;
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Image Readback Buffer"),
+            size: padded_row_size as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let mapped = map_read(slice);
+        self.device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapped)?;
+        let mut pixels = Vec::with_capacity((row_size * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row_size as usize) {
+            pixels.extend_from_slice(&row[..row_size as usize]);
+        }
+        readback.unmap();
+        // Surfaces are BGRA
+        if matches!(
+            self.surface_config.format,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+        ) {
+            pixels.chunks_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+        RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| eyre!("Image of {}x{} doesn't match its pixels", width, height))
+    }
+
+    /// Wall time in milliseconds of the stages of a frame, each submitted on
+    /// its own and waited on: the compute raster, the present pass with the
+    /// overlays and the whole frame. Coarser than GPU timestamps but they
+    /// work on every adapter. Drawn into a target of the surface size.
+    pub fn time_frame(&mut self) -> Vec<(&'static str, f32)> {
+        let (width, height) = self.surface_size();
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bench Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        let view = texture.create_view(&Default::default());
+        let time = |renderer: &Self, encoder: wgpu::CommandEncoder| {
+            let start = Instant::now();
+            renderer.queue.submit(Some(encoder.finish()));
+            renderer.device.poll(wgpu::Maintain::Wait);
+            start.elapsed().as_secs_f32() * 1000.0
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Bench Encoder"),
+            });
+        self.record_passes(&mut encoder, &self.frame(None), |stage| {
+            stage == Stage::Raster
+        });
+        let raster = time(self, encoder);
+        // Skipped frames only present
+        let dirty = std::mem::replace(&mut self.dirty, false);
+        let present = time(self, self.record_frame(&view));
+        self.dirty = true;
+        let frame = time(self, self.record_frame(&view));
+        self.dirty = dirty;
+        vec![("Raster", raster), ("Present", present), ("Frame", frame)]
     }
 }
@@ -0,0 +1,55 @@
+#include "color.wgsl"
+
+// RGB9E5 packed by raster.wgsl, see color.wgsl
+struct ColorBuffer {
+  values: array<u32>,
+}
+
+struct DepthBuffer {
+  values: array<u32>,
+}
+
+// Running average of the jittered frames, alpha is unused
+struct AccumulationBuffer {
+  values: array<vec4<f32>>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct AccumulateUniform {
+  background: vec3<f32>,
+  // Frames averaged so far, 0 restarts the accumulation
+  frame: u32,
+}
+
+@group(0) @binding(0) var<storage, read> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read> depth_buffer: DepthBuffer;
+@group(0) @binding(2) var<storage, read_write> accumulation: AccumulationBuffer;
+@group(1) @binding(0) var<uniform> screen_dims: Uniform;
+@group(1) @binding(1) var<uniform> params: AccumulateUniform;
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn accumulate(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let index = global_id.x;
+  if (index >= u32(screen_dims.width * screen_dims.height)) {
+    return;
+  }
+
+  // Uncovered samples count as background so that edges blend into it, their
+  // color is black unless the sky pass wrote the sky
+  var color = unpack_color(color_buffer.values[index]);
+  if (depth_buffer.values[index] == 0xffffffffu) {
+    color = color + params.background;
+  }
+
+  if (params.frame == 0u) {
+    accumulation.values[index] = vec4<f32>(color, 1.0);
+    return;
+  }
+  let average = accumulation.values[index].rgb;
+  let weight = 1.0 / f32(params.frame + 1u);
+  accumulation.values[index] = vec4<f32>(mix(average, color, weight), 1.0);
+}
@@ -0,0 +1,209 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass, Targets},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Accumulate");
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct AccumulateUniform {
+    background: [f32; 3],
+    frame: u32,
+}
+
+impl AccumulateUniform {
+    /// `frame` is the number of frames already averaged, 0 restarts the accumulation.
+    pub fn new(background: [f32; 3], frame: u32) -> Self {
+        Self { background, frame }
+    }
+}
+
+/// Averages the jittered frames into a float buffer while the view is static,
+/// converging to a supersampled image.
+pub struct AccumulatePass {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl AccumulatePass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[
+                    storage_entry(0, true),
+                    storage_entry(1, true),
+                    storage_entry(2, false),
+                ],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "accumulate.wgsl",
+            include_str!("accumulate.wgsl"),
+            workgroup_size,
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "accumulate",
+        });
+        Self { pipeline }
+    }
+}
+
+pub struct AccumulateBindings {
+    buffers: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+}
+
+impl AccumulateBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        pass: &AccumulatePass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        uniform: &wgpu::Buffer,
+        accumulate_uniform: &wgpu::Buffer,
+    ) -> Self {
+        let AccumulatePass { pipeline } = pass;
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: accumulate_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        Self {
+            buffers: Self::buffers_bind_group(
+                device,
+                pass,
+                color_buffer,
+                depth_buffer,
+                accumulation_buffer,
+            ),
+            uniform,
+        }
+    }
+
+    pub fn rebind(&mut self, device: &wgpu::Device, pass: &AccumulatePass, targets: &Targets) {
+        self.buffers = Self::buffers_bind_group(
+            device,
+            pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            targets.accumulation_buffer,
+        );
+    }
+
+    fn buffers_bind_group(
+        device: &wgpu::Device,
+        AccumulatePass { pipeline }: &AccumulatePass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
+
+impl<'a> AccumulatePass {
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a AccumulateBindings,
+        dispatch_size: u32,
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        cpass.set_bind_group(1, &bindings.uniform, &[]);
+        cpass.dispatch_workgroups(dispatch_size, 1, 1);
+    }
+}
+
+impl Pass for Node<'_, AccumulatePass, AccumulateBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Color Buffer", "Depth Buffer", "Accumulation"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Accumulation"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass
+            .record(&mut cpass, self.bindings, frame.pixel_dispatch());
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![frame.occupancy(self.name(), frame.pixels())]
+    }
+}
@@ -0,0 +1,89 @@
+// Luminance histogram of the presented image, drawn over it by present.wgsl.
+// Pixels without any fragment are left out.
+
+#include "color.wgsl"
+
+struct ColorBuffer {
+  value: array<u32>,
+}
+
+struct DepthBuffer {
+  value: array<u32>,
+}
+
+struct AccumulationBuffer {
+  value: array<vec4<f32>>,
+}
+
+struct Histogram {
+  bins: array<atomic<u32>, 64>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct PresentUniform {
+  distortion: u32,
+  distortion_strength: f32,
+  surface_width: f32,
+  surface_height: f32,
+  background: vec3<f32>,
+  accumulate: u32,
+  analysis: u32,
+  exposure: f32,
+  tonemap: u32,
+}
+
+let HISTOGRAM_BINS = 64u;
+
+@group(0) @binding(0) var<storage, read> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read> depth_buffer: DepthBuffer;
+@group(0) @binding(2) var<storage, read> accumulation: AccumulationBuffer;
+@group(0) @binding(3) var<storage, read_write> histogram: Histogram;
+@group(1) @binding(0) var<uniform> screen_dims: Uniform;
+@group(1) @binding(1) var<uniform> present: PresentUniform;
+
+// Counted per workgroup first, most pixels of a workgroup fall into a few bins
+var<workgroup> local_bins: array<atomic<u32>, 64>;
+
+fn luminance(color: vec3<f32>) -> f32 {
+  return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn luminance_histogram(
+  @builtin(global_invocation_id) global_id: vec3<u32>,
+  @builtin(local_invocation_index) local_index: u32,
+) {
+  // Workgroups may be smaller than the bins
+  for (var bin = local_index; bin < HISTOGRAM_BINS; bin = bin + WORKGROUP_SIZE) {
+    atomicStore(&local_bins[bin], 0u);
+  }
+  workgroupBarrier();
+
+  let index = global_id.x;
+  if (index < u32(screen_dims.width * screen_dims.height) && depth_buffer.value[index] != 0xffffffffu) {
+    var color: vec3<f32>;
+    if (present.accumulate != 0u) {
+      color = accumulation.value[index].rgb;
+    } else {
+      // RGB9E5 packed by raster.wgsl, see color.wgsl
+      let p = color_buffer.value[index];
+      color = unpack_color(p);
+    }
+    // Binned as displayed
+    color = tonemap(color, present.exposure, present.tonemap);
+    let bin = min(u32(clamp(luminance(color), 0.0, 1.0) * f32(HISTOGRAM_BINS)), HISTOGRAM_BINS - 1u);
+    atomicAdd(&local_bins[bin], 1u);
+  }
+  workgroupBarrier();
+
+  for (var bin = local_index; bin < HISTOGRAM_BINS; bin = bin + WORKGROUP_SIZE) {
+    let count = atomicLoad(&local_bins[bin]);
+    if (count != 0u) {
+      atomicAdd(&histogram.bins[bin], count);
+    }
+  }
+}
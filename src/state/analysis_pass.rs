@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass, Targets},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Analysis");
+
+/// Bins of the luminance histogram, over [0, 1].
+pub const HISTOGRAM_BINS: usize = 64;
+
+/// Exposure overlay drawn over the presented image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Analysis {
+    Off,
+    /// Luminance histogram of the covered pixels in the bottom left corner
+    Histogram,
+    /// Clipped blacks in blue, clipped whites in red and middle gray in green
+    FalseColor,
+}
+
+impl Analysis {
+    pub fn next(self) -> Self {
+        match self {
+            Analysis::Off => Analysis::Histogram,
+            Analysis::Histogram => Analysis::FalseColor,
+            Analysis::FalseColor => Analysis::Off,
+        }
+    }
+}
+
+/// Counts the luminance of the presented pixels into a histogram, the
+/// accumulated image while accumulating. Read by the present pass.
+pub struct AnalysisPass {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl AnalysisPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[
+                    storage_entry(0, true),
+                    storage_entry(1, true),
+                    storage_entry(2, true),
+                    storage_entry(3, false),
+                ],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "analysis.wgsl",
+            include_str!("analysis.wgsl"),
+            workgroup_size,
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "luminance_histogram",
+        });
+        Self { pipeline }
+    }
+}
+
+pub struct AnalysisBindings {
+    buffers: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+    histogram: wgpu::Buffer,
+}
+
+impl AnalysisBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        pass: &AnalysisPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        uniform: &wgpu::Buffer,
+        present_uniform: &wgpu::Buffer,
+    ) -> Self {
+        let AnalysisPass { pipeline } = pass;
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: present_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        // Cleared before every dispatch
+        let histogram = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Histogram Buffer"),
+            size: (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffers: Self::buffers_bind_group(
+                device,
+                pass,
+                color_buffer,
+                depth_buffer,
+                accumulation_buffer,
+                &histogram,
+            ),
+            uniform,
+            histogram,
+        }
+    }
+
+    pub fn rebind(&mut self, device: &wgpu::Device, pass: &AnalysisPass, targets: &Targets) {
+        self.buffers = Self::buffers_bind_group(
+            device,
+            pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            targets.accumulation_buffer,
+            &self.histogram,
+        );
+    }
+
+    fn buffers_bind_group(
+        device: &wgpu::Device,
+        AnalysisPass { pipeline }: &AnalysisPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        histogram: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: histogram.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// `HISTOGRAM_BINS` pixel counts, only valid once the pass ran.
+    pub fn histogram(&self) -> &wgpu::Buffer {
+        &self.histogram
+    }
+}
+
+impl<'a> AnalysisPass {
+    /// Clears the histogram and counts the current frame into it.
+    pub fn record(
+        &'a self,
+        encoder: &mut wgpu::CommandEncoder,
+        bindings: &'a AnalysisBindings,
+        dispatch_size: u32,
+    ) {
+        encoder.clear_buffer(&bindings.histogram, 0, None);
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        cpass.set_bind_group(1, &bindings.uniform, &[]);
+        cpass.dispatch_workgroups(dispatch_size, 1, 1);
+    }
+}
+
+impl Pass for Node<'_, AnalysisPass, AnalysisBindings> {
+    fn name(&self) -> &'static str {
+        "Histogram"
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Color Buffer", "Depth Buffer", "Accumulation"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Histogram"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        self.pass
+            .record(encoder, self.bindings, frame.pixel_dispatch());
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![frame.occupancy(self.name(), frame.pixels())]
+    }
+}
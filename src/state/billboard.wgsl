@@ -0,0 +1,102 @@
+// Camera-facing sprites drawn into the buffers of the compute raster, at a
+// whole number of pixels per texel. The depth of the anchor applies to the
+// whole sprite. As for the raster, the depth permutation defines DEPTH_PASS
+// and runs before the triangles, the color one after them.
+
+#include "camera.wgsl"
+#include "color.wgsl"
+
+struct ColorBuffer {
+  values: array<atomic<u32>>,
+}
+
+struct DepthBuffer {
+  values: array<atomic<u32>>,
+}
+
+struct Billboard {
+  position: vec3<f32>,
+  sprite: u32,
+  tint: vec3<f32>,
+  // Pixels per texel
+  scale: u32,
+}
+
+struct Billboards {
+  values: array<Billboard>,
+}
+
+// RGBA8 texels of the sprites one after the other, red in the lowest byte
+struct Sprites {
+  texels: array<u32>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+let SPRITE_SIZE = 16u;
+// Texels more transparent than this are left out
+let ALPHA_CUTOFF = 128u;
+// Fraction of the way to the eye the anchor is moved by, so that markers on
+// a surface stay in front of it
+let DEPTH_BIAS = 0.01;
+
+@group(0) @binding(0) var<storage, read_write> color_buffer : ColorBuffer;
+@group(0) @binding(1) var<storage, read_write> depth_buffer : DepthBuffer;
+@group(1) @binding(0) var<storage, read> billboards : Billboards;
+@group(1) @binding(1) var<storage, read> sprites : Sprites;
+@group(2) @binding(0) var<uniform> camera : Camera;
+@group(2) @binding(1) var<uniform> screen_dims : Uniform;
+
+// One invocation per pixel of the billboard `global_id.y`. Only drawn with
+// the perspective projection, like the reference raster.
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn billboard(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  if (camera.projection != PROJECTION_PERSPECTIVE) {
+    return;
+  }
+  let billboard = billboards.values[global_id.y];
+  let extent = SPRITE_SIZE * billboard.scale;
+  if (global_id.x >= extent * extent) {
+    return;
+  }
+
+  let anchor = mix(billboard.position, camera.view_pos.xyz, DEPTH_BIAS);
+  let clip = camera.view_proj * vec4<f32>(anchor, 1.0);
+  if (clip.w <= 0.0) {
+    return;
+  }
+  let depth = clip.z / clip.w;
+  if (depth < 0.0 || depth > 1.0) {
+    return;
+  }
+
+  // Centered on the pixel of the anchor, so the texels cover whole pixels
+  let dims = vec2<f32>(screen_dims.width, screen_dims.height);
+  let texel = vec2<u32>(global_id.x % extent, global_id.x / extent);
+  let pixel = floor(clip.xy / clip.w * dims) + vec2<f32>(texel) - f32(extent / 2u);
+  if (any(pixel < vec2<f32>(0.0)) || any(pixel >= dims)) {
+    return;
+  }
+  let sprite_texel = texel / billboard.scale;
+  let rgba = sprites.texels[(billboard.sprite * SPRITE_SIZE + sprite_texel.y) * SPRITE_SIZE + sprite_texel.x];
+  if ((rgba >> 24u) < ALPHA_CUTOFF) {
+    return;
+  }
+
+  let pixel_id = u32(pixel.x) + u32(pixel.y) * u32(dims.x);
+  let depth_bits = bitcast<u32>(depth);
+#ifdef DEPTH_PASS
+  // Zeroes the clear color as `depth_test` of raster.wgsl does
+  if (atomicMin(&depth_buffer.values[pixel_id], depth_bits) == 0xffffffffu) {
+    atomicStore(&color_buffer.values[pixel_id], 0u);
+  }
+#else
+  if (atomicLoad(&depth_buffer.values[pixel_id]) == depth_bits) {
+    let color = srgb_to_linear(unpack_rgba8(rgba)) * billboard.tint;
+    atomicMax(&color_buffer.values[pixel_id], pack_color(color));
+  }
+#endif
+}
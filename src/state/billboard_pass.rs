@@ -0,0 +1,389 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Pass, Stage, Targets},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Billboard");
+
+/// Width and height of the sprites in texels.
+pub const SPRITE_SIZE: u32 = 16;
+
+/// Largest number of pixels per texel of a billboard.
+pub const MAX_BILLBOARD_SCALE: u32 = 4;
+
+/// Built-in images of the billboards, symmetric so that they read the same
+/// whichever way the raster is oriented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sprite {
+    Dot,
+    Ring,
+    Cross,
+    /// Light icon
+    Sun,
+}
+
+impl Sprite {
+    const ALL: [Sprite; 4] = [Sprite::Dot, Sprite::Ring, Sprite::Cross, Sprite::Sun];
+
+    /// Whether the sprite covers `p`, in [-1, 1] over the sprite.
+    fn covers(self, p: Vec2) -> bool {
+        let r = p.length();
+        match self {
+            Sprite::Dot => r <= 0.8,
+            Sprite::Ring => (0.55..=0.9).contains(&r),
+            Sprite::Cross => (p.x.abs() - p.y.abs()).abs() < 0.25 && r <= 1.0,
+            Sprite::Sun => {
+                let ray = p.x.abs() < 0.15 || p.y.abs() < 0.15;
+                r <= 0.45 || (ray && (0.6..=1.0).contains(&r))
+            }
+        }
+    }
+}
+
+/// Camera-facing sprite anchored at a point of the mesh, drawn with its
+/// texels covering `scale` x `scale` pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Billboard {
+    pub position: Vec3,
+    pub sprite: Sprite,
+    pub color: [f32; 3],
+    /// Clamped to `MAX_BILLBOARD_SCALE`
+    pub scale: u32,
+}
+
+impl Billboard {
+    pub fn new(position: Vec3, sprite: Sprite, color: [f32; 3]) -> Self {
+        Self {
+            position,
+            sprite,
+            color,
+            scale: 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BillboardInstance {
+    position: [f32; 3],
+    sprite: u32,
+    tint: [f32; 3],
+    scale: u32,
+}
+
+impl From<&Billboard> for BillboardInstance {
+    fn from(billboard: &Billboard) -> Self {
+        Self {
+            position: billboard.position.to_array(),
+            sprite: billboard.sprite as u32,
+            tint: billboard.color,
+            scale: billboard.scale.clamp(1, MAX_BILLBOARD_SCALE),
+        }
+    }
+}
+
+/// sRGB encoded RGBA8 texels of the `Sprite`s in order, white where they're
+/// covered.
+fn sprite_atlas() -> Vec<u32> {
+    let texel = |i: u32| (i as f32 + 0.5) / SPRITE_SIZE as f32 * 2.0 - 1.0;
+    Sprite::ALL
+        .iter()
+        .flat_map(|&sprite| {
+            (0..SPRITE_SIZE * SPRITE_SIZE).map(move |i| {
+                let p = Vec2::new(texel(i % SPRITE_SIZE), texel(i / SPRITE_SIZE));
+                match sprite.covers(p) {
+                    true => u32::MAX,
+                    false => 0,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Draws billboards into the color and depth buffers of the compute raster,
+/// so that the triangles hide them and the other way around.
+pub struct BillboardPass {
+    depth_pipeline: wgpu::ComputePipeline,
+    color_pipeline: wgpu::ComputePipeline,
+    sprites: wgpu::Buffer,
+    workgroup_size: WorkgroupSize,
+}
+
+impl BillboardPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[storage_entry(0, false), storage_entry(1, false)],
+            });
+        let billboards_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Billboards Bind Group Layout")),
+                entries: &[storage_entry(0, true), storage_entry(1, true)],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[
+                &buffers_bind_group_layout,
+                &billboards_bind_group_layout,
+                &uniform_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let source = shaders.embedded("billboard.wgsl", include_str!("billboard.wgsl"));
+        let size = workgroup_size.literal();
+        let pipeline = |label: &str, defines: &[(&str, &str)]| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &source.module(device, defines).unwrap(),
+                entry_point: "billboard",
+            })
+        };
+        let depth_pipeline = pipeline(
+            &LABELS.of("Depth Pipeline"),
+            &[("WORKGROUP_SIZE", &size), ("DEPTH_PASS", "")],
+        );
+        let color_pipeline = pipeline(&LABELS.of("Color Pipeline"), &[("WORKGROUP_SIZE", &size)]);
+
+        let sprites = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Sprite Buffer")),
+            contents: bytemuck::cast_slice(&sprite_atlas()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        Self {
+            depth_pipeline,
+            color_pipeline,
+            sprites,
+            workgroup_size,
+        }
+    }
+}
+
+pub struct BillboardBindings {
+    buffers: wgpu::BindGroup,
+    billboards: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+    count: u32,
+}
+
+impl BillboardBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        pass: &BillboardPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        camera_buffer: &wgpu::Buffer,
+        uniform: &wgpu::Buffer,
+        billboards: &[Billboard],
+    ) -> Self {
+        let BillboardPass {
+            depth_pipeline,
+            sprites,
+            ..
+        } = pass;
+        // Bindings can't be empty, the dispatch is skipped instead
+        let mut instances: Vec<BillboardInstance> = billboards.iter().map(Into::into).collect();
+        let count = instances.len() as u32;
+        if instances.is_empty() {
+            instances.push(BillboardInstance::zeroed());
+        }
+        let billboard_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Billboard Buffer")),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let billboards = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Billboards Bind Group")),
+            layout: &depth_pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: billboard_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: sprites.as_entire_binding(),
+                },
+            ],
+        });
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &depth_pipeline.get_bind_group_layout(2),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+        });
+        Self {
+            buffers: Self::buffers_bind_group(device, pass, color_buffer, depth_buffer),
+            billboards,
+            uniform,
+            count,
+        }
+    }
+
+    pub fn rebind(&mut self, device: &wgpu::Device, pass: &BillboardPass, targets: &Targets) {
+        self.buffers =
+            Self::buffers_bind_group(device, pass, targets.color_buffer, targets.depth_buffer);
+    }
+
+    fn buffers_bind_group(
+        device: &wgpu::Device,
+        BillboardPass { depth_pipeline, .. }: &BillboardPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &depth_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl<'a> BillboardPass {
+    /// The depth pass goes right after the buffers are cleared, the color pass
+    /// after the triangles so that it only writes the billboards in front.
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a BillboardBindings,
+        depth_pass: bool,
+    ) where
+        'a: 'pass,
+    {
+        if bindings.is_empty() {
+            return;
+        }
+        let pipeline = match depth_pass {
+            true => &self.depth_pipeline,
+            false => &self.color_pipeline,
+        };
+        let extent = SPRITE_SIZE * MAX_BILLBOARD_SCALE;
+        cpass.set_pipeline(pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        cpass.set_bind_group(1, &bindings.billboards, &[]);
+        cpass.set_bind_group(2, &bindings.uniform, &[]);
+        cpass.dispatch_workgroups(
+            self.workgroup_size.dispatch_size(extent * extent),
+            bindings.count,
+            1,
+        );
+    }
+}
+
+/// Either pass of the billboards in the frame, along with the billboards for
+/// the extent they cover.
+pub struct BillboardNode<'a> {
+    pub pass: &'a BillboardPass,
+    pub bindings: &'a BillboardBindings,
+    pub billboards: &'a [Billboard],
+    pub depth_pass: bool,
+}
+
+impl Pass for BillboardNode<'_> {
+    fn name(&self) -> &'static str {
+        match self.depth_pass {
+            true => "Billboard Depth",
+            false => "Billboard Color",
+        }
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        match self.depth_pass {
+            true => vec!["Billboards", "Camera"],
+            false => vec!["Billboards", "Camera", "Depth Buffer"],
+        }
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        match self.depth_pass {
+            true => vec!["Depth Buffer"],
+            false => vec!["Color Buffer"],
+        }
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass.record(&mut cpass, self.bindings, self.depth_pass);
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Raster
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        if self.billboards.is_empty() {
+            return vec![];
+        }
+        let extent = SPRITE_SIZE * MAX_BILLBOARD_SCALE;
+        let size = frame.workgroup_size;
+        vec![PassOccupancy {
+            name: self.name(),
+            workgroup_size: size.get(),
+            workgroups: size.dispatch_size(extent * extent) * self.billboards.len() as u32,
+            items: self
+                .billboards
+                .iter()
+                .map(|billboard| {
+                    let extent = SPRITE_SIZE * billboard.scale.clamp(1, MAX_BILLBOARD_SCALE);
+                    (extent * extent) as u64
+                })
+                .sum(),
+        }]
+    }
+}
@@ -0,0 +1,110 @@
+// Bloom over the HDR color buffer. What exceeds the threshold is halved level
+// by level into a chain packed one level after another in the same buffer,
+// then the levels are upsampled back, each added onto the next larger one.
+// The last upsample writes the full resolution glow the present pass adds
+// before the tonemap.
+
+#include "color.wgsl"
+
+// RGB9E5 packed by raster.wgsl, see color.wgsl
+struct ColorBuffer {
+  values: array<u32>,
+}
+
+struct Texels {
+  values: array<vec4<f32>>,
+}
+
+struct BloomLevel {
+  src_width: u32,
+  src_height: u32,
+  src_offset: u32,
+  dst_width: u32,
+  dst_height: u32,
+  dst_offset: u32,
+  // Matches `BloomStep`
+  step: u32,
+}
+
+struct BloomUniform {
+  threshold: f32,
+  intensity: f32,
+}
+
+let STEP_PREFILTER = 0u;
+
+@group(0) @binding(0) var<storage, read> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read_write> chain: Texels;
+@group(0) @binding(2) var<storage, read_write> output: Texels;
+@group(1) @binding(0) var<uniform> level: BloomLevel;
+@group(1) @binding(1) var<uniform> params: BloomUniform;
+
+// Only what exceeds the threshold glows, the hue is kept. Uncovered pixels
+// are black unless the sky pass filled them in.
+fn bright(index: u32) -> vec3<f32> {
+  let color = unpack_color(color_buffer.values[index]);
+  let peak = max(max(color.r, color.g), color.b);
+  return color * max(peak - params.threshold, 0.0) / max(peak, 1e-4);
+}
+
+// Out of range pixels clamp to the edges
+fn load(xy: vec2<i32>) -> vec3<f32> {
+  let size = vec2<i32>(i32(level.src_width), i32(level.src_height));
+  let clamped = clamp(xy, vec2<i32>(0), size - 1);
+  let index = u32(clamped.x + clamped.y * size.x);
+  if (level.step == STEP_PREFILTER) {
+    return bright(index);
+  }
+  return chain.values[level.src_offset + index].rgb;
+}
+
+// 1 3 3 1 tent over the 4x4 source pixels around the 2x2 ones halved into
+// the pixel, smoother than a box against flickering highlights
+fn downsampled(xy: vec2<i32>) -> vec3<f32> {
+  let weights = vec4<f32>(1.0, 3.0, 3.0, 1.0) / 8.0;
+  let origin = xy * 2 - 1;
+  var color = vec3<f32>(0.0);
+  for (var y = 0; y < 4; y = y + 1) {
+    for (var x = 0; x < 4; x = x + 1) {
+      color = color + load(origin + vec2<i32>(x, y)) * weights[x] * weights[y];
+    }
+  }
+  return color;
+}
+
+// Bilinear lookup of the smaller source level at the center of the pixel
+fn upsampled(xy: vec2<i32>) -> vec3<f32> {
+  let src = vec2<f32>(f32(level.src_width), f32(level.src_height));
+  let dst = vec2<f32>(f32(level.dst_width), f32(level.dst_height));
+  let p = (vec2<f32>(xy) + 0.5) * src / dst - 0.5;
+  let base = vec2<i32>(floor(p));
+  let t = p - floor(p);
+  let top = mix(load(base), load(base + vec2<i32>(1, 0)), t.x);
+  let bottom = mix(load(base + vec2<i32>(0, 1)), load(base + vec2<i32>(1, 1)), t.x);
+  return mix(top, bottom, t.y);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn bloom(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let index = global_id.x;
+  if (index >= level.dst_width * level.dst_height) {
+    return;
+  }
+  let xy = vec2<i32>(i32(index % level.dst_width), i32(index / level.dst_width));
+
+  switch (level.step) {
+    // Upsample
+    case 2u: {
+      let dst = level.dst_offset + index;
+      chain.values[dst] = vec4<f32>(chain.values[dst].rgb + upsampled(xy), 1.0);
+    }
+    // Composite, the first level into the output
+    case 3u: {
+      output.values[index] = vec4<f32>(upsampled(xy) * params.intensity, 1.0);
+    }
+    // Prefilter and downsample
+    default: {
+      chain.values[level.dst_offset + index] = vec4<f32>(downsampled(xy), 1.0);
+    }
+  }
+}
@@ -0,0 +1,348 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Bloom");
+
+/// Halvings of the color buffer the glow spreads over, fewer for small ones.
+const BLOOM_LEVELS: usize = 6;
+
+/// Bloom settings, the threshold and intensity are applied to the linear HDR
+/// color before the exposure.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bloom {
+    pub enabled: bool,
+    /// Brightness the brightest channel has to exceed to glow
+    pub threshold: f32,
+    /// Scale of the glow added to the image
+    pub intensity: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 1.0,
+            intensity: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct BloomUniform {
+    threshold: f32,
+    intensity: f32,
+    _padding: [f32; 2],
+}
+
+impl BloomUniform {
+    pub fn new(bloom: Bloom) -> Self {
+        Self {
+            threshold: bloom.threshold,
+            intensity: bloom.intensity,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// What a dispatch of the chain does, see bloom.wgsl.
+#[derive(Clone, Copy)]
+enum BloomStep {
+    Prefilter,
+    Downsample,
+    Upsample,
+    Composite,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BloomLevel {
+    src_width: u32,
+    src_height: u32,
+    src_offset: u32,
+    dst_width: u32,
+    dst_height: u32,
+    dst_offset: u32,
+    step: u32,
+    _padding: u32,
+}
+
+/// Sizes of the chain levels for a `width` x `height` color buffer, halved
+/// from it down to `BLOOM_LEVELS` levels or 1x1.
+fn bloom_level_sizes(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let mut sizes = vec![];
+    let (mut width, mut height) = (width, height);
+    while sizes.len() < BLOOM_LEVELS && (width > 1 || height > 1) {
+        width = width.div_ceil(2);
+        height = height.div_ceil(2);
+        sizes.push((width, height));
+    }
+    sizes
+}
+
+/// Glow around the parts of the HDR color buffer brighter than a threshold,
+/// written into a float buffer the present pass adds to the image.
+pub struct BloomPass {
+    pipeline: wgpu::ComputePipeline,
+    workgroup_size: WorkgroupSize,
+}
+
+impl BloomPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[
+                    storage_entry(0, true),
+                    storage_entry(1, false),
+                    storage_entry(2, false),
+                ],
+            });
+        let level_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Level Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &level_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "bloom.wgsl",
+            include_str!("bloom.wgsl"),
+            workgroup_size,
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "bloom",
+        });
+        Self {
+            pipeline,
+            workgroup_size,
+        }
+    }
+}
+
+/// The chain of a color buffer and its output, with one bind group per
+/// dispatch. Rebuilt along with the color buffer.
+pub struct BloomBindings {
+    output: wgpu::Buffer,
+    buffers: wgpu::BindGroup,
+    steps: Vec<(wgpu::BindGroup, u32)>,
+    /// Written over all the dispatches
+    pixels: u64,
+}
+
+impl BloomBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        BloomPass {
+            pipeline,
+            workgroup_size,
+        }: &BloomPass,
+        color_buffer: &wgpu::Buffer,
+        bloom_uniform: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let sizes = bloom_level_sizes(width, height);
+        let texel_size = std::mem::size_of::<[f32; 4]>() as u64;
+        let texels: u32 = sizes.iter().map(|(w, h)| w * h).sum();
+        let chain = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Chain Buffer")),
+            size: texel_size * texels.max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Output Buffer")),
+            size: texel_size * width as u64 * height as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let buffers = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: chain.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        // (width, height, offset) of the color buffer and of every level
+        let mut levels = vec![(width, height, 0)];
+        let mut offset = 0;
+        for &(level_width, level_height) in &sizes {
+            levels.push((level_width, level_height, offset));
+            offset += level_width * level_height;
+        }
+        // Down the chain and back up, the last level is only read
+        let mut dispatches = vec![];
+        for i in 1..levels.len() {
+            let step = match i {
+                1 => BloomStep::Prefilter,
+                _ => BloomStep::Downsample,
+            };
+            dispatches.push((step, levels[i - 1], levels[i]));
+        }
+        for i in (2..levels.len()).rev() {
+            dispatches.push((BloomStep::Upsample, levels[i], levels[i - 1]));
+        }
+        if levels.len() > 1 {
+            dispatches.push((BloomStep::Composite, levels[1], levels[0]));
+        }
+
+        let pixels = dispatches
+            .iter()
+            .map(|(_, _, (width, height, _))| (width * height) as u64)
+            .sum();
+        let steps = dispatches
+            .into_iter()
+            .map(|(step, src, dst)| {
+                let level = BloomLevel {
+                    src_width: src.0,
+                    src_height: src.1,
+                    src_offset: src.2,
+                    dst_width: dst.0,
+                    dst_height: dst.1,
+                    dst_offset: dst.2,
+                    step: step as u32,
+                    _padding: 0,
+                };
+                let uniform = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some(&LABELS.of("Level Uniform Buffer")),
+                    contents: bytemuck::bytes_of(&level),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(&LABELS.of("Level Bind Group")),
+                    layout: &pipeline.get_bind_group_layout(1),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniform.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: bloom_uniform.as_entire_binding(),
+                        },
+                    ],
+                });
+                (bind_group, workgroup_size.dispatch_size(dst.0 * dst.1))
+            })
+            .collect();
+
+        Self {
+            output,
+            buffers,
+            steps,
+            pixels,
+        }
+    }
+
+    /// Full resolution glow, a vec4 per pixel.
+    pub fn output(&self) -> &wgpu::Buffer {
+        &self.output
+    }
+
+    /// Workgroups over all the dispatches.
+    pub fn workgroups(&self) -> u32 {
+        self.steps
+            .iter()
+            .map(|&(_, dispatch_size)| dispatch_size)
+            .sum()
+    }
+
+    pub fn pixels(&self) -> u64 {
+        self.pixels
+    }
+}
+
+impl<'a> BloomPass {
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a BloomBindings,
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        for (step, dispatch_size) in &bindings.steps {
+            cpass.set_bind_group(1, step, &[]);
+            cpass.dispatch_workgroups(*dispatch_size, 1, 1);
+        }
+    }
+}
+
+impl Pass for Node<'_, BloomPass, BloomBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Color Buffer"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Bloom"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass.record(&mut cpass, self.bindings);
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![PassOccupancy {
+            name: self.name(),
+            workgroup_size: frame.workgroup_size.get(),
+            workgroups: self.bindings.workgroups(),
+            items: self.bindings.pixels(),
+        }]
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+
+use glam::{Mat4, Vec3, Vec4};
+
+use super::{
+    line_pass::{DebugDraw, LineStyle},
+    util::Mesh,
+};
+
+/// Leaves are split until they hold at most this many objects.
+const LEAF_OBJECTS: usize = 2;
+
+/// Colors of the drawn nodes, cycled through by depth.
+const LEVEL_COLORS: [[f32; 3]; 4] = [
+    [0.2, 0.9, 0.2],
+    [0.2, 0.6, 1.0],
+    [1.0, 0.9, 0.2],
+    [1.0, 0.3, 0.8],
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    min: Vec3,
+    max: Vec3,
+    /// First child of inner nodes, first object of leaves
+    first: u32,
+    /// Objects of a leaf, 0 for inner nodes whose children are `first` and
+    /// `first + 1`
+    count: u32,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.count != 0
+    }
+}
+
+/// The triangles of a glTF primitive instance.
+struct Object {
+    id: u32,
+    min: Vec3,
+    max: Vec3,
+    /// Index of the triangles in the mesh
+    triangles: Vec<u32>,
+    /// Corners of the triangles, kept to pick without the mesh
+    corners: Vec<[Vec3; 3]>,
+}
+
+impl Object {
+    fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+}
+
+/// Work done by a traversal of the hierarchy.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraversalStats {
+    pub nodes_visited: u32,
+    /// Nodes skipped with all of their subtree
+    pub nodes_culled: u32,
+    pub objects_tested: u32,
+    pub triangles_tested: u32,
+}
+
+/// Closest triangle along a picking ray.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    /// glTF primitive instance of the triangle
+    pub object: u32,
+    /// Index of the triangle in the mesh
+    pub triangle: u32,
+    /// In the space of the mesh
+    pub position: Vec3,
+    pub distance: f32,
+}
+
+/// Bounding volume hierarchy over the objects of a mesh, splitting them at
+/// the median along the longest axis of their centroids. Culls and picks on
+/// the CPU, the compute raster still draws every triangle it's given.
+pub struct Bvh {
+    /// Root first
+    nodes: Vec<Node>,
+    /// In the order the leaves refer to
+    objects: Vec<Object>,
+    depth: u32,
+}
+
+impl Bvh {
+    pub fn new(mesh: &Mesh) -> Self {
+        let mut objects: Vec<Object> = Vec::new();
+        // Position of every object id in `objects`
+        let mut slots = HashMap::new();
+        for (triangle, (info, indices)) in mesh
+            .triangles
+            .iter()
+            .zip(mesh.indices.chunks_exact(3))
+            .enumerate()
+        {
+            let corners = [0, 1, 2].map(|i| mesh.vertices[indices[i] as usize].position());
+            let index = *slots.entry(info.object).or_insert_with(|| {
+                objects.push(Object {
+                    id: info.object,
+                    min: Vec3::splat(f32::MAX),
+                    max: Vec3::splat(f32::MIN),
+                    triangles: Vec::new(),
+                    corners: Vec::new(),
+                });
+                objects.len() - 1
+            });
+            let object = &mut objects[index];
+            for corner in corners {
+                object.min = object.min.min(corner);
+                object.max = object.max.max(corner);
+            }
+            object.triangles.push(triangle as u32);
+            object.corners.push(corners);
+        }
+
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            objects,
+            depth: 0,
+        };
+        if !bvh.objects.is_empty() {
+            bvh.nodes.push(bvh.leaf(0, bvh.objects.len()));
+            bvh.depth = bvh.subdivide(0);
+        }
+        bvh
+    }
+
+    fn leaf(&self, first: usize, count: usize) -> Node {
+        let (min, max) = self.objects[first..first + count].iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), object| (min.min(object.min), max.max(object.max)),
+        );
+        Node {
+            min,
+            max,
+            first: first as u32,
+            count: count as u32,
+        }
+    }
+
+    /// Splits the leaf at `index` and its children in turn. Returns the
+    /// number of levels below it.
+    fn subdivide(&mut self, index: usize) -> u32 {
+        let Node { first, count, .. } = self.nodes[index];
+        let (first, count) = (first as usize, count as usize);
+        if count <= LEAF_OBJECTS {
+            return 0;
+        }
+        let objects = &mut self.objects[first..first + count];
+        let (min, max) = objects.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), object| (min.min(object.centroid()), max.max(object.centroid())),
+        );
+        let extent = max - min;
+        let axis = if extent.x >= extent.y.max(extent.z) {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        objects.sort_by(|a, b| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+
+        let half = count / 2;
+        let left = self.nodes.len();
+        self.nodes.push(self.leaf(first, half));
+        self.nodes.push(self.leaf(first + half, count - half));
+        self.nodes[index].first = left as u32;
+        self.nodes[index].count = 0;
+        1 + self.subdivide(left).max(self.subdivide(left + 1))
+    }
+
+    /// Levels below the root.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Objects intersecting the volume `view_proj` maps to [0, 1] in all
+    /// three coordinates, as the raster does.
+    pub fn cull(&self, view_proj: Mat4) -> (Vec<u32>, TraversalStats) {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [x, w - x, y, w - y, z, w - z];
+        // Outside when the corner furthest along a plane is still behind it
+        let outside = |min: Vec3, max: Vec3| {
+            planes.iter().any(|plane: &Vec4| {
+                let normal = plane.truncate();
+                let corner = Vec3::select(normal.cmpge(Vec3::ZERO), max, min);
+                normal.dot(corner) + plane.w < 0.0
+            })
+        };
+
+        let mut stats = TraversalStats::default();
+        let mut visible = Vec::new();
+        self.traverse(&mut stats, |node, stats| {
+            if outside(node.min, node.max) {
+                return false;
+            }
+            if node.is_leaf() {
+                for object in self.leaf_objects(node) {
+                    stats.objects_tested += 1;
+                    if !outside(object.min, object.max) {
+                        visible.push(object.id);
+                    }
+                }
+            }
+            true
+        });
+        (visible, stats)
+    }
+
+    /// Closest triangle hit by the ray from `origin` along `direction`, both
+    /// sides of the triangles are hit.
+    pub fn pick(&self, origin: Vec3, direction: Vec3) -> (Option<Hit>, TraversalStats) {
+        let inverse = direction.recip();
+        let mut stats = TraversalStats::default();
+        let mut closest: Option<Hit> = None;
+        self.traverse(&mut stats, |node, stats| {
+            let limit = closest.map_or(f32::MAX, |hit| hit.distance);
+            if !ray_box(origin, inverse, node.min, node.max).is_some_and(|t| t < limit) {
+                return false;
+            }
+            if node.is_leaf() {
+                for object in self.leaf_objects(node) {
+                    stats.objects_tested += 1;
+                    for (&triangle, corners) in object.triangles.iter().zip(&object.corners) {
+                        stats.triangles_tested += 1;
+                        let limit = closest.map_or(f32::MAX, |hit| hit.distance);
+                        match ray_triangle(origin, direction, corners) {
+                            Some(distance) if distance < limit => {
+                                closest = Some(Hit {
+                                    object: object.id,
+                                    triangle,
+                                    position: origin + direction * distance,
+                                    distance,
+                                });
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            true
+        });
+        (closest, stats)
+    }
+
+    /// Visits the nodes depth first, the children of a node are skipped when
+    /// `visit` returns false for it.
+    fn traverse(
+        &self,
+        stats: &mut TraversalStats,
+        mut visit: impl FnMut(&Node, &mut TraversalStats) -> bool,
+    ) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            stats.nodes_visited += 1;
+            if !visit(node, stats) {
+                stats.nodes_culled += 1;
+                continue;
+            }
+            if !node.is_leaf() {
+                stack.push(node.first as usize + 1);
+                stack.push(node.first as usize);
+            }
+        }
+    }
+
+    fn leaf_objects(&self, node: &Node) -> &[Object] {
+        &self.objects[node.first as usize..(node.first + node.count) as usize]
+    }
+
+    /// Bounds of the nodes `level` below the root, leaves above it included.
+    pub fn draw_level(&self, draw: &mut DebugDraw, level: u32) {
+        let color = LEVEL_COLORS[level as usize % LEVEL_COLORS.len()];
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push((0, 0));
+        }
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            if depth == level || node.is_leaf() {
+                draw.aabb(node.min, node.max, color, LineStyle::Solid);
+            } else {
+                stack.push((node.first as usize, depth + 1));
+                stack.push((node.first as usize + 1, depth + 1));
+            }
+        }
+    }
+}
+
+/// Distance along the ray to where it enters the box, 0 from within.
+/// `inverse` is the reciprocal of the direction.
+fn ray_box(origin: Vec3, inverse: Vec3, min: Vec3, max: Vec3) -> Option<f32> {
+    let t1 = (min - origin) * inverse;
+    let t2 = (max - origin) * inverse;
+    let near = t1.min(t2).max_element().max(0.0);
+    let far = t1.max(t2).min_element();
+    (near <= far).then_some(near)
+}
+
+/// Möller-Trumbore, distance along the ray to the triangle.
+fn ray_triangle(origin: Vec3, direction: Vec3, [a, b, c]: &[Vec3; 3]) -> Option<f32> {
+    let (ab, ac) = (*b - *a, *c - *a);
+    let p = direction.cross(ac);
+    let det = ab.dot(p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - *a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(ab);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = ac.dot(q) * inv_det;
+    (t > 0.0).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::util::{v, Material, Vertex};
+
+    /// A unit triangle per object in a row along x, object `i` at `2 * i`.
+    fn row(count: u32) -> Mesh {
+        let vertices: Vec<Vertex> = (0..count)
+            .flat_map(|i| {
+                let x = 2.0 * i as f32;
+                [v!(x, 0., 0.), v!(x + 1., 0., 0.), v!(x, 0., 1.)]
+            })
+            .collect();
+        let mut mesh = Mesh::with_material(vertices, Material::default());
+        for (i, info) in mesh.triangles.iter_mut().enumerate() {
+            info.object = i as u32;
+        }
+        mesh
+    }
+
+    fn contains(outer: &Node, min: Vec3, max: Vec3) -> bool {
+        outer.min.cmple(min).all() && outer.max.cmpge(max).all()
+    }
+
+    #[test]
+    fn nodes_bound_their_children() {
+        let mesh = row(8);
+        let bvh = Bvh::new(&mesh);
+        let (min, max) = mesh.bounds();
+        assert_eq!((bvh.nodes[0].min, bvh.nodes[0].max), (min, max));
+        assert_eq!(bvh.depth(), 2);
+        for node in &bvh.nodes {
+            if node.is_leaf() {
+                assert!(node.count as usize <= LEAF_OBJECTS);
+                for object in bvh.leaf_objects(node) {
+                    assert!(contains(node, object.min, object.max));
+                }
+            } else {
+                for child in &bvh.nodes[node.first as usize..node.first as usize + 2] {
+                    assert!(contains(node, child.min, child.max));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let bvh = Bvh::new(&row(0));
+        assert_eq!(bvh.depth(), 0);
+        assert!(bvh.pick(Vec3::Y, -Vec3::Y).0.is_none());
+        assert!(bvh.cull(Mat4::IDENTITY).0.is_empty());
+    }
+
+    #[test]
+    fn picks_the_closest_triangle() {
+        let bvh = Bvh::new(&row(8));
+        let (hit, stats) = bvh.pick(Vec3::new(6.25, 5.0, 0.25), -Vec3::Y);
+        let hit = hit.unwrap();
+        assert_eq!((hit.object, hit.triangle), (3, 3));
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert!(hit.position.abs_diff_eq(Vec3::new(6.25, 0.0, 0.25), 1e-5));
+        // Only the leaf of the hit is searched
+        assert_eq!(stats.triangles_tested, 2);
+        assert!(stats.nodes_culled > 0);
+
+        // Between two triangles, and behind the ray
+        assert!(bvh.pick(Vec3::new(7.5, 5.0, 0.25), -Vec3::Y).0.is_none());
+        assert!(bvh.pick(Vec3::new(6.25, 5.0, 0.25), Vec3::Y).0.is_none());
+    }
+
+    #[test]
+    fn culls_the_objects_outside_the_frustum() {
+        let bvh = Bvh::new(&row(8));
+        // Maps x from -0.5 to 5.5, y from -1 to 1 and z from -1 to 2 to [0, 1]
+        let view_proj = Mat4::from_scale(Vec3::new(1.0 / 6.0, 0.5, 1.0 / 3.0))
+            * Mat4::from_translation(Vec3::new(0.5, 1.0, 1.0));
+        let (mut visible, stats) = bvh.cull(view_proj);
+        visible.sort_unstable();
+        assert_eq!(visible, [0, 1, 2]);
+        assert!(stats.nodes_culled > 0);
+        assert!(stats.objects_tested < 8);
+    }
+}
@@ -0,0 +1,40 @@
+// Stream compaction: indices of the non-zero flags are written densely into
+// `indices`, with the survivor count and matching dispatch size in `result`.
+// `offsets` holds the exclusive scan of the flags, see scan.wgsl.
+
+struct Values {
+  values: array<u32>,
+}
+
+struct Total {
+  value: u32,
+}
+
+// Laid out as wgpu::util::DispatchIndirect followed by the element count
+struct CompactResult {
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
+  count: u32,
+}
+
+@group(0) @binding(0) var<storage, read> flags : Values;
+@group(0) @binding(1) var<storage, read> offsets : Values;
+@group(0) @binding(2) var<storage, read> total : Total;
+@group(0) @binding(3) var<storage, read_write> indices : Values;
+@group(0) @binding(4) var<storage, read_write> result : CompactResult;
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn scatter(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  if (global_id.x == 0u) {
+    result.dispatch_x = (total.value + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+    result.dispatch_y = 1u;
+    result.dispatch_z = 1u;
+    result.count = total.value;
+  }
+
+  if (global_id.x >= arrayLength(&flags.values) || flags.values[global_id.x] == 0u) {
+    return;
+  }
+  indices.values[offsets.values[global_id.x]] = global_id.x;
+}
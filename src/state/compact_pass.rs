@@ -0,0 +1,170 @@
+use super::{
+    scan_pass::{ScanBindings, ScanPass},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Compact");
+
+/// Size of the compaction result: indirect dispatch arguments followed by the count.
+pub const COMPACT_RESULT_SIZE: u64 = 4 * std::mem::size_of::<u32>() as u64;
+
+/// Generic stream compaction, turns a buffer of 0/1 u32 flags into a dense
+/// list of the indices of the set ones, along with the exact dispatch size
+/// to process them with `dispatch_workgroups_indirect`.
+pub struct CompactPass {
+    scan: ScanPass,
+    scatter_pipeline: wgpu::ComputePipeline,
+    workgroup_size: WorkgroupSize,
+}
+
+impl CompactPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&LABELS.of("Bind Group Layout")),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "compact.wgsl",
+            include_str!("compact.wgsl"),
+            workgroup_size,
+        );
+        let scatter_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.of("Scatter Pipeline")),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "scatter",
+        });
+        Self {
+            scan: ScanPass::new(device, shaders, workgroup_size),
+            scatter_pipeline,
+            workgroup_size,
+        }
+    }
+}
+
+/// Scratch and output buffers for compacting `len` flags.
+pub struct CompactBindings {
+    scan: ScanBindings,
+    bind_group: wgpu::BindGroup,
+    indices: wgpu::Buffer,
+    result: wgpu::Buffer,
+    len: u32,
+}
+
+impl CompactBindings {
+    /// `flags` must hold exactly `len` u32 values, each either 0 or 1.
+    pub fn new(
+        device: &wgpu::Device,
+        CompactPass {
+            scan,
+            scatter_pipeline,
+            ..
+        }: &CompactPass,
+        flags: &wgpu::Buffer,
+        len: u32,
+    ) -> Self {
+        let len = len.max(1);
+        let u32_size = std::mem::size_of::<u32>() as u64;
+        let storage = |label: &str, size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let offsets = storage(&LABELS.of("Offsets Buffer"), u32_size * len as u64);
+        let indices = storage(&LABELS.of("Indices Buffer"), u32_size * len as u64);
+        let result = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Result Buffer")),
+            size: COMPACT_RESULT_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let scan = ScanBindings::new(device, scan, flags, &offsets, len);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Bind Group")),
+            layout: &scatter_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: flags.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: scan.total().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: result.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            scan,
+            bind_group,
+            indices,
+            result,
+            len,
+        }
+    }
+
+    /// Dense list of surviving indices, only the first `count` are valid.
+    pub fn indices(&self) -> &wgpu::Buffer {
+        &self.indices
+    }
+
+    /// Indirect dispatch arguments covering the survivors with workgroups
+    /// of `WORKGROUP_SIZE`, followed by the survivor count.
+    pub fn result(&self) -> &wgpu::Buffer {
+        &self.result
+    }
+}
+
+impl<'a> CompactPass {
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a CompactBindings,
+    ) where
+        'a: 'pass,
+    {
+        self.scan.record(cpass, &bindings.scan);
+        cpass.set_pipeline(&self.scatter_pipeline);
+        cpass.set_bind_group(0, &bindings.bind_group, &[]);
+        cpass.dispatch_workgroups(self.workgroup_size.dispatch_size(bindings.len), 1, 1);
+    }
+}
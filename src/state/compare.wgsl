@@ -0,0 +1,82 @@
+// Compares the compute raster with the hardware reference, writing the result
+// into the color buffer so that it goes through the usual present.
+
+#include "color.wgsl"
+
+// RGB9E5 packed by raster.wgsl, see color.wgsl
+struct ColorBuffer {
+  values: array<u32>,
+}
+
+struct DepthBuffer {
+  values: array<u32>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct CompareUniform {
+  mode: u32,
+}
+
+let COMPARE_REFERENCE = 1u;
+let COMPARE_SPLIT = 2u;
+let COMPARE_DIFF = 3u;
+let CLEARED_DEPTH = 0xffffffffu;
+// Color differences are scaled up to stand out in the heatmap
+let DIFF_SCALE = 8u;
+
+@group(0) @binding(0) var<storage, read_write> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read_write> depth_buffer: DepthBuffer;
+@group(0) @binding(2) var reference: texture_2d<f32>;
+@group(1) @binding(0) var<uniform> screen_dims: Uniform;
+@group(1) @binding(1) var<uniform> params: CompareUniform;
+
+// Uncovered pixels keep the cleared depth and show the background
+fn write(index: u32, covered: bool, rgb: vec3<u32>) {
+  color_buffer.values[index] = pack_color(vec3<f32>(rgb) / 255.0);
+  depth_buffer.values[index] = select(CLEARED_DEPTH, 0u, covered);
+}
+
+// Split shows the compute raster on the left and the reference on the right.
+// The diff heatmap is black where both agree, yellow by how much the colors
+// differ, red where only the compute raster and blue where only the reference
+// covers the pixel.
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn compare(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let width = u32(screen_dims.width);
+  let index = global_id.x;
+  if (index >= width * u32(screen_dims.height)) {
+    return;
+  }
+  let x = index % width;
+  let y = index / width;
+  if (params.mode == COMPARE_SPLIT && x < width / 2u) {
+    return;
+  }
+
+  let texel = textureLoad(reference, vec2<i32>(i32(x), i32(y)), 0);
+  let reference_covered = texel.a > 0.0;
+  let reference_rgb = vec3<u32>(round(texel.rgb * 255.0));
+  if (params.mode != COMPARE_DIFF) {
+    write(index, reference_covered, reference_rgb);
+    return;
+  }
+
+  let covered = depth_buffer.values[index] != CLEARED_DEPTH;
+  if (!covered && !reference_covered) {
+    return;
+  }
+  if (covered != reference_covered) {
+    write(index, true, select(vec3<u32>(0u, 0u, 255u), vec3<u32>(255u, 0u, 0u), covered));
+    return;
+  }
+  // Compared as the reference texture quantizes it
+  let color = clamp(unpack_color(color_buffer.values[index]), vec3<f32>(0.0), vec3<f32>(1.0));
+  let rgb = vec3<u32>(round(color * 255.0));
+  let diff = max(rgb, reference_rgb) - min(rgb, reference_rgb);
+  let heat = min(max(max(diff.r, diff.g), diff.b) * DIFF_SCALE, 255u);
+  write(index, true, vec3<u32>(heat, heat, 0u));
+}
@@ -0,0 +1,246 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass, Targets},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Compare");
+
+/// What the compare pass shows of the hardware reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompareMode {
+    /// Compute raster only, the reference isn't drawn
+    Off,
+    /// Reference only
+    Reference,
+    /// Compute raster on the left half, reference on the right
+    Split,
+    /// Heatmap of the differences between the two
+    Diff,
+}
+
+impl CompareMode {
+    pub fn next(self) -> Self {
+        match self {
+            CompareMode::Off => CompareMode::Reference,
+            CompareMode::Reference => CompareMode::Split,
+            CompareMode::Split => CompareMode::Diff,
+            CompareMode::Diff => CompareMode::Off,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct CompareUniform {
+    mode: u32,
+    _padding: [u32; 3],
+}
+
+impl CompareUniform {
+    pub fn new(mode: CompareMode) -> Self {
+        Self {
+            mode: mode as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Combines the color buffer with the hardware reference according to the
+/// compare mode, catching fill rule and precision bugs of the compute raster.
+pub struct ComparePass {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ComparePass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[
+                    storage_entry(0),
+                    storage_entry(1),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "compare.wgsl",
+            include_str!("compare.wgsl"),
+            workgroup_size,
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "compare",
+        });
+        Self { pipeline }
+    }
+}
+
+pub struct CompareBindings {
+    buffers: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+}
+
+impl CompareBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        pass: &ComparePass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        reference: &wgpu::TextureView,
+        uniform: &wgpu::Buffer,
+        compare_uniform: &wgpu::Buffer,
+    ) -> Self {
+        let ComparePass { pipeline } = pass;
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: compare_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        Self {
+            buffers: Self::buffers_bind_group(device, pass, color_buffer, depth_buffer, reference),
+            uniform,
+        }
+    }
+
+    /// `reference` is the target of the reference pass, rebound first.
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        pass: &ComparePass,
+        targets: &Targets,
+        reference: &wgpu::TextureView,
+    ) {
+        self.buffers = Self::buffers_bind_group(
+            device,
+            pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            reference,
+        );
+    }
+
+    fn buffers_bind_group(
+        device: &wgpu::Device,
+        ComparePass { pipeline }: &ComparePass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        reference: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(reference),
+                },
+            ],
+        })
+    }
+}
+
+impl<'a> ComparePass {
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a CompareBindings,
+        dispatch_size: u32,
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        cpass.set_bind_group(1, &bindings.uniform, &[]);
+        cpass.dispatch_workgroups(dispatch_size, 1, 1);
+    }
+}
+
+impl Pass for Node<'_, ComparePass, CompareBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Color Buffer", "Depth Buffer", "Reference"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Color Buffer"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass
+            .record(&mut cpass, self.bindings, frame.pixel_dispatch());
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![frame.occupancy(self.name(), frame.pixels())]
+    }
+}
@@ -0,0 +1,292 @@
+//! Rasterizer on the CPU, the last resort of `--output` when there's no
+//! adapter at all, hardware or software. It follows raster.wgsl: the same
+//! projection, subpixel snapping and top-left fill rule, the Lambert view lit
+//! by the sun. The image matches the first frame of the compute raster with
+//! the default settings, the other views, the shadows, the sky and the
+//! non-linear projections are left to the GPU.
+
+use glam::{Mat3, Vec3, Vec4};
+use image::{Rgba, RgbaImage};
+
+use super::util::Mesh;
+use super::{BACKGROUNDS, LIGHT_DIR};
+use crate::camera::Camera;
+
+/// Vertices are snapped to 1/256 of a pixel, see `SUBPIXEL_SCALE` of raster.wgsl.
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_SCALE: i64 = 1 << SUBPIXEL_BITS;
+/// Keeps the differences of snapped coordinates within i32, as raster.wgsl.
+const SNAP_LIMIT: f32 = 536870912.0;
+/// Ambient term of `lambert` in raster.wgsl.
+const AMBIENT: f32 = 0.1;
+
+/// Color, depth and fragment count per pixel, laid out as the buffers of the
+/// compute raster.
+struct Target {
+    width: u32,
+    height: u32,
+    color: Vec<Vec3>,
+    depth: Vec<f32>,
+    fragments: Vec<u32>,
+}
+
+impl Target {
+    fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Self {
+            width,
+            height,
+            color: vec![Vec3::ZERO; len],
+            depth: vec![f32::INFINITY; len],
+            fragments: vec![0; len],
+        }
+    }
+}
+
+/// Projected vertex: screen space xy, NDC depth and clip space w, along with
+/// its barycentrics in the triangle of the mesh.
+#[derive(Clone, Copy)]
+struct Corner {
+    screen: Vec4,
+    bc: Vec3,
+}
+
+/// Renders `mesh` seen by `camera` into an image of `width` x `height`, the
+/// pixels as `Renderer::render_to_image` would show them.
+pub fn render_on_cpu(mesh: &Mesh, camera: &Camera, width: u32, height: u32) -> RgbaImage {
+    let target = raster(mesh, camera, width, height);
+    let background = Vec3::from(BACKGROUNDS[0]);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let index = (x + y * width) as usize;
+        let color = match target.fragments[index] {
+            0 => background,
+            _ => target.color[index].min(Vec3::ONE),
+        };
+        let [r, g, b] = linear_to_srgb(color)
+            .to_array()
+            .map(|c| (c * 255.0).round() as u8);
+        Rgba([r, g, b, 255])
+    })
+}
+
+fn raster(mesh: &Mesh, camera: &Camera, width: u32, height: u32) -> Target {
+    let view_proj = camera.build_view_projection_matrix();
+    let mut target = Target::new(width, height);
+    for (triangle, corners) in mesh.indices.chunks_exact(3).enumerate() {
+        let clip = [corners[0], corners[1], corners[2]].map(|i| {
+            let p = mesh.vertices[i as usize].position();
+            view_proj * p.extend(1.0)
+        });
+        let polygon = clip_near(clip);
+        for i in 2..polygon.len() {
+            let fan = [polygon[0], polygon[i - 1], polygon[i]].map(|(clip, bc)| Corner {
+                screen: project(clip, width, height),
+                bc,
+            });
+            raster_triangle(&mut target, mesh, triangle, fan);
+        }
+    }
+    target
+}
+
+/// Sutherland-Hodgman clipping against the near plane, where clip space z
+/// is zero, as `raster_clipped`. The vertices are kept with their
+/// barycentrics in the original triangle.
+fn clip_near(clip: [Vec4; 3]) -> Vec<(Vec4, Vec3)> {
+    let corners = [Vec3::X, Vec3::Y, Vec3::Z];
+    let mut polygon = Vec::with_capacity(4);
+    for i in 0..3 {
+        let j = (i + 1) % 3;
+        let (a, b) = (clip[i], clip[j]);
+        if a.z >= 0.0 {
+            polygon.push((a, corners[i]));
+        }
+        if (a.z >= 0.0) != (b.z >= 0.0) {
+            let t = a.z / (a.z - b.z);
+            polygon.push((a.lerp(b, t), corners[i].lerp(corners[j], t)));
+        }
+    }
+    polygon
+}
+
+fn project(clip: Vec4, width: u32, height: u32) -> Vec4 {
+    let ndc = clip / clip.w;
+    Vec4::new(ndc.x * width as f32, ndc.y * height as f32, ndc.z, clip.w)
+}
+
+fn snap(v: Vec4) -> [i64; 2] {
+    [v.x, v.y].map(|c| {
+        (c * SUBPIXEL_SCALE as f32)
+            .round()
+            .clamp(-SNAP_LIMIT, SNAP_LIMIT) as i64
+    })
+}
+
+fn edge_function(a: [i64; 2], b: [i64; 2], p: [i64; 2]) -> i64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Samples exactly on top and left edges are inside, as in Direct3D.
+fn is_top_left(a: [i64; 2], b: [i64; 2]) -> bool {
+    let (x, y) = (b[0] - a[0], b[1] - a[1]);
+    y < 0 || (y == 0 && x > 0)
+}
+
+fn covers(value: i64, top_left: bool) -> bool {
+    value > 0 || (value == 0 && top_left)
+}
+
+fn raster_triangle(target: &mut Target, mesh: &Mesh, triangle: usize, corners: [Corner; 3]) {
+    let [a, mut b, mut c] = corners.map(|corner| snap(corner.screen));
+    let mut area = edge_function(a, b, c);
+    // Degenerate once snapped, it covers no sample
+    if area == 0 {
+        return;
+    }
+    let material = &mesh.materials[mesh.triangles[triangle].material as usize];
+    // Counter-clockwise triangles are front facing, as in glTF
+    let back_facing = area < 0;
+    if back_facing && !material.is_double_sided() {
+        return;
+    }
+    let mut corners = corners;
+    if back_facing {
+        corners.swap(1, 2);
+        std::mem::swap(&mut b, &mut c);
+        area = -area;
+    }
+
+    let first =
+        |axis: usize| (a[axis].min(b[axis]).min(c[axis]) + SUBPIXEL_SCALE - 1) >> SUBPIXEL_BITS;
+    let last = |axis: usize| a[axis].max(b[axis]).max(c[axis]) >> SUBPIXEL_BITS;
+    let (start_x, end_x) = (first(0).max(0), last(0).min(target.width as i64 - 1));
+    let (start_y, end_y) = (first(1).max(0), last(1).min(target.height as i64 - 1));
+
+    let edges = [(b, c), (c, a), (a, b)];
+    let top_left = edges.map(|(from, to)| is_top_left(from, to));
+    for x in start_x..=end_x {
+        for y in start_y..=end_y {
+            let sample = [x * SUBPIXEL_SCALE, y * SUBPIXEL_SCALE];
+            let w = edges.map(|(from, to)| edge_function(from, to, sample));
+            if !(0..3).all(|i| covers(w[i], top_left[i])) {
+                continue;
+            }
+            let bc = Vec3::new(w[0] as f32, w[1] as f32, w[2] as f32) / area as f32;
+            draw_fragment(target, mesh, triangle, &corners, back_facing, (x, y), bc);
+        }
+    }
+}
+
+/// `bc` are the screen space barycentrics of the sample in `corners`.
+fn draw_fragment(
+    target: &mut Target,
+    mesh: &Mesh,
+    triangle: usize,
+    corners: &[Corner; 3],
+    back_facing: bool,
+    (x, y): (i64, i64),
+    bc: Vec3,
+) {
+    // Attributes are linear in 1/w screen space, not in screen space
+    let w = Vec3::from(corners.map(|corner| corner.screen.w));
+    let bc_over_w = bc / w;
+    let inv_w = bc_over_w.x + bc_over_w.y + bc_over_w.z;
+    let triangle_bc =
+        Mat3::from_cols(corners[0].bc, corners[1].bc, corners[2].bc) * (bc_over_w / inv_w);
+
+    let vertices = [0, 1, 2].map(|i| &mesh.vertices[mesh.indices[triangle * 3 + i] as usize]);
+    let interpolate = |attribute: &dyn Fn(usize) -> Vec4| {
+        (0..3).fold(Vec4::ZERO, |sum, i| sum + attribute(i) * triangle_bc[i])
+    };
+    let color = interpolate(&|i| vertices[i].color());
+    let material = &mesh.materials[mesh.triangles[triangle].material as usize];
+    if material.is_masked(color.w) {
+        return;
+    }
+    let depth = bc.dot(Vec3::from(corners.map(|corner| corner.screen.z)));
+    if !(0.0..=1.0).contains(&depth) {
+        return;
+    }
+
+    let index = (x as u32 + y as u32 * target.width) as usize;
+    target.fragments[index] += 1;
+    if depth >= target.depth[index] {
+        return;
+    }
+    target.depth[index] = depth;
+
+    let mut n = interpolate(&|i| vertices[i].normal().extend(0.0))
+        .truncate()
+        .normalize_or_zero();
+    if back_facing {
+        n = -n;
+    }
+    let lambert = AMBIENT + (1.0 - AMBIENT) * n.dot(LIGHT_DIR.normalize()).max(0.0);
+    target.color[index] = material.base_color() * color.truncate() * lambert;
+}
+
+/// Exact sRGB transfer function, as `linear_to_srgb` of color.wgsl.
+fn linear_to_srgb(color: Vec3) -> Vec3 {
+    color
+        .clamp(Vec3::ZERO, Vec3::ONE)
+        .to_array()
+        .map(|c| match c <= 0.0031308 {
+            true => c * 12.92,
+            false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+        })
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::state::TestScene;
+
+    const WIDTH: u32 = 160;
+    const HEIGHT: u32 = 120;
+
+    /// Camera looking down at the scenes, centered on the screen.
+    fn camera() -> Camera {
+        let aspect = WIDTH as f32 / HEIGHT as f32;
+        let mut camera = Camera::new(3.0, 1.0, 0.3, Vec3::ZERO, aspect);
+        camera.pan(0.5 * aspect, -0.5);
+        camera
+    }
+
+    #[test]
+    fn shared_edges_are_covered_once() {
+        for scene in [TestScene::SharedEdge, TestScene::Fan] {
+            let target = raster(&scene.mesh(), &camera(), WIDTH, HEIGHT);
+            let covered = target.fragments.iter().filter(|&&count| count > 0).count();
+            assert!(covered > 0, "{} is out of view", scene);
+            assert!(
+                target.fragments.iter().all(|&count| count <= 1),
+                "{} has pixels covered twice",
+                scene
+            );
+        }
+    }
+
+    #[test]
+    fn uncovered_pixels_show_the_background() {
+        let image = render_on_cpu(&TestScene::Fan.mesh(), &camera(), WIDTH, HEIGHT);
+        assert_eq!(*image.get_pixel(WIDTH - 1, HEIGHT - 1), Rgba([255; 4]));
+        assert!(image.pixels().any(|pixel| *pixel != Rgba([255; 4])));
+    }
+
+    #[test]
+    fn clips_against_the_near_plane() {
+        let clip = [
+            Vec4::new(0.0, 0.0, 1.0, 1.0),
+            Vec4::new(1.0, 0.0, -1.0, 1.0),
+            Vec4::new(0.0, 1.0, 1.0, 1.0),
+        ];
+        let polygon = clip_near(clip);
+        assert_eq!(polygon.len(), 4);
+        assert!(polygon.iter().all(|(clip, _)| clip.z >= 0.0));
+        assert_eq!(polygon[1].1, Vec3::new(0.5, 0.5, 0.0));
+        assert!(clip_near(clip.map(|v| Vec4::new(v.x, v.y, -1.0, v.w))).is_empty());
+    }
+}
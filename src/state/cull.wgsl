@@ -0,0 +1,177 @@
+// Frustum and occlusion culling, flags every triangle with 1 when it may be
+// visible and 0 otherwise. The flags are then compacted into the list of
+// triangles to raster. Every workgroup handles a meshlet, which is tested as
+// a whole before its triangles.
+
+#include "camera.wgsl"
+#include "vertex.wgsl"
+
+struct Values {
+  values: array<u32>,
+}
+
+struct Meshlet {
+  center: vec3<f32>,
+  radius: f32,
+  cone_axis: vec3<f32>,
+  cone_cutoff: f32,
+  triangle_offset: u32,
+  triangle_count: u32,
+}
+
+struct MeshletBuffer {
+  values: array<Meshlet>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct CullUniform {
+  enabled: u32,
+  // Also test against the depth pyramid, see hiz.wgsl
+  occlusion: u32,
+  hiz_levels: u32,
+  // Test the bounds and normal cone of the meshlets
+  meshlets: u32,
+}
+
+// Keeps triangles whose depth only differs by rounding from the depth pass
+let DEPTH_EPSILON = 1e-5;
+
+@group(0) @binding(0) var<storage, read> vertex_buffer : VertexBuffer;
+@group(0) @binding(1) var<storage, read> index_buffer : Values;
+@group(0) @binding(2) var<storage, read_write> flags : Values;
+@group(0) @binding(3) var<storage, read> hiz : Values;
+@group(0) @binding(4) var<storage, read> meshlets : MeshletBuffer;
+@group(1) @binding(0) var<uniform> camera : Camera;
+@group(1) @binding(1) var<uniform> cull : CullUniform;
+@group(1) @binding(2) var<uniform> screen_dims : Uniform;
+
+fn clip_position(corner: u32) -> vec4<f32> {
+  let v = vertex_buffer.values[index_buffer.values[corner]];
+  return camera.view_proj * vec4<f32>(position(v), 1.0);
+}
+
+// A triangle is outside when all of its vertices are beyond the same plane
+fn outside_frustum(c1: vec4<f32>, c2: vec4<f32>, c3: vec4<f32>) -> bool {
+  let w = vec3<f32>(c1.w, c2.w, c3.w);
+  let x = vec3<f32>(c1.x, c2.x, c3.x);
+  let y = vec3<f32>(c1.y, c2.y, c3.y);
+  let z = vec3<f32>(c1.z, c2.z, c3.z);
+  return all(x < -w) || all(x > w) || all(y < -w) || all(y > w) || all(z < vec3<f32>(0.0)) || all(z > w);
+}
+
+// Farthest depth of the pyramid texels of `level` overlapping the pixel rect
+fn farthest_depth(level: u32, min_pixel: vec2<u32>, max_pixel: vec2<u32>) -> u32 {
+  var size = vec2<u32>(u32(screen_dims.width), u32(screen_dims.height));
+  var offset = 0u;
+  for (var i = 0u; i <= level; i = i + 1u) {
+    size = (size + 1u) / 2u;
+    if (i < level) {
+      offset = offset + size.x * size.y;
+    }
+  }
+
+  let lo = min(min_pixel >> vec2<u32>(level + 1u), size - 1u);
+  let hi = min(max_pixel >> vec2<u32>(level + 1u), size - 1u);
+  var farthest = 0u;
+  for (var y = lo.y; y <= hi.y; y = y + 1u) {
+    for (var x = lo.x; x <= hi.x; x = x + 1u) {
+      farthest = max(farthest, hiz.values[offset + x + y * size.x]);
+    }
+  }
+  return farthest;
+}
+
+// A triangle is occluded when its nearest point is behind the farthest depth
+// of the pyramid over its screen bounds. The bounds are mapped the same way
+// as the rasterizer does, grown by a pixel to cover the sample jitter.
+fn occluded(c1: vec4<f32>, c2: vec4<f32>, c3: vec4<f32>) -> bool {
+  if (c1.w <= 0.0 || c2.w <= 0.0 || c3.w <= 0.0) {
+    return false;
+  }
+  let p1 = c1.xyz / c1.w;
+  let p2 = c2.xyz / c2.w;
+  let p3 = c3.xyz / c3.w;
+  let nearest = min(min(p1.z, p2.z), p3.z);
+  if (nearest < 0.0) {
+    return false;
+  }
+
+  let dims = vec2<f32>(screen_dims.width, screen_dims.height);
+  let lo = clamp(min(min(p1.xy, p2.xy), p3.xy) * dims - 1.0, vec2<f32>(0.0), dims - 1.0);
+  let hi = clamp(max(max(p1.xy, p2.xy), p3.xy) * dims + 1.0, vec2<f32>(0.0), dims - 1.0);
+  // Coarsest level where the rect overlaps at most 2x2 texels
+  let extent = max(hi.x - lo.x, hi.y - lo.y);
+  let level = min(u32(max(ceil(log2(max(extent, 1.0))) - 1.0, 0.0)), cull.hiz_levels - 1u);
+
+  let farthest = bitcast<f32>(farthest_depth(level, vec2<u32>(lo), vec2<u32>(hi)));
+  return nearest - DEPTH_EPSILON > farthest;
+}
+
+// Non-linear projections see around the camera, there is no frustum to test
+fn triangle_visible(triangle_id: u32) -> bool {
+  if (camera.projection != PROJECTION_PERSPECTIVE) {
+    return true;
+  }
+  let index = triangle_id * 3u;
+  let c1 = clip_position(index);
+  let c2 = clip_position(index + 1u);
+  let c3 = clip_position(index + 2u);
+  if (cull.enabled != 0u && outside_frustum(c1, c2, c3)) {
+    return false;
+  }
+  return cull.occlusion == 0u || cull.hiz_levels == 0u || !occluded(c1, c2, c3);
+}
+
+// The sphere is outside when it's entirely behind one of the planes, which
+// are taken from the rows of the projection and point inwards
+fn sphere_outside_frustum(center: vec3<f32>, radius: f32) -> bool {
+  let m = transpose(camera.view_proj);
+  var planes = array<vec4<f32>, 6>(m[3] + m[0], m[3] - m[0], m[3] + m[1], m[3] - m[1], m[2], m[3] - m[2]);
+  for (var i = 0u; i < 6u; i = i + 1u) {
+    let plane = planes[i];
+    if (dot(plane.xyz, center) + plane.w < -radius * length(plane.xyz)) {
+      return true;
+    }
+  }
+  return false;
+}
+
+// The eye is recovered from the view matrix as `view_pos` doesn't follow the turntable
+fn eye_position() -> vec3<f32> {
+  let rotation = mat3x3<f32>(camera.view[0].xyz, camera.view[1].xyz, camera.view[2].xyz);
+  return -(transpose(rotation) * camera.view[3].xyz);
+}
+
+// Every triangle of the normal cone faces away from the eye
+fn facing_away(meshlet: Meshlet) -> bool {
+  let to_center = meshlet.center - eye_position();
+  return dot(to_center, meshlet.cone_axis) >= meshlet.cone_cutoff * length(to_center) + meshlet.radius;
+}
+
+fn meshlet_visible(meshlet: Meshlet) -> bool {
+  if (cull.meshlets == 0u || camera.projection != PROJECTION_PERSPECTIVE) {
+    return true;
+  }
+  if (facing_away(meshlet)) {
+    return false;
+  }
+  return cull.enabled == 0u || !sphere_outside_frustum(meshlet.center, meshlet.radius);
+}
+
+// Meshlets hold at most 124 triangles, see `MESHLET_TRIANGLES`
+@compute @workgroup_size(128, 1)
+fn cull_meshlets(
+  @builtin(workgroup_id) workgroup_id: vec3<u32>,
+  @builtin(local_invocation_index) local_index: u32,
+) {
+  let meshlet = meshlets.values[workgroup_id.x];
+  if (local_index >= meshlet.triangle_count) {
+    return;
+  }
+  let triangle_id = meshlet.triangle_offset + local_index;
+  flags.values[triangle_id] = u32(meshlet_visible(meshlet) && triangle_visible(triangle_id));
+}
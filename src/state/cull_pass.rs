@@ -0,0 +1,349 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{
+    compact_pass::{CompactBindings, CompactPass},
+    hiz_pass::{HiZBindings, HiZPass},
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass, Stage},
+    shaders::Shaders,
+    util::{Labels, Meshlet, WorkgroupSize},
+};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+const LABELS: Labels = Labels::new("Cull");
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct CullUniform {
+    enabled: u32,
+    occlusion: u32,
+    hiz_levels: u32,
+    meshlets: u32,
+}
+
+impl CullUniform {
+    /// `enabled` tests the triangles against the frustum, `occlusion` against
+    /// the depth pyramid and `meshlets` culls whole meshlets first.
+    pub fn new(enabled: bool, occlusion: bool, meshlets: bool, hiz_levels: u32) -> Self {
+        Self {
+            enabled: enabled as u32,
+            occlusion: occlusion as u32,
+            hiz_levels,
+            meshlets: meshlets as u32,
+        }
+    }
+}
+
+/// Frustum culling pre-pass, the surviving triangles are compacted into a
+/// list along with the indirect dispatch arguments covering them. Running it
+/// again after the depth pass with occlusion enabled shrinks the list to the
+/// triangles not hidden behind the depth pyramid. The work is dispatched
+/// per meshlet, skipping the triangles of meshlets outside the frustum or
+/// facing away from the camera.
+pub struct CullPass {
+    pipeline: wgpu::ComputePipeline,
+    compact: CompactPass,
+}
+
+impl CullPass {
+    /// `workgroup_size` is the one of the compaction, the culling itself
+    /// runs a workgroup per meshlet.
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let geometry_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Geometry Bind Group Layout")),
+                entries: &[
+                    storage_entry(0, true),
+                    storage_entry(1, true),
+                    storage_entry(2, false),
+                    storage_entry(3, true),
+                    storage_entry(4, true),
+                ],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1), uniform_entry(2)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&geometry_bind_group_layout, &uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.module(device, "cull.wgsl", include_str!("cull.wgsl"));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cull_meshlets",
+        });
+        Self {
+            pipeline,
+            compact: CompactPass::new(device, shaders, workgroup_size),
+        }
+    }
+}
+
+/// Of `cull_meshlets` in cull.wgsl, a workgroup per meshlet.
+pub const CULL_WORKGROUP_SIZE: u32 = 128;
+
+pub struct CullBindings {
+    geometry: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+    occlusion_uniform: wgpu::BindGroup,
+    compact: CompactBindings,
+    flags: wgpu::Buffer,
+    meshlets: wgpu::Buffer,
+    meshlet_count: u32,
+}
+
+impl CullBindings {
+    /// `occlusion_uniform` is used by the culling after the depth pass.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pass: &CullPass,
+        vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+        hiz_pyramid: &wgpu::Buffer,
+        camera_uniform: &wgpu::Buffer,
+        screen_uniform: &wgpu::Buffer,
+        cull_uniform: &wgpu::Buffer,
+        occlusion_uniform: &wgpu::Buffer,
+        meshlets: &[Meshlet],
+        triangle_count: u32,
+    ) -> Self {
+        let CullPass { pipeline, compact } = pass;
+        let flags = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Flags Buffer")),
+            size: std::mem::size_of::<u32>() as u64 * triangle_count.max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let meshlet_count = meshlets.len() as u32;
+        let meshlets = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Meshlet Buffer")),
+            contents: bytemuck::cast_slice(meshlets),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let geometry = Self::geometry_bind_group(
+            device,
+            pass,
+            vertex_buffer,
+            index_buffer,
+            &flags,
+            hiz_pyramid,
+            &meshlets,
+        );
+        let uniform_bind_group = |cull_uniform: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group")),
+                layout: &pipeline.get_bind_group_layout(1),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: camera_uniform.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: cull_uniform.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: screen_uniform.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let compact = CompactBindings::new(device, compact, &flags, triangle_count);
+        Self {
+            geometry,
+            uniform: uniform_bind_group(cull_uniform),
+            occlusion_uniform: uniform_bind_group(occlusion_uniform),
+            compact,
+            flags,
+            meshlets,
+            meshlet_count,
+        }
+    }
+
+    /// The depth pyramid is reallocated along with the depth buffer.
+    pub fn update_hiz_pyramid(
+        &mut self,
+        device: &wgpu::Device,
+        pass: &CullPass,
+        vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+        hiz_pyramid: &wgpu::Buffer,
+    ) {
+        self.geometry = Self::geometry_bind_group(
+            device,
+            pass,
+            vertex_buffer,
+            index_buffer,
+            &self.flags,
+            hiz_pyramid,
+            &self.meshlets,
+        );
+    }
+
+    fn geometry_bind_group(
+        device: &wgpu::Device,
+        CullPass { pipeline, .. }: &CullPass,
+        vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+        flags: &wgpu::Buffer,
+        hiz_pyramid: &wgpu::Buffer,
+        meshlets: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Geometry Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: index_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: flags.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: hiz_pyramid.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: meshlets.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Ids of the triangles that survived culling.
+    pub fn visible(&self) -> &wgpu::Buffer {
+        self.compact.indices()
+    }
+
+    /// A dispatch of the culling, a workgroup per meshlet.
+    pub fn occupancy(&self, name: &'static str, triangle_count: u32) -> PassOccupancy {
+        PassOccupancy {
+            name,
+            workgroup_size: CULL_WORKGROUP_SIZE,
+            workgroups: self.meshlet_count,
+            items: triangle_count as u64,
+        }
+    }
+
+    /// Indirect dispatch arguments for the visible triangles followed by their count.
+    pub fn result(&self) -> &wgpu::Buffer {
+        self.compact.result()
+    }
+}
+
+impl<'a> CullPass {
+    pub fn record<'pass>(&'a self, cpass: &mut wgpu::ComputePass<'pass>, bindings: &'a CullBindings)
+    where
+        'a: 'pass,
+    {
+        self.record_with(cpass, bindings, &bindings.uniform);
+    }
+
+    /// Culls again against the depth pyramid built from the depth pass,
+    /// overwriting the visible list of `record`.
+    pub fn record_occlusion<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a CullBindings,
+    ) where
+        'a: 'pass,
+    {
+        self.record_with(cpass, bindings, &bindings.occlusion_uniform);
+    }
+
+    fn record_with<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a CullBindings,
+        uniform: &'a wgpu::BindGroup,
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.geometry, &[]);
+        cpass.set_bind_group(1, uniform, &[]);
+        cpass.dispatch_workgroups(bindings.meshlet_count, 1, 1);
+        self.compact.record(cpass, &bindings.compact);
+    }
+}
+
+/// Occlusion culling between the depth and the color pass of the raster.
+#[derive(Clone, Copy)]
+pub struct Occlusion<'a> {
+    pub hiz_pass: &'a HiZPass,
+    pub hiz_bindings: &'a HiZBindings,
+    pub cull_pass: &'a CullPass,
+}
+
+impl<'a> Occlusion<'a> {
+    pub fn record<'pass>(&self, cpass: &mut wgpu::ComputePass<'pass>, cull: &'a CullBindings)
+    where
+        'a: 'pass,
+    {
+        self.hiz_pass.record(cpass, self.hiz_bindings);
+        self.cull_pass.record_occlusion(cpass, cull);
+    }
+}
+
+impl Pass for Node<'_, CullPass, CullBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Vertices", "Indices", "Camera"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Visible Triangles"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass.record(&mut cpass, self.bindings);
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Raster
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![self.bindings.occupancy(self.name(), frame.triangle_count)]
+    }
+}
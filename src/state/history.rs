@@ -0,0 +1,91 @@
+/// Most snapshots kept to undo, the oldest are dropped first.
+const MAX_SNAPSHOTS: usize = 64;
+
+/// Undo and redo stacks of whole snapshots of some state.
+#[derive(Debug)]
+pub struct History<T> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl<T> History<T> {
+    /// Keeps the state from before an edit, the undone edits can't be
+    /// redone anymore.
+    pub fn record(&mut self, before: T) {
+        if self.undo.len() == MAX_SNAPSHOTS {
+            self.undo.remove(0);
+        }
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    /// Swaps `current` for the state before the last edit.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Swaps `current` for the state after the last undone edit.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let next = self.redo.pop()?;
+        self.undo.push(current);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_and_redo_swap_the_snapshots() {
+        let mut history = History::default();
+        history.record(0);
+        history.record(1);
+        assert_eq!(history.undo(2), Some(1));
+        assert_eq!(history.undo(1), Some(0));
+        assert_eq!(history.undo(0), None);
+        assert_eq!(history.redo(0), Some(1));
+        assert_eq!(history.redo(1), Some(2));
+        assert_eq!(history.redo(2), None);
+    }
+
+    #[test]
+    fn an_edit_truncates_the_redo() {
+        let mut history = History::default();
+        history.record(0);
+        history.record(1);
+        assert_eq!(history.undo(2), Some(1));
+        // Edited from 1 into 3, 2 is gone
+        history.record(1);
+        assert_eq!(history.redo(3), None);
+        assert_eq!(history.undo(3), Some(1));
+        assert_eq!(history.undo(1), Some(0));
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots() {
+        let mut history = History::default();
+        for state in 0..MAX_SNAPSHOTS + 10 {
+            history.record(state);
+        }
+        let mut current = MAX_SNAPSHOTS + 10;
+        let mut undone = 0;
+        while let Some(previous) = history.undo(current) {
+            current = previous;
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_SNAPSHOTS);
+        assert_eq!(current, 10);
+    }
+}
@@ -0,0 +1,44 @@
+// Hierarchical depth pyramid, every texel holds the farthest depth of the 2x2
+// texels below it. Level 0 is built from the depth buffer at half its size,
+// all levels are packed one after another in the same buffer.
+
+struct Values {
+  values: array<u32>,
+}
+
+struct HiZLevel {
+  src_width: u32,
+  src_height: u32,
+  src_offset: u32,
+  dst_offset: u32,
+  // Level 0 reads the depth buffer instead of the pyramid
+  from_depth: u32,
+}
+
+@group(0) @binding(0) var<storage, read> depth_buffer: Values;
+@group(0) @binding(1) var<storage, read_write> pyramid: Values;
+@group(1) @binding(0) var<uniform> level: HiZLevel;
+
+// Depth bits compare like the floats as all depths are positive
+fn load(x: u32, y: u32) -> u32 {
+  let index = min(x, level.src_width - 1u) + min(y, level.src_height - 1u) * level.src_width;
+  if (level.from_depth != 0u) {
+    return depth_buffer.values[index];
+  }
+  return pyramid.values[level.src_offset + index];
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn build_hiz(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let dst_width = (level.src_width + 1u) / 2u;
+  let dst_height = (level.src_height + 1u) / 2u;
+  if (global_id.x >= dst_width * dst_height) {
+    return;
+  }
+
+  // Odd sizes clamp to the last row and column, the edge texels cover them
+  let x = (global_id.x % dst_width) * 2u;
+  let y = (global_id.x / dst_width) * 2u;
+  let farthest = max(max(load(x, y), load(x + 1u, y)), max(load(x, y + 1u), load(x + 1u, y + 1u)));
+  pyramid.values[level.dst_offset + global_id.x] = farthest;
+}
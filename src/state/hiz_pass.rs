@@ -0,0 +1,194 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("HiZ");
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct HiZLevel {
+    src_width: u32,
+    src_height: u32,
+    src_offset: u32,
+    dst_offset: u32,
+    from_depth: u32,
+    _padding: [u32; 3],
+}
+
+/// Sizes of the pyramid levels for a `width` x `height` depth buffer, down to 1x1.
+pub fn hiz_level_sizes(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let mut sizes = vec![];
+    let (mut width, mut height) = (width, height);
+    while width > 1 || height > 1 {
+        width = width.div_ceil(2);
+        height = height.div_ceil(2);
+        sizes.push((width, height));
+    }
+    sizes
+}
+
+/// Builds the hierarchical depth pyramid used by the occlusion culling.
+pub struct HiZPass {
+    pipeline: wgpu::ComputePipeline,
+    workgroup_size: WorkgroupSize,
+}
+
+impl HiZPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[storage_entry(0, true), storage_entry(1, false)],
+            });
+        let level_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Level Bind Group Layout")),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &level_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader =
+            shaders.compute_module(device, "hiz.wgsl", include_str!("hiz.wgsl"), workgroup_size);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "build_hiz",
+        });
+        Self {
+            pipeline,
+            workgroup_size,
+        }
+    }
+}
+
+/// The pyramid of a depth buffer with one bind group per level.
+pub struct HiZBindings {
+    pyramid: wgpu::Buffer,
+    buffers: wgpu::BindGroup,
+    levels: Vec<(wgpu::BindGroup, u32)>,
+}
+
+impl HiZBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        HiZPass {
+            pipeline,
+            workgroup_size,
+        }: &HiZPass,
+        depth_buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let sizes = hiz_level_sizes(width, height);
+        let texels: u32 = sizes.iter().map(|(w, h)| w * h).sum();
+        let pyramid = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Pyramid Buffer")),
+            size: std::mem::size_of::<u32>() as u64 * texels.max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let buffers = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: pyramid.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut levels = vec![];
+        let (mut src_width, mut src_height, mut src_offset) = (width, height, 0);
+        let mut dst_offset = 0;
+        for (i, &(dst_width, dst_height)) in sizes.iter().enumerate() {
+            let level = HiZLevel {
+                src_width,
+                src_height,
+                src_offset,
+                dst_offset,
+                from_depth: (i == 0) as u32,
+                _padding: [0; 3],
+            };
+            let uniform = device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(&LABELS.of("Level Uniform Buffer")),
+                contents: bytemuck::bytes_of(&level),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&LABELS.of("Level Bind Group")),
+                layout: &pipeline.get_bind_group_layout(1),
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                }],
+            });
+            levels.push((
+                bind_group,
+                workgroup_size.dispatch_size(dst_width * dst_height),
+            ));
+
+            (src_width, src_height, src_offset) = (dst_width, dst_height, dst_offset);
+            dst_offset += dst_width * dst_height;
+        }
+
+        Self {
+            pyramid,
+            buffers,
+            levels,
+        }
+    }
+
+    pub fn pyramid(&self) -> &wgpu::Buffer {
+        &self.pyramid
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+}
+
+impl<'a> HiZPass {
+    pub fn record<'pass>(&'a self, cpass: &mut wgpu::ComputePass<'pass>, bindings: &'a HiZBindings)
+    where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        for (level, dispatch_size) in &bindings.levels {
+            cpass.set_bind_group(1, level, &[]);
+            cpass.dispatch_workgroups(*dispatch_size, 1, 1);
+        }
+    }
+}
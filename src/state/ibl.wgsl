@@ -0,0 +1,137 @@
+// Prefilters the environment map into the levels of include/ibl.wgsl, once
+// per environment. The first specular level is the environment averaged
+// down to its size, the rougher ones and the irradiance are integrated from
+// it.
+
+#include "color.wgsl"
+#include "pbr.wgsl"
+
+// RGB9E5 texels, row by row from the zenith down
+struct Texels {
+  texels: array<u32>,
+}
+
+struct IblStep {
+  // Matches `IblStep` of ibl_pass.rs
+  step: u32,
+  // Written specular level
+  level: u32,
+  environment_width: u32,
+  environment_height: u32,
+}
+
+// Matches `IblStep::step`, the switch below needs the literals
+let STEP_IRRADIANCE = 2u;
+// GGX samples per texel of the rougher specular levels
+let SPECULAR_SAMPLES = 256u;
+
+@group(0) @binding(0) var<storage, read> environment: Texels;
+@group(0) @binding(1) var<storage, read_write> ibl: Texels;
+@group(1) @binding(0) var<uniform> params: IblStep;
+
+// After the `ibl` buffer it reads
+#include "ibl.wgsl"
+
+// Box filter over the texels of the environment covering the texel
+fn downsampled(xy: vec2<u32>) -> vec3<f32> {
+  let size = vec2<u32>(params.environment_width, params.environment_height);
+  let levels = vec2<u32>(IBL_WIDTH, IBL_HEIGHT);
+  let start = xy * size / levels;
+  let end = max((xy + 1u) * size / levels, start + 1u);
+  var color = vec3<f32>(0.0);
+  for (var y = start.y; y < end.y; y = y + 1u) {
+    for (var x = start.x; x < end.x; x = x + 1u) {
+      color = color + unpack_color(environment.texels[x + y * size.x]);
+    }
+  }
+  let area = (end - start).x * (end - start).y;
+  return color / f32(area);
+}
+
+// Van der Corput radical inverse, the second coordinate of the Hammersley set
+fn radical_inverse(i: u32) -> f32 {
+  var bits = (i << 16u) | (i >> 16u);
+  bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+  bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+  bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+  bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+  return f32(bits) * 2.3283064365386963e-10;
+}
+
+// GGX lobe around `n` seen along `n`, importance sampled as in "Real Shading
+// in Unreal Engine 4" by Brian Karis
+fn specular(n: vec3<f32>, roughness: f32) -> vec3<f32> {
+  let alpha = roughness * roughness;
+  var up = vec3<f32>(1.0, 0.0, 0.0);
+  if (abs(n.z) < 0.999) {
+    up = vec3<f32>(0.0, 0.0, 1.0);
+  }
+  let tangent = normalize(cross(up, n));
+  let bitangent = cross(n, tangent);
+  let size = vec2<u32>(IBL_WIDTH, IBL_HEIGHT);
+
+  var color = vec3<f32>(0.0);
+  var weight = 0.0;
+  for (var i = 0u; i < SPECULAR_SAMPLES; i = i + 1u) {
+    let xi = vec2<f32>(f32(i) / f32(SPECULAR_SAMPLES), radical_inverse(i));
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = tangent * (sin_theta * cos(phi)) + bitangent * (sin_theta * sin(phi)) + n * cos_theta;
+    let l = 2.0 * dot(n, h) * h - n;
+    let n_dot_l = dot(n, l);
+    if (n_dot_l > 0.0) {
+      color = color + ibl_sample(0u, size, l) * n_dot_l;
+      weight = weight + n_dot_l;
+    }
+  }
+  return color / max(weight, 1e-4);
+}
+
+// Cosine weighted sum over every texel of the first level, by its solid angle
+fn irradiance(n: vec3<f32>) -> vec3<f32> {
+  let size = vec2<f32>(f32(IBL_WIDTH), f32(IBL_HEIGHT));
+  var color = vec3<f32>(0.0);
+  for (var y = 0u; y < IBL_HEIGHT; y = y + 1u) {
+    let v = (f32(y) + 0.5) / size.y;
+    let solid_angle = (2.0 * PI / size.x) * (PI / size.y) * sin(v * PI);
+    for (var x = 0u; x < IBL_WIDTH; x = x + 1u) {
+      let l = equirect_dir(vec2<f32>((f32(x) + 0.5) / size.x, v));
+      let texel = unpack_color(ibl.texels[x + y * IBL_WIDTH]);
+      color = color + texel * max(dot(n, l), 0.0) * solid_angle;
+    }
+  }
+  return color / PI;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn prefilter(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  var size = vec2<u32>(IBL_IRRADIANCE_WIDTH, IBL_IRRADIANCE_HEIGHT);
+  var offset = ibl_level_offset(IBL_SPECULAR_LEVELS);
+  if (params.step != STEP_IRRADIANCE) {
+    size = vec2<u32>(IBL_WIDTH >> params.level, IBL_HEIGHT >> params.level);
+    offset = ibl_level_offset(params.level);
+  }
+  let index = global_id.x;
+  if (index >= size.x * size.y) {
+    return;
+  }
+  let xy = vec2<u32>(index % size.x, index / size.x);
+  let n = equirect_dir((vec2<f32>(xy) + 0.5) / vec2<f32>(size));
+
+  var color: vec3<f32>;
+  switch (params.step) {
+    // Downsample
+    case 0u: {
+      color = downsampled(xy);
+    }
+    // Specular
+    case 1u: {
+      color = specular(n, f32(params.level) / f32(IBL_SPECULAR_LEVELS - 1u));
+    }
+    default: {
+      color = irradiance(n);
+    }
+  }
+  ibl.texels[offset + index] = pack_color(color);
+}
@@ -0,0 +1,212 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    shaders::Shaders,
+    sky_pass::Environment,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Ibl");
+
+/// Size of the sharpest specular level, see include/ibl.wgsl.
+const IBL_WIDTH: u32 = 128;
+const IBL_HEIGHT: u32 = 64;
+/// Roughness 0 to 1, each level half the size of the previous one
+const IBL_SPECULAR_LEVELS: u32 = 5;
+const IBL_IRRADIANCE_WIDTH: u32 = 32;
+const IBL_IRRADIANCE_HEIGHT: u32 = 16;
+
+/// What a dispatch of the prefilter does, see ibl.wgsl.
+#[derive(Clone, Copy)]
+enum IblStepKind {
+    /// Averages the environment down to the sharpest level
+    Downsample,
+    /// Integrates a rougher level from the sharpest one
+    Specular,
+    Irradiance,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct IblStep {
+    step: u32,
+    level: u32,
+    environment_width: u32,
+    environment_height: u32,
+}
+
+/// Sizes of the specular levels followed by the irradiance.
+fn ibl_level_sizes() -> Vec<(u32, u32)> {
+    (0..IBL_SPECULAR_LEVELS)
+        .map(|level| (IBL_WIDTH >> level, IBL_HEIGHT >> level))
+        .chain(Some((IBL_IRRADIANCE_WIDTH, IBL_IRRADIANCE_HEIGHT)))
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct IblUniform {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 4]; 4],
+    enabled: u32,
+    _padding: [u32; 3],
+}
+
+impl IblUniform {
+    /// The environment is looked up in world space, `model` places the
+    /// object space the mesh is shaded in.
+    pub fn new(model: Mat4, enabled: bool) -> Self {
+        Self {
+            model: model.to_cols_array_2d(),
+            normal: model.inverse().transpose().to_cols_array_2d(),
+            enabled: enabled as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Image-based lighting, the environment map prefiltered into the ambient
+/// light of the shading: a few specular levels of growing roughness and the
+/// irradiance, packed into a buffer the raster pass reads.
+pub struct IblPass {
+    pipeline: wgpu::ComputePipeline,
+    workgroup_size: WorkgroupSize,
+    buffer: wgpu::Buffer,
+}
+
+impl IblPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[storage_entry(0, true), storage_entry(1, false)],
+            });
+        let step_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Step Bind Group Layout")),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &step_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader =
+            shaders.compute_module(device, "ibl.wgsl", include_str!("ibl.wgsl"), workgroup_size);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "prefilter",
+        });
+        // Black until an environment is prefiltered into it
+        let texels: u32 = ibl_level_sizes().iter().map(|(w, h)| w * h).sum();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Levels Buffer")),
+            size: std::mem::size_of::<u32>() as u64 * texels as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        Self {
+            pipeline,
+            workgroup_size,
+            buffer,
+        }
+    }
+
+    /// The prefiltered levels, bound by the raster pass.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Fills the levels from `environment` in a submission of its own, only
+    /// needed when the environment changes.
+    pub fn prefilter(&self, device: &wgpu::Device, queue: &wgpu::Queue, environment: &Environment) {
+        let buffers = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: environment.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let (environment_width, environment_height) = environment.size();
+        // The sharpest level first, the others are integrated from it
+        let steps: Vec<_> = ibl_level_sizes()
+            .into_iter()
+            .enumerate()
+            .map(|(level, (width, height))| {
+                let step = match level as u32 {
+                    0 => IblStepKind::Downsample,
+                    IBL_SPECULAR_LEVELS => IblStepKind::Irradiance,
+                    _ => IblStepKind::Specular,
+                };
+                let uniform = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some(&LABELS.of("Step Uniform Buffer")),
+                    contents: bytemuck::bytes_of(&IblStep {
+                        step: step as u32,
+                        level: level as u32,
+                        environment_width,
+                        environment_height,
+                    }),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(&LABELS.of("Step Bind Group")),
+                    layout: &self.pipeline.get_bind_group_layout(1),
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform.as_entire_binding(),
+                    }],
+                });
+                (
+                    bind_group,
+                    self.workgroup_size.dispatch_size(width * height),
+                )
+            })
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(&LABELS.of("Encoder")),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(&LABELS.pass()),
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &buffers, &[]);
+            for (step, dispatch_size) in &steps {
+                cpass.set_bind_group(1, step, &[]);
+                cpass.dispatch_workgroups(*dispatch_size, 1, 1);
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
@@ -0,0 +1,12 @@
+// Layout of `CameraUniform` in camera.rs
+struct Camera {
+  view_pos: vec4<f32>,
+  view_proj: mat4x4<f32>,
+  view: mat4x4<f32>,
+  projection: u32,
+  fov: f32,
+}
+
+let PROJECTION_PERSPECTIVE = 0u;
+let PROJECTION_FISHEYE = 1u;
+let PROJECTION_EQUIRECTANGULAR = 2u;
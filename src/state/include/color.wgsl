@@ -0,0 +1,61 @@
+// Linear HDR color packed into one u32 per pixel as RGB9E5: a 9 bit mantissa
+// per channel, red in the lowest bits, sharing the 5 bit exponent in the top
+// bits. Brighter colors compare greater, atomicMax keeps its tie-break.
+let EXPONENT_BIAS = 15;
+let MANTISSA_BITS = 9;
+// Largest representable channel, (511 / 512) * 2^16
+let MAX_COLOR = 65408.0;
+
+fn pack_color(color: vec3<f32>) -> u32 {
+  let c = clamp(color, vec3<f32>(0.0), vec3<f32>(MAX_COLOR));
+  let m = max(max(c.r, c.g), max(c.b, exp2(-16.0)));
+  var exponent = max(-EXPONENT_BIAS - 1, i32(floor(log2(m)))) + 1 + EXPONENT_BIAS;
+  var scale = exp2(f32(exponent - EXPONENT_BIAS - MANTISSA_BITS));
+  // Rounding the largest channel up can overflow its mantissa
+  if (u32(round(m / scale)) == 512u) {
+    exponent = exponent + 1;
+    scale = scale * 2.0;
+  }
+  let rgb = min(vec3<u32>(round(c / scale)), vec3<u32>(511u));
+  return rgb.r | (rgb.g << 9u) | (rgb.b << 18u) | (u32(exponent) << 27u);
+}
+
+fn unpack_color(p: u32) -> vec3<f32> {
+  let scale = exp2(f32(i32(p >> 27u) - EXPONENT_BIAS - MANTISSA_BITS));
+  return vec3<f32>(vec3<u32>(p, p >> 9u, p >> 18u) & vec3<u32>(511u)) * scale;
+}
+
+// RGBA8 texels of the sprites, red in the lowest byte
+fn unpack_rgba8(p: u32) -> vec3<f32> {
+  return vec3<f32>(vec3<u32>(p, p >> 8u, p >> 16u) & vec3<u32>(255u)) / 255.0;
+}
+
+// Maps linear HDR to the displayed [0, 1] after scaling it by the exposure,
+// clipping when no curve is set. `curve` matches `Tonemap`.
+fn tonemap(color: vec3<f32>, exposure: f32, curve: u32) -> vec3<f32> {
+  let c = max(color * exposure, vec3<f32>(0.0));
+  switch (curve) {
+    // Reinhard
+    case 1u: {
+      return c / (1.0 + c);
+    }
+    // Fit of the ACES filmic curve by Krzysztof Narkowicz
+    case 2u: {
+      return clamp((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+    default: {
+      return min(c, vec3<f32>(1.0));
+    }
+  }
+}
+
+// Exact sRGB transfer functions, the encoding is applied by the passes
+// writing to a target that doesn't do it itself
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+  let c = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+  return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+  return select(pow((color + 0.055) / 1.055, vec3<f32>(2.4)), color / 12.92, color <= vec3<f32>(0.04045));
+}
@@ -0,0 +1,78 @@
+// Image-based lighting prefiltered from the environment map by ibl.wgsl.
+// Every level is an equirectangular map of RGB9E5 texels, see color.wgsl,
+// packed one after another in the `ibl` buffer of the including shader, to be
+// declared before the include: the
+// specular levels halving from IBL_WIDTH x IBL_HEIGHT as the roughness goes
+// from 0 to 1, then the irradiance. Needs PI of pbr.wgsl and color.wgsl, the
+// sizes match the ones of ibl_pass.rs.
+let IBL_WIDTH = 128u;
+let IBL_HEIGHT = 64u;
+let IBL_SPECULAR_LEVELS = 5u;
+let IBL_IRRADIANCE_WIDTH = 32u;
+let IBL_IRRADIANCE_HEIGHT = 16u;
+
+fn ibl_level_offset(level: u32) -> u32 {
+  var offset = 0u;
+  for (var i = 0u; i < level; i = i + 1u) {
+    offset = offset + (IBL_WIDTH >> i) * (IBL_HEIGHT >> i);
+  }
+  return offset;
+}
+
+// Longitude from -z towards +x, latitude from the zenith down
+fn equirect_uv(dir: vec3<f32>) -> vec2<f32> {
+  return vec2<f32>(atan2(dir.x, -dir.z) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+}
+
+// Inverse of `equirect_uv`
+fn equirect_dir(uv: vec2<f32>) -> vec3<f32> {
+  let longitude = (uv.x - 0.5) * 2.0 * PI;
+  let polar = uv.y * PI;
+  return vec3<f32>(sin(polar) * sin(longitude), cos(polar), -sin(polar) * cos(longitude));
+}
+
+// Longitude wraps around, latitude clamps at the poles
+fn ibl_texel(offset: u32, size: vec2<u32>, xy: vec2<i32>) -> vec3<f32> {
+  let width = i32(size.x);
+  let x = ((xy.x % width) + width) % width;
+  let y = clamp(xy.y, 0, i32(size.y) - 1);
+  return unpack_color(ibl.texels[offset + u32(x + y * width)]);
+}
+
+fn ibl_sample(offset: u32, size: vec2<u32>, dir: vec3<f32>) -> vec3<f32> {
+  let p = equirect_uv(dir) * vec2<f32>(size) - 0.5;
+  let base = vec2<i32>(floor(p));
+  let t = p - floor(p);
+  let top = mix(ibl_texel(offset, size, base), ibl_texel(offset, size, base + vec2<i32>(1, 0)), t.x);
+  let bottom = mix(ibl_texel(offset, size, base + vec2<i32>(0, 1)), ibl_texel(offset, size, base + vec2<i32>(1, 1)), t.x);
+  return mix(top, bottom, t.y);
+}
+
+// Cosine weighted light arriving around `n` over PI, the radiance a white
+// Lambert surface reflects
+fn ibl_irradiance(n: vec3<f32>) -> vec3<f32> {
+  let size = vec2<u32>(IBL_IRRADIANCE_WIDTH, IBL_IRRADIANCE_HEIGHT);
+  return ibl_sample(ibl_level_offset(IBL_SPECULAR_LEVELS), size, n);
+}
+
+// Light reflected along `r` by the GGX lobe of `roughness`, blended between
+// the two closest levels
+fn ibl_specular(r: vec3<f32>, roughness: f32) -> vec3<f32> {
+  let level = clamp(roughness, 0.0, 1.0) * f32(IBL_SPECULAR_LEVELS - 1u);
+  let lower = u32(floor(level));
+  let upper = min(lower + 1u, IBL_SPECULAR_LEVELS - 1u);
+  let a = ibl_sample(ibl_level_offset(lower), vec2<u32>(IBL_WIDTH >> lower, IBL_HEIGHT >> lower), r);
+  let b = ibl_sample(ibl_level_offset(upper), vec2<u32>(IBL_WIDTH >> upper, IBL_HEIGHT >> upper), r);
+  return mix(a, b, fract(level));
+}
+
+// Scale and bias of f0 by the split sum of the GGX BRDF, the analytic fit of
+// Brian Karis in place of a lookup table
+fn ibl_brdf(f0: vec3<f32>, roughness: f32, n_dot_v: f32) -> vec3<f32> {
+  let c0 = vec4<f32>(-1.0, -0.0275, -0.572, 0.022);
+  let c1 = vec4<f32>(1.0, 0.0425, 1.04, -0.04);
+  let r = roughness * c0 + c1;
+  let a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+  let ab = vec2<f32>(-1.04, 1.04) * a004 + r.zw;
+  return f0 * ab.x + ab.y;
+}
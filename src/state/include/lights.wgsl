@@ -0,0 +1,46 @@
+// Layout of `LightInstance` in lights.rs
+struct Light {
+  position: vec3<f32>,
+  kind: u32,
+  direction: vec3<f32>,
+  intensity: f32,
+  color: vec3<f32>,
+  range: f32,
+  cos_inner: f32,
+  cos_outer: f32,
+}
+
+struct LightBuffer {
+  values: array<Light>,
+}
+
+let LIGHT_DIRECTIONAL = 0u;
+let LIGHT_POINT = 1u;
+let LIGHT_SPOT = 2u;
+
+// Light of a single light arriving at a point, `l` points towards the light
+struct Incident {
+  l: vec3<f32>,
+  radiance: vec3<f32>,
+}
+
+fn incident(light: Light, p: vec3<f32>) -> Incident {
+  let radiance = light.color * light.intensity;
+  if (light.kind == LIGHT_DIRECTIONAL) {
+    return Incident(-light.direction, radiance);
+  }
+  let to_light = light.position - p;
+  let distance2 = max(dot(to_light, to_light), 1e-4);
+  let l = to_light * inverseSqrt(distance2);
+  var attenuation = 1.0 / distance2;
+  // Windowed falloff of KHR_lights_punctual
+  if (light.range > 0.0) {
+    let ratio = distance2 / (light.range * light.range);
+    let fade = clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    attenuation = attenuation * fade * fade;
+  }
+  if (light.kind == LIGHT_SPOT) {
+    attenuation = attenuation * smoothstep(light.cos_outer, light.cos_inner, dot(-l, light.direction));
+  }
+  return Incident(l, radiance * attenuation);
+}
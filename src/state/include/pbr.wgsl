@@ -0,0 +1,34 @@
+// Metallic-roughness BRDF of the glTF spec, appendix B: Lambert diffuse with
+// the GGX distribution, the Smith joint visibility and the Schlick Fresnel
+let PI = 3.14159265359;
+// Ambient light, the same fraction of the base color as the Lambert view
+let PBR_AMBIENT = 0.1;
+
+// Ambient light, added once to the light of all the lights
+fn pbr_ambient(base_color: vec3<f32>, metallic: f32) -> vec3<f32> {
+  return PBR_AMBIENT * base_color * (1.0 - 0.5 * metallic);
+}
+
+// `n` is the shading normal, `v` points towards the eye and `l` towards the
+// light, all normalized. A white light of unit intensity is scaled so that
+// a white rough dielectric facing it comes out white with the ambient.
+fn pbr(base_color: vec3<f32>, metallic: f32, roughness: f32, n: vec3<f32>, v: vec3<f32>, l: vec3<f32>) -> vec3<f32> {
+  let alpha = max(roughness * roughness, 1e-3);
+  let alpha2 = alpha * alpha;
+  let h = normalize(l + v);
+  let n_dot_l = max(dot(n, l), 0.0);
+  let n_dot_v = max(dot(n, v), 1e-4);
+  let n_dot_h = max(dot(n, h), 0.0);
+  let v_dot_h = max(dot(v, h), 0.0);
+
+  let f0 = mix(vec3<f32>(0.04), base_color, metallic);
+  let fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+  let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+  let distribution = alpha2 / (PI * d * d);
+  let visibility = 0.5 / (n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2)
+    + n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2) + 1e-6);
+
+  let diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color / PI;
+  let specular = fresnel * distribution * visibility;
+  return (diffuse + specular) * n_dot_l * PI * (1.0 - PBR_AMBIENT);
+}
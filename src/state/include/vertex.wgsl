@@ -0,0 +1,27 @@
+// Layout of `Vertex` in util.rs
+struct Vertex {
+  x: f32, y: f32, z: f32,
+  nx: f32, ny: f32, nz: f32,
+  u: f32, v: f32,
+  r: f32, g: f32, b: f32, a: f32,
+}
+
+struct VertexBuffer {
+  values: array<Vertex>,
+}
+
+fn position(v: Vertex) -> vec3<f32> {
+  return vec3<f32>(v.x, v.y, v.z);
+}
+
+fn normal(v: Vertex) -> vec3<f32> {
+  return vec3<f32>(v.nx, v.ny, v.nz);
+}
+
+fn uv(v: Vertex) -> vec2<f32> {
+  return vec2<f32>(v.u, v.v);
+}
+
+fn color(v: Vertex) -> vec4<f32> {
+  return vec4<f32>(v.r, v.g, v.b, v.a);
+}
@@ -0,0 +1,123 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+/// Capacity of the light buffer.
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    /// Infinitely far away, only the direction matters
+    Directional,
+    Point,
+    /// Point light restricted to a cone around its direction
+    Spot,
+}
+
+/// Punctual light in the object space of the mesh, as the billboards.
+/// The point and spot lights fall off with the squared distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Ignored by directional lights
+    pub position: Vec3,
+    /// Where the light shines, ignored by point lights
+    pub direction: Vec3,
+    /// Linear RGB
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely, infinite when zero
+    pub range: f32,
+    /// Half angles in radians of the full and the faded cone of a spot light
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: Vec3::ZERO,
+            direction,
+            color,
+            intensity,
+            range: 0.0,
+            inner_angle: 0.0,
+            outer_angle: std::f32::consts::FRAC_PI_4,
+        }
+    }
+
+    pub fn point(position: Vec3, color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            ..Self::directional(Vec3::NEG_Y, color, intensity)
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        outer_angle: f32,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            position,
+            direction,
+            outer_angle,
+            inner_angle: outer_angle * 0.75,
+            ..Self::directional(direction, color, intensity)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct LightInstance {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    range: f32,
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [u32; 2],
+}
+
+impl From<&Light> for LightInstance {
+    fn from(light: &Light) -> Self {
+        Self {
+            position: light.position.to_array(),
+            kind: light.kind as u32,
+            direction: light.direction.normalize_or_zero().to_array(),
+            intensity: light.intensity,
+            color: light.color,
+            range: light.range.max(0.0),
+            cos_inner: light.inner_angle.cos(),
+            cos_outer: light.outer_angle.cos(),
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Storage buffer holding up to `MAX_LIGHTS` lights, the count goes with
+/// the raster uniform.
+pub fn create_light_buffer(device: &wgpu::Device, lights: &[Light]) -> wgpu::Buffer {
+    let mut instances = [LightInstance::zeroed(); MAX_LIGHTS];
+    for (instance, light) in instances.iter_mut().zip(lights) {
+        *instance = light.into();
+    }
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Light Buffer"),
+        contents: bytemuck::cast_slice(&instances),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// Layout of the light buffer for `lights`.
+pub fn light_instances(lights: &[Light]) -> Vec<LightInstance> {
+    lights.iter().map(LightInstance::from).collect()
+}
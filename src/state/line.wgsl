@@ -0,0 +1,66 @@
+// Debug lines drawn over the presented image, projected the same way as the
+// compute raster and letterboxed like present.wgsl. The lens distortion isn't
+// applied to them.
+
+#include "camera.wgsl"
+#include "color.wgsl"
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct PresentUniform {
+  distortion: u32,
+  distortion_strength: f32,
+  surface_width: f32,
+  surface_height: f32,
+  background: vec3<f32>,
+  accumulate: u32,
+  analysis: u32,
+  exposure: f32,
+  tonemap: u32,
+  encode_srgb: u32,
+}
+
+@group(0) @binding(0) var<uniform> camera : Camera;
+@group(0) @binding(1) var<uniform> screen_dims : Uniform;
+@group(0) @binding(2) var<uniform> present : PresentUniform;
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(0) color: vec3<f32>,
+  // Position along the segment in dashes, negative for solid lines
+  @location(1) dash: f32,
+}
+
+@vertex
+fn vs_line(@location(0) position: vec3<f32>, @location(1) color: vec3<f32>, @location(2) dash: f32) -> VertexOutput {
+  let clip = camera.view_proj * vec4<f32>(position, 1.0);
+  let dims = vec2<f32>(screen_dims.width, screen_dims.height);
+  let surface = vec2<f32>(present.surface_width, present.surface_height);
+  let scale = min(surface.x / dims.x, surface.y / dims.y);
+  // The raster samples at the pixel corners, they are shown at the pixel centers
+  let offset = 0.5 * (surface - dims * scale) + 0.5 * scale;
+  // Surface position times w, kept linear in clip space for the clipping
+  let pixel = clip.xy * dims * scale + offset * clip.w;
+  let xy = vec2<f32>(pixel.x / surface.x * 2.0 - clip.w, clip.w - pixel.y / surface.y * 2.0);
+
+  var out: VertexOutput;
+  out.position = vec4<f32>(xy, clip.z, clip.w);
+  out.color = color;
+  out.dash = dash;
+  return out;
+}
+
+@fragment
+fn fs_line(in: VertexOutput) -> @location(0) vec4<f32> {
+  if (in.dash >= 0.0 && fract(in.dash) >= 0.5) {
+    discard;
+  }
+  // Linear like the image below, encoded as present.wgsl does
+  if (present.encode_srgb != 0u) {
+    return vec4<f32>(linear_to_srgb(in.color), 1.0);
+  }
+  return vec4<f32>(in.color, 1.0);
+}
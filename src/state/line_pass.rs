@@ -0,0 +1,484 @@
+use std::str::FromStr;
+
+use bytemuck::{Pod, Zeroable};
+use color_eyre::{eyre::eyre, Report, Result};
+use glam::{vec2, BVec3, Mat4, Vec2, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    render_graph::{Frame, Pass, Stage},
+    shaders::{validated, Source},
+    util::{Labels, Mesh, Vertex, DEPTH_TARGET_FORMAT},
+};
+
+const LABELS: Labels = Labels::new("Line");
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+    /// Position along the segment in dashes, negative for solid lines
+    dash: f32,
+}
+
+impl LineVertex {
+    const ATTR: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32];
+}
+
+/// Dashed segments are split into this many dashes, whatever their length.
+const DASHES_PER_SEGMENT: f32 = 16.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStyle {
+    Solid,
+    Dashed,
+}
+
+/// Line segments colored and styled one by one, drawn by the line pass.
+#[derive(Clone, Default)]
+pub struct DebugDraw {
+    vertices: Vec<LineVertex>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: [f32; 3], style: LineStyle) {
+        let dashes = match style {
+            LineStyle::Solid => -1.0,
+            LineStyle::Dashed => DASHES_PER_SEGMENT,
+        };
+        for (position, dash) in [(from, dashes.min(0.0)), (to, dashes)] {
+            self.vertices.push(LineVertex {
+                position: position.to_array(),
+                color,
+                dash,
+            });
+        }
+    }
+
+    /// Red, green and blue unit axes, scaled by `size`.
+    pub fn axes(&mut self, origin: Vec3, size: f32) {
+        for (axis, color) in [
+            (Vec3::X, [1.0, 0.0, 0.0]),
+            (Vec3::Y, [0.0, 1.0, 0.0]),
+            (Vec3::Z, [0.0, 0.0, 1.0]),
+        ] {
+            self.line(origin, origin + axis * size, color, LineStyle::Solid);
+        }
+    }
+
+    /// Outline of a rectangle in the plane z = 0.
+    pub fn rect(&mut self, min: Vec2, max: Vec2, color: [f32; 3], style: LineStyle) {
+        let corners = [min, vec2(max.x, min.y), max, vec2(min.x, max.y)];
+        for (i, &from) in corners.iter().enumerate() {
+            let to = corners[(i + 1) % corners.len()];
+            self.line(from.extend(0.0), to.extend(0.0), color, style);
+        }
+    }
+
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: [f32; 3], style: LineStyle) {
+        self.cuboid(
+            |i| Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min),
+            color,
+            style,
+        );
+    }
+
+    /// Edges of the volume the raster shows for `view_proj`, which maps it to
+    /// [0, 1] in all three coordinates.
+    pub fn frustum(&mut self, view_proj: Mat4, color: [f32; 3], style: LineStyle) {
+        let inverse = view_proj.inverse();
+        self.cuboid(
+            |i| {
+                let ndc = Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32);
+                inverse.project_point3(ndc)
+            },
+            color,
+            style,
+        );
+    }
+
+    /// A segment of `length` along the normal of every vertex.
+    pub fn normals(&mut self, vertices: &[Vertex], length: f32, color: [f32; 3]) {
+        for vertex in vertices {
+            let position = vertex.position();
+            self.line(
+                position,
+                position + vertex.normal() * length,
+                color,
+                LineStyle::Solid,
+            );
+        }
+    }
+
+    /// Every pair of the 8 corners differing by a single bit of their index is an edge.
+    fn cuboid(&mut self, corner: impl Fn(u32) -> Vec3, color: [f32; 3], style: LineStyle) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color, style);
+                }
+            }
+        }
+    }
+
+    pub fn vertices(&self) -> &[LineVertex] {
+        &self.vertices
+    }
+}
+
+/// Meshes with more vertices than this don't get their normals drawn.
+const MAX_NORMAL_LINES: usize = 1 << 14;
+
+/// World axes at the origin, the bounding box of the mesh and the vertex
+/// normals of small meshes.
+pub fn debug_lines(mesh: &Mesh) -> DebugDraw {
+    let (min, max) = mesh.bounds();
+    let mut draw = DebugDraw::default();
+    draw.axes(Vec3::ZERO, 1.0);
+    draw.aabb(min, max, [0.5; 3], LineStyle::Dashed);
+    if mesh.vertices.len() <= MAX_NORMAL_LINES {
+        let length = 0.02 * (max - min).max_element();
+        draw.normals(&mesh.vertices, length, [1.0, 0.0, 1.0]);
+    }
+    draw
+}
+
+/// Overlay helping to frame screenshots and captures, always drawn with a
+/// cross at the center.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Guides {
+    #[default]
+    Off,
+    /// Rule of thirds grid
+    Thirds,
+    /// Crops of the image to 16:9 and 4:3, with their title safe areas dashed
+    SafeAreas,
+    All,
+}
+
+impl Guides {
+    pub fn next(self) -> Self {
+        match self {
+            Guides::Off => Guides::Thirds,
+            Guides::Thirds => Guides::SafeAreas,
+            Guides::SafeAreas => Guides::All,
+            Guides::All => Guides::Off,
+        }
+    }
+}
+
+impl FromStr for Guides {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Guides::Off),
+            "thirds" => Ok(Guides::Thirds),
+            "safe" => Ok(Guides::SafeAreas),
+            "all" => Ok(Guides::All),
+            _ => Err(eyre!(
+                "Unknown guides `{}`, expected off, thirds, safe or all",
+                s
+            )),
+        }
+    }
+}
+
+/// Width over height of the crops shown by `Guides::SafeAreas`, with their colors.
+const SAFE_AREA_ASPECTS: [(f32, [f32; 3]); 2] =
+    [(16.0 / 9.0, [1.0, 0.8, 0.2]), (4.0 / 3.0, [0.2, 0.8, 1.0])];
+
+/// Fraction of a crop taken by its title safe area.
+const TITLE_SAFE: f32 = 0.9;
+
+/// Guides over an image of `aspect` width over height. The lines are in the
+/// [0, 1] screen space of the raster, with y down, and are drawn with an
+/// identity camera.
+pub fn framing_guides(guides: Guides, aspect: f32) -> DebugDraw {
+    let mut draw = DebugDraw::default();
+    if guides == Guides::Off {
+        return draw;
+    }
+    let center = Vec2::splat(0.5);
+    let cross = vec2(0.03 / aspect, 0.03);
+    let white = [1.0; 3];
+    draw.line(
+        (center - cross * Vec2::X).extend(0.0),
+        (center + cross * Vec2::X).extend(0.0),
+        white,
+        LineStyle::Solid,
+    );
+    draw.line(
+        (center - cross * Vec2::Y).extend(0.0),
+        (center + cross * Vec2::Y).extend(0.0),
+        white,
+        LineStyle::Solid,
+    );
+    if matches!(guides, Guides::Thirds | Guides::All) {
+        for third in [1.0 / 3.0, 2.0 / 3.0] {
+            let color = [0.8; 3];
+            draw.line(
+                Vec3::new(third, 0.0, 0.0),
+                Vec3::new(third, 1.0, 0.0),
+                color,
+                LineStyle::Solid,
+            );
+            draw.line(
+                Vec3::new(0.0, third, 0.0),
+                Vec3::new(1.0, third, 0.0),
+                color,
+                LineStyle::Solid,
+            );
+        }
+    }
+    if matches!(guides, Guides::SafeAreas | Guides::All) {
+        for (crop, color) in SAFE_AREA_ASPECTS {
+            // Largest centered rectangle of the crop within the image
+            let size = if crop < aspect {
+                vec2(crop / aspect, 1.0)
+            } else {
+                vec2(1.0, aspect / crop)
+            };
+            draw.rect(
+                center - size * 0.5,
+                center + size * 0.5,
+                color,
+                LineStyle::Solid,
+            );
+            let safe = size * TITLE_SAFE;
+            draw.rect(
+                center - safe * 0.5,
+                center + safe * 0.5,
+                color,
+                LineStyle::Dashed,
+            );
+        }
+    }
+    draw
+}
+
+/// Draws debug lines within the present render pass, multisampled along with it.
+pub struct LinePass {
+    pipeline: wgpu::RenderPipeline,
+    /// Kept to rebuild the pipeline for a new target, the bindings stay valid
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+}
+
+impl LinePass {
+    pub fn new(
+        device: &wgpu::Device,
+        source: &Source,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1), uniform_entry(2)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = source.module(device, &[]).unwrap();
+        let pipeline = Self::create_pipeline(device, &layout, &shader, format, sample_count);
+        Self {
+            pipeline,
+            layout,
+            shader,
+            format,
+            sample_count,
+        }
+    }
+
+    /// Rebuilds the pipeline from a new line.wgsl, it's kept when the shader
+    /// fails validation.
+    pub fn reload(&mut self, device: &wgpu::Device, source: &Source) -> Result<()> {
+        (self.shader, self.pipeline) = validated(device, || -> Result<_> {
+            let shader = source.module(device, &[])?;
+            let pipeline = Self::create_pipeline(
+                device,
+                &self.layout,
+                &shader,
+                self.format,
+                self.sample_count,
+            );
+            Ok((shader, pipeline))
+        })??;
+        Ok(())
+    }
+
+    /// Rebuilds the pipeline when the surface format or the sample count changed.
+    pub fn update_target(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        if (format, sample_count) == (self.format, self.sample_count) {
+            return;
+        }
+        self.pipeline =
+            Self::create_pipeline(device, &self.layout, &self.shader, format, sample_count);
+        self.format = format;
+        self.sample_count = sample_count;
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_line",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<LineVertex>() as _,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &LineVertex::ATTR,
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_line",
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Hidden behind the geometry, the present pass fills in the depth
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_TARGET_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+}
+
+pub struct LineBindings {
+    uniform: wgpu::BindGroup,
+    vertices: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl LineBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        LinePass { pipeline, .. }: &LinePass,
+        camera_uniform: &wgpu::Buffer,
+        uniform: &wgpu::Buffer,
+        present_uniform: &wgpu::Buffer,
+        lines: &[LineVertex],
+    ) -> Self {
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: present_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        let vertices = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Vertex Buffer")),
+            contents: bytemuck::cast_slice(lines),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self {
+            uniform,
+            vertices,
+            vertex_count: lines.len() as u32,
+        }
+    }
+}
+
+impl<'a> LinePass {
+    pub fn record<'pass>(&'a self, rpass: &mut wgpu::RenderPass<'pass>, bindings: &'a LineBindings)
+    where
+        'a: 'pass,
+    {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &bindings.uniform, &[]);
+        rpass.set_vertex_buffer(0, bindings.vertices.slice(..));
+        rpass.draw(0..bindings.vertex_count, 0..1);
+    }
+}
+
+/// Lines drawn over the presented frame, either the debug lines of the mesh
+/// or the guides.
+pub struct LineNode<'a> {
+    pub pass: &'a LinePass,
+    pub bindings: &'a LineBindings,
+    pub guides: bool,
+}
+
+impl Pass for LineNode<'_> {
+    fn name(&self) -> &'static str {
+        match self.guides {
+            true => "Guides",
+            false => "Debug Lines",
+        }
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        match self.guides {
+            true => vec!["Guides"],
+            false => vec!["Debug Lines", "Camera", "Depth Target"],
+        }
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Surface"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let Some(surface) = &frame.surface else {
+            return;
+        };
+        let mut rpass = surface.begin_render_pass(encoder, &LABELS.pass(), false);
+        self.pass.record(&mut rpass, self.bindings);
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Present
+    }
+}
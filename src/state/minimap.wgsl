@@ -0,0 +1,92 @@
+// Top-down overview of the mesh in the top right corner of the surface, with
+// the position and the field of view of the camera marked over it. The
+// overview itself is drawn once per mesh by the reference pass.
+
+#include "color.wgsl"
+
+struct PresentUniform {
+  distortion: u32,
+  distortion_strength: f32,
+  surface_width: f32,
+  surface_height: f32,
+  background: vec3<f32>,
+  accumulate: u32,
+  analysis: u32,
+  exposure: f32,
+  tonemap: u32,
+  encode_srgb: u32,
+}
+
+// Positions in the inset, [0, 1] over its corners
+struct MinimapUniform {
+  // Top left corner and size of the inset over the overview
+  window: vec4<f32>,
+  eye: vec2<f32>,
+  left: vec2<f32>,
+  right: vec2<f32>,
+}
+
+let INSET_SIZE = 256.0;
+let INSET_MARGIN = 8.0;
+
+@group(0) @binding(0) var<uniform> present : PresentUniform;
+@group(0) @binding(1) var<uniform> minimap : MinimapUniform;
+@group(1) @binding(0) var overview: texture_2d<f32>;
+@group(1) @binding(1) var overview_sampler: sampler;
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(0) uv: vec2<f32>,
+}
+
+// Quad of two triangles covering the inset
+@vertex
+fn vs_minimap(@builtin(vertex_index) index: u32) -> VertexOutput {
+  let corner = vec2<f32>(f32((index + 1u) % 6u / 3u), f32(index % 2u));
+  let surface = vec2<f32>(present.surface_width, present.surface_height);
+  let origin = vec2<f32>(surface.x - INSET_MARGIN - INSET_SIZE, INSET_MARGIN);
+  let pixel = origin + corner * INSET_SIZE;
+
+  var out: VertexOutput;
+  out.position = vec4<f32>(pixel.x / surface.x * 2.0 - 1.0, 1.0 - pixel.y / surface.y * 2.0, 0.0, 1.0);
+  out.uv = corner;
+  return out;
+}
+
+// Distance from `p` to the segment a -> b
+fn segment_distance(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+  let ab = b - a;
+  let t = clamp(dot(p - a, ab) / max(dot(ab, ab), 1e-12), 0.0, 1.0);
+  return length(p - a - ab * t);
+}
+
+// Linear like the overview, encoded as present.wgsl does
+fn output(color: vec3<f32>) -> vec4<f32> {
+  if (present.encode_srgb != 0u) {
+    return vec4<f32>(linear_to_srgb(color), 1.0);
+  }
+  return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_minimap(in: VertexOutput) -> @location(0) vec4<f32> {
+  // The overview is rendered with north at its bottom row, alpha marks the mesh
+  let source = minimap.window.xy + in.uv * minimap.window.z;
+  var mesh = textureSample(overview, overview_sampler, vec2<f32>(source.x, 1.0 - source.y));
+  if (any(source < vec2<f32>(0.0)) || any(source > vec2<f32>(1.0))) {
+    mesh = vec4<f32>(0.0);
+  }
+  let pixel = 1.0 / INSET_SIZE;
+  if (any(in.uv < vec2<f32>(pixel)) || any(in.uv > vec2<f32>(1.0 - pixel))) {
+    return output(vec3<f32>(0.9, 0.9, 0.9));
+  }
+  if (distance(in.uv, minimap.eye) < 3.0 * pixel) {
+    return output(vec3<f32>(1.0, 0.2, 0.2));
+  }
+  let edge = min(segment_distance(in.uv, minimap.eye, minimap.left), segment_distance(in.uv, minimap.eye, minimap.right));
+  if (edge < pixel) {
+    return output(vec3<f32>(1.0, 0.8, 0.2));
+  }
+
+  return output(mix(vec3<f32>(0.1), mesh.rgb, mesh.a));
+}
@@ -0,0 +1,393 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    lights::{create_light_buffer, Light},
+    raster_pass::{DebugView, RasterUniform},
+    reference_pass::{ReferenceBindings, ReferencePass},
+    render_graph::{Frame, Node, Pass, Stage},
+    shaders::Shaders,
+    util::{Labels, Material, Mesh, TriangleInfo, Uniform, DEPTH_TARGET_FORMAT},
+};
+use crate::camera::CameraUniform;
+
+const LABELS: Labels = Labels::new("Minimap");
+
+/// Resolution of the overview, drawn at its size in the top right corner.
+pub const MINIMAP_SIZE: u32 = 256;
+
+/// Length of the marked field of view, relative to the size of the inset.
+const FOV_MARKER_LENGTH: f32 = 0.25;
+
+/// Space kept around the camera when the inset zooms out to include it.
+const EYE_MARGIN: f32 = 0.05;
+
+/// Top-down orthographic view of the bounds of a mesh, looking down -y with
+/// -z up in the inset.
+#[derive(Debug, Clone, Copy)]
+pub struct Overview {
+    /// Middle of the bounds in x and z
+    center: Vec2,
+    /// Width of the view, the larger side of the bounds with a margin
+    extent: f32,
+    /// Height mapped to the near plane
+    top: f32,
+    /// Height range mapped to the depth
+    depth: f32,
+}
+
+impl Overview {
+    pub fn new(mesh: &Mesh) -> Self {
+        let (min, max) = mesh.bounds();
+        let size = (max - min).max(Vec3::splat(1e-3));
+        let margin = 0.01 * size.max_element();
+        Self {
+            center: Vec2::new(min.x + max.x, min.z + max.z) * 0.5,
+            extent: 1.1 * size.x.max(size.z),
+            top: max.y + margin,
+            depth: size.y + 2.0 * margin,
+        }
+    }
+
+    /// Camera of the reference raster, which maps [0, 1] over its target.
+    /// The x axis stays right and -z up as for a camera looking down, so the
+    /// triangles keep their winding.
+    pub fn camera_uniform(&self) -> CameraUniform {
+        let Self {
+            center,
+            extent,
+            top,
+            depth,
+        } = *self;
+        let mut uniform = CameraUniform::default();
+        uniform.view_proj = Mat4::from_cols(
+            Vec4::new(1.0 / extent, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, -1.0 / depth, 0.0),
+            Vec4::new(0.0, -1.0 / extent, 0.0, 0.0),
+            Vec4::new(
+                0.5 - center.x / extent,
+                0.5 + center.y / extent,
+                top / depth,
+                1.0,
+            ),
+        )
+        .to_cols_array_2d();
+        uniform
+    }
+
+    /// Position of `p` seen from above, [0, 1] over the bounds from the top left.
+    pub fn position(&self, p: Vec3) -> Vec2 {
+        (Vec2::new(p.x, p.z) - self.center) / self.extent + 0.5
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct MinimapUniform {
+    /// Top left corner and size of the inset over the overview
+    window: [f32; 4],
+    eye: [f32; 2],
+    /// Ends of the edges of the field of view, seen from above
+    left: [f32; 2],
+    right: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl MinimapUniform {
+    /// Marks the camera of `view` with `half_fov` the horizontal half angle
+    /// of its field of view, in radians. The inset zooms out of the overview
+    /// while the camera is beyond the bounds of the mesh.
+    pub fn new(overview: &Overview, view: Mat4, half_fov: f32) -> Self {
+        let world = view.inverse();
+        let eye = world.w_axis.truncate();
+        let eye_position = overview.position(eye);
+        let min = (eye_position - EYE_MARGIN).min(Vec2::ZERO);
+        let max = (eye_position + EYE_MARGIN).max(Vec2::ONE);
+        let size = (max - min).max_element();
+        let origin = (min + max - size) * 0.5;
+
+        let inset_position = (eye_position - origin) / size;
+        let forward = overview.position(eye - world.z_axis.truncate()) - eye_position;
+        let forward = forward.normalize_or_zero() * FOV_MARKER_LENGTH;
+        let edge = |angle: f32| inset_position + Vec2::from_angle(angle).rotate(forward);
+        Self {
+            window: [origin.x, origin.y, size, 0.0],
+            eye: inset_position.to_array(),
+            left: edge(-half_fov).to_array(),
+            right: edge(half_fov).to_array(),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Reference bindings drawing the overview of a mesh, with uniforms of its own.
+pub fn overview_bindings(
+    device: &wgpu::Device,
+    reference_pass: &ReferencePass,
+    materials: &[Material],
+    triangles: &[TriangleInfo],
+    overview: &Overview,
+    light: Light,
+) -> ReferenceBindings {
+    let buffer = |label: &str, contents: &[u8]| {
+        device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    };
+    let camera = buffer(
+        &LABELS.of("Camera Buffer"),
+        bytemuck::bytes_of(&overview.camera_uniform()),
+    );
+    let screen = buffer(
+        &LABELS.of("Screen Uniform Buffer"),
+        bytemuck::bytes_of(&Uniform::new(MINIMAP_SIZE as _, MINIMAP_SIZE as _)),
+    );
+    let raster = buffer(
+        &LABELS.of("Raster Uniform Buffer"),
+        bytemuck::bytes_of(&RasterUniform::new(
+            DebugView::Lambert,
+            1,
+            Vec2::ZERO,
+            false,
+        )),
+    );
+    let lights = create_light_buffer(device, &[light]);
+    ReferenceBindings::new(
+        device,
+        reference_pass,
+        materials,
+        triangles,
+        &camera,
+        &screen,
+        &raster,
+        &lights,
+        MINIMAP_SIZE,
+        MINIMAP_SIZE,
+    )
+}
+
+/// Draws the overview as an inset within the present render pass.
+pub struct MinimapPass {
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    /// Kept to rebuild the pipeline for a new target, the bindings stay valid
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+}
+
+impl MinimapPass {
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &Shaders,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[
+                    uniform_entry(0, wgpu::ShaderStages::VERTEX_FRAGMENT),
+                    uniform_entry(1, wgpu::ShaderStages::FRAGMENT),
+                ],
+            });
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Texture Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.module(device, "minimap.wgsl", include_str!("minimap.wgsl"));
+        let pipeline = Self::create_pipeline(device, &layout, &shader, format, sample_count);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&LABELS.of("Overview Sampler")),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            pipeline,
+            sampler,
+            layout,
+            shader,
+            format,
+            sample_count,
+        }
+    }
+
+    /// Rebuilds the pipeline when the surface format or the sample count changed.
+    pub fn update_target(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        if (format, sample_count) == (self.format, self.sample_count) {
+            return;
+        }
+        self.pipeline =
+            Self::create_pipeline(device, &self.layout, &self.shader, format, sample_count);
+        self.format = format;
+        self.sample_count = sample_count;
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_minimap",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_minimap",
+                targets: &[Some(format.into())],
+            }),
+            primitive: Default::default(),
+            // In front of the image and the debug lines
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_TARGET_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+}
+
+pub struct MinimapBindings {
+    uniform: wgpu::BindGroup,
+    texture: wgpu::BindGroup,
+}
+
+impl MinimapBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        MinimapPass {
+            pipeline, sampler, ..
+        }: &MinimapPass,
+        present_uniform: &wgpu::Buffer,
+        minimap_uniform: &wgpu::Buffer,
+        overview: &ReferenceBindings,
+    ) -> Self {
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: present_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: minimap_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        let texture = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Texture Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(overview.color_view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        Self { uniform, texture }
+    }
+}
+
+impl<'a> MinimapPass {
+    pub fn record<'pass>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'pass>,
+        bindings: &'a MinimapBindings,
+    ) where
+        'a: 'pass,
+    {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &bindings.uniform, &[]);
+        rpass.set_bind_group(1, &bindings.texture, &[]);
+        rpass.draw(0..6, 0..1);
+    }
+}
+
+impl Pass for Node<'_, MinimapPass, MinimapBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Overview"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Surface"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let Some(surface) = &frame.surface else {
+            return;
+        };
+        let mut rpass = surface.begin_render_pass(encoder, &LABELS.pass(), false);
+        self.pass.record(&mut rpass, self.bindings);
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Present
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// Invocations a pass dispatches against the items they have work for.
+#[derive(Debug, Clone, Copy)]
+pub struct PassOccupancy {
+    pub name: &'static str,
+    pub workgroup_size: u32,
+    pub workgroups: u32,
+    /// The invocations past them are padding
+    pub items: u64,
+}
+
+impl PassOccupancy {
+    pub fn invocations(&self) -> u64 {
+        self.workgroup_size as u64 * self.workgroups as u64
+    }
+
+    /// Fraction of the invocations with an item, the persistent raster
+    /// loops over more items than it has invocations.
+    pub fn utilization(&self) -> f32 {
+        match self.invocations() {
+            0 => 0.0,
+            invocations => (self.items as f64 / invocations as f64).min(1.0) as f32,
+        }
+    }
+}
+
+/// Workgroups of the compute passes of a frame with the current settings.
+/// Passes dispatched indirectly count every triangle, the ones culled on
+/// the GPU are unknown here. The tile raster and the scans are left out.
+#[derive(Debug, Clone)]
+pub struct OccupancyReport {
+    /// Limit of the adapter
+    pub max_invocations_per_workgroup: u32,
+    pub passes: Vec<PassOccupancy>,
+}
+
+impl OccupancyReport {
+    /// Fraction of the largest workgroup of the adapter `pass` fills. wgpu
+    /// exposes neither the subgroup size nor the compute units, so this is
+    /// only an upper bound of what the hardware gets.
+    pub fn occupancy(&self, pass: &PassOccupancy) -> f32 {
+        pass.workgroup_size as f32 / self.max_invocations_per_workgroup.max(1) as f32
+    }
+}
+
+impl fmt::Display for OccupancyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for pass in &self.passes {
+            writeln!(
+                f,
+                "{:<18} {:>4} x {:>7} workgroups, {:>5.1}% busy, {:>5.1}% occupancy",
+                pass.name,
+                pass.workgroup_size,
+                pass.workgroups,
+                pass.utilization() * 100.0,
+                self.occupancy(pass) * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use color_eyre::{eyre::eyre, Result};
+
+/// Snippets shared between the shaders, `#include "camera.wgsl"` pulls in
+/// `include/camera.wgsl`.
+pub const INCLUDES: [(&str, &str); 6] = [
+    ("camera.wgsl", include_str!("include/camera.wgsl")),
+    ("color.wgsl", include_str!("include/color.wgsl")),
+    ("ibl.wgsl", include_str!("include/ibl.wgsl")),
+    ("lights.wgsl", include_str!("include/lights.wgsl")),
+    ("pbr.wgsl", include_str!("include/pbr.wgsl")),
+    ("vertex.wgsl", include_str!("include/vertex.wgsl")),
+];
+
+/// Expands the directives of a WGSL source, one per line:
+///
+/// - `#include "name.wgsl"` inserts a snippet of `includes`, once per shader
+/// - `#define NAME value` replaces the identifier `NAME` in the following lines
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep lines depending
+///   on whether `NAME` is defined
+///
+/// `defines` are defined before the first line, they select the permutation
+/// of a pipeline. Errors point at the line of `name` or of the include.
+pub fn preprocess(
+    name: &str,
+    source: &str,
+    defines: &[(&str, &str)],
+    includes: &[(&str, &str)],
+) -> Result<String> {
+    let mut preprocessor = Preprocessor {
+        defines: defines
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect(),
+        includes,
+        included: Vec::new(),
+        output: String::with_capacity(source.len()),
+    };
+    preprocessor.expand(source, name)?;
+    Ok(preprocessor.output)
+}
+
+struct Preprocessor<'a> {
+    defines: HashMap<String, String>,
+    includes: &'a [(&'a str, &'a str)],
+    included: Vec<&'a str>,
+    output: String,
+}
+
+impl<'a> Preprocessor<'a> {
+    fn expand(&mut self, source: &str, file: &str) -> Result<()> {
+        // Whether the lines are kept, for each enclosing `#ifdef`
+        let mut conditions = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let active = conditions.iter().all(|&kept| kept);
+            let directive = match line.trim_start().strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        self.substitute(line);
+                        self.output.push('\n');
+                    }
+                    continue;
+                }
+            };
+
+            let at = || format!("{}:{}", file, index + 1);
+            let (name, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive.trim_end(), ""), |(name, argument)| {
+                    (name, argument.trim())
+                });
+            match name {
+                "ifdef" => conditions.push(self.defines.contains_key(argument)),
+                "ifndef" => conditions.push(!self.defines.contains_key(argument)),
+                "else" => {
+                    let kept = conditions
+                        .last_mut()
+                        .ok_or_else(|| eyre!("{}: `#else` without `#ifdef`", at()))?;
+                    *kept = !*kept;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| eyre!("{}: `#endif` without `#ifdef`", at()))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((argument, ""));
+                    self.defines
+                        .insert(define.to_owned(), value.trim().to_owned());
+                }
+                "include" => {
+                    let requested = argument.trim_matches('"');
+                    let &(name, source) = self
+                        .includes
+                        .iter()
+                        .find(|(name, _)| *name == requested)
+                        .ok_or_else(|| eyre!("{}: unknown include `{}`", at(), requested))?;
+                    if !self.included.contains(&name) {
+                        self.included.push(name);
+                        self.expand(source, name)?;
+                    }
+                }
+                _ => return Err(eyre!("{}: unknown directive `#{}`", at(), name)),
+            }
+        }
+        match conditions.is_empty() {
+            true => Ok(()),
+            false => Err(eyre!("{}: `#ifdef` without `#endif`", file)),
+        }
+    }
+
+    /// Appends `line` with the defined identifiers replaced by their value.
+    fn substitute(&mut self, line: &str) {
+        let is_identifier = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            let end = rest[start..]
+                .find(|c: char| !is_identifier(c))
+                .map_or(rest.len(), |end| start + end);
+            // Digits before the identifier make it a literal suffix like `1u`
+            let suffix = rest[..start].ends_with(|c: char| c.is_ascii_digit());
+            let word = &rest[start..end];
+            self.output.push_str(&rest[..start]);
+            match self.defines.get(word) {
+                Some(value) if !suffix => self.output.push_str(value),
+                _ => self.output.push_str(word),
+            }
+            rest = &rest[end..];
+        }
+        self.output.push_str(rest);
+    }
+}
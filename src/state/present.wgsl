@@ -1,15 +1,16 @@
-struct Pixel {
-    r: u32,
-    g: u32,
-    b: u32,
+#include "color.wgsl"
+
+// RGB9E5 packed by raster.wgsl, see color.wgsl
+struct ColorBuffer {
+    value: array<u32>,
 }
 
-fn pixel_to_vec(p: Pixel) -> vec3<f32> {
-    return vec3<f32>(f32(p.r), f32(p.g), f32(p.b)) / 255.0;
+struct DepthBuffer {
+  value: array<u32>,
 }
 
-struct ColorBuffer {
-    value: array<Pixel>,
+struct AccumulationBuffer {
+  value: array<vec4<f32>>,
 }
 
 struct Uniform {
@@ -17,8 +18,46 @@ struct Uniform {
     screen_height: f32,
 }
 
+struct PresentUniform {
+  distortion: u32,
+  distortion_strength: f32,
+  // Can differ from the color buffer size while a resize is pending
+  surface_width: f32,
+  surface_height: f32,
+  // Shown where no fragment was written and around the letterboxed image,
+  // black while the sky pass fills in the uncovered pixels
+  background: vec3<f32>,
+  // Show the accumulated frames instead of the last one
+  accumulate: u32,
+  analysis: u32,
+  // Linear scale of the HDR color before the tonemap curve
+  exposure: f32,
+  tonemap: u32,
+  // The target stores the written values as they are, see `encode`
+  encode_srgb: u32,
+  // Add the glow of bloom.wgsl
+  bloom: u32,
+}
+
+struct Histogram {
+  bins: array<u32, 64>,
+}
+
+let ANALYSIS_HISTOGRAM = 1u;
+let ANALYSIS_FALSE_COLOR = 2u;
+let HISTOGRAM_BINS = 64u;
+// Pixels averaged along each axis by the present pass when downscaling
+let MAX_TAPS = 4u;
+
 @group(0) @binding(0) var<storage, read> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read> depth_buffer: DepthBuffer;
+@group(0) @binding(2) var<storage, read> accumulation: AccumulationBuffer;
+// Counted by analysis.wgsl
+@group(0) @binding(3) var<storage, read> histogram: Histogram;
+// Written by bloom.wgsl, linear like the color buffer
+@group(0) @binding(4) var<storage, read> bloom: AccumulationBuffer;
 @group(1) @binding(0) var<uniform> screen_dims : Uniform;
+@group(1) @binding(1) var<uniform> present : PresentUniform;
 
 struct VertexOutput {
     @builtin(position) pos: vec4<f32>,
@@ -39,15 +78,216 @@ fn vs_main_trig(@builtin(vertex_index) vertex_idx: u32) -> VertexOutput {
     return out;
 }
 
+// Inverse Panini projection, maps a point on the Panini image plane back to
+// the rectilinear image plane. From: http://tksharpless.net/vedutismo/Pannini/panini.pdf
+fn panini_to_rectilinear(p: vec2<f32>, d: f32) -> vec2<f32> {
+  let k = p.x * p.x / ((d + 1.0) * (d + 1.0));
+  let dscr = k * k * d * d - (k + 1.0) * (k * d * d - 1.0);
+  let clon = (-k * d + sqrt(dscr)) / (k + 1.0);
+  let s = (d + 1.0) / (d + clon);
+  let lon = atan2(p.x, s * clon);
+  let lat = atan2(p.y, s);
+  return vec2<f32>(tan(lon), tan(lat) / cos(lon));
+}
+
+// Maps a point in aspect corrected [-1, 1] screen space to the point to sample
+fn distort(p: vec2<f32>) -> vec2<f32> {
+  let k = present.distortion_strength;
+  switch (present.distortion) {
+    // Barrel
+    case 1u: {
+      return p * (1.0 + k * dot(p, p));
+    }
+    // Pincushion
+    case 2u: {
+      return p / (1.0 + k * dot(p, p));
+    }
+    // Panini
+    case 3u: {
+      return panini_to_rectilinear(p, k);
+    }
+    default: {
+      return p;
+    }
+  }
+}
+
+// Depth of the shown pixel for the debug lines, far plane over the background
+struct FragmentOutput {
+  @location(0) color: vec4<f32>,
+  @builtin(frag_depth) depth: f32,
+}
+
+fn background() -> FragmentOutput {
+  return FragmentOutput(vec4<f32>(present.background, 1.0), 1.0);
+}
+
+// Maps the fragment to the color buffer, letterboxed into the surface keeping
+// its aspect ratio and distorted. Outside of [0, 1) shows the background.
+fn source_uv(frag: vec2<f32>) -> vec2<f32> {
+  let dims = vec2<f32>(screen_dims.screen_width, screen_dims.screen_height);
+  let surface = vec2<f32>(present.surface_width, present.surface_height);
+  let scale = min(surface.x / dims.x, surface.y / dims.y);
+  let pos = (frag - 0.5 * (surface - dims * scale)) / scale;
+
+  let aspect = vec2<f32>(dims.x / dims.y, 1.0);
+  let src = distort((pos / dims * 2.0 - 1.0) * aspect) / aspect;
+  return src * 0.5 + 0.5;
+}
+
+fn outside(uv: vec2<f32>) -> bool {
+  return any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0));
+}
+
+fn pixel_index(uv: vec2<f32>) -> u32 {
+  let xy = floor(uv * vec2<f32>(screen_dims.screen_width, screen_dims.screen_height));
+  return u32(xy.x + xy.y * screen_dims.screen_width);
+}
+
+fn depth_or_far(depth_bits: u32) -> f32 {
+  return select(bitcast<f32>(depth_bits), 1.0, depth_bits == 0xffffffffu);
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+  return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Exposure zones of the false color analysis, grayscale in between
+fn false_color(color: vec3<f32>) -> vec3<f32> {
+  let l = luminance(color);
+  if (l < 0.02) {
+    return vec3<f32>(0.0, 0.0, 1.0);
+  }
+  if (l > 0.98) {
+    return vec3<f32>(1.0, 0.0, 0.0);
+  }
+  // Around middle gray, 18% linear
+  if (abs(l - 0.18) < 0.02) {
+    return vec3<f32>(0.0, 1.0, 0.0);
+  }
+  return vec3<f32>(l);
+}
+
+fn image(color: vec3<f32>, depth: f32) -> FragmentOutput {
+  if (present.analysis == ANALYSIS_FALSE_COLOR) {
+    return FragmentOutput(vec4<f32>(false_color(color), 1.0), depth);
+  }
+  return FragmentOutput(vec4<f32>(color, 1.0), depth);
+}
+
+// Bars of the histogram over a dark box in the bottom left corner of the
+// surface, scaled to the fullest bin. Drawn in front of the debug lines.
+fn histogram_overlay(out: FragmentOutput, frag: vec2<f32>) -> FragmentOutput {
+  if (present.analysis != ANALYSIS_HISTOGRAM) {
+    return out;
+  }
+  let size = vec2<f32>(256.0, 96.0);
+  let origin = vec2<f32>(8.0, present.surface_height - 8.0 - size.y);
+  let pos = (frag - origin) / size;
+  if (outside(pos)) {
+    return out;
+  }
+
+  var fullest = 1u;
+  for (var i = 0u; i < HISTOGRAM_BINS; i = i + 1u) {
+    fullest = max(fullest, histogram.bins[i]);
+  }
+  let bin = min(u32(pos.x * f32(HISTOGRAM_BINS)), HISTOGRAM_BINS - 1u);
+  let bar = f32(histogram.bins[bin]) / f32(fullest);
+  var color = mix(out.color.rgb, vec3<f32>(0.0), 0.75);
+  if (1.0 - pos.y <= bar) {
+    color = vec3<f32>(0.9);
+  }
+  return FragmentOutput(vec4<f32>(color, 1.0), 0.0);
+}
+
+// Source pixels per surface pixel, above 1 when the color buffer is larger
+// than the image shown, e.g. rendered at a higher internal resolution
+fn footprint() -> f32 {
+  let dims = vec2<f32>(screen_dims.screen_width, screen_dims.screen_height);
+  let surface = vec2<f32>(present.surface_width, present.surface_height);
+  return max(dims.x / surface.x, dims.y / surface.y);
+}
+
+// Tonemapped per pixel before the box filter, the background is shown as is
+fn display(color: vec3<f32>) -> vec3<f32> {
+  return tonemap(color, present.exposure, present.tonemap);
+}
+
+fn glow(index: u32) -> vec3<f32> {
+  if (present.bloom == 0u) {
+    return vec3<f32>(0.0);
+  }
+  return bloom.value[index].rgb;
+}
+
+fn present_buffers(frag: vec2<f32>) -> FragmentOutput {
+  let uv = source_uv(frag);
+  if (outside(uv)) {
+    return background();
+  }
+
+  // Box filter over the pixels the fragment covers when downscaling, a
+  // single nearest pixel otherwise
+  let dims = vec2<f32>(screen_dims.screen_width, screen_dims.screen_height);
+  let size = footprint();
+  let taps = clamp(u32(ceil(size - 0.01)), 1u, MAX_TAPS);
+  var color = vec3<f32>(0.0);
+  var covered = 0u;
+  for (var y = 0u; y < taps; y = y + 1u) {
+    for (var x = 0u; x < taps; x = x + 1u) {
+      let offset = (vec2<f32>(f32(x), f32(y)) + 0.5) / f32(taps) - 0.5;
+      let xy = clamp(floor(uv * dims + offset * size), vec2<f32>(0.0), dims - 1.0);
+      let index = u32(xy.x + xy.y * dims.x);
+      if (present.accumulate != 0u) {
+        color = color + display(accumulation.value[index].rgb + glow(index));
+        covered = covered + 1u;
+      // Pixels without any fragment keep the cleared depth, their color is
+      // black unless the sky pass wrote the sky
+      } else if (depth_buffer.value[index] == 0xffffffffu) {
+        color = color + present.background + display(unpack_color(color_buffer.value[index]) + glow(index));
+      } else {
+        color = color + display(unpack_color(color_buffer.value[index]) + glow(index));
+        covered = covered + 1u;
+      }
+    }
+  }
+  // The background or the sky, without the false color of the analysis
+  if (covered == 0u) {
+    return FragmentOutput(vec4<f32>(color / f32(taps * taps), 1.0), 1.0);
+  }
+
+  let depth_bits = depth_buffer.value[pixel_index(uv)];
+  return image(color / f32(taps * taps), depth_or_far(depth_bits));
+}
+
+// Everything is shaded in linear, sRGB targets encode on write and the
+// others are encoded here
+fn encode(out: FragmentOutput) -> FragmentOutput {
+  if (present.encode_srgb == 0u) {
+    return out;
+  }
+  return FragmentOutput(vec4<f32>(linear_to_srgb(out.color.rgb), out.color.a), out.depth);
+}
+
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let x = floor(in.pos.x);
-    let y = floor(in.pos.y);
-    let index = u32(x + y * screen_dims.screen_width);
-    let p = color_buffer.value[index];
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+  return encode(histogram_overlay(present_buffers(in.pos.xy), in.pos.xy));
+}
 
-    let pixel = pixel_to_vec(p);
+// Written by resolve.wgsl with the background, the accumulation and the
+// tonemap applied
+@group(2) @binding(0) var output_texture: texture_2d<f32>;
+@group(2) @binding(1) var output_sampler: sampler;
 
-    let col = vec4<f32>(pixel, 1.0);
-    return col;
+// Texture output mode, the image is filtered when it's scaled
+@fragment
+fn fs_texture(in: VertexOutput) -> FragmentOutput {
+  let uv = source_uv(in.pos.xy);
+  if (outside(uv)) {
+    return encode(histogram_overlay(background(), in.pos.xy));
+  }
+  let color = textureSampleLevel(output_texture, output_sampler, uv, 0.0);
+  let out = image(color.rgb, depth_or_far(depth_buffer.value[pixel_index(uv)]));
+  return encode(histogram_overlay(out, in.pos.xy));
 }
@@ -1,114 +1,493 @@
+use bytemuck::{Pod, Zeroable};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    analysis_pass::Analysis,
+    render_graph::{Frame, Node, Pass, Stage, Targets},
+    shaders::{validated, Source},
+    util::{Labels, DEPTH_TARGET_FORMAT},
+};
+
+const LABELS: Labels = Labels::new("Present");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Distortion {
+    None,
+    Barrel,
+    Pincushion,
+    Panini,
+}
+
+impl Distortion {
+    pub fn next(self) -> Self {
+        match self {
+            Distortion::None => Distortion::Barrel,
+            Distortion::Barrel => Distortion::Pincushion,
+            Distortion::Pincushion => Distortion::Panini,
+            Distortion::Panini => Distortion::None,
+        }
+    }
+}
+
+/// Curve mapping the linear HDR color buffer to the displayed range, after
+/// the exposure. `None` clips at 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tonemap {
+    #[default]
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl Tonemap {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemap::None => Tonemap::Reinhard,
+            Tonemap::Reinhard => Tonemap::Aces,
+            Tonemap::Aces => Tonemap::None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct PresentUniform {
+    distortion: u32,
+    distortion_strength: f32,
+    surface_width: f32,
+    surface_height: f32,
+    background: [f32; 3],
+    accumulate: u32,
+    analysis: u32,
+    exposure: f32,
+    tonemap: u32,
+    encode_srgb: u32,
+    bloom: u32,
+    _padding: [u32; 3],
+}
+
+impl PresentUniform {
+    /// `exposure` is linear, not in stops. `encode_srgb` is set for targets
+    /// of a non-sRGB format, the shaders then encode their linear output.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        distortion: Distortion,
+        distortion_strength: f32,
+        surface_width: u32,
+        surface_height: u32,
+        background: [f32; 3],
+        accumulate: bool,
+        analysis: Analysis,
+        exposure: f32,
+        tonemap: Tonemap,
+        encode_srgb: bool,
+        bloom: bool,
+    ) -> Self {
+        Self {
+            distortion: distortion as u32,
+            distortion_strength,
+            surface_width: surface_width as _,
+            surface_height: surface_height as _,
+            background,
+            accumulate: accumulate as u32,
+            analysis: analysis as u32,
+            exposure,
+            tonemap: tonemap as u32,
+            encode_srgb: encode_srgb as u32,
+            bloom: bloom as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
 pub struct PresentPass {
     pipeline: wgpu::RenderPipeline,
+    /// Samples the texture written by the resolve pass instead of the buffers
+    texture_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    /// Kept to rebuild the pipelines for a new target, the bindings stay valid
+    layout: wgpu::PipelineLayout,
+    texture_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
 }
 
 impl PresentPass {
-    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        source: &Source,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
         let output_color_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Present: Output Buffer Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                label: Some(&LABELS.of("Output Buffer Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
         let uniform_bind_group =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Present: Uniform Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Texture Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
                     },
-                    count: None,
-                }],
+                ],
             });
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Present Pipeline Layout"),
+            label: Some(&LABELS.pipeline_layout()),
             bind_group_layouts: &[&output_color_bind_group_layout, &uniform_bind_group],
             push_constant_ranges: &[],
         });
-        let shader = device.create_shader_module(wgpu::include_wgsl!("present.wgsl"));
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Present Pipeline"),
-            layout: Some(&layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main_trig",
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(format.into())],
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+        let texture_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.of("Texture Pipeline Layout")),
+            bind_group_layouts: &[
+                &output_color_bind_group_layout,
+                &uniform_bind_group,
+                &texture_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = source.module(device, &[]).unwrap();
+        let (pipeline, texture_pipeline) = Self::create_pipelines(
+            device,
+            &layout,
+            &texture_layout,
+            &shader,
+            format,
+            sample_count,
+        );
+        // Linear filtering smooths the image when it's scaled to the surface
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&LABELS.of("Output Sampler")),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
         });
 
-        Self { pipeline }
+        Self {
+            pipeline,
+            texture_pipeline,
+            sampler,
+            layout,
+            texture_layout,
+            shader,
+            format,
+            sample_count,
+        }
+    }
+
+    /// Rebuilds the pipelines from a new present.wgsl, they are kept when the
+    /// shader fails validation.
+    pub fn reload(&mut self, device: &wgpu::Device, source: &Source) -> Result<()> {
+        let (shader, (pipeline, texture_pipeline)) = validated(device, || -> Result<_> {
+            let shader = source.module(device, &[])?;
+            let pipelines = Self::create_pipelines(
+                device,
+                &self.layout,
+                &self.texture_layout,
+                &shader,
+                self.format,
+                self.sample_count,
+            );
+            Ok((shader, pipelines))
+        })??;
+        self.shader = shader;
+        self.pipeline = pipeline;
+        self.texture_pipeline = texture_pipeline;
+        Ok(())
+    }
+
+    /// Rebuilds the pipelines when the surface format or the sample count changed.
+    pub fn update_target(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        if (format, sample_count) == (self.format, self.sample_count) {
+            return;
+        }
+        (self.pipeline, self.texture_pipeline) = Self::create_pipelines(
+            device,
+            &self.layout,
+            &self.texture_layout,
+            &self.shader,
+            format,
+            sample_count,
+        );
+        self.format = format;
+        self.sample_count = sample_count;
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        texture_layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let create_pipeline = |label: &str, layout, entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main_trig",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point,
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                // Copies the depth buffer for the debug lines drawn afterwards
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: DEPTH_TARGET_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+        };
+        (
+            create_pipeline(&LABELS.pipeline(), layout, "fs_main"),
+            create_pipeline(&LABELS.of("Texture Pipeline"), texture_layout, "fs_texture"),
+        )
     }
 }
 
 pub struct PresentBindings {
     uniform: wgpu::BindGroup,
     color_buffer: wgpu::BindGroup,
+    texture: wgpu::BindGroup,
 }
 
 impl PresentBindings {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
-        PresentPass { pipeline }: &PresentPass,
+        pass: &PresentPass,
         color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        histogram: &wgpu::Buffer,
+        bloom: &wgpu::Buffer,
+        output_view: &wgpu::TextureView,
         uniform: &wgpu::Buffer,
+        present_uniform: &wgpu::Buffer,
     ) -> Self {
-        let color_buffer = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Present: Output Buffer Bind Group"),
-            layout: &pipeline.get_bind_group_layout(0),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: color_buffer.as_entire_binding(),
-            }],
-        });
         let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Present: Uniform Bind Group"),
-            layout: &pipeline.get_bind_group_layout(1),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform.as_entire_binding(),
-            }],
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pass.pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: present_uniform.as_entire_binding(),
+                },
+            ],
         });
         Self {
-            color_buffer,
+            color_buffer: Self::color_buffer_bind_group(
+                device,
+                pass,
+                color_buffer,
+                depth_buffer,
+                accumulation_buffer,
+                histogram,
+                bloom,
+            ),
+            texture: Self::texture_bind_group(device, pass, output_view),
             uniform,
         }
     }
 
-    pub fn update_color_buffer(
+    /// The histogram, the glow and the resolved texture are the outputs of
+    /// the analysis, the bloom and the resolve pass, rebound first.
+    pub fn rebind(
         &mut self,
         device: &wgpu::Device,
-        PresentPass { pipeline }: &PresentPass,
-        color_buffer: &wgpu::Buffer,
+        pass: &PresentPass,
+        targets: &Targets,
+        histogram: &wgpu::Buffer,
+        bloom: &wgpu::Buffer,
+        output_view: &wgpu::TextureView,
     ) {
-        self.color_buffer = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Present: Output Buffer Bind Group"),
+        self.color_buffer = Self::color_buffer_bind_group(
+            device,
+            pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            targets.accumulation_buffer,
+            histogram,
+            bloom,
+        );
+        self.texture = Self::texture_bind_group(device, pass, output_view);
+    }
+
+    fn color_buffer_bind_group(
+        device: &wgpu::Device,
+        PresentPass { pipeline, .. }: &PresentPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        histogram: &wgpu::Buffer,
+        bloom: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Output Buffer Bind Group")),
             layout: &pipeline.get_bind_group_layout(0),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: color_buffer.as_entire_binding(),
-            }],
-        });
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: bloom.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn texture_bind_group(
+        device: &wgpu::Device,
+        PresentPass {
+            texture_pipeline,
+            sampler,
+            ..
+        }: &PresentPass,
+        output_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Texture Bind Group")),
+            layout: &texture_pipeline.get_bind_group_layout(2),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
     }
 }
 
@@ -117,12 +496,52 @@ impl<'a> PresentPass {
         &'a self,
         rpass: &mut wgpu::RenderPass<'pass>,
         bindings: &'a PresentBindings,
+        from_texture: bool,
     ) where
         'a: 'pass,
     {
-        rpass.set_pipeline(&self.pipeline);
+        if from_texture {
+            rpass.set_pipeline(&self.texture_pipeline);
+            rpass.set_bind_group(2, &bindings.texture, &[]);
+        } else {
+            rpass.set_pipeline(&self.pipeline);
+        }
         rpass.set_bind_group(0, &bindings.color_buffer, &[]);
         rpass.set_bind_group(1, &bindings.uniform, &[]);
         rpass.draw(0..3, 0..1);
     }
 }
+
+impl Pass for Node<'_, PresentPass, PresentBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, frame: &Frame) -> Vec<&'static str> {
+        if frame.texture_output {
+            return vec!["Output Texture"];
+        }
+        let mut reads = vec!["Color Buffer", "Depth Buffer", "Accumulation", "Histogram"];
+        if frame.bloom {
+            reads.push("Bloom");
+        }
+        reads
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Surface", "Depth Target"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let Some(surface) = &frame.surface else {
+            return;
+        };
+        let mut rpass = surface.begin_render_pass(encoder, &LABELS.pass(), true);
+        self.pass
+            .record(&mut rpass, self.bindings, frame.texture_output);
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Present
+    }
+}
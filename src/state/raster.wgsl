@@ -1,119 +1,667 @@
+#include "camera.wgsl"
+#include "color.wgsl"
+#include "lights.wgsl"
+#include "pbr.wgsl"
+#include "vertex.wgsl"
+
+// Linear HDR packed into one u32 per pixel, see color.wgsl
 struct ColorBuffer {
   values: array<atomic<u32>>,
 }
 
-struct Vertex { x: f32, y: f32, z: f32 }
+struct DepthBuffer {
+  values: array<atomic<u32>>,
+}
+
+struct IndexBuffer {
+  values: array<u32>,
+}
+
+struct TileCounts {
+  values: array<atomic<u32>>,
+}
+
+// Exclusive scan of the tile counts, one extra entry holds the total
+struct TileOffsets {
+  values: array<u32>,
+}
+
+// Triangle ids binned per tile, packed according to the tile offsets
+struct TileEntries {
+  values: array<u32>,
+}
+
+struct VisibleTriangles {
+  values: array<u32>,
+}
+
+// Written by the culling pass, see compact.wgsl
+struct CullResult {
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
+  count: u32,
+}
+
+struct WorkQueue {
+  depth: atomic<u32>,
+  color: atomic<u32>,
+}
+
+struct Material {
+  double_sided: u32,
+  alpha_mode: u32,
+  alpha_cutoff: f32,
+  alpha: f32,
+  base_color: vec3<f32>,
+  metallic: f32,
+  roughness: f32,
+}
+
+struct MaterialBuffer {
+  values: array<Material>,
+}
+
+struct TriangleInfo {
+  material: u32,
+  object: u32,
+}
 
-struct VertexBuffer {
-  values: array<Vertex>,
+struct TriangleBuffer {
+  values: array<TriangleInfo>,
 }
 
+let ALPHA_MASK = 1u;
+
 struct Uniform {
   width: f32,
   height: f32,
 }
 
-struct Camera {
-  view_pos: vec4<f32>,
-  view_proj: mat4x4<f32>,
+struct IblLevels {
+  texels: array<u32>,
+}
+
+struct IblUniform {
+  // Object space of the mesh to the world the environment is looked up in
+  model: mat4x4<f32>,
+  normal: mat4x4<f32>,
+  enabled: u32,
+}
+
+// Matches `ClearUniform` of raster_pass.rs
+struct ClearUniform {
+  // Packed linear color of the pixels left uncovered
+  color: u32,
+  // Zero when `clear_buffer` already cleared the color
+  write_color: u32,
+}
+
+struct RasterUniform {
+  // Lights at the start of the light buffer
+  light_count: u32,
+  debug_view: u32,
+  // Subpixel offset of the accumulated frames
+  jitter: vec2<f32>,
+  // Look the first light up in the shadow map
+  shadows: u32,
 }
 
+// Range of the visible triangles rasterized by one dispatch, the work is
+// split into chunks to stay below the driver timeout on huge meshes
+struct RasterChunk {
+  offset: u32,
+  count: u32,
+}
+
+let VIEW_OVERDRAW = 8u;
+// The overdraw view sets the exponent of the packed color once, then adds a
+// quarter on every channel per fragment. See `Coverage`.
+let OVERDRAW_EXPONENT = 0x78000000u;
+let OVERDRAW_STEP = 0x2010080u;
+
+// Sub-triangles per edge used to approximate curved edges of non-linear projections
+let SUBDIVISIONS = 4u;
+// Screen tiles of the binned raster are TILE_SIZE x TILE_SIZE pixels
+let TILE_SIZE = 16u;
+let F32_MAX = 3.40282347e38;
+// Side of the square shadow map, see `SHADOW_MAP_SIZE`
+let SHADOW_MAP_SIZE = 1024u;
+// Depth offset keeping the surfaces facing the light from shadowing themselves
+let SHADOW_BIAS = 0.002;
+
 @group(0) @binding(0) var<storage, read_write> color_buffer : ColorBuffer;
+@group(0) @binding(1) var<storage, read_write> depth_buffer : DepthBuffer;
+// Only bound by the clear pass
+@group(0) @binding(2) var<uniform> clear_uniform : ClearUniform;
 @group(1) @binding(0) var<storage, read> vertex_buffer : VertexBuffer;
+@group(1) @binding(1) var<storage, read> materials : MaterialBuffer;
+@group(1) @binding(2) var<storage, read_write> work_queue : WorkQueue;
+@group(1) @binding(3) var<storage, read> triangle_info : TriangleBuffer;
+@group(1) @binding(4) var<storage, read> index_buffer : IndexBuffer;
+@group(1) @binding(5) var<storage, read> visible_triangles : VisibleTriangles;
+@group(1) @binding(6) var<storage, read> cull_result : CullResult;
+
+// Pixel rect drawing is restricted to, covers a single tile in the binned raster
+var<private> tile_rect: vec4<f32> = vec4<f32>(0.0, 0.0, F32_MAX, F32_MAX);
+// Binning only gathers the screen bounds of the triangles instead of drawing them
+var<private> bounds_only: bool;
+var<private> triangle_bounds: vec4<f32>;
+
+// Material of the triangle being rasterized
+var<private> material: Material;
+var<private> current_object: u32;
 @group(2) @binding(0) var<uniform> screen_dims : Uniform;
+@group(2) @binding(1) var<uniform> raster_uniform : RasterUniform;
+@group(2) @binding(2) var<storage, read_write> tile_counts : TileCounts;
+@group(2) @binding(3) var<storage, read> tile_offsets : TileOffsets;
+@group(2) @binding(4) var<storage, read_write> tile_entries : TileEntries;
+@group(2) @binding(5) var<uniform> chunk : RasterChunk;
 @group(3) @binding(0) var<uniform> camera : Camera;
+@group(3) @binding(1) var<storage, read> lights : LightBuffer;
+// Depth of the mesh seen from the first light, rendered by the depth pass of
+// this shader with `shadow_camera` as its camera
+@group(3) @binding(2) var<storage, read> shadow_map : IndexBuffer;
+@group(3) @binding(3) var<uniform> shadow_camera : Camera;
+// Prefiltered environment of ibl.wgsl, the ambient light when enabled
+@group(3) @binding(4) var<storage, read> ibl : IblLevels;
+@group(3) @binding(5) var<uniform> ibl_uniform : IblUniform;
+
+#include "ibl.wgsl"
+
+// Equidistant fisheye and equirectangular mappings of a view space position,
+// the distance to the camera is used in place of w and remapped to [0, 1) for depth
+fn project_non_linear(p: vec3<f32>) -> vec4<f32> {
+  let view_pos = (camera.view * vec4<f32>(p, 1.0)).xyz;
+  let dist = length(view_pos);
+  let dir = view_pos / dist;
+  let aspect = screen_dims.width / screen_dims.height;
+
+  var ndc: vec2<f32>;
+  if (camera.projection == PROJECTION_FISHEYE) {
+    let theta = acos(clamp(-dir.z, -1.0, 1.0));
+    let r = theta / (camera.fov * 0.5);
+    ndc = r * dir.xy / max(length(dir.xy), 1e-6);
+    ndc.x = ndc.x / aspect;
+  } else {
+    ndc = vec2<f32>(atan2(dir.x, -dir.z) / PI, asin(dir.y) / (PI * 0.5));
+  }
+
+  let depth = dist / (1.0 + dist);
+  return vec4<f32>(ndc.x * screen_dims.width, ndc.y * screen_dims.height, depth, dist);
+}
 
-fn project(v: Vertex) -> vec3<f32> {
-  var screen_pos = camera.view_proj * vec4<f32>(v.x, v.y, v.z, 1.0);
+fn project_perspective(p: vec3<f32>) -> vec4<f32> {
+  var screen_pos = camera.view_proj * vec4<f32>(p, 1.0);
   screen_pos.x = (screen_pos.x / screen_pos.w) * screen_dims.width;
   screen_pos.y = (screen_pos.y / screen_pos.w) * screen_dims.height;
+  screen_pos.z = screen_pos.z / screen_pos.w;
+
+  return screen_pos;
+}
+
+// Returns screen space xy, NDC depth and clip space w
+fn project(p: vec3<f32>) -> vec4<f32> {
+  var screen_pos: vec4<f32>;
+  if (camera.projection != PROJECTION_PERSPECTIVE) {
+    screen_pos = project_non_linear(p);
+  } else {
+    screen_pos = project_perspective(p);
+  }
+  return vec4<f32>(screen_pos.xy + raster_uniform.jitter, screen_pos.zw);
+}
+
+// Atomic max makes the result independent of scheduling when several
+// fragments end up with exactly the same depth
+fn color_pixel(x: u32, y: u32, color: vec3<f32>) {
+  let pixelID = u32(x + y * u32(screen_dims.width));
 
-  return screen_pos.xyw;
+  atomicMax(&color_buffer.values[pixelID], pack_color(color));
 }
 
-fn color_pixel(x: u32, y: u32, r: u32, g: u32, b: u32) {
-  let pixelID = u32(x + y * u32(screen_dims.width)) * 3u;
+// Overdraw view, counts the fragments written to the pixel
+fn count_fragment(x: u32, y: u32) {
+  let pixelID = u32(x + y * u32(screen_dims.width));
 
-  atomicMin(&color_buffer.values[pixelID + 0u], r);
-  atomicMin(&color_buffer.values[pixelID + 1u], g);
-  atomicMin(&color_buffer.values[pixelID + 2u], b);
+  atomicMax(&color_buffer.values[pixelID], OVERDRAW_EXPONENT);
+  atomicAdd(&color_buffer.values[pixelID], OVERDRAW_STEP);
 }
 
-fn draw_line(v1: vec3<f32>, v2: vec3<f32>) {
+// Rasterization runs twice: the depth pass only resolves the closest depth per
+// pixel, then the color pass writes the fragments matching it. Doing the depth
+// test and the color write in a single pass would race between the two.
+// Both passes are permutations of this shader, the depth one defines DEPTH_PASS.
+
+// Returns true when the fragment should be colored
+fn depth_test(x: u32, y: u32, depth: f32) -> bool {
+  if (depth < 0.0 || depth > 1.0) {
+    return false;
+  }
+  let pixelID = x + y * u32(screen_dims.width);
+  let depth_bits = bitcast<u32>(depth);
+#ifdef DEPTH_PASS
+  // The first fragment of the pixel zeroes the clear color for the atomicMax
+  // of the color pass
+  if (atomicMin(&depth_buffer.values[pixelID], depth_bits) == 0xffffffffu) {
+    atomicStore(&color_buffer.values[pixelID], 0u);
+  }
+  return false;
+#else
+  return atomicLoad(&depth_buffer.values[pixelID]) == depth_bits;
+#endif
+}
+
+fn draw_line(v1: vec4<f32>, v2: vec4<f32>) {
   let dist = i32(distance(v1.xy, v2.xy));
   for (var i = 0; i < dist; i = i + 1) {
     let x = v1.x + (v2.x - v1.x) * (f32(i) / f32(dist));
     let y = v1.y + (v2.y - v1.y) * (f32(i) / f32(dist));
-    // color_pixel(u32(x), u32(y), vec3<f32>(1.0, 1.0, 1.0));
-    color_pixel(u32(x), u32(y), 255u, 255u, 255u);
+    color_pixel(u32(x), u32(y), vec3<f32>(1.0, 1.0, 1.0));
   }
 }
 
-// From: https://github.com/ssloy/tinyrenderer/wiki/Lesson-2:-Triangle-rasterization-and-back-face-culling
-fn barycentric(v1: vec3<f32>, v2: vec3<f32>, v3: vec3<f32>, p: vec2<f32>) -> vec3<f32> {
-  let u = cross(vec3<f32>(v3.x - v1.x, v2.x - v1.x, v1.x - p.x),
-                vec3<f32>(v3.y - v1.y, v2.y - v1.y, v1.y - p.y));
+// Vertices are snapped to 1/256 of a pixel, the edge functions are then exact
+// integers. Adjacent triangles evaluate a shared edge to exactly opposite
+// values, which together with the top-left rule covers every sample once.
+let SUBPIXEL_BITS = 8u;
+let SUBPIXEL_SCALE = 256;
+// Keeps the differences of snapped coordinates within i32, about 2M pixels
+let SNAP_LIMIT = 536870912.0;
 
-  if (abs(u.z) < 1.0) {
-    return vec3<f32>(-1.0, 1.0, 1.0);
+fn snap(v: vec4<f32>) -> vec2<i32> {
+  let scaled = round(v.xy * f32(SUBPIXEL_SCALE));
+  return vec2<i32>(clamp(scaled, vec2<f32>(-SNAP_LIMIT), vec2<f32>(SNAP_LIMIT)));
+}
+
+// Edge functions reach 2^60, they are kept as 64 bit two's complement
+// integers with x holding the low and y the high word
+fn wide_add(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+  let lo = a.x + b.x;
+  return vec2<u32>(lo, a.y + b.y + select(0u, 1u, lo < a.x));
+}
+
+fn wide_neg(a: vec2<u32>) -> vec2<u32> {
+  return wide_add(~a, vec2<u32>(1u, 0u));
+}
+
+fn wide_mul(a: i32, b: i32) -> vec2<u32> {
+  let ua = u32(abs(a));
+  let ub = u32(abs(b));
+  let lo_lo = (ua & 0xffffu) * (ub & 0xffffu);
+  let lo_hi = (ua & 0xffffu) * (ub >> 16u);
+  let hi_lo = (ua >> 16u) * (ub & 0xffffu);
+  let hi_hi = (ua >> 16u) * (ub >> 16u);
+  let mid = lo_hi + hi_lo;
+  let mid_carry = select(0u, 0x10000u, mid < lo_hi);
+  let lo = lo_lo + (mid << 16u);
+  let hi = hi_hi + (mid >> 16u) + mid_carry + select(0u, 1u, lo < lo_lo);
+  if ((a < 0) != (b < 0)) {
+    return wide_neg(vec2<u32>(lo, hi));
+  }
+  return vec2<u32>(lo, hi);
+}
+
+fn wide_is_negative(a: vec2<u32>) -> bool {
+  return (a.y >> 31u) == 1u;
+}
+
+fn wide_is_zero(a: vec2<u32>) -> bool {
+  return a.x == 0u && a.y == 0u;
+}
+
+fn wide_to_f32(a: vec2<u32>) -> f32 {
+  return f32(bitcast<i32>(a.y)) * 4294967296.0 + f32(a.x);
+}
+
+// Twice the signed area of a, b, p. Positive when p lies on the right of
+// a -> b, y pointing down.
+fn edge_function(a: vec2<i32>, b: vec2<i32>, p: vec2<i32>) -> vec2<u32> {
+  let e = b - a;
+  return wide_add(wide_mul(e.x, p.y - a.y), wide_neg(wide_mul(e.y, p.x - a.x)));
+}
+
+// Edge function of a -> b at the first sample and its steps to the next pixel
+struct Edge {
+  value: vec2<u32>,
+  step_x: vec2<u32>,
+  step_y: vec2<u32>,
+  // Samples exactly on top and left edges are inside, as in Direct3D
+  top_left: bool,
+}
+
+// Expects the triangle oriented so that its area is positive
+fn setup_edge(a: vec2<i32>, b: vec2<i32>, origin: vec2<i32>) -> Edge {
+  let e = b - a;
+  var edge: Edge;
+  edge.value = edge_function(a, b, origin);
+  edge.step_x = wide_mul(-e.y, SUBPIXEL_SCALE);
+  edge.step_y = wide_mul(e.x, SUBPIXEL_SCALE);
+  edge.top_left = e.y < 0 || (e.y == 0 && e.x > 0);
+  return edge;
+}
+
+fn covers(value: vec2<u32>, top_left: bool) -> bool {
+  if (wide_is_negative(value)) {
+    return false;
   }
+  return top_left || !wide_is_zero(value);
+}
 
-  return vec3<f32>(1.0 - (u.x + u.y) / u.z, u.y / u.z, u.x / u.z);
+// Bounding box of the pixels within the snapped vertices, clamped to the
+// screen. The triangle is fully off screen when it's empty.
+fn screen_bounds(a: vec2<i32>, b: vec2<i32>, c: vec2<i32>) -> vec4<f32> {
+  let shift = vec2<u32>(SUBPIXEL_BITS);
+  let first = vec2<f32>((min(min(a, b), c) + vec2<i32>(SUBPIXEL_SCALE - 1)) >> shift);
+  let last = vec2<f32>(max(max(a, b), c) >> shift);
+  let max_pixel = min(vec2<f32>(screen_dims.width - 1.0, screen_dims.height - 1.0), tile_rect.zw);
+  return vec4<f32>(max(first, tile_rect.xy), min(last, max_pixel));
 }
 
-fn get_min_max(v1: vec3<f32>, v2: vec3<f32>, v3: vec3<f32>) -> vec4<f32> {
-  var min_max = vec4<f32>(0.);
-  min_max.x = min(min(v1.x, v2.x), v3.x);
-  min_max.y = min(min(v1.y, v2.y), v3.y);
-  min_max.z = max(max(v1.x, v2.x), v3.x);
-  min_max.w = max(max(v1.y, v2.y), v3.y);
+// Triangle being rasterized, its attributes are fetched per fragment
+var<private> current_triangle: u32;
+// Two-sided triangles seen from behind are lit with their normals flipped
+var<private> back_facing: bool;
 
-  return min_max;
+fn fetch_vertex(corner: u32) -> Vertex {
+  return vertex_buffer.values[index_buffer.values[current_triangle * 3u + corner]];
 }
 
-fn draw_triangle(v1: vec3<f32>, v2: vec3<f32>, v3: vec3<f32>) {
-  let min_max = get_min_max(v1, v2, v3);
+fn interpolate_uv(bc: vec3<f32>) -> vec2<f32> {
+  return bc.x * uv(fetch_vertex(0u)) + bc.y * uv(fetch_vertex(1u)) + bc.z * uv(fetch_vertex(2u));
+}
+
+fn interpolate_color(bc: vec3<f32>) -> vec4<f32> {
+  return bc.x * color(fetch_vertex(0u)) + bc.y * color(fetch_vertex(1u)) + bc.z * color(fetch_vertex(2u));
+}
+
+// Alpha cutout, fragments of masked materials below the cutoff are discarded
+fn is_masked(bc: vec3<f32>) -> bool {
+  return material.alpha_mode == ALPHA_MASK && material.alpha * interpolate_color(bc).a < material.alpha_cutoff;
+}
+
+// Vertex color times the base color of the material
+fn base_color(bc: vec3<f32>) -> vec3<f32> {
+  return material.base_color * interpolate_color(bc).rgb;
+}
+
+fn interpolate_position(bc: vec3<f32>) -> vec3<f32> {
+  return bc.x * position(fetch_vertex(0u)) + bc.y * position(fetch_vertex(1u)) + bc.z * position(fetch_vertex(2u));
+}
+
+fn tile_columns() -> u32 {
+  return (u32(screen_dims.width) + TILE_SIZE - 1u) / TILE_SIZE;
+}
+
+fn tile_count() -> u32 {
+  return tile_columns() * ((u32(screen_dims.height) + TILE_SIZE - 1u) / TILE_SIZE);
+}
+
+// PCG hash, from: https://jcgt.org/published/0009/03/02/
+fn hash(x: u32) -> u32 {
+  let state = x * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+fn random_color(id: u32) -> vec3<f32> {
+  let h = hash(id);
+  let rgb = vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(255u);
+  // Keep away from black so that the lighting stays readable
+  return 0.25 + 0.75 * vec3<f32>(rgb) / 255.0;
+}
+
+// Attributes of the fragment given to `custom_shade`
+struct Fragment {
+  // Facing the camera on two-sided triangles
+  normal: vec3<f32>,
+  uv: vec2<f32>,
+  // View depth
+  depth: f32,
+  barycentrics: vec3<f32>,
+  // Vertex color times the base color of the material
+  color: vec3<f32>,
+}
+
+// BEGIN custom_shade
+// Replaced by the snippet passed to `Renderer::set_shading_snippet`, which
+// has to define this function as well.
+fn custom_shade(fragment: Fragment) -> vec3<f32> {
+  let sky = vec3<f32>(0.9, 0.95, 1.0);
+  let ground = vec3<f32>(0.3, 0.25, 0.2);
+  return mix(ground, sky, fragment.normal.y * 0.5 + 0.5);
+}
+// END custom_shade
+
+// Hard shadow of the first light, 0 where something is closer to it than
+// `p`. Only a directional first light casts shadows.
+fn shadow(i: u32, p: vec3<f32>) -> f32 {
+  if (raster_uniform.shadows == 0u || i != 0u || lights.values[0].kind != LIGHT_DIRECTIONAL) {
+    return 1.0;
+  }
+  let clip = shadow_camera.view_proj * vec4<f32>(p, 1.0);
+  let uv = clip.xy / clip.w;
+  if (any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0))) {
+    return 1.0;
+  }
+  let texel = vec2<u32>(uv * f32(SHADOW_MAP_SIZE));
+  let occluder = shadow_map.values[texel.x + texel.y * SHADOW_MAP_SIZE];
+  // Cleared, nothing in the way
+  if (occluder == 0xffffffffu) {
+    return 1.0;
+  }
+  return select(1.0, 0.0, clip.z / clip.w - SHADOW_BIAS > bitcast<f32>(occluder));
+}
+
+fn world_normal(n: vec3<f32>) -> vec3<f32> {
+  return normalize((ibl_uniform.normal * vec4<f32>(n, 0.0)).xyz);
+}
+
+// Lambert diffuse of all the lights at `p` along with an ambient term
+fn lambert(n: vec3<f32>, p: vec3<f32>) -> vec3<f32> {
+  let ambient = 0.1;
+  var lit = vec3<f32>(0.0);
+  for (var i = 0u; i < raster_uniform.light_count; i = i + 1u) {
+    let light = incident(lights.values[i], p);
+    lit = lit + light.radiance * max(dot(n, light.l), 0.0) * shadow(i, p);
+  }
+  if (ibl_uniform.enabled != 0u) {
+    return ibl_irradiance(world_normal(n)) + (1.0 - ambient) * lit;
+  }
+  return ambient + (1.0 - ambient) * lit;
+}
+
+// Environment light reflected by the metallic-roughness BRDF, the diffuse
+// part from the irradiance and the specular one from the split sum
+fn ibl_ambient(base_color: vec3<f32>, n: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+  let n_world = world_normal(n);
+  let v_world = normalize((ibl_uniform.model * vec4<f32>(v, 0.0)).xyz);
+  let n_dot_v = max(dot(n_world, v_world), 1e-4);
+  let f0 = mix(vec3<f32>(0.04), base_color, material.metallic);
+  let specular = ibl_brdf(f0, material.roughness, n_dot_v);
+  let diffuse = (1.0 - specular) * (1.0 - material.metallic) * base_color;
+  let r = reflect(-v_world, n_world);
+  return diffuse * ibl_irradiance(n_world) + specular * ibl_specular(r, material.roughness);
+}
+
+// `bc` are perspective-correct barycentrics in the current triangle, `w` is
+// the interpolated view depth
+fn shade(bc: vec3<f32>, w: f32, x: u32, y: u32) -> vec3<f32> {
+  var n = normalize(bc.x * normal(fetch_vertex(0u)) + bc.y * normal(fetch_vertex(1u)) + bc.z * normal(fetch_vertex(2u)));
+  if (back_facing) {
+    n = -n;
+  }
+  let p = interpolate_position(bc);
+  let diffuse = lambert(n, p);
+
+  // See `DebugView`, the overdraw is counted in `draw_fragment` instead
+  switch (raster_uniform.debug_view) {
+    // Normals
+    case 1u: {
+      return n * 0.5 + 0.5;
+    }
+    // Depth
+    case 2u: {
+      return vec3<f32>((w * 25. - 100.) / 255.);
+    }
+    // Uv
+    case 3u: {
+      let uv = interpolate_uv(bc);
+      let checker = (floor(uv.x * 16.0) + floor(uv.y * 16.0)) % 2.0;
+      return vec3<f32>(fract(uv), 1.0) * (0.5 + 0.5 * checker);
+    }
+    // Object
+    case 4u: {
+      return random_color(current_object) * diffuse;
+    }
+    // Triangle
+    case 5u: {
+      return random_color(current_triangle) * diffuse;
+    }
+    // Barycentrics
+    case 6u: {
+      return bc;
+    }
+    // Tiles
+    case 7u: {
+      return random_color(x / TILE_SIZE + y / TILE_SIZE * tile_columns()) * diffuse;
+    }
+    // Custom
+    case 9u: {
+      return custom_shade(Fragment(n, interpolate_uv(bc), w, bc, base_color(bc)));
+    }
+    // Pbr
+    case 10u: {
+      let v = normalize(camera.view_pos.xyz - p);
+      let base = base_color(bc);
+      var color = pbr_ambient(base, material.metallic);
+      if (ibl_uniform.enabled != 0u) {
+        color = ibl_ambient(base, n, v);
+      }
+      for (var i = 0u; i < raster_uniform.light_count; i = i + 1u) {
+        let light = incident(lights.values[i], p);
+        color = color + light.radiance * pbr(base, material.metallic, material.roughness, n, v, light.l) * shadow(i, p);
+      }
+      return color;
+    }
+    default: {
+      return base_color(bc) * diffuse;
+    }
+  }
+}
+
+// `bc` are the screen space barycentrics of the sample in v1, v2, v3
+fn draw_fragment(x: u32, y: u32, bc: vec3<f32>, v1: vec4<f32>, v2: vec4<f32>, v3: vec4<f32>, corners: mat3x3<f32>) {
+  // Attributes are linear in 1/w screen space, not in screen space
+  let bc_over_w = bc / vec3<f32>(v1.w, v2.w, v3.w);
+  let inv_w = bc_over_w.x + bc_over_w.y + bc_over_w.z;
+  let triangle_bc = corners * (bc_over_w / inv_w);
+  if (is_masked(triangle_bc)) {
+    return;
+  }
+  let depth = bc.x * v1.z + bc.y * v2.z + bc.z * v3.z;
+  if (!depth_test(x, y, depth)) {
+    return;
+  }
+  if (raster_uniform.debug_view == VIEW_OVERDRAW) {
+    count_fragment(x, y);
+    return;
+  }
+
+  color_pixel(x, y, shade(triangle_bc, 1.0 / inv_w, x, y));
+}
+
+// `corners` holds the barycentrics of v1, v2, v3 in the current triangle,
+// they differ from the identity for subdivided triangles. `a`, `b`, `c` are
+// v1, v2, v3 snapped and `area` their edge function.
+fn draw_triangle(v1: vec4<f32>, v2: vec4<f32>, v3: vec4<f32>, corners: mat3x3<f32>, a: vec2<i32>, b: vec2<i32>, c: vec2<i32>, area: vec2<u32>) {
+  // Back facing triangles get two vertices swapped, so that the edge
+  // functions are positive inside
+  var p2 = v2;
+  var p3 = v3;
+  var s2 = b;
+  var s3 = c;
+  var swapped = corners;
+  var positive_area = area;
+  if (wide_is_negative(area)) {
+    p2 = v3;
+    p3 = v2;
+    s2 = c;
+    s3 = b;
+    swapped = mat3x3<f32>(corners[0], corners[2], corners[1]);
+    positive_area = wide_neg(area);
+  }
+  let inv_area = 1.0 / wide_to_f32(positive_area);
+
+  let min_max = screen_bounds(a, b, c);
   let startX = u32(min_max.x);
   let startY = u32(min_max.y);
   let endX = u32(min_max.z);
   let endY = u32(min_max.w);
 
+  // Stepped from pixel to pixel, exact since everything is an integer
+  let origin = vec2<i32>(i32(startX), i32(startY)) * SUBPIXEL_SCALE;
+  let e1 = setup_edge(s2, s3, origin);
+  let e2 = setup_edge(s3, a, origin);
+  let e3 = setup_edge(a, s2, origin);
+  var w1 = e1.value;
+  var w2 = e2.value;
+  var w3 = e3.value;
+
   for (var x: u32 = startX; x <= endX; x = x + 1u) {
+    var r1 = w1;
+    var r2 = w2;
+    var r3 = w3;
     for (var y : u32 = startY; y <= endY; y = y + 1u) {
-      let bc = barycentric(v1, v2, v3, vec2<f32>(f32(x), f32(y)));
-      let color = (bc.x * v1.z + bc.y * v2.z + bc.z * v3.z) * 25. - 100.;
-
-      let R = color;
-      let G = color;
-      let B = color;
-
-      if (bc.x < 0.0 || bc.y < 0.0 || bc.z < 0.0) {
-        continue;
+      if (covers(r1, e1.top_left) && covers(r2, e2.top_left) && covers(r3, e3.top_left)) {
+        let bc = vec3<f32>(wide_to_f32(r1), wide_to_f32(r2), wide_to_f32(r3)) * inv_area;
+        draw_fragment(x, y, bc, v1, p2, p3, swapped);
       }
-      color_pixel(x, y, u32(R), u32(G), u32(B));
+      r1 = wide_add(r1, e1.step_y);
+      r2 = wide_add(r2, e2.step_y);
+      r3 = wide_add(r3, e3.step_y);
     }
+    w1 = wide_add(w1, e1.step_x);
+    w2 = wide_add(w2, e2.step_x);
+    w3 = wide_add(w3, e3.step_x);
   }
 }
 
-// move it inside the color pix function
-fn is_off_screen(v: vec3<f32>) -> bool {
-  if (v.x < 0.0 || v.x > screen_dims.width || v.y < 0.0 ||
-      v.y > screen_dims.height) {
-    return true;
-  }
-
-  return false;
+// Equirectangular triangles crossing the seam would otherwise span the whole screen
+fn wraps_around(v1: vec4<f32>, v2: vec4<f32>, v3: vec4<f32>) -> bool {
+  let span = max(max(v1.x, v2.x), v3.x) - min(min(v1.x, v2.x), v3.x);
+  return camera.projection == PROJECTION_EQUIRECTANGULAR && span > screen_dims.width;
 }
 
-@compute @workgroup_size(256, 1)
-fn raster(@builtin(global_invocation_id) global_id: vec3<u32>) {
-  let index = global_id.x * 3u;
+fn raster_triangle(p1: vec3<f32>, p2: vec3<f32>, p3: vec3<f32>, corners: mat3x3<f32>) {
+  let v1 = project(p1);
+  let v2 = project(p2);
+  let v3 = project(p3);
+  let a = snap(v1);
+  let b = snap(v2);
+  let c = snap(v3);
 
-  let v1 = project(vertex_buffer.values[index + 0u]);
-  let v2 = project(vertex_buffer.values[index + 1u]);
-  let v3 = project(vertex_buffer.values[index + 2u]);
+  let bounds = screen_bounds(a, b, c);
+  if (bounds.x > bounds.z || bounds.y > bounds.w) {
+    return;
+  }
 
-  if (is_off_screen(v1) || is_off_screen(v2) || is_off_screen(v3)) {
+  // Degenerate once snapped, it covers no sample
+  let area = edge_function(a, b, c);
+  if (wide_is_zero(area)) {
+    return;
+  }
+  // Counter-clockwise triangles are front facing, as in glTF
+  back_facing = wide_is_negative(area);
+  if (material.double_sided == 0u && back_facing) {
+    return;
+  }
+
+  if (wraps_around(v1, v2, v3)) {
+    return;
+  }
+
+  if (bounds_only) {
+    triangle_bounds = vec4<f32>(min(triangle_bounds.xy, bounds.xy), max(triangle_bounds.zw, bounds.zw));
     return;
   }
 
@@ -125,14 +673,202 @@ fn raster(@builtin(global_invocation_id) global_id: vec3<u32>) {
   // draw_line(v1, v3);
   // draw_line(v2, v3);
 
-  draw_triangle(v1, v2, v3);
+  draw_triangle(v1, v2, v3, corners, a, b, c, area);
 }
 
-@compute @workgroup_size(256, 1)
-fn clear(@builtin(global_invocation_id) global_id: vec3<u32>) {
-  let index = global_id.x * 3u;
+// Sutherland-Hodgman clipping against the near plane, the clipped polygon
+// has at most 4 vertices and is fanned into up to two triangles. Vertices
+// are kept as barycentrics of the original triangle.
+fn raster_clipped(p1: vec3<f32>, p2: vec3<f32>, p3: vec3<f32>) {
+  let positions = mat3x3<f32>(p1, p2, p3);
+  var identity = mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0));
+  // Clip space z, the visible depth range is [0, w]
+  var dist = vec3<f32>(
+    (camera.view_proj * vec4<f32>(p1, 1.0)).z,
+    (camera.view_proj * vec4<f32>(p2, 1.0)).z,
+    (camera.view_proj * vec4<f32>(p3, 1.0)).z,
+  );
+  if (all(dist < vec3<f32>(0.0))) {
+    return;
+  }
+  if (all(dist >= vec3<f32>(0.0))) {
+    raster_triangle(p1, p2, p3, identity);
+    return;
+  }
+
+  var polygon: array<vec3<f32>, 4>;
+  var count = 0u;
+  for (var i = 0u; i < 3u; i = i + 1u) {
+    let j = (i + 1u) % 3u;
+    if (dist[i] >= 0.0) {
+      polygon[count] = identity[i];
+      count = count + 1u;
+    }
+    if ((dist[i] >= 0.0) != (dist[j] >= 0.0)) {
+      let t = dist[i] / (dist[i] - dist[j]);
+      polygon[count] = mix(identity[i], identity[j], t);
+      count = count + 1u;
+    }
+  }
+  for (var k = 1u; k + 1u < count; k = k + 1u) {
+    let corners = mat3x3<f32>(polygon[0], polygon[k], polygon[k + 1u]);
+    raster_triangle(positions * corners[0], positions * corners[1], positions * corners[2], corners);
+  }
+}
+
+// Splits the triangle into SUBDIVISIONS^2 smaller ones so that straight edges
+// follow the curvature of non-linear projections
+fn raster_subdivided(p1: vec3<f32>, p2: vec3<f32>, p3: vec3<f32>) {
+  let positions = mat3x3<f32>(p1, p2, p3);
+  let n = f32(SUBDIVISIONS);
+  let du = vec3<f32>(-1.0, 1.0, 0.0) / n;
+  let dv = vec3<f32>(-1.0, 0.0, 1.0) / n;
+  for (var i = 0u; i < SUBDIVISIONS; i = i + 1u) {
+    for (var j = 0u; j < SUBDIVISIONS - i; j = j + 1u) {
+      let a = vec3<f32>(1.0, 0.0, 0.0) + du * f32(i) + dv * f32(j);
+      let up = mat3x3<f32>(a, a + du, a + dv);
+      raster_triangle(positions * up[0], positions * up[1], positions * up[2], up);
+      if (i + j + 1u < SUBDIVISIONS) {
+        let down = mat3x3<f32>(a + du, a + du + dv, a + dv);
+        raster_triangle(positions * down[0], positions * down[1], positions * down[2], down);
+      }
+    }
+  }
+}
+
+fn raster_index(triangle_id: u32) {
+  current_triangle = triangle_id;
+  current_object = triangle_info.values[triangle_id].object;
+  material = materials.values[triangle_info.values[triangle_id].material];
+
+  let p1 = position(fetch_vertex(0u));
+  let p2 = position(fetch_vertex(1u));
+  let p3 = position(fetch_vertex(2u));
+
+  if (camera.projection == PROJECTION_PERSPECTIVE) {
+    raster_clipped(p1, p2, p3);
+  } else {
+    raster_subdivided(p1, p2, p3);
+  }
+}
+
+// Only the triangles that survived culling are rasterized
+fn triangle_count() -> u32 {
+  return cull_result.count;
+}
 
-  atomicStore(&color_buffer.values[index + 0u], 255u);
-  atomicStore(&color_buffer.values[index + 1u], 255u);
-  atomicStore(&color_buffer.values[index + 2u], 255u);
+fn visible_triangle(i: u32) -> u32 {
+  return visible_triangles.values[i];
+}
+
+fn raster_all(global_id: vec3<u32>) {
+  let index = chunk.offset + global_id.x;
+  if (global_id.x >= chunk.count || index >= triangle_count()) {
+    return;
+  }
+  raster_index(visible_triangle(index));
+}
+
+// Persistent threads, every invocation pulls triangles from the global queue
+// so that workgroups stuck on big triangles don't hold back the others
+fn raster_queue() {
+  let count = triangle_count();
+  loop {
+#ifdef DEPTH_PASS
+    let triangle_id = atomicAdd(&work_queue.depth, 1u);
+#else
+    let triangle_id = atomicAdd(&work_queue.color, 1u);
+#endif
+    if (triangle_id >= count) {
+      break;
+    }
+    raster_index(visible_triangle(triangle_id));
+  }
+}
+
+// Range of tiles covered by the triangle, including all of its clipped or
+// subdivided parts. Empty ranges have x > z.
+fn triangle_tiles(triangle_id: u32) -> vec4<i32> {
+  bounds_only = true;
+  triangle_bounds = vec4<f32>(F32_MAX, F32_MAX, -F32_MAX, -F32_MAX);
+  raster_index(triangle_id);
+  if (triangle_bounds.x > triangle_bounds.z || triangle_bounds.y > triangle_bounds.w) {
+    return vec4<i32>(1, 1, 0, 0);
+  }
+  return vec4<i32>(vec4<u32>(triangle_bounds) / TILE_SIZE);
+}
+
+// First binning pass, counts the triangles overlapping every tile
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn bin_count(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  if (global_id.x >= triangle_count()) {
+    return;
+  }
+  let tiles = triangle_tiles(visible_triangle(global_id.x));
+  for (var y = tiles.y; y <= tiles.w; y = y + 1) {
+    for (var x = tiles.x; x <= tiles.z; x = x + 1) {
+      atomicAdd(&tile_counts.values[u32(x) + u32(y) * tile_columns()], 1u);
+    }
+  }
+}
+
+// Second binning pass, runs after the scan of the counts and writes the
+// triangle ids into their tile ranges, counting the tiles back down to zero
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn bin_scatter(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  if (global_id.x >= triangle_count()) {
+    return;
+  }
+  let triangle_id = visible_triangle(global_id.x);
+  let tiles = triangle_tiles(triangle_id);
+  for (var y = tiles.y; y <= tiles.w; y = y + 1) {
+    for (var x = tiles.x; x <= tiles.z; x = x + 1) {
+      let tile = u32(x) + u32(y) * tile_columns();
+      let slot = tile_offsets.values[tile] + atomicSub(&tile_counts.values[tile], 1u) - 1u;
+      // Entries past the capacity are dropped
+      if (slot < arrayLength(&tile_entries.values)) {
+        tile_entries.values[slot] = triangle_id;
+      }
+    }
+  }
+}
+
+// One workgroup per tile, the invocations share the binned triangles
+fn raster_tile(tile: u32, local_id: u32) {
+  if (tile >= tile_count()) {
+    return;
+  }
+  let origin = vec2<f32>(vec2<u32>(tile % tile_columns(), tile / tile_columns()) * TILE_SIZE);
+  tile_rect = vec4<f32>(origin, origin + f32(TILE_SIZE - 1u));
+
+  let end = min(tile_offsets.values[tile + 1u], arrayLength(&tile_entries.values));
+  for (var i = tile_offsets.values[tile] + local_id; i < end; i = i + WORKGROUP_SIZE) {
+    raster_index(tile_entries.values[i]);
+  }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn raster_tiles(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_id) local_id: vec3<u32>) {
+  raster_tile(workgroup_id.x, local_id.x);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn raster(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  raster_all(global_id);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn raster_persistent() {
+  raster_queue();
+}
+
+// The depth pass zeroes the color of the pixels it covers again, the clear
+// color stays in the others. The background is added to it by the present
+// pass wherever the depth is left cleared.
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn clear(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  if (clear_uniform.write_color != 0u) {
+    atomicStore(&color_buffer.values[global_id.x], clear_uniform.color);
+  }
+  atomicStore(&depth_buffer.values[global_id.x], 0xffffffffu);
 }
@@ -1,68 +1,592 @@
+use std::fmt;
+
+use bytemuck::{Pod, Zeroable};
+use color_eyre::Result;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    cull_pass::{CullBindings, Occlusion},
+    lights::MAX_LIGHTS,
+    occupancy::PassOccupancy,
+    render_graph::{Frame, GraphPass, Node, Pass, Stage, Targets},
+    scan_pass::{ScanBindings, ScanPass},
+    shaders::{validated, Shaders, Source},
+    util::{pack_color, Labels, WorkgroupSize, PERSISTENT_WORKGROUPS},
+};
+
+const LABELS: Labels = Labels::new("Raster");
+const CLEAR_LABELS: Labels = Labels::new("Clear");
+
+/// What the rasterizer writes into the color buffer, a single switch in
+/// `shade` of raster.wgsl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DebugView {
+    /// Diffuse lighting from a single directional light
+    Lambert,
+    /// Interpolated normals facing the camera, mapped to [0, 1]
+    Normals,
+    /// Interpolated view depth
+    Depth,
+    /// Perspective-correct texture coordinates over a checkerboard
+    Uv,
+    /// Stable random color per glTF primitive, lit like `Lambert`
+    Object,
+    /// Stable random color per triangle, lit like `Lambert`
+    Triangle,
+    /// Perspective-correct barycentrics of the triangle as RGB
+    Barycentrics,
+    /// Screen tiles of the binned raster in random colors, lit like `Lambert`
+    Tiles,
+    /// Fragments written per pixel, shared edges hit twice show up brighter
+    Overdraw,
+    /// `custom_shade` of raster.wgsl, see `Renderer::set_shading_snippet`
+    Custom,
+    /// Metallic-roughness BRDF of the glTF materials under the light
+    Pbr,
+}
+
+impl DebugView {
+    const ALL: [DebugView; 11] = [
+        DebugView::Lambert,
+        DebugView::Normals,
+        DebugView::Depth,
+        DebugView::Uv,
+        DebugView::Object,
+        DebugView::Triangle,
+        DebugView::Barycentrics,
+        DebugView::Tiles,
+        DebugView::Overdraw,
+        DebugView::Custom,
+        DebugView::Pbr,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn prev(self) -> Self {
+        Self::ALL[(self as usize + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+/// Exponent the overdraw view sets and what it then adds to the packed color
+/// per fragment, a quarter on every channel as in raster.wgsl.
+const OVERDRAW_EXPONENT: u32 = 0x7800_0000;
+const OVERDRAW_STEP: u32 = 0x201_0080;
+
+/// Fragments per pixel of a frame rendered with the overdraw view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Pixels written at least once
+    pub covered: u32,
+    /// Pixels written more than once, the fill rule prevents it along shared edges
+    pub double_hits: u32,
+    /// Empty pixels between two covered ones, horizontally or vertically. Only
+    /// meaningful for closed surfaces like `TestScene::SharedEdge`.
+    pub cracks: u32,
+}
+
+impl Coverage {
+    /// `colors` is the color buffer read back, one packed color per pixel.
+    /// Counts past 3 carry over into the next channel, the packed value past
+    /// the exponent still divides into the exact count.
+    pub fn new(colors: &[u32], width: u32, height: u32) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        let hits = |x: usize, y: usize| {
+            colors[x + y * width].saturating_sub(OVERDRAW_EXPONENT) / OVERDRAW_STEP
+        };
+        let covered = |x: Option<usize>, y: Option<usize>| match (x, y) {
+            (Some(x), Some(y)) if x < width && y < height => hits(x, y) > 0,
+            _ => false,
+        };
+        let mut coverage = Self::default();
+        for y in 0..height {
+            for x in 0..width {
+                let hits = hits(x, y);
+                coverage.covered += (hits > 0) as u32;
+                coverage.double_hits += (hits > 1) as u32;
+                let horizontal =
+                    covered(x.checked_sub(1), Some(y)) && covered(Some(x + 1), Some(y));
+                let vertical = covered(Some(x), y.checked_sub(1)) && covered(Some(x), Some(y + 1));
+                coverage.cracks += (hits == 0 && (horizontal || vertical)) as u32;
+            }
+        }
+        coverage
+    }
+
+    pub fn is_watertight(&self) -> bool {
+        self.double_hits == 0 && self.cracks == 0
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pixels covered, {} hit more than once, {} cracks",
+            self.covered, self.double_hits, self.cracks
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct RasterUniform {
+    light_count: u32,
+    debug_view: u32,
+    jitter: [f32; 2],
+    shadows: u32,
+    _padding: [u32; 3],
+}
+
+impl RasterUniform {
+    /// `light_count` lights of the light buffer are shaded with, `jitter`
+    /// offsets the samples in pixels. `shadows` looks the first light up in
+    /// the shadow map, see `ShadowMap`.
+    pub fn new(debug_view: DebugView, light_count: usize, jitter: Vec2, shadows: bool) -> Self {
+        Self {
+            light_count: light_count.min(MAX_LIGHTS) as u32,
+            debug_view: debug_view as u32,
+            jitter: jitter.to_array(),
+            shadows: shadows as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct ClearUniform {
+    color: u32,
+    write_color: u32,
+    _padding: [u32; 2],
+}
+
+impl ClearUniform {
+    /// `color` is linear, it goes through the exposure like the rest of the
+    /// color buffer.
+    pub fn new(color: [f32; 3]) -> Self {
+        let color = pack_color(color);
+        Self {
+            color,
+            write_color: (color != 0) as u32,
+            _padding: [0; 2],
+        }
+    }
+
+    /// A black clear is left to `CommandEncoder::clear_buffer`, the compute
+    /// clear then only resets the depth.
+    pub fn writes_color(&self) -> bool {
+        self.write_color != 0
+    }
+}
+
+/// How triangles are distributed over the invocations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RasterMode {
+    /// One invocation per triangle
+    Triangles,
+    /// Fixed number of workgroups pulling triangles from a queue
+    Persistent,
+    /// Triangles binned into screen tiles, one workgroup per tile
+    Tiled,
+}
+
+impl RasterMode {
+    pub fn next(self) -> Self {
+        match self {
+            RasterMode::Triangles => RasterMode::Persistent,
+            RasterMode::Persistent => RasterMode::Tiled,
+            RasterMode::Tiled => RasterMode::Triangles,
+        }
+    }
+}
+
+/// Side of the square screen tiles, matches TILE_SIZE in raster.wgsl.
+pub const TILE_SIZE: u32 = 16;
+/// Average number of triangles a tile can hold before entries are dropped.
+const TILE_CAPACITY: u32 = 64;
+
+/// Per-tile triangle lists of the binned raster, sized for a screen resolution.
+pub struct TileBins {
+    counts: wgpu::Buffer,
+    offsets: wgpu::Buffer,
+    entries: wgpu::Buffer,
+    scan: ScanBindings,
+    tiles: u32,
+}
+
+impl TileBins {
+    pub fn new(
+        device: &wgpu::Device,
+        RasterPass { scan, .. }: &RasterPass,
+        width: u32,
+        height: u32,
+        triangle_count: u32,
+    ) -> Self {
+        let tiles = width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE);
+        let capacity = (tiles * TILE_CAPACITY).max(triangle_count * 4);
+        let u32_size = std::mem::size_of::<u32>() as u64;
+        let storage = |label: &str, len: u32, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: u32_size * len as u64,
+                usage: wgpu::BufferUsages::STORAGE | usage,
+                mapped_at_creation: false,
+            })
+        };
+        // The extra entry makes the scan also write the total at the end of the offsets
+        let counts = storage(
+            &LABELS.of("Tile Counts Buffer"),
+            tiles + 1,
+            wgpu::BufferUsages::COPY_DST,
+        );
+        let offsets = storage(
+            &LABELS.of("Tile Offsets Buffer"),
+            tiles + 1,
+            wgpu::BufferUsages::empty(),
+        );
+        let entries = storage(
+            &LABELS.of("Tile Entries Buffer"),
+            capacity,
+            wgpu::BufferUsages::empty(),
+        );
+        let scan = ScanBindings::new(device, scan, &counts, &offsets, tiles + 1);
+        Self {
+            counts,
+            offsets,
+            entries,
+            scan,
+            tiles,
+        }
+    }
+
+    /// Has to be zeroed before binning.
+    pub fn counts(&self) -> &wgpu::Buffer {
+        &self.counts
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct RasterChunk {
+    offset: u32,
+    count: u32,
+    _padding: [u32; 2],
+}
+
+/// Ranges of the visible triangles rasterized by separate dispatches, each
+/// bound with a dynamic offset. The first entry covers all the triangles.
+pub struct RasterChunks {
+    buffer: wgpu::Buffer,
+    stride: u32,
+    size: u32,
+    count: u32,
+}
+
+impl RasterChunks {
+    /// `size` triangles per chunk, `None` keeps everything in one dispatch.
+    pub fn new(device: &wgpu::Device, triangle_count: u32, size: Option<u32>) -> Self {
+        let stride = device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(std::mem::size_of::<RasterChunk>() as u32);
+        let size = size.unwrap_or(triangle_count).max(1);
+        let count = triangle_count.div_ceil(size).max(1);
+
+        let mut contents = vec![0; (stride * (count + 1)) as usize];
+        let ranges = std::iter::once((0, u32::MAX)).chain((0..count).map(|i| (i * size, size)));
+        for (entry, (offset, count)) in contents.chunks_mut(stride as usize).zip(ranges) {
+            let chunk = RasterChunk {
+                offset,
+                count,
+                _padding: [0; 2],
+            };
+            entry[..std::mem::size_of::<RasterChunk>()].copy_from_slice(bytemuck::bytes_of(&chunk));
+        }
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Chunks Buffer")),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        Self {
+            buffer,
+            stride,
+            size,
+            count,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    fn offset(&self, chunk: Option<u32>) -> u32 {
+        chunk.map_or(0, |i| (i + 1) * self.stride)
+    }
+}
+
 pub struct RasterPass {
+    depth_pipeline: wgpu::ComputePipeline,
     pipeline: wgpu::ComputePipeline,
+    persistent_depth_pipeline: wgpu::ComputePipeline,
+    persistent_pipeline: wgpu::ComputePipeline,
+    bin_count_pipeline: wgpu::ComputePipeline,
+    bin_scatter_pipeline: wgpu::ComputePipeline,
+    tiles_depth_pipeline: wgpu::ComputePipeline,
+    tiles_pipeline: wgpu::ComputePipeline,
+    scan: ScanPass,
+    /// Kept to rebuild the pipelines from an edited shader, the bindings stay valid
+    layout: wgpu::PipelineLayout,
+    workgroup_size: WorkgroupSize,
 }
 
 impl RasterPass {
-    pub fn new(device: &wgpu::Device) -> Self {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
         let output_color_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Raster: Uniform Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                label: Some(&LABELS.of("Output Buffer Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
+        // Indices share the geometry group, the default limits only allow 4 bind groups
         let vertex_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Raster: Vertex Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                label: Some(&LABELS.of("Vertex Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
         let uniform_bind_group =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Raster: Uniform Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Raster: Camera Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                label: Some(&LABELS.of("Camera Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Raster Pipeline Layout"),
+            label: Some(&LABELS.pipeline_layout()),
             bind_group_layouts: &[
                 &output_color_bind_group_layout,
                 &vertex_bind_group_layout,
@@ -71,82 +595,365 @@ impl RasterPass {
             ],
             push_constant_ranges: &[],
         });
-        let shader = device.create_shader_module(wgpu::include_wgsl!("raster.wgsl"));
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Raster Pipeline"),
-            layout: Some(&layout),
-            module: &shader,
-            entry_point: "raster",
-        });
-        Self { pipeline }
+        let (depth_shader, shader) =
+            Self::create_shaders(device, &shaders.raster(), workgroup_size).unwrap();
+        let [depth_pipeline, pipeline, persistent_depth_pipeline, persistent_pipeline, bin_count_pipeline, bin_scatter_pipeline, tiles_depth_pipeline, tiles_pipeline] =
+            Self::create_pipelines(device, &layout, &depth_shader, &shader);
+        Self {
+            depth_pipeline,
+            pipeline,
+            persistent_depth_pipeline,
+            persistent_pipeline,
+            bin_count_pipeline,
+            bin_scatter_pipeline,
+            tiles_depth_pipeline,
+            tiles_pipeline,
+            scan: ScanPass::new(device, shaders, workgroup_size),
+            layout,
+            workgroup_size,
+        }
+    }
+
+    /// Rebuilds the pipelines from a new raster.wgsl, they are kept when it
+    /// fails validation.
+    pub fn reload(&mut self, device: &wgpu::Device, source: &Source) -> Result<()> {
+        [
+            self.depth_pipeline,
+            self.pipeline,
+            self.persistent_depth_pipeline,
+            self.persistent_pipeline,
+            self.bin_count_pipeline,
+            self.bin_scatter_pipeline,
+            self.tiles_depth_pipeline,
+            self.tiles_pipeline,
+        ] = validated(device, || -> Result<_> {
+            let (depth_shader, shader) = Self::create_shaders(device, source, self.workgroup_size)?;
+            Ok(Self::create_pipelines(
+                device,
+                &self.layout,
+                &depth_shader,
+                &shader,
+            ))
+        })??;
+        Ok(())
+    }
+
+    /// The depth and color permutations of raster.wgsl.
+    fn create_shaders(
+        device: &wgpu::Device,
+        source: &Source,
+        workgroup_size: WorkgroupSize,
+    ) -> Result<(wgpu::ShaderModule, wgpu::ShaderModule)> {
+        let size = workgroup_size.literal();
+        Ok((
+            source.module(device, &[("WORKGROUP_SIZE", &size), ("DEPTH_PASS", "")])?,
+            source.module(device, &[("WORKGROUP_SIZE", &size)])?,
+        ))
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        depth_shader: &wgpu::ShaderModule,
+        shader: &wgpu::ShaderModule,
+    ) -> [wgpu::ComputePipeline; 8] {
+        let pipeline = |label: &str, module, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                module,
+                entry_point,
+            })
+        };
+        [
+            pipeline(&LABELS.of("Depth Pipeline"), depth_shader, "raster"),
+            pipeline(&LABELS.pipeline(), shader, "raster"),
+            pipeline(
+                &LABELS.of("Persistent Depth Pipeline"),
+                depth_shader,
+                "raster_persistent",
+            ),
+            pipeline(
+                &LABELS.of("Persistent Pipeline"),
+                shader,
+                "raster_persistent",
+            ),
+            pipeline(&LABELS.of("Bin Count Pipeline"), shader, "bin_count"),
+            pipeline(&LABELS.of("Bin Scatter Pipeline"), shader, "bin_scatter"),
+            pipeline(
+                &LABELS.of("Tiles Depth Pipeline"),
+                depth_shader,
+                "raster_tiles",
+            ),
+            pipeline(&LABELS.of("Tiles Pipeline"), shader, "raster_tiles"),
+        ]
     }
 }
 
 impl<'a> RasterPass {
+    fn set_bind_groups<'pass>(cpass: &mut wgpu::ComputePass<'pass>, bindings: &'a RasterBindings)
+    where
+        'a: 'pass,
+    {
+        cpass.set_bind_group(0, &bindings.color_buffer, &[]);
+        cpass.set_bind_group(1, &bindings.vertex_buffer, &[]);
+        cpass.set_bind_group(2, &bindings.uniform, &[0]);
+        cpass.set_bind_group(3, &bindings.camera_uniform, &[]);
+    }
+
+    /// Dispatches all the visible triangles at once with the culling result,
+    /// or chunk by chunk when there are several.
+    fn dispatch<'pass>(
+        &self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a RasterBindings,
+        chunks: &RasterChunks,
+        cull: &'a CullBindings,
+    ) where
+        'a: 'pass,
+    {
+        if chunks.count() == 1 {
+            cpass.dispatch_workgroups_indirect(cull.result(), 0);
+            return;
+        }
+        for chunk in 0..chunks.count() {
+            cpass.set_bind_group(2, &bindings.uniform, &[chunks.offset(Some(chunk))]);
+            cpass.dispatch_workgroups(self.workgroup_size.dispatch_size(chunks.size()), 1, 1);
+        }
+        cpass.set_bind_group(2, &bindings.uniform, &[chunks.offset(None)]);
+    }
+
+    /// One invocation per visible triangle, dispatched with the culling result.
+    /// `chunks` split the triangles over several dispatches of the pass.
+    /// With `occlusion` the color pass only gets the triangles left visible
+    /// by the depth of the depth pass.
     pub fn record<'pass>(
         &'a self,
         cpass: &mut wgpu::ComputePass<'pass>,
         bindings: &'a RasterBindings,
-        dispatch_size: u32,
+        chunks: &RasterChunks,
+        cull: &'a CullBindings,
+        occlusion: Option<Occlusion<'a>>,
     ) where
         'a: 'pass,
     {
+        Self::set_bind_groups(cpass, bindings);
+        cpass.set_pipeline(&self.depth_pipeline);
+        self.dispatch(cpass, bindings, chunks, cull);
+        if let Some(occlusion) = occlusion {
+            occlusion.record(cpass, cull);
+            Self::set_bind_groups(cpass, bindings);
+        }
         cpass.set_pipeline(&self.pipeline);
-        cpass.set_bind_group(0, &bindings.color_buffer, &[]);
-        cpass.set_bind_group(1, &bindings.vertex_buffer, &[]);
-        cpass.set_bind_group(2, &bindings.uniform, &[]);
-        cpass.set_bind_group(3, &bindings.camera_uniform, &[]);
-        cpass.dispatch_workgroups(dispatch_size, 1, 1);
+        self.dispatch(cpass, bindings, chunks, cull);
+    }
+
+    /// Only the depth pass of `record`, for a depth buffer read by another
+    /// pass like the shadow map.
+    pub fn record_depth<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a RasterBindings,
+        chunks: &RasterChunks,
+        cull: &'a CullBindings,
+    ) where
+        'a: 'pass,
+    {
+        Self::set_bind_groups(cpass, bindings);
+        cpass.set_pipeline(&self.depth_pipeline);
+        self.dispatch(cpass, bindings, chunks, cull);
+    }
+
+    /// Rasterizes a single chunk, either the depth or the color pass of it.
+    /// Used to spread the work of huge meshes over several submissions.
+    pub fn record_chunk<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a RasterBindings,
+        chunks: &RasterChunks,
+        chunk: u32,
+        depth_pass: bool,
+    ) where
+        'a: 'pass,
+    {
+        Self::set_bind_groups(cpass, bindings);
+        cpass.set_bind_group(2, &bindings.uniform, &[chunks.offset(Some(chunk))]);
+        if depth_pass {
+            cpass.set_pipeline(&self.depth_pipeline);
+        } else {
+            cpass.set_pipeline(&self.pipeline);
+        }
+        cpass.dispatch_workgroups(self.workgroup_size.dispatch_size(chunks.size()), 1, 1);
+    }
+
+    /// Persistent threads variant, a fixed number of workgroups keep pulling
+    /// triangles from the work queues until they are drained.
+    /// The work queues have to be zeroed before the pass.
+    pub fn record_persistent<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a RasterBindings,
+        workgroups: u32,
+        cull: &'a CullBindings,
+        occlusion: Option<Occlusion<'a>>,
+    ) where
+        'a: 'pass,
+    {
+        Self::set_bind_groups(cpass, bindings);
+        cpass.set_pipeline(&self.persistent_depth_pipeline);
+        cpass.dispatch_workgroups(workgroups, 1, 1);
+        if let Some(occlusion) = occlusion {
+            occlusion.record(cpass, cull);
+            Self::set_bind_groups(cpass, bindings);
+        }
+        cpass.set_pipeline(&self.persistent_pipeline);
+        cpass.dispatch_workgroups(workgroups, 1, 1);
+    }
+
+    /// Tile binned variant, triangles are first counted and scattered into
+    /// per-tile lists, then every tile is rasterized by its own workgroup.
+    /// The tile counts have to be zeroed before the pass.
+    pub fn record_tiled<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a RasterBindings,
+        tile_bins: &'a TileBins,
+        cull: &'a CullBindings,
+    ) where
+        'a: 'pass,
+    {
+        let set_bind_groups = |cpass: &mut wgpu::ComputePass<'pass>| {
+            cpass.set_bind_group(0, &bindings.color_buffer, &[]);
+            cpass.set_bind_group(1, &bindings.vertex_buffer, &[]);
+            cpass.set_bind_group(2, &bindings.uniform, &[0]);
+            cpass.set_bind_group(3, &bindings.camera_uniform, &[]);
+        };
+        set_bind_groups(cpass);
+        cpass.set_pipeline(&self.bin_count_pipeline);
+        cpass.dispatch_workgroups_indirect(cull.result(), 0);
+
+        self.scan.record(cpass, &tile_bins.scan);
+
+        set_bind_groups(cpass);
+        cpass.set_pipeline(&self.bin_scatter_pipeline);
+        cpass.dispatch_workgroups_indirect(cull.result(), 0);
+        cpass.set_pipeline(&self.tiles_depth_pipeline);
+        cpass.dispatch_workgroups(tile_bins.tiles, 1, 1);
+        cpass.set_pipeline(&self.tiles_pipeline);
+        cpass.dispatch_workgroups(tile_bins.tiles, 1, 1);
     }
 }
 
 pub struct RasterBindings {
-    pub color_buffer: wgpu::BindGroup,
+    color_buffer: wgpu::BindGroup,
     vertex_buffer: wgpu::BindGroup,
     uniform: wgpu::BindGroup,
     camera_uniform: wgpu::BindGroup,
 }
 
 impl RasterBindings {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
-        RasterPass { pipeline }: &RasterPass,
+        RasterPass { pipeline, .. }: &RasterPass,
         color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
         vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+        materials: &wgpu::Buffer,
+        triangles: &wgpu::Buffer,
+        work_queue: &wgpu::Buffer,
+        cull: &CullBindings,
         uniform: &wgpu::Buffer,
+        raster_uniform: &wgpu::Buffer,
+        tile_bins: &TileBins,
+        chunks: &RasterChunks,
         camera_uniform: &wgpu::Buffer,
+        lights: &wgpu::Buffer,
+        shadow_map: &wgpu::Buffer,
+        shadow_camera: &wgpu::Buffer,
+        ibl: &wgpu::Buffer,
+        ibl_uniform: &wgpu::Buffer,
     ) -> Self {
         let color_buffer = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Raster: Output Buffer Bind Group"),
+            label: Some(&LABELS.of("Output Buffer Bind Group")),
             layout: &pipeline.get_bind_group_layout(0),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: color_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+            ],
         });
         let vertex_buffer = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Raster: Vertex Buffer Bind Group"),
+            label: Some(&LABELS.of("Vertex Buffer Bind Group")),
             layout: &pipeline.get_bind_group_layout(1),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: vertex_buffer.as_entire_binding(),
-            }],
-        });
-        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Raster: Uniform Bind Group"),
-            layout: &pipeline.get_bind_group_layout(2),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: materials.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: work_queue.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: triangles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: index_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: cull.visible().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: cull.result().as_entire_binding(),
+                },
+            ],
         });
+        let uniform =
+            Self::uniform_bind_group(device, pipeline, uniform, raster_uniform, tile_bins, chunks);
         let camera_uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Raster: Camera Uniform Bind Group"),
+            label: Some(&LABELS.of("Camera Uniform Bind Group")),
             layout: &pipeline.get_bind_group_layout(3),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_uniform.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: shadow_map.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: shadow_camera.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: ibl.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: ibl_uniform.as_entire_binding(),
+                },
+            ],
         });
         Self {
             color_buffer,
@@ -156,56 +963,206 @@ impl RasterBindings {
         }
     }
 
-    pub fn update_color_buffer(
+    fn uniform_bind_group(
+        device: &wgpu::Device,
+        pipeline: &wgpu::ComputePipeline,
+        uniform: &wgpu::Buffer,
+        raster_uniform: &wgpu::Buffer,
+        tile_bins: &TileBins,
+        chunks: &RasterChunks,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(2),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: raster_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: tile_bins.counts.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: tile_bins.offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: tile_bins.entries.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &chunks.buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<RasterChunk>() as u64),
+                    }),
+                },
+            ],
+        })
+    }
+
+    /// Rebinds the uniforms after the tile bins or the chunks were recreated.
+    pub fn update_uniform(
         &mut self,
         device: &wgpu::Device,
-        RasterPass { pipeline }: &RasterPass,
-        color_buffer: &wgpu::Buffer,
+        RasterPass { pipeline, .. }: &RasterPass,
+        uniform: &wgpu::Buffer,
+        raster_uniform: &wgpu::Buffer,
+        tile_bins: &TileBins,
+        chunks: &RasterChunks,
+    ) {
+        self.uniform =
+            Self::uniform_bind_group(device, pipeline, uniform, raster_uniform, tile_bins, chunks);
+    }
+
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        RasterPass { pipeline, .. }: &RasterPass,
+        Targets {
+            color_buffer,
+            depth_buffer,
+            ..
+        }: &Targets,
     ) {
         self.color_buffer = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Raster: Output Buffer Bind Group"),
+            label: Some(&LABELS.of("Output Buffer Bind Group")),
             layout: &pipeline.get_bind_group_layout(0),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: color_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+            ],
         });
     }
 }
 
 pub struct ClearPass {
     pipeline: wgpu::ComputePipeline,
+    layout: wgpu::PipelineLayout,
+    workgroup_size: WorkgroupSize,
 }
 
 impl ClearPass {
-    pub fn new(device: &wgpu::Device) -> Self {
+    pub fn new(device: &wgpu::Device, source: &Source, workgroup_size: WorkgroupSize) -> Self {
         let output_color_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Clear: Uniform Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                label: Some(&CLEAR_LABELS.of("Output Buffer Bind Group Layout")),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Clear Pipeline Layout"),
+            label: Some(&CLEAR_LABELS.pipeline_layout()),
             bind_group_layouts: &[&output_color_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let shader = device.create_shader_module(wgpu::include_wgsl!("raster.wgsl"));
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Clear Pipeline"),
-            layout: Some(&layout),
-            module: &shader,
+        let size = workgroup_size.literal();
+        let shader = source.module(device, &[("WORKGROUP_SIZE", &size)]).unwrap();
+        let pipeline = Self::create_pipeline(device, &layout, &shader);
+        Self {
+            pipeline,
+            layout,
+            workgroup_size,
+        }
+    }
+
+    /// Same as `RasterPass::reload`, the clear lives in raster.wgsl as well.
+    pub fn reload(&mut self, device: &wgpu::Device, source: &Source) -> Result<()> {
+        self.pipeline = validated(device, || -> Result<_> {
+            let size = self.workgroup_size.literal();
+            let shader = source.module(device, &[("WORKGROUP_SIZE", &size)])?;
+            Ok(Self::create_pipeline(device, &self.layout, &shader))
+        })??;
+        Ok(())
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+    ) -> wgpu::ComputePipeline {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&CLEAR_LABELS.pipeline()),
+            layout: Some(layout),
+            module: shader,
             entry_point: "clear",
+        })
+    }
+}
+
+/// The color and depth buffers along with the clear color, rebuilt with them.
+pub struct ClearBindings {
+    buffers: wgpu::BindGroup,
+}
+
+impl ClearBindings {
+    pub fn new(
+        device: &wgpu::Device,
+        ClearPass { pipeline, .. }: &ClearPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        clear_uniform: &wgpu::Buffer,
+    ) -> Self {
+        let buffers = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&CLEAR_LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: clear_uniform.as_entire_binding(),
+                },
+            ],
         });
-        Self { pipeline }
+        Self { buffers }
     }
 }
 
@@ -213,13 +1170,278 @@ impl<'a> ClearPass {
     pub fn record<'pass>(
         &'a self,
         cpass: &mut wgpu::ComputePass<'pass>,
-        bindings: &'a RasterBindings,
+        bindings: &'a ClearBindings,
         dispatch_size: u32,
     ) where
         'a: 'pass,
     {
         cpass.set_pipeline(&self.pipeline);
-        cpass.set_bind_group(0, &bindings.color_buffer, &[]);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
         cpass.dispatch_workgroups(dispatch_size, 1, 1);
     }
 }
+
+/// The raster as a pass of the frame, with what it's dispatched from.
+pub struct RasterNode<'a> {
+    pub pass: &'a RasterPass,
+    pub bindings: &'a RasterBindings,
+    pub mode: RasterMode,
+    pub cull: &'a CullBindings,
+    pub occlusion: Option<Occlusion<'a>>,
+    pub chunks: &'a RasterChunks,
+    /// Every chunk goes in a submission of its own
+    pub submit_chunks: bool,
+    pub tile_bins: &'a TileBins,
+    pub work_queue: &'a wgpu::Buffer,
+    /// Looked up by the color pass
+    pub shadows: bool,
+    pub ibl: bool,
+}
+
+impl RasterNode<'_> {
+    /// Chunks always go through the per triangle raster.
+    fn mode(&self) -> RasterMode {
+        match self.chunks.count() > 1 {
+            true => RasterMode::Triangles,
+            false => self.mode,
+        }
+    }
+
+    /// Submits `encoder`, then every chunk of the depth and the color pass in
+    /// a submission of its own so that none of them runs into the driver
+    /// timeout. `encoder` is left with a new one for the rest of the frame,
+    /// the debug group the pass is recorded in moves over to it.
+    fn submit_chunks(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let create_encoder = || {
+            frame
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Encoder"),
+                })
+        };
+        let mut recorded = std::mem::replace(encoder, create_encoder());
+        recorded.pop_debug_group();
+        frame.queue.submit(Some(recorded.finish()));
+        encoder.push_debug_group(self.name());
+
+        for depth_pass in [true, false] {
+            if let (false, Some(occlusion)) = (depth_pass, &self.occlusion) {
+                let mut encoder = create_encoder();
+                occlusion.record(
+                    &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("Occlusion Pass"),
+                    }),
+                    self.cull,
+                );
+                frame.queue.submit(Some(encoder.finish()));
+            }
+            for chunk in 0..self.chunks.count() {
+                let mut encoder = create_encoder();
+                self.pass.record_chunk(
+                    &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("Raster Chunk Pass"),
+                    }),
+                    self.bindings,
+                    self.chunks,
+                    chunk,
+                    depth_pass,
+                );
+                frame.queue.submit(Some(encoder.finish()));
+            }
+        }
+    }
+}
+
+impl Pass for RasterNode<'_> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        let mut reads = vec!["Vertices", "Indices", "Camera", "Visible Triangles"];
+        if self.shadows {
+            reads.push("Shadow Map");
+        }
+        if self.ibl {
+            reads.push("Prefiltered Environment");
+        }
+        reads
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Color Buffer", "Depth Buffer"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        match self.mode {
+            RasterMode::Triangles => {}
+            RasterMode::Persistent => encoder.clear_buffer(self.work_queue, 0, None),
+            RasterMode::Tiled => encoder.clear_buffer(self.tile_bins.counts(), 0, None),
+        }
+        if self.submit_chunks && self.chunks.count() > 1 {
+            self.submit_chunks(encoder, frame);
+            return;
+        }
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        match self.mode() {
+            RasterMode::Triangles => self.pass.record(
+                &mut cpass,
+                self.bindings,
+                self.chunks,
+                self.cull,
+                self.occlusion,
+            ),
+            RasterMode::Persistent => self.pass.record_persistent(
+                &mut cpass,
+                self.bindings,
+                frame
+                    .workgroup_size
+                    .dispatch_size(frame.triangle_count)
+                    .min(PERSISTENT_WORKGROUPS),
+                self.cull,
+                self.occlusion,
+            ),
+            RasterMode::Tiled => {
+                self.pass
+                    .record_tiled(&mut cpass, self.bindings, self.tile_bins, self.cull)
+            }
+        }
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Raster
+    }
+
+    fn steps(&self, frame: &Frame) -> Vec<GraphPass> {
+        let raster = ["Vertices", "Indices", "Camera", "Visible Triangles"];
+        let shaded = self.reads(frame);
+        let occlusion = match self.occlusion {
+            Some(_) => vec![
+                GraphPass::new("Hi-Z", &["Depth Buffer"], &["Hi-Z Pyramid"]),
+                GraphPass::new(
+                    "Occlusion Cull",
+                    &["Vertices", "Indices", "Camera", "Hi-Z Pyramid"],
+                    &["Visible Triangles"],
+                ),
+            ],
+            None => vec![],
+        };
+        let mut steps = vec![];
+        match self.mode {
+            RasterMode::Triangles => {}
+            RasterMode::Persistent => {
+                steps.push(GraphPass::new("Clear Work Queue", &[], &["Work Queue"]))
+            }
+            RasterMode::Tiled => {
+                steps.push(GraphPass::new("Clear Tile Counts", &[], &["Tile Bins"]))
+            }
+        }
+        match self.mode() {
+            RasterMode::Triangles => {
+                steps.push(GraphPass::new("Raster Depth", &raster, &["Depth Buffer"]));
+                steps.extend(occlusion);
+                steps.push(GraphPass::new("Raster Color", &shaded, &["Color Buffer"]));
+            }
+            RasterMode::Persistent => {
+                steps.push(GraphPass::new(
+                    "Persistent Depth",
+                    &raster,
+                    &["Work Queue", "Depth Buffer"],
+                ));
+                steps.extend(occlusion);
+                steps.push(GraphPass::new(
+                    "Persistent Color",
+                    &shaded,
+                    &["Work Queue", "Color Buffer"],
+                ));
+            }
+            RasterMode::Tiled => {
+                steps.push(GraphPass::new("Bin Count", &raster, &["Tile Bins"]));
+                steps.push(GraphPass::new("Bin Scan", &["Tile Bins"], &["Tile Bins"]));
+                steps.push(GraphPass::new("Bin Scatter", &raster, &["Tile Bins"]));
+                steps.push(GraphPass::new(
+                    "Tiles Depth",
+                    &["Tile Bins", "Vertices"],
+                    &["Depth Buffer"],
+                ));
+                steps.push(GraphPass::new(
+                    "Tiles Color",
+                    &["Tile Bins", "Vertices", "Depth Buffer"],
+                    &["Color Buffer"],
+                ));
+            }
+        }
+        steps
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        let size = frame.workgroup_size;
+        let triangles = frame.triangle_count;
+        let workgroups = match self.chunks.count() > 1 {
+            true => size.dispatch_size(self.chunks.size()) * self.chunks.count(),
+            false => size.dispatch_size(triangles),
+        };
+        let raster = |name, workgroups| PassOccupancy {
+            name,
+            workgroup_size: size.get(),
+            workgroups,
+            items: triangles as u64,
+        };
+        let (depth, color, workgroups) = match self.mode() {
+            RasterMode::Tiled => {
+                return vec![
+                    raster("Bin Count", workgroups),
+                    raster("Bin Scatter", workgroups),
+                ]
+            }
+            RasterMode::Persistent => (
+                "Persistent Depth",
+                "Persistent Color",
+                workgroups.min(PERSISTENT_WORKGROUPS),
+            ),
+            RasterMode::Triangles => ("Raster Depth", "Raster Color", workgroups),
+        };
+        let mut passes = vec![raster(depth, workgroups)];
+        if self.occlusion.is_some() {
+            passes.push(self.cull.occupancy("Occlusion Cull", triangles));
+        }
+        passes.push(raster(color, workgroups));
+        passes
+    }
+}
+
+impl Pass for Node<'_, ClearPass, ClearBindings> {
+    fn name(&self) -> &'static str {
+        CLEAR_LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Color Buffer", "Depth Buffer"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        if !ClearUniform::new(frame.clear_color).writes_color() {
+            encoder.clear_buffer(frame.color_buffer, 0, None);
+        }
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&CLEAR_LABELS.pass()),
+        });
+        self.pass
+            .record(&mut cpass, self.bindings, frame.pixel_dispatch());
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Raster
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![frame.occupancy(self.name(), frame.pixels())]
+    }
+}
@@ -0,0 +1,178 @@
+// Hardware raster of the same mesh as raster.wgsl, used as a reference to
+// validate the compute raster against. Only the perspective projection is drawn.
+
+#include "camera.wgsl"
+#include "lights.wgsl"
+#include "pbr.wgsl"
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct RasterUniform {
+  light_count: u32,
+  debug_view: u32,
+  jitter: vec2<f32>,
+  // Ignored, the reference is drawn without shadows
+  shadows: u32,
+}
+
+// Consecutive triangles sharing a material and an object
+struct Run {
+  double_sided: u32,
+  alpha_mode: u32,
+  alpha_cutoff: f32,
+  alpha: f32,
+  base_color: vec3<f32>,
+  metallic: f32,
+  roughness: f32,
+  // Of `Material` in util.rs
+  _padding0: u32,
+  _padding1: u32,
+  _padding2: u32,
+  object: u32,
+}
+
+let ALPHA_MASK = 1u;
+
+// What the overdraw view of raster.wgsl adds per fragment
+let OVERDRAW_STEP = 0.25;
+let TILE_SIZE = 16u;
+
+@group(0) @binding(0) var<uniform> camera : Camera;
+@group(0) @binding(1) var<uniform> screen_dims : Uniform;
+@group(0) @binding(2) var<uniform> raster_uniform : RasterUniform;
+@group(0) @binding(3) var<storage, read> lights : LightBuffer;
+@group(1) @binding(0) var<uniform> run : Run;
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) normal: vec3<f32>,
+  @location(2) uv: vec2<f32>,
+  @location(3) color: vec4<f32>,
+}
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(0) normal: vec3<f32>,
+  @location(1) uv: vec2<f32>,
+  // Interpolated perspective-correct, as the view depth of the compute raster
+  @location(2) w: f32,
+  @location(3) color: vec4<f32>,
+  @location(4) object_position: vec3<f32>,
+}
+
+// The compute raster maps NDC [0, 1] over the screen without flipping y and
+// samples at the pixel corners. The same mapping is applied in clip space so
+// that the hardware clipping still works, with the pixel centers moved onto
+// the corners.
+@vertex
+fn vs_reference(in: VertexInput) -> VertexOutput {
+  let clip = camera.view_proj * vec4<f32>(in.position, 1.0);
+  let dims = vec2<f32>(screen_dims.width, screen_dims.height);
+  let offset = (0.5 + raster_uniform.jitter) / dims * 2.0 - 1.0;
+  let xy = vec2<f32>(2.0 * clip.x + offset.x * clip.w, -(2.0 * clip.y + offset.y * clip.w));
+
+  var out: VertexOutput;
+  out.position = vec4<f32>(xy, clip.z, clip.w);
+  out.normal = in.normal;
+  out.uv = in.uv;
+  out.w = clip.w;
+  out.color = in.color;
+  out.object_position = in.position;
+  return out;
+}
+
+// PCG hash, from: https://jcgt.org/published/0009/03/02/
+fn hash(x: u32) -> u32 {
+  let state = x * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+fn random_color(id: u32) -> vec3<f32> {
+  let h = hash(id);
+  let rgb = vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(255u);
+  return 0.25 + 0.75 * vec3<f32>(rgb) / 255.0;
+}
+
+// Same as `lambert` in raster.wgsl, always with the constant ambient light
+// instead of the environment
+fn lambert(n: vec3<f32>, p: vec3<f32>) -> vec3<f32> {
+  let ambient = 0.1;
+  var lit = vec3<f32>(0.0);
+  for (var i = 0u; i < raster_uniform.light_count; i = i + 1u) {
+    let light = incident(lights.values[i], p);
+    lit = lit + light.radiance * max(dot(n, light.l), 0.0);
+  }
+  return ambient + (1.0 - ambient) * lit;
+}
+
+// Same as `shade` in raster.wgsl. Triangle ids and barycentrics aren't
+// available to the fragment shader, these views and the custom shading fall
+// back to Lambert.
+fn shade(in: VertexOutput, back_facing: bool) -> vec3<f32> {
+  var n = normalize(in.normal);
+  if (back_facing) {
+    n = -n;
+  }
+  let diffuse = lambert(n, in.object_position);
+
+  switch (raster_uniform.debug_view) {
+    // Normals
+    case 1u: {
+      return n * 0.5 + 0.5;
+    }
+    // Depth
+    case 2u: {
+      return vec3<f32>((in.w * 25. - 100.) / 255.);
+    }
+    // Uv
+    case 3u: {
+      let checker = (floor(in.uv.x * 16.0) + floor(in.uv.y * 16.0)) % 2.0;
+      return vec3<f32>(fract(in.uv), 1.0) * (0.5 + 0.5 * checker);
+    }
+    // Object
+    case 4u: {
+      return random_color(run.object) * diffuse;
+    }
+    // Tiles
+    case 7u: {
+      let tile = vec2<u32>(in.position.xy) / TILE_SIZE;
+      let columns = (u32(screen_dims.width) + TILE_SIZE - 1u) / TILE_SIZE;
+      return random_color(tile.x + tile.y * columns) * diffuse;
+    }
+    // Overdraw, the depth test leaves a single fragment per pixel
+    case 8u: {
+      return vec3<f32>(OVERDRAW_STEP);
+    }
+    // Pbr
+    case 10u: {
+      let v = normalize(camera.view_pos.xyz - in.object_position);
+      let base = run.base_color * in.color.rgb;
+      var color = pbr_ambient(base, run.metallic);
+      for (var i = 0u; i < raster_uniform.light_count; i = i + 1u) {
+        let light = incident(lights.values[i], in.object_position);
+        color = color + light.radiance * pbr(base, run.metallic, run.roughness, n, v, light.l);
+      }
+      return color;
+    }
+    default: {
+      return run.base_color * in.color.rgb * diffuse;
+    }
+  }
+}
+
+// Alpha marks the covered pixels, the color is clipped to the range of the
+// texture and compared to the color buffer rounded the same way
+@fragment
+fn fs_reference(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+  if (run.double_sided == 0u && !front_facing) {
+    discard;
+  }
+  if (run.alpha_mode == ALPHA_MASK && run.alpha * in.color.a < run.alpha_cutoff) {
+    discard;
+  }
+  return vec4<f32>(shade(in, !front_facing), 1.0);
+}
@@ -0,0 +1,326 @@
+use std::ops::Range;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    render_graph::{Frame, Node, Pass, Targets},
+    shaders::Shaders,
+    util::{Labels, Material, TriangleInfo, Vertex},
+};
+
+const LABELS: Labels = Labels::new("Reference");
+
+/// Render target format of the reference, alpha marks the covered pixels.
+pub const REFERENCE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const REFERENCE_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct ReferenceRun {
+    material: Material,
+    object: u32,
+    _padding: [u32; 3],
+}
+
+/// Draws the mesh through the hardware raster pipeline into a texture laid out
+/// like the color buffer, the reference the compute raster is compared with.
+pub struct ReferencePass {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ReferencePass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders) -> Self {
+        let uniform_entry = |binding, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[
+                    uniform_entry(0, false),
+                    uniform_entry(1, false),
+                    uniform_entry(2, false),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let run_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Run Bind Group Layout")),
+                entries: &[uniform_entry(0, true)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&uniform_bind_group_layout, &run_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.module(device, "reference.wgsl", include_str!("reference.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_reference",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: Vertex::SIZE,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &Vertex::ATTR,
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_reference",
+                targets: &[Some(REFERENCE_FORMAT.into())],
+            }),
+            // Flipping y mirrors the screen, counter-clockwise triangles end up clockwise.
+            // Back faces are discarded per material by the fragment shader.
+            primitive: wgpu::PrimitiveState {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: REFERENCE_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: Default::default(),
+            multiview: None,
+        });
+        Self { pipeline }
+    }
+}
+
+/// Splits the triangles into runs sharing a material and an object,
+/// each drawn with its own uniform.
+fn material_runs(triangles: &[TriangleInfo]) -> Vec<Range<u32>> {
+    let mut runs: Vec<Range<u32>> = vec![];
+    for (i, info) in triangles.iter().enumerate() {
+        let i = i as u32;
+        match runs.last_mut() {
+            Some(run) if triangles[run.start as usize] == *info => run.end = i + 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    runs
+}
+
+pub struct ReferenceBindings {
+    uniform: wgpu::BindGroup,
+    run_uniform: wgpu::BindGroup,
+    /// Index range of every run along with its dynamic offset
+    runs: Vec<(Range<u32>, u32)>,
+    color_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+}
+
+impl ReferenceBindings {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        ReferencePass { pipeline }: &ReferencePass,
+        materials: &[Material],
+        triangles: &[TriangleInfo],
+        camera_uniform: &wgpu::Buffer,
+        uniform: &wgpu::Buffer,
+        raster_uniform: &wgpu::Buffer,
+        lights: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let stride = device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(std::mem::size_of::<ReferenceRun>() as u32);
+        let ranges = material_runs(triangles);
+        let mut contents = vec![0; (stride as usize * ranges.len()).max(stride as usize)];
+        for (entry, range) in contents.chunks_mut(stride as usize).zip(&ranges) {
+            let info = triangles[range.start as usize];
+            let run = ReferenceRun {
+                material: materials[info.material as usize],
+                object: info.object,
+                _padding: [0; 3],
+            };
+            entry[..std::mem::size_of::<ReferenceRun>()].copy_from_slice(bytemuck::bytes_of(&run));
+        }
+        let run_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Run Buffer")),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let run_uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Run Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &run_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<ReferenceRun>() as u64),
+                }),
+            }],
+        });
+        let runs = ranges
+            .into_iter()
+            .enumerate()
+            .map(|(i, range)| (range.start * 3..range.end * 3, i as u32 * stride))
+            .collect();
+
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: raster_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: lights.as_entire_binding(),
+                },
+            ],
+        });
+
+        let (color_view, depth_view) = Self::create_targets(device, width, height);
+        Self {
+            uniform,
+            run_uniform,
+            runs,
+            color_view,
+            depth_view,
+        }
+    }
+
+    pub fn rebind(&mut self, device: &wgpu::Device, targets: &Targets) {
+        (self.color_view, self.depth_view) =
+            Self::create_targets(device, targets.width, targets.height);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::TextureView, wgpu::TextureView) {
+        let target = |label: &str, format, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+                })
+                .create_view(&Default::default())
+        };
+        (
+            target(
+                &LABELS.of("Color Texture"),
+                REFERENCE_FORMAT,
+                wgpu::TextureUsages::TEXTURE_BINDING,
+            ),
+            target(
+                &LABELS.of("Depth Texture"),
+                REFERENCE_DEPTH_FORMAT,
+                wgpu::TextureUsages::empty(),
+            ),
+        )
+    }
+
+    /// Reference image, read by the compare pass.
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+}
+
+impl<'a> ReferencePass {
+    pub fn record(
+        &'a self,
+        encoder: &mut wgpu::CommandEncoder,
+        bindings: &'a ReferenceBindings,
+        vertex_buffer: &'a wgpu::Buffer,
+        index_buffer: &'a wgpu::Buffer,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&LABELS.pass()),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &bindings.color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &bindings.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &bindings.uniform, &[]);
+        rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        rpass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for (indices, offset) in &bindings.runs {
+            rpass.set_bind_group(1, &bindings.run_uniform, &[*offset]);
+            rpass.draw_indexed(indices.clone(), 0, 0..1);
+        }
+    }
+}
+
+impl Pass for Node<'_, ReferencePass, ReferenceBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Vertices", "Indices", "Camera"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Reference"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        self.pass.record(
+            encoder,
+            self.bindings,
+            frame.vertex_buffer,
+            frame.index_buffer,
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt::{self, Write};
+
+use super::occupancy::PassOccupancy;
+use super::util::WorkgroupSize;
+
+/// What the passes share while recording a frame.
+pub struct Frame<'a> {
+    pub device: &'a wgpu::Device,
+    /// Passes splitting their work over several submissions submit on it
+    pub queue: &'a wgpu::Queue,
+    pub vertex_buffer: &'a wgpu::Buffer,
+    pub index_buffer: &'a wgpu::Buffer,
+    pub color_buffer: &'a wgpu::Buffer,
+    /// Linear, black leaves the color to `CommandEncoder::clear_buffer`
+    pub clear_color: [f32; 3],
+    /// Of the color buffer
+    pub width: u32,
+    pub height: u32,
+    pub workgroup_size: WorkgroupSize,
+    pub triangle_count: u32,
+    /// The bloom pass runs this frame, its glow is read after it
+    pub bloom: bool,
+    /// The resolve pass runs this frame, the present samples its texture
+    pub texture_output: bool,
+    /// What the present passes draw into, none while the frame is only
+    /// described
+    pub surface: Option<Surface<'a>>,
+}
+
+impl Frame<'_> {
+    pub fn pixels(&self) -> u32 {
+        self.width * self.height
+    }
+
+    /// Workgroups of a dispatch with an invocation per pixel.
+    pub fn pixel_dispatch(&self) -> u32 {
+        self.workgroup_size.dispatch_size(self.pixels())
+    }
+
+    /// A dispatch of `items` invocations with the workgroup size of the frame.
+    pub fn occupancy(&self, name: &'static str, items: u32) -> PassOccupancy {
+        PassOccupancy {
+            name,
+            workgroup_size: self.workgroup_size.get(),
+            workgroups: self.workgroup_size.dispatch_size(items),
+            items: items as u64,
+        }
+    }
+}
+
+/// The surface texture of the frame with the targets drawn along with it.
+pub struct Surface<'a> {
+    pub view: &'a wgpu::TextureView,
+    /// Drawn into instead and resolved into `view`
+    pub msaa_target: Option<&'a wgpu::TextureView>,
+    pub depth_target: &'a wgpu::TextureView,
+    pub background: wgpu::Color,
+}
+
+impl<'a> Surface<'a> {
+    /// Render pass into the surface, the first one of the frame clears it and
+    /// the ones after draw over it.
+    pub fn begin_render_pass<'pass>(
+        &self,
+        encoder: &'pass mut wgpu::CommandEncoder,
+        label: &str,
+        clear: bool,
+    ) -> wgpu::RenderPass<'pass>
+    where
+        'a: 'pass,
+    {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.msaa_target.unwrap_or(self.view),
+                resolve_target: self.msaa_target.and(Some(self.view)),
+                ops: wgpu::Operations {
+                    load: match clear {
+                        true => wgpu::LoadOp::Clear(self.background),
+                        false => wgpu::LoadOp::Load,
+                    },
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: self.depth_target,
+                depth_ops: Some(wgpu::Operations {
+                    load: match clear {
+                        true => wgpu::LoadOp::Clear(1.0),
+                        false => wgpu::LoadOp::Load,
+                    },
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+}
+
+/// Where a pass goes in the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Culling and raster into the color buffer
+    Raster,
+    /// Reads the color buffer of the raster
+    Post,
+    /// Draws into the surface, the only passes of a frame skipped by the
+    /// dirty tracking
+    Present,
+}
+
+/// A pass of the frame, with the resources it reads and writes. Implemented
+/// for the `Node` of a pass and its bindings, or a node of its own for the
+/// passes recorded with more than their bindings. A pass joins the frame by
+/// being listed in `Renderer::frame_passes`, which records it, describes it
+/// in the render graph and reports its dispatches in the occupancy. Its
+/// bindings are rebuilt against the new `Targets` by
+/// `Renderer::rebind_passes` on resize.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn reads(&self, frame: &Frame) -> Vec<&'static str>;
+    fn writes(&self) -> Vec<&'static str>;
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame);
+
+    fn stage(&self) -> Stage {
+        Stage::Post
+    }
+
+    /// What the render graph shows of the pass, a pass going through several
+    /// dispatches may list each of them.
+    fn steps(&self, frame: &Frame) -> Vec<GraphPass> {
+        vec![GraphPass::new(
+            self.name(),
+            &self.reads(frame),
+            &self.writes(),
+        )]
+    }
+
+    /// The compute dispatches of the pass, none for a render pass.
+    fn occupancy(&self, _: &Frame) -> Vec<PassOccupancy> {
+        vec![]
+    }
+}
+
+/// A pass along with the bindings it's recorded with.
+pub struct Node<'a, P, B> {
+    pub pass: &'a P,
+    pub bindings: &'a B,
+}
+
+/// The buffers sized to the color buffer, recreated together on resize.
+pub struct Targets<'a> {
+    pub color_buffer: &'a wgpu::Buffer,
+    pub depth_buffer: &'a wgpu::Buffer,
+    pub accumulation_buffer: &'a wgpu::Buffer,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A pass of the frame with the resources it reads and writes.
+#[derive(Debug, Clone)]
+pub struct GraphPass {
+    pub name: &'static str,
+    pub reads: Vec<&'static str>,
+    pub writes: Vec<&'static str>,
+}
+
+impl GraphPass {
+    pub fn new(name: &'static str, reads: &[&'static str], writes: &[&'static str]) -> Self {
+        Self {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        }
+    }
+}
+
+/// Passes of a frame in the order they are recorded, as a description.
+#[derive(Debug, Default)]
+pub struct RenderGraph {
+    passes: Vec<GraphPass>,
+}
+
+impl RenderGraph {
+    pub fn pass(&mut self, name: &'static str, reads: &[&'static str], writes: &[&'static str]) {
+        self.passes.push(GraphPass::new(name, reads, writes));
+    }
+
+    /// Describes `pass` as declared by it.
+    pub fn add(&mut self, pass: &dyn Pass, frame: &Frame) {
+        self.passes.extend(pass.steps(frame));
+    }
+
+    pub fn passes(&self) -> &[GraphPass] {
+        &self.passes
+    }
+
+    /// Graphviz graph of the passes between the resources. Every write makes
+    /// a new version of its resource, so that passes reading it again later
+    /// in the frame don't turn into cycles.
+    pub fn to_dot(&self) -> String {
+        // Resource names with the number of writes to them so far
+        let mut versions: Vec<(&str, u32)> = Vec::new();
+        let mut version = |name: &'static str, write: bool| {
+            let index = match versions.iter().position(|(other, _)| *other == name) {
+                Some(index) => index,
+                None => {
+                    versions.push((name, 0));
+                    versions.len() - 1
+                }
+            };
+            if write {
+                versions[index].1 += 1;
+            }
+            (index, versions[index].1)
+        };
+
+        let mut nodes = String::new();
+        let mut edges = String::new();
+        let mut declared = Vec::new();
+        let mut resource = |nodes: &mut String, (index, version): (usize, u32), name: &str| {
+            let id = format!("r{}_{}", index, version);
+            if !declared.contains(&id) {
+                let label = match version {
+                    0 => name.to_owned(),
+                    version => format!("{} v{}", name, version),
+                };
+                let _ = writeln!(nodes, "    {} [label=\"{}\" shape=ellipse];", id, label);
+                declared.push(id.clone());
+            }
+            id
+        };
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let id = format!("p{}", index);
+            let _ = writeln!(
+                nodes,
+                "    {} [label=\"{}. {}\" shape=box style=filled fillcolor=lightblue];",
+                id,
+                index + 1,
+                pass.name
+            );
+            for &name in &pass.reads {
+                let read = resource(&mut nodes, version(name, false), name);
+                let _ = writeln!(edges, "    {} -> {};", read, id);
+            }
+            for &name in &pass.writes {
+                let written = resource(&mut nodes, version(name, true), name);
+                let _ = writeln!(edges, "    {} -> {};", id, written);
+            }
+        }
+        format!("digraph frame {{\n    rankdir=LR;\n{}{}}}\n", nodes, edges)
+    }
+}
+
+impl fmt::Display for RenderGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, pass) in self.passes.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>2}. {}: reads [{}], writes [{}]",
+                index + 1,
+                pass.name,
+                pass.reads.join(", "),
+                pass.writes.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> RenderGraph {
+        let mut graph = RenderGraph::default();
+        graph.pass("Clear", &[], &["Color"]);
+        graph.pass("Raster", &["Vertices", "Color"], &["Color"]);
+        graph.pass("Present", &["Color"], &["Surface"]);
+        graph
+    }
+
+    #[test]
+    fn dot_versions_the_written_resources() {
+        let pass = "shape=box style=filled fillcolor=lightblue";
+        let expected = format!(
+            "digraph frame {{
+    rankdir=LR;
+    p0 [label=\"1. Clear\" {pass}];
+    r0_1 [label=\"Color v1\" shape=ellipse];
+    p1 [label=\"2. Raster\" {pass}];
+    r1_0 [label=\"Vertices\" shape=ellipse];
+    r0_2 [label=\"Color v2\" shape=ellipse];
+    p2 [label=\"3. Present\" {pass}];
+    r2_1 [label=\"Surface v1\" shape=ellipse];
+    p0 -> r0_1;
+    r1_0 -> p1;
+    r0_1 -> p1;
+    p1 -> r0_2;
+    r0_2 -> p2;
+    p2 -> r2_1;
+}}
+"
+        );
+        assert_eq!(graph().to_dot(), expected);
+    }
+
+    #[test]
+    fn lists_the_passes() {
+        let expected = " 1. Clear: reads [], writes [Color]
+ 2. Raster: reads [Vertices, Color], writes [Color]
+ 3. Present: reads [Color], writes [Surface]
+";
+        assert_eq!(graph().to_string(), expected);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{collections::HashMap, fmt};
+
+use glam::Vec3;
+
+use super::util::Mesh;
+
+/// Fixes `Mesh::repair` applies to a loaded model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairOptions {
+    /// Drop the vertices with NaN or infinite attributes, along with the
+    /// triangles using them or indexing past the vertices
+    pub remove_invalid: bool,
+    /// Drop the triangles without area
+    pub remove_degenerate: bool,
+    /// Flip the triangles of an object that are wound against most of its
+    /// triangles, compared to their vertex normals
+    pub fix_winding: bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            remove_invalid: true,
+            remove_degenerate: true,
+            fix_winding: true,
+        }
+    }
+}
+
+/// What was fixed while loading a model.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Faces of more than 3 vertices split into triangles
+    pub triangulated_polygons: usize,
+    pub invalid_vertices: usize,
+    pub invalid_triangles: usize,
+    pub degenerate_triangles: usize,
+    pub flipped_triangles: usize,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fixes = [
+            (self.triangulated_polygons, "triangulated polygons"),
+            (self.invalid_vertices, "removed invalid vertices"),
+            (self.invalid_triangles, "removed invalid triangles"),
+            (self.degenerate_triangles, "removed degenerate triangles"),
+            (self.flipped_triangles, "flipped triangles"),
+        ];
+        let fixes: Vec<_> = fixes
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, fix)| format!("{} {}", fix, count))
+            .collect();
+        match fixes.is_empty() {
+            true => write!(f, "nothing to repair"),
+            false => write!(f, "{}", fixes.join(", ")),
+        }
+    }
+}
+
+impl Mesh {
+    /// Applies `options`, adding what was fixed to `report`. The dropped
+    /// triangles don't take part in the vote on the winding.
+    pub fn repair(&mut self, options: RepairOptions, report: &mut RepairReport) {
+        let before = *report;
+        if options.remove_invalid {
+            let invalid: Vec<bool> = self
+                .vertices
+                .iter()
+                .map(|vertex| !vertex.is_finite())
+                .collect();
+            let vertex_count = self.vertices.len();
+            report.invalid_triangles += self.retain_triangles(|_, indices| {
+                indices
+                    .iter()
+                    .all(|&i| (i as usize) < vertex_count && !invalid[i as usize])
+            });
+            report.invalid_vertices += self.remove_vertices(&invalid);
+        }
+        if options.remove_degenerate {
+            report.degenerate_triangles += self.retain_triangles(|corners, _| {
+                let [a, b, c] = corners;
+                (b - a).cross(c - a).length_squared() > 0.0
+            });
+        }
+        if options.fix_winding {
+            report.flipped_triangles += self.fix_winding();
+        }
+        if *report != before {
+            self.build_meshlets();
+        }
+    }
+
+    /// Keeps the triangles `keep` accepts given their corners and indices,
+    /// returns how many were dropped. The corners of out of range indices are
+    /// at the origin.
+    fn retain_triangles(&mut self, mut keep: impl FnMut([Vec3; 3], [u32; 3]) -> bool) -> usize {
+        let vertices = &self.vertices;
+        let corner = |i: u32| {
+            vertices
+                .get(i as usize)
+                .map_or(Vec3::ZERO, |v| v.position())
+        };
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut triangles = Vec::with_capacity(self.triangles.len());
+        for (triangle, info) in self.indices.chunks_exact(3).zip(&self.triangles) {
+            let triangle = [triangle[0], triangle[1], triangle[2]];
+            if keep(triangle.map(corner), triangle) {
+                indices.extend(triangle);
+                triangles.push(*info);
+            }
+        }
+        let removed = self.triangles.len() - triangles.len();
+        self.indices = indices;
+        self.triangles = triangles;
+        removed
+    }
+
+    /// Drops the `removed` vertices, no triangle may use them anymore.
+    /// Returns how many were dropped.
+    fn remove_vertices(&mut self, removed: &[bool]) -> usize {
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        let mut kept = 0;
+        for &removed in removed {
+            remap.push(kept);
+            kept += !removed as u32;
+        }
+        let count = self.vertices.len() - kept as usize;
+        if count == 0 {
+            return 0;
+        }
+        let mut index = 0;
+        self.vertices.retain(|_| {
+            index += 1;
+            !removed[index - 1]
+        });
+        for i in &mut self.indices {
+            *i = remap[*i as usize];
+        }
+        count
+    }
+
+    /// Flips the triangles of every object whose face normal disagrees with
+    /// their vertex normals when most of the object agrees, and the other
+    /// way around. Returns how many were flipped.
+    fn fix_winding(&mut self) -> usize {
+        let vertices = &self.vertices;
+        // Per triangle, whether the winding agrees with the vertex normals,
+        // `None` without normals or vertices to compare to
+        let agrees: Vec<Option<bool>> = self
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| vertices.get(triangle[i] as usize));
+                let (a, b, c) = (a?, b?, c?);
+                let face = (b.position() - a.position()).cross(c.position() - a.position());
+                let normal = a.normal() + b.normal() + c.normal();
+                let alignment = face.dot(normal);
+                (alignment != 0.0).then_some(alignment > 0.0)
+            })
+            .collect();
+
+        // Votes for agreeing minus votes against, per object
+        let mut votes: HashMap<u32, i64> = HashMap::new();
+        for (info, agrees) in self.triangles.iter().zip(&agrees) {
+            if let Some(agrees) = agrees {
+                *votes.entry(info.object).or_default() += if *agrees { 1 } else { -1 };
+            }
+        }
+        let mut flipped = 0;
+        for ((triangle, info), agrees) in self
+            .indices
+            .chunks_exact_mut(3)
+            .zip(&self.triangles)
+            .zip(agrees)
+        {
+            let majority = votes.get(&info.object).copied().unwrap_or(0);
+            let outvoted = match agrees {
+                Some(true) => majority < 0,
+                Some(false) => majority > 0,
+                None => false,
+            };
+            if outvoted {
+                triangle.swap(1, 2);
+                flipped += 1;
+            }
+        }
+        flipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::util::{load_model, v, Material, Vertex};
+
+    const UP: [f32; 3] = [0.0, 1.0, 0.0];
+
+    /// Triangle at `x` facing up, wound the other way with `flipped`.
+    fn triangle(x: f32, flipped: bool) -> [Vertex; 3] {
+        let [a, b, c] =
+            [v!(x, 0., 0.), v!(x, 0., 1.), v!(x + 1., 0., 0.)].map(|v| v.with_normal(UP));
+        match flipped {
+            true => [a, c, b],
+            false => [a, b, c],
+        }
+    }
+
+    fn mesh(triangles: &[[Vertex; 3]]) -> Mesh {
+        Mesh::with_material(triangles.concat(), Material::default())
+    }
+
+    fn repair(mesh: &mut Mesh) -> RepairReport {
+        let mut report = RepairReport::default();
+        mesh.repair(RepairOptions::default(), &mut report);
+        report
+    }
+
+    #[test]
+    fn removes_invalid_vertices_and_triangles() {
+        let mut nan = triangle(1.0, false);
+        nan[1] = v!(f32::NAN, 0., 1.);
+        let mut mesh = mesh(&[triangle(0.0, false), nan, triangle(2.0, false)]);
+        // Past the vertices
+        mesh.indices[8] = 100;
+
+        let report = repair(&mut mesh);
+        assert_eq!(report.invalid_vertices, 1);
+        assert_eq!(report.invalid_triangles, 2);
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.triangles.len(), 1);
+    }
+
+    #[test]
+    fn removes_degenerate_triangles() {
+        let collinear = [v!(0., 0., 0.), v!(1., 0., 1.), v!(2., 0., 2.)];
+        let point = [v!(1., 0., 1.); 3];
+        let mut mesh = mesh(&[collinear, triangle(0.0, false), point]);
+
+        let report = repair(&mut mesh);
+        assert_eq!(report.degenerate_triangles, 2);
+        assert_eq!(mesh.triangle_count(), 1);
+        assert_eq!(mesh.indices, [3, 4, 5]);
+    }
+
+    #[test]
+    fn flips_the_outvoted_triangles() {
+        let mut mesh = mesh(&[
+            triangle(0.0, false),
+            triangle(1.0, true),
+            triangle(2.0, false),
+        ]);
+
+        let report = repair(&mut mesh);
+        assert_eq!(report.flipped_triangles, 1);
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 5, 4, 6, 7, 8]);
+        // Consistent now, nothing left to flip
+        assert!(repair(&mut mesh).is_clean());
+    }
+
+    #[test]
+    fn disabled_fixes_keep_the_mesh() {
+        let mut mesh = mesh(&[triangle(0.0, false), [v!(0., 0., 0.); 3]]);
+        let mut report = RepairReport::default();
+        let options = RepairOptions {
+            remove_invalid: false,
+            remove_degenerate: false,
+            fix_winding: false,
+        };
+        mesh.repair(options, &mut report);
+        assert!(report.is_clean());
+        assert_eq!(report.to_string(), "nothing to repair");
+        assert_eq!(mesh.triangle_count(), 2);
+    }
+
+    #[test]
+    fn imports_a_malformed_obj() {
+        // A quad, and a triangle with a position past the list
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nf 1 2 3 4\nf 1 2 9\n";
+        let path = std::env::temp_dir().join(format!("repair-{}.obj", std::process::id()));
+        std::fs::write(&path, obj).unwrap();
+        let mesh = load_model(&path, RepairOptions::default());
+        std::fs::remove_file(&path).unwrap();
+
+        let mesh = mesh.unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!(mesh.vertices.iter().all(Vertex::is_finite));
+    }
+}
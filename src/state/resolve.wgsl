@@ -0,0 +1,68 @@
+// Texture output mode, resolves the color buffer into a storage texture the
+// present pass samples. Uncovered pixels get the background, covered ones are
+// tonemapped as the present pass does.
+
+#include "color.wgsl"
+
+struct ColorBuffer {
+  values: array<u32>,
+}
+
+struct DepthBuffer {
+  values: array<u32>,
+}
+
+struct AccumulationBuffer {
+  values: array<vec4<f32>>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct PresentUniform {
+  distortion: u32,
+  distortion_strength: f32,
+  surface_width: f32,
+  surface_height: f32,
+  background: vec3<f32>,
+  accumulate: u32,
+  analysis: u32,
+  exposure: f32,
+  tonemap: u32,
+  encode_srgb: u32,
+  bloom: u32,
+}
+
+@group(0) @binding(0) var<storage, read> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read> depth_buffer: DepthBuffer;
+@group(0) @binding(2) var<storage, read> accumulation: AccumulationBuffer;
+@group(0) @binding(3) var output: texture_storage_2d<rgba16float, write>;
+// Written by bloom.wgsl
+@group(0) @binding(4) var<storage, read> bloom: AccumulationBuffer;
+@group(1) @binding(0) var<uniform> screen_dims: Uniform;
+@group(1) @binding(1) var<uniform> present: PresentUniform;
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn resolve(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let width = u32(screen_dims.width);
+  let index = global_id.x;
+  if (index >= width * u32(screen_dims.height)) {
+    return;
+  }
+
+  var glow = vec3<f32>(0.0);
+  if (present.bloom != 0u) {
+    glow = bloom.values[index].rgb;
+  }
+  // RGB9E5 packed by raster.wgsl, or by sky.wgsl for the uncovered pixels
+  let p = color_buffer.values[index];
+  var color = tonemap(unpack_color(p) + glow, present.exposure, present.tonemap);
+  if (present.accumulate != 0u) {
+    color = tonemap(accumulation.values[index].rgb + glow, present.exposure, present.tonemap);
+  } else if (depth_buffer.values[index] == 0xffffffffu) {
+    color = color + present.background;
+  }
+  textureStore(output, vec2<i32>(i32(index % width), i32(index / width)), vec4<f32>(color, 1.0));
+}
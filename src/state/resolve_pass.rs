@@ -0,0 +1,264 @@
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass, Targets},
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Resolve");
+
+/// Format of the texture output, the resolve pass tonemaps into it. Still
+/// linear, half floats keep the dark values the sRGB encoding stretches.
+pub const OUTPUT_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Resolves the color buffer into a storage texture with the background and
+/// the accumulation applied, the present pass then samples it with filtering.
+pub struct ResolvePass {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ResolvePass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[
+                    storage_entry(0),
+                    storage_entry(1),
+                    storage_entry(2),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: OUTPUT_TEXTURE_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    storage_entry(4),
+                ],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "resolve.wgsl",
+            include_str!("resolve.wgsl"),
+            workgroup_size,
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "resolve",
+        });
+        Self { pipeline }
+    }
+}
+
+pub struct ResolveBindings {
+    buffers: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+    output_view: wgpu::TextureView,
+}
+
+impl ResolveBindings {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pass: &ResolvePass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        bloom: &wgpu::Buffer,
+        uniform: &wgpu::Buffer,
+        present_uniform: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let ResolvePass { pipeline } = pass;
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: present_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        let output_view = Self::create_output(device, width, height);
+        Self {
+            buffers: Self::buffers_bind_group(
+                device,
+                pass,
+                color_buffer,
+                depth_buffer,
+                accumulation_buffer,
+                bloom,
+                &output_view,
+            ),
+            uniform,
+            output_view,
+        }
+    }
+
+    /// `bloom` is the glow of the bloom pass, rebound first.
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        pass: &ResolvePass,
+        targets: &Targets,
+        bloom: &wgpu::Buffer,
+    ) {
+        self.output_view = Self::create_output(device, targets.width, targets.height);
+        self.buffers = Self::buffers_bind_group(
+            device,
+            pass,
+            targets.color_buffer,
+            targets.depth_buffer,
+            targets.accumulation_buffer,
+            bloom,
+            &self.output_view,
+        );
+    }
+
+    fn create_output(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(&LABELS.of("Output Texture")),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: OUTPUT_TEXTURE_FORMAT,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            })
+            .create_view(&Default::default())
+    }
+
+    fn buffers_bind_group(
+        device: &wgpu::Device,
+        ResolvePass { pipeline }: &ResolvePass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
+        bloom: &wgpu::Buffer,
+        output_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: bloom.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Resolved image, sampled by the present pass.
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.output_view
+    }
+}
+
+impl<'a> ResolvePass {
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a ResolveBindings,
+        dispatch_size: u32,
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        cpass.set_bind_group(1, &bindings.uniform, &[]);
+        cpass.dispatch_workgroups(dispatch_size, 1, 1);
+    }
+}
+
+impl Pass for Node<'_, ResolvePass, ResolveBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, frame: &Frame) -> Vec<&'static str> {
+        let mut reads = vec!["Color Buffer", "Depth Buffer", "Accumulation"];
+        if frame.bloom {
+            reads.push("Bloom");
+        }
+        reads
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Output Texture"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass
+            .record(&mut cpass, self.bindings, frame.pixel_dispatch());
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![frame.occupancy(self.name(), frame.pixels())]
+    }
+}
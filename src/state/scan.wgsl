@@ -0,0 +1,94 @@
+// Device-wide exclusive prefix sum of `input` into `output`, with the sum of
+// all the values written to `total`. Done in three steps: a scan inside each
+// workgroup, a scan of the workgroup totals, and adding those back.
+
+struct Values {
+  values: array<u32>,
+}
+
+struct Total {
+  value: u32,
+}
+
+@group(0) @binding(0) var<storage, read> input : Values;
+@group(0) @binding(1) var<storage, read_write> output : Values;
+@group(0) @binding(2) var<storage, read_write> block_sums : Values;
+@group(0) @binding(3) var<storage, read_write> total : Total;
+
+// WORKGROUP_SIZE is defined by the pass, a power of two within the adapter limits
+var<workgroup> scratch: array<u32, WORKGROUP_SIZE>;
+
+// Hillis-Steele scan across the workgroup, returns the exclusive prefix of
+// `value`. The workgroup total is left in the last element of `scratch`.
+fn workgroup_exclusive_scan(local: u32, value: u32) -> u32 {
+  scratch[local] = value;
+  workgroupBarrier();
+  for (var offset = 1u; offset < WORKGROUP_SIZE; offset = offset * 2u) {
+    var acc = scratch[local];
+    if (local >= offset) {
+      acc = acc + scratch[local - offset];
+    }
+    workgroupBarrier();
+    scratch[local] = acc;
+    workgroupBarrier();
+  }
+  return scratch[local] - value;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn scan_blocks(
+  @builtin(global_invocation_id) global_id: vec3<u32>,
+  @builtin(local_invocation_id) local_id: vec3<u32>,
+  @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+  let len = arrayLength(&input.values);
+  var value = 0u;
+  if (global_id.x < len) {
+    value = input.values[global_id.x];
+  }
+
+  let prefix = workgroup_exclusive_scan(local_id.x, value);
+  if (global_id.x < len) {
+    output.values[global_id.x] = prefix;
+  }
+  if (local_id.x == WORKGROUP_SIZE - 1u) {
+    block_sums.values[workgroup_id.x] = prefix + value;
+  }
+}
+
+// Single workgroup, walks over the block totals in chunks carrying the sum
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn scan_block_sums(@builtin(local_invocation_id) local_id: vec3<u32>) {
+  let blocks = arrayLength(&block_sums.values);
+  var carry = 0u;
+  for (var base = 0u; base < blocks; base = base + WORKGROUP_SIZE) {
+    let i = base + local_id.x;
+    var value = 0u;
+    if (i < blocks) {
+      value = block_sums.values[i];
+    }
+
+    let prefix = workgroup_exclusive_scan(local_id.x, value);
+    let chunk_total = scratch[WORKGROUP_SIZE - 1u];
+    if (i < blocks) {
+      block_sums.values[i] = carry + prefix;
+    }
+    carry = carry + chunk_total;
+    workgroupBarrier();
+  }
+
+  if (local_id.x == 0u) {
+    total.value = carry;
+  }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn add_block_offsets(
+  @builtin(global_invocation_id) global_id: vec3<u32>,
+  @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+  if (global_id.x >= arrayLength(&input.values)) {
+    return;
+  }
+  output.values[global_id.x] = output.values[global_id.x] + block_sums.values[workgroup_id.x];
+}
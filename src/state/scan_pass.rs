@@ -0,0 +1,296 @@
+use super::{
+    shaders::Shaders,
+    util::{Labels, WorkgroupSize},
+};
+
+const LABELS: Labels = Labels::new("Scan");
+
+/// Device-wide exclusive prefix sum over a buffer of u32.
+///
+/// Each workgroup scans its block of `WORKGROUP_SIZE` values, a single
+/// workgroup then scans the block totals and the last dispatch adds them back,
+/// so any length is supported with three dispatches.
+pub struct ScanPass {
+    scan_pipeline: wgpu::ComputePipeline,
+    block_sums_pipeline: wgpu::ComputePipeline,
+    add_offsets_pipeline: wgpu::ComputePipeline,
+    workgroup_size: WorkgroupSize,
+}
+
+impl ScanPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&LABELS.of("Bind Group Layout")),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                storage_entry(2, false),
+                storage_entry(3, false),
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.compute_module(
+            device,
+            "scan.wgsl",
+            include_str!("scan.wgsl"),
+            workgroup_size,
+        );
+        let pipeline = |label: &str, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        Self {
+            scan_pipeline: pipeline(&LABELS.of("Blocks Pipeline"), "scan_blocks"),
+            block_sums_pipeline: pipeline(&LABELS.of("Block Sums Pipeline"), "scan_block_sums"),
+            add_offsets_pipeline: pipeline(&LABELS.of("Add Offsets Pipeline"), "add_block_offsets"),
+            workgroup_size,
+        }
+    }
+}
+
+pub struct ScanBindings {
+    /// None when `len` is zero, empty buffers can't be bound
+    bind_group: Option<wgpu::BindGroup>,
+    total: wgpu::Buffer,
+    len: u32,
+}
+
+impl ScanBindings {
+    /// `input` and `output` must hold exactly `len` u32 values,
+    /// `output` can't alias `input`. An empty scan records nothing, its
+    /// total stays zero.
+    pub fn new(
+        device: &wgpu::Device,
+        ScanPass {
+            scan_pipeline,
+            workgroup_size,
+            ..
+        }: &ScanPass,
+        input: &wgpu::Buffer,
+        output: &wgpu::Buffer,
+        len: u32,
+    ) -> Self {
+        let u32_size = std::mem::size_of::<u32>() as u64;
+        let block_sums = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Block Sums Buffer")),
+            size: u32_size * workgroup_size.dispatch_size(len.max(1)) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let total = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&LABELS.of("Total Buffer")),
+            size: u32_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = (len > 0).then(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&LABELS.of("Bind Group")),
+                layout: &scan_pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: input.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: output.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: block_sums.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: total.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+
+        Self {
+            bind_group,
+            total,
+            len,
+        }
+    }
+
+    /// Single u32 holding the sum of all the input values.
+    pub fn total(&self) -> &wgpu::Buffer {
+        &self.total
+    }
+}
+
+impl<'a> ScanPass {
+    pub fn record<'pass>(&'a self, cpass: &mut wgpu::ComputePass<'pass>, bindings: &'a ScanBindings)
+    where
+        'a: 'pass,
+    {
+        let Some(bind_group) = &bindings.bind_group else {
+            return;
+        };
+        let blocks = self.workgroup_size.dispatch_size(bindings.len);
+
+        cpass.set_bind_group(0, bind_group, &[]);
+        cpass.set_pipeline(&self.scan_pipeline);
+        cpass.dispatch_workgroups(blocks, 1, 1);
+        cpass.set_pipeline(&self.block_sums_pipeline);
+        cpass.dispatch_workgroups(1, 1, 1);
+        cpass.set_pipeline(&self.add_offsets_pipeline);
+        cpass.dispatch_workgroups(blocks, 1, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+    use super::*;
+    use crate::state::util::{drive, map_read};
+
+    /// Device on the backends of `WGPU_BACKEND`, with the limits of the adapter
+    /// so that the workgroups are as large as in the renderer.
+    fn device() -> (wgpu::Device, wgpu::Queue) {
+        let backends = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
+        let instance = wgpu::Instance::new(backends);
+        let adapter =
+            pollster::block_on(instance.request_adapter(&Default::default())).expect("No adapter");
+        pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: adapter.limits(),
+            },
+            None,
+        ))
+        .expect("No device")
+    }
+
+    fn exclusive_scan(values: &[u32]) -> (Vec<u32>, u32) {
+        let mut sum = 0u32;
+        let prefix = values
+            .iter()
+            .map(|value| {
+                let prefix = sum;
+                sum = sum.wrapping_add(*value);
+                prefix
+            })
+            .collect();
+        (prefix, sum)
+    }
+
+    /// The first `len` words of `buffer`.
+    fn read(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        len: u32,
+    ) -> Vec<u32> {
+        let size = (len as usize * std::mem::size_of::<u32>()) as u64;
+        if size == 0 {
+            return Vec::new();
+        }
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, size);
+        queue.submit(Some(encoder.finish()));
+        pollster::block_on(drive(device, map_read(readback.slice(..)))).unwrap();
+        let words = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+        words
+    }
+
+    /// Scans `len` pseudo-random values on the GPU and compares the prefix
+    /// and the total with the ones of the CPU.
+    fn check_scan(len: impl FnOnce(WorkgroupSize) -> u32) {
+        let (device, queue) = device();
+        let workgroup_size = WorkgroupSize::new(&device.limits(), None);
+        let len = len(workgroup_size);
+        let values: Vec<u32> = (0..len).map(|i| i.wrapping_mul(2654435761) >> 28).collect();
+
+        let pass = ScanPass::new(&device, &Shaders::new(None), workgroup_size);
+        let usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let input = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&values),
+            usage,
+        });
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (values.len() * std::mem::size_of::<u32>()) as u64,
+            usage,
+            mapped_at_creation: false,
+        });
+        let bindings = ScanBindings::new(&device, &pass, &input, &output, len);
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        pass.record(
+            &mut encoder.begin_compute_pass(&Default::default()),
+            &bindings,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let (prefix, total) = exclusive_scan(&values);
+        assert_eq!(read(&device, &queue, &output, len), prefix, "len {}", len);
+        assert_eq!(
+            read(&device, &queue, bindings.total(), 1),
+            [total],
+            "len {}",
+            len
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scans_nothing() {
+        check_scan(|_| 0);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scans_a_single_value() {
+        check_scan(|_| 1);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scans_a_workgroup() {
+        check_scan(|size| size.get());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scans_past_a_workgroup() {
+        check_scan(|size| size.get() + 1);
+    }
+
+    /// More blocks than a workgroup, `scan_block_sums` walks them in chunks.
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scans_more_blocks_than_a_workgroup() {
+        check_scan(|size| size.get() * size.get() + 3 * size.get() + 5);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{f32::consts::TAU, fmt, str::FromStr};
+
+use color_eyre::{eyre::eyre, Report};
+
+use super::util::{flat_normals, v, Material, Mesh, Vertex};
+
+/// Synthetic scenes exercising rasterizer edge cases.
+/// Geometry lies in the XZ plane around the origin, same footprint as Suzanne.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestScene {
+    /// Fan of long sliver triangles
+    Thin,
+    /// Triangle much bigger than the screen
+    Huge,
+    /// Two overlapping quads at the same depth
+    Coplanar,
+    /// Grid of tiny triangles close to the far plane
+    Tiny,
+    /// Quad edge with a vertex of the neighbouring quads lying on it
+    TJunction,
+    /// Two triangles sharing a diagonal edge, checked with `--check-coverage`
+    SharedEdge,
+    /// Closed fan of triangles sharing their edges and the center vertex
+    Fan,
+}
+
+impl TestScene {
+    pub const ALL: [TestScene; 7] = [
+        TestScene::Thin,
+        TestScene::Huge,
+        TestScene::Coplanar,
+        TestScene::Tiny,
+        TestScene::TJunction,
+        TestScene::SharedEdge,
+        TestScene::Fan,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TestScene::Thin => "thin",
+            TestScene::Huge => "huge",
+            TestScene::Coplanar => "coplanar",
+            TestScene::Tiny => "tiny",
+            TestScene::TJunction => "t-junction",
+            TestScene::SharedEdge => "shared-edge",
+            TestScene::Fan => "fan",
+        }
+    }
+
+    pub fn mesh(self) -> Mesh {
+        let mut vertices = match self {
+            TestScene::Thin => thin(),
+            TestScene::Huge => Vec::from([v!(-50., 0., -50.), v!(50., 0., -50.), v!(0., 0., 50.)]),
+            TestScene::Coplanar => [
+                quad([-1.0, -1.0], [0.5, 0.5]),
+                quad([-0.5, -0.5], [1.0, 1.0]),
+            ]
+            .concat(),
+            TestScene::Tiny => tiny(),
+            TestScene::TJunction => [
+                quad([-1.0, -1.0], [0.0, 1.0]),
+                quad([0.0, -1.0], [1.0, 0.0]),
+                quad([0.0, 0.0], [1.0, 1.0]),
+            ]
+            .concat(),
+            TestScene::SharedEdge => shared_edge(),
+            TestScene::Fan => fan(),
+        };
+        flat_normals(&mut vertices);
+        Mesh::with_material(vertices, Material::two_sided())
+    }
+}
+
+impl fmt::Display for TestScene {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TestScene {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TestScene::ALL
+            .into_iter()
+            .find(|scene| scene.name() == s)
+            .ok_or_else(|| {
+                let names = TestScene::ALL.map(TestScene::name).join(", ");
+                eyre!("Unknown test scene `{}`, expected one of: {}", s, names)
+            })
+    }
+}
+
+fn quad([x0, z0]: [f32; 2], [x1, z1]: [f32; 2]) -> Vec<Vertex> {
+    Vec::from([
+        v!(x0, 0., z0),
+        v!(x1, 0., z0),
+        v!(x1, 0., z1),
+        v!(x0, 0., z0),
+        v!(x1, 0., z1),
+        v!(x0, 0., z1),
+    ])
+}
+
+fn thin() -> Vec<Vertex> {
+    let count = 32;
+    let (radius, width) = (1.5, 0.01);
+    (0..count)
+        .flat_map(|i| {
+            let a = i as f32 * TAU / count as f32;
+            [
+                v!(0., 0., 0.),
+                v!(a.cos() * radius, 0., a.sin() * radius),
+                v!((a + width).cos() * radius, 0., (a + width).sin() * radius),
+            ]
+        })
+        .collect()
+}
+
+fn tiny() -> Vec<Vertex> {
+    let (count, spacing, size, depth) = (32, 0.5, 0.04, -20.);
+    let offset = count as f32 * spacing / 2.;
+    (0..count * count)
+        .flat_map(|i| {
+            let x = (i % count) as f32 * spacing - offset;
+            let z = (i / count) as f32 * spacing - offset;
+            [
+                v!(x, depth, z),
+                v!(x + size, depth, z),
+                v!(x, depth, z + size),
+            ]
+        })
+        .collect()
+}
+
+/// Irregular quad so that the diagonal isn't aligned with the pixel grid.
+fn shared_edge() -> Vec<Vertex> {
+    let corners = [
+        v!(-1.1, 0., -0.9),
+        v!(0.9, 0., -1.2),
+        v!(1.2, 0., 0.8),
+        v!(-0.7, 0., 1.1),
+    ];
+    Vec::from([
+        corners[0], corners[1], corners[2], corners[0], corners[2], corners[3],
+    ])
+}
+
+/// Rotated off the axes so that no edge is aligned with the pixel grid.
+fn fan() -> Vec<Vertex> {
+    let (count, radius, rotation) = (12, 1.2, 0.1);
+    let rim = |i: usize| {
+        // The last triangle closes on the exact vertex of the first
+        let a = (i % count) as f32 * TAU / count as f32 + rotation;
+        v!(a.cos() * radius, 0., a.sin() * radius)
+    };
+    (0..count)
+        .flat_map(|i| [v!(0., 0., 0.), rim(i), rim(i + 1)])
+        .collect()
+}
@@ -0,0 +1,288 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use color_eyre::{eyre::eyre, Result};
+
+use super::{
+    preprocess::{preprocess, INCLUDES},
+    util::WorkgroupSize,
+};
+
+/// How often the shader files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lines around the `custom_shade` of raster.wgsl replaced by a snippet.
+const CUSTOM_SHADE_BEGIN: &str = "// BEGIN custom_shade";
+const CUSTOM_SHADE_END: &str = "// END custom_shade";
+
+/// A shader read at runtime from the source tree or the assets directory,
+/// so that it can be edited without recompiling. The copy embedded at build
+/// time is used when the file isn't around.
+pub struct ShaderFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    source: Cow<'static, str>,
+}
+
+impl ShaderFile {
+    fn new(path: PathBuf, embedded: &'static str) -> Self {
+        Self {
+            path,
+            modified: None,
+            source: Cow::Borrowed(embedded),
+        }
+    }
+
+    /// A file outside of the source tree, empty until it's read.
+    fn open(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+            source: Cow::Borrowed(""),
+        }
+    }
+
+    /// Last source read, the embedded one until the file changed.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The new source when the file was modified since the last call.
+    fn poll(&mut self) -> Option<&str> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        if self.modified.replace(modified) == Some(modified) {
+            return None;
+        }
+        let source = fs::read_to_string(&self.path).ok()?;
+        if source == self.source {
+            return None;
+        }
+        self.source = Cow::Owned(source);
+        Some(&self.source)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+}
+
+/// Watches the shaders iterated on the most, their passes are rebuilt when
+/// one changes.
+pub struct Shaders {
+    pub raster: ShaderFile,
+    pub present: ShaderFile,
+    pub line: ShaderFile,
+    /// Snippets of `include/`, a change rebuilds every watched shader
+    pub includes: Vec<ShaderFile>,
+    /// Defines `custom_shade` in place of the one of raster.wgsl
+    pub snippet: Option<ShaderFile>,
+    /// Overrides the embedded shaders that aren't watched, see `Shaders::new`
+    assets_dir: Option<PathBuf>,
+    checked_at: Option<Instant>,
+}
+
+/// Which of the watched shaders were modified.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShaderChanges {
+    pub raster: bool,
+    pub present: bool,
+    pub line: bool,
+    pub snippet: bool,
+}
+
+impl Shaders {
+    /// The shaders are read from `src/state` of the source tree, or from the
+    /// `shaders` directory of `assets_dir` when it's given. Unlike the source
+    /// tree, the assets are read before the first pipelines are built and
+    /// they replace every embedded shader, not only the watched ones.
+    pub fn new(assets_dir: Option<&Path>) -> Self {
+        let root = match assets_dir {
+            Some(dir) => dir.join("shaders"),
+            None => [env!("CARGO_MANIFEST_DIR"), "src", "state"]
+                .iter()
+                .collect(),
+        };
+        let file = |name: &str, embedded| ShaderFile::new(root.join(name), embedded);
+        let mut shaders = Self {
+            raster: file("raster.wgsl", include_str!("raster.wgsl")),
+            present: file("present.wgsl", include_str!("present.wgsl")),
+            line: file("line.wgsl", include_str!("line.wgsl")),
+            includes: INCLUDES
+                .iter()
+                .map(|&(name, embedded)| file(&format!("include/{}", name), embedded))
+                .collect(),
+            snippet: None,
+            assets_dir: assets_dir.map(|_| root.clone()),
+            checked_at: None,
+        };
+        if shaders.assets_dir.is_some() {
+            shaders.poll();
+        }
+        shaders
+    }
+
+    /// Starts watching the snippet at `path`, it's read on the next poll.
+    pub fn watch_snippet(&mut self, path: PathBuf) {
+        self.snippet = Some(ShaderFile::open(path));
+        self.checked_at = None;
+    }
+
+    /// raster.wgsl with its `custom_shade` replaced by the snippet, as is
+    /// until the snippet was read.
+    pub fn raster_source(&self) -> Cow<'_, str> {
+        let raster = self.raster.source();
+        let snippet = match &self.snippet {
+            Some(snippet) if !snippet.source().is_empty() => snippet.source(),
+            _ => return Cow::Borrowed(raster),
+        };
+        match (
+            raster.find(CUSTOM_SHADE_BEGIN),
+            raster.find(CUSTOM_SHADE_END),
+        ) {
+            (Some(begin), Some(end)) if begin < end => Cow::Owned(format!(
+                "{}{}\n{}",
+                &raster[..begin],
+                snippet,
+                &raster[end..]
+            )),
+            _ => Cow::Borrowed(raster),
+        }
+    }
+
+    pub fn raster(&self) -> Source<'_> {
+        self.source("raster.wgsl", self.raster_source())
+    }
+
+    pub fn present(&self) -> Source<'_> {
+        self.source("present.wgsl", Cow::Borrowed(self.present.source()))
+    }
+
+    pub fn line(&self) -> Source<'_> {
+        self.source("line.wgsl", Cow::Borrowed(self.line.source()))
+    }
+
+    /// A shader that isn't watched, embedded unless the assets directory
+    /// has a file of the same name.
+    pub fn embedded(&self, name: &'static str, text: &'static str) -> Source<'_> {
+        let text = self
+            .assets_dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(dir.join(name)).ok())
+            .map_or(Cow::Borrowed(text), Cow::Owned);
+        self.source(name, text)
+    }
+
+    /// Compiles an unwatched shader, it's expected to preprocess.
+    pub fn module(
+        &self,
+        device: &wgpu::Device,
+        name: &'static str,
+        text: &'static str,
+    ) -> wgpu::ShaderModule {
+        self.embedded(name, text).module(device, &[]).unwrap()
+    }
+
+    /// Compiles an unwatched shader of a pass over flat buffers, with
+    /// `WORKGROUP_SIZE` defined.
+    pub fn compute_module(
+        &self,
+        device: &wgpu::Device,
+        name: &'static str,
+        text: &'static str,
+        workgroup_size: WorkgroupSize,
+    ) -> wgpu::ShaderModule {
+        let size = workgroup_size.literal();
+        self.embedded(name, text)
+            .module(device, &[("WORKGROUP_SIZE", &size)])
+            .unwrap()
+    }
+
+    fn source<'a>(&'a self, name: &'a str, text: Cow<'a, str>) -> Source<'a> {
+        Source {
+            name,
+            text,
+            includes: self
+                .includes
+                .iter()
+                .map(|include| (include.name(), include.source()))
+                .collect(),
+        }
+    }
+
+    /// Checks the files at most every `POLL_INTERVAL`. The first check picks
+    /// up the edits made since the build.
+    pub fn poll(&mut self) -> ShaderChanges {
+        if self
+            .checked_at
+            .is_some_and(|checked_at| checked_at.elapsed() < POLL_INTERVAL)
+        {
+            return ShaderChanges::default();
+        }
+        self.checked_at = Some(Instant::now());
+        let mut included = false;
+        for include in &mut self.includes {
+            included |= include.poll().is_some();
+        }
+        ShaderChanges {
+            raster: self.raster.poll().is_some() || included,
+            present: self.present.poll().is_some() || included,
+            line: self.line.poll().is_some() || included,
+            snippet: self
+                .snippet
+                .as_mut()
+                .is_some_and(|snippet| snippet.poll().is_some()),
+        }
+    }
+}
+
+/// A shader to preprocess, with the snippets it can include.
+pub struct Source<'a> {
+    pub name: &'a str,
+    pub text: Cow<'a, str>,
+    pub includes: Vec<(&'a str, &'a str)>,
+}
+
+impl Source<'_> {
+    /// Preprocesses the source with `defines` and compiles it. Only the
+    /// preprocessing errors are returned, see `validated` for the others.
+    pub fn module(
+        &self,
+        device: &wgpu::Device,
+        defines: &[(&str, &str)],
+    ) -> Result<wgpu::ShaderModule> {
+        let source = preprocess(self.name, &self.text, defines, &self.includes)?;
+        Ok(create_shader(device, self.name, &source))
+    }
+}
+
+pub fn create_shader(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+    })
+}
+
+/// Runs `build` catching the validation errors, which would otherwise abort.
+/// Lets a pass keep its pipelines when an edited shader doesn't compile.
+pub fn validated<T>(device: &wgpu::Device, build: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let built = build();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(wgpu::Error::Validation { description, .. }) => Err(eyre!(description)),
+        Some(error) => Err(eyre!("{}", error)),
+        None => Ok(built),
+    }
+}
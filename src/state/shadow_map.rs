@@ -0,0 +1,333 @@
+use glam::{Mat4, Vec2, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    cull_pass::{CullBindings, CullPass, CullUniform},
+    default_chunk_size,
+    ibl_pass::IblUniform,
+    lights::{Light, LightKind},
+    occupancy::PassOccupancy,
+    raster_pass::{
+        ClearBindings, ClearPass, ClearUniform, DebugView, RasterBindings, RasterChunks,
+        RasterPass, RasterUniform, TileBins,
+    },
+    render_graph::{Frame, GraphPass, Pass, Stage},
+    util::{create_color_buffer, create_depth_buffer, Labels, Meshlet, Uniform, WorkgroupSize},
+};
+use crate::camera::CameraUniform;
+
+const LABELS: Labels = Labels::new("Shadow");
+
+/// Side of the square shadow map, matches SHADOW_MAP_SIZE in raster.wgsl.
+pub const SHADOW_MAP_SIZE: u32 = 1024;
+
+/// Depth of the mesh seen from the first light when it is directional,
+/// rasterized by the depth pass of `RasterPass` and looked up by the color
+/// pass of the main view. Shared by the tabs, see `ShadowBindings` for the
+/// part built from the mesh.
+pub struct ShadowMap {
+    depth_buffer: wgpu::Buffer,
+    /// Never read, the clear and the raster bind it along with the depth
+    color_buffer: wgpu::Buffer,
+    screen_uniform: wgpu::Buffer,
+    raster_uniform: wgpu::Buffer,
+    cull_uniform: wgpu::Buffer,
+    camera_buffer: wgpu::Buffer,
+    /// Without image-based lighting, the depth pass doesn't shade
+    ibl_uniform: wgpu::Buffer,
+    /// Bound in place of the shadow map and the depth pyramid by the passes
+    /// rendering it, which can't read what they write
+    placeholder: wgpu::Buffer,
+    /// Resets the depth, the color is never read
+    clear: ClearBindings,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, clear_pass: &ClearPass) -> Self {
+        let buffer = |label: &str, contents: &[u8], usage| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage,
+            })
+        };
+        let uniform = wgpu::BufferUsages::UNIFORM;
+        let depth_buffer = create_depth_buffer(device, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE);
+        let color_buffer = create_color_buffer(device, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE);
+        let clear = ClearBindings::new(
+            device,
+            clear_pass,
+            &color_buffer,
+            &depth_buffer,
+            &buffer(
+                &LABELS.of("Clear Uniform Buffer"),
+                bytemuck::bytes_of(&ClearUniform::new([0.0; 3])),
+                uniform,
+            ),
+        );
+        Self {
+            depth_buffer,
+            color_buffer,
+            screen_uniform: buffer(
+                &LABELS.of("Screen Uniform Buffer"),
+                bytemuck::bytes_of(&Uniform::new(SHADOW_MAP_SIZE as _, SHADOW_MAP_SIZE as _)),
+                uniform,
+            ),
+            raster_uniform: buffer(
+                &LABELS.of("Raster Uniform Buffer"),
+                bytemuck::bytes_of(&RasterUniform::new(
+                    DebugView::Lambert,
+                    0,
+                    Vec2::ZERO,
+                    false,
+                )),
+                uniform,
+            ),
+            // Without occlusion, and the normal cones of the meshlets would be
+            // tested against the light as a point
+            cull_uniform: buffer(
+                &LABELS.of("Cull Uniform Buffer"),
+                bytemuck::bytes_of(&CullUniform::new(true, false, false, 0)),
+                uniform,
+            ),
+            camera_buffer: buffer(
+                &LABELS.of("Camera Buffer"),
+                bytemuck::bytes_of(&CameraUniform::default()),
+                uniform | wgpu::BufferUsages::COPY_DST,
+            ),
+            ibl_uniform: buffer(
+                &LABELS.of("Ibl Uniform Buffer"),
+                bytemuck::bytes_of(&IblUniform::new(Mat4::IDENTITY, false)),
+                uniform,
+            ),
+            placeholder: buffer(
+                &LABELS.of("Placeholder Buffer"),
+                bytemuck::bytes_of(&0u32),
+                wgpu::BufferUsages::STORAGE,
+            ),
+            clear,
+        }
+    }
+
+    /// Read by the color pass of the main view.
+    pub fn depth_buffer(&self) -> &wgpu::Buffer {
+        &self.depth_buffer
+    }
+
+    /// Light camera the shadow map is rendered with.
+    pub fn camera_buffer(&self) -> &wgpu::Buffer {
+        &self.camera_buffer
+    }
+
+    /// Fits the light camera to the bounds of the mesh. Returns false when
+    /// the first light doesn't cast shadows, only a directional one does.
+    pub fn write_camera(
+        &self,
+        queue: &wgpu::Queue,
+        lights: &[Light],
+        bindings: &ShadowBindings,
+    ) -> bool {
+        match lights.first() {
+            Some(light) if light.kind == LightKind::Directional => {
+                let uniform = light_camera(light.direction, bindings.center, bindings.radius);
+                queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&uniform));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Orthographic camera looking along `direction` at the sphere of `center`
+/// and `radius`. Maps the sphere into [0, 1] over the shadow map as the
+/// rasterizer does, the triangles keep their winding.
+fn light_camera(direction: Vec3, center: Vec3, radius: f32) -> CameraUniform {
+    let direction = match direction.normalize_or_zero() {
+        Vec3::ZERO => Vec3::NEG_Y,
+        direction => direction,
+    };
+    let radius = radius.max(1e-3);
+    let eye = center - direction * radius * 2.0;
+    let up = match direction.y.abs() > 0.99 {
+        true => Vec3::Z,
+        false => Vec3::Y,
+    };
+    let view = Mat4::look_at_rh(eye, center, up);
+    let projection = Mat4::orthographic_rh(-radius, radius, -radius, radius, radius, radius * 3.0);
+    let to_map = Mat4::from_translation(Vec3::new(0.5, 0.5, 0.0))
+        * Mat4::from_scale(Vec3::new(0.5, 0.5, 1.0));
+
+    let mut uniform = CameraUniform::default();
+    uniform.view_position = eye.extend(1.0).to_array();
+    uniform.view_proj = (to_map * projection * view).to_cols_array_2d();
+    uniform.view = view.to_cols_array_2d();
+    uniform
+}
+
+/// Culling and raster bindings of a mesh for the shadow map.
+pub struct ShadowBindings {
+    cull: CullBindings,
+    chunks: RasterChunks,
+    raster: RasterBindings,
+    /// Bounding sphere of the mesh the light camera is fitted to
+    center: Vec3,
+    radius: f32,
+}
+
+impl ShadowBindings {
+    /// `bounds` are the ones of the mesh in its object space. The work queue
+    /// is unused, the shadow map is only rasterized per triangle.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        cull_pass: &CullPass,
+        raster_pass: &RasterPass,
+        shadow_map: &ShadowMap,
+        vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+        material_buffer: &wgpu::Buffer,
+        triangle_buffer: &wgpu::Buffer,
+        work_queue: &wgpu::Buffer,
+        lights: &wgpu::Buffer,
+        meshlets: &[Meshlet],
+        triangle_count: u32,
+        (min, max): (Vec3, Vec3),
+    ) -> Self {
+        let cull = CullBindings::new(
+            device,
+            cull_pass,
+            vertex_buffer,
+            index_buffer,
+            &shadow_map.placeholder,
+            &shadow_map.camera_buffer,
+            &shadow_map.screen_uniform,
+            &shadow_map.cull_uniform,
+            &shadow_map.cull_uniform,
+            meshlets,
+            triangle_count,
+        );
+        // Never binned, sized for the smallest map
+        let tile_bins = TileBins::new(device, raster_pass, 1, 1, 0);
+        let chunks = RasterChunks::new(device, triangle_count, default_chunk_size(triangle_count));
+        let raster = RasterBindings::new(
+            device,
+            raster_pass,
+            &shadow_map.color_buffer,
+            &shadow_map.depth_buffer,
+            vertex_buffer,
+            index_buffer,
+            material_buffer,
+            triangle_buffer,
+            work_queue,
+            &cull,
+            &shadow_map.screen_uniform,
+            &shadow_map.raster_uniform,
+            &tile_bins,
+            &chunks,
+            &shadow_map.camera_buffer,
+            lights,
+            &shadow_map.placeholder,
+            &shadow_map.camera_buffer,
+            &shadow_map.placeholder,
+            &shadow_map.ibl_uniform,
+        );
+        Self {
+            cull,
+            chunks,
+            raster,
+            center: (min + max) * 0.5,
+            radius: (max - min).length() * 0.5,
+        }
+    }
+}
+
+impl<'a> ShadowBindings {
+    /// Clears the shadow map and rasterizes the depth of the triangles the
+    /// light camera sees into it.
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        shadow_map: &'a ShadowMap,
+        cull_pass: &'a CullPass,
+        clear_pass: &'a ClearPass,
+        raster_pass: &'a RasterPass,
+        workgroup_size: WorkgroupSize,
+    ) where
+        'a: 'pass,
+    {
+        cull_pass.record(cpass, &self.cull);
+        clear_pass.record(
+            cpass,
+            &shadow_map.clear,
+            workgroup_size.dispatch_size(SHADOW_MAP_SIZE * SHADOW_MAP_SIZE),
+        );
+        raster_pass.record_depth(cpass, &self.raster, &self.chunks, &self.cull);
+    }
+}
+
+/// The shadow map as a pass of the frame, along with the passes it's culled,
+/// cleared and rasterized by.
+pub struct ShadowNode<'a> {
+    pub shadow_map: &'a ShadowMap,
+    pub bindings: &'a ShadowBindings,
+    pub cull_pass: &'a CullPass,
+    pub clear_pass: &'a ClearPass,
+    pub raster_pass: &'a RasterPass,
+}
+
+impl Pass for ShadowNode<'_> {
+    fn name(&self) -> &'static str {
+        "Shadow Map"
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Vertices", "Indices", "Light Camera"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Shadow Map"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.bindings.record(
+            &mut cpass,
+            self.shadow_map,
+            self.cull_pass,
+            self.clear_pass,
+            self.raster_pass,
+            frame.workgroup_size,
+        );
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Raster
+    }
+
+    fn steps(&self, _: &Frame) -> Vec<GraphPass> {
+        vec![
+            GraphPass::new(
+                "Shadow Cull",
+                &["Vertices", "Indices", "Light Camera"],
+                &["Shadow Triangles"],
+            ),
+            GraphPass::new("Shadow Clear", &[], &["Shadow Map"]),
+            GraphPass::new(
+                "Shadow Depth",
+                &["Vertices", "Indices", "Light Camera", "Shadow Triangles"],
+                &["Shadow Map"],
+            ),
+        ]
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        let triangles = frame.triangle_count;
+        vec![
+            self.bindings.cull.occupancy("Shadow Cull", triangles),
+            frame.occupancy("Shadow Clear", SHADOW_MAP_SIZE * SHADOW_MAP_SIZE),
+            frame.occupancy("Shadow Depth", triangles),
+        ]
+    }
+}
@@ -0,0 +1,118 @@
+// Fills the pixels no fragment was written to with the sky seen through them,
+// a gradient or an equirectangular environment map looked up by the view
+// direction of the pixel. Only the color is written, the depth stays cleared.
+
+#include "camera.wgsl"
+#include "color.wgsl"
+
+struct ColorBuffer {
+  values: array<u32>,
+}
+
+struct DepthBuffer {
+  values: array<u32>,
+}
+
+// RGB9E5 texels of the environment, row by row from the zenith down
+struct Environment {
+  texels: array<u32>,
+}
+
+struct Uniform {
+  width: f32,
+  height: f32,
+}
+
+struct SkyUniform {
+  // Of the world space camera, the mesh transform doesn't turn the sky
+  inv_view_proj: mat4x4<f32>,
+  inv_view: mat4x4<f32>,
+  // Matches `Sky`
+  mode: u32,
+  // Matches `Projection`
+  projection: u32,
+  fov: f32,
+  environment_width: u32,
+  environment_height: u32,
+}
+
+let PI = 3.14159265359;
+let SKY_ENVIRONMENT = 2u;
+
+// Linear colors of the gradient
+let ZENITH = vec3<f32>(0.12, 0.28, 0.65);
+let HORIZON = vec3<f32>(0.65, 0.75, 0.9);
+let GROUND = vec3<f32>(0.2, 0.18, 0.16);
+
+@group(0) @binding(0) var<storage, read_write> color_buffer: ColorBuffer;
+@group(0) @binding(1) var<storage, read> depth_buffer: DepthBuffer;
+@group(0) @binding(2) var<storage, read> environment: Environment;
+@group(1) @binding(0) var<uniform> screen_dims: Uniform;
+@group(1) @binding(1) var<uniform> params: SkyUniform;
+
+// World space direction through the pixel, the inverse of `project` of
+// raster.wgsl, which maps NDC xy in [0, 1] onto the color buffer
+fn view_direction(xy: vec2<u32>) -> vec3<f32> {
+  let ndc = (vec2<f32>(xy) + 0.5) / vec2<f32>(screen_dims.width, screen_dims.height);
+  if (params.projection == PROJECTION_PERSPECTIVE) {
+    let near = params.inv_view_proj * vec4<f32>(ndc, 0.0, 1.0);
+    let far = params.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    return normalize(far.xyz / far.w - near.xyz / near.w);
+  }
+
+  var dir: vec3<f32>;
+  if (params.projection == PROJECTION_FISHEYE) {
+    let p = vec2<f32>(ndc.x * screen_dims.width / screen_dims.height, ndc.y);
+    let r = length(p);
+    let theta = min(r * params.fov * 0.5, PI);
+    let xy = p / max(r, 1e-6) * sin(theta);
+    dir = vec3<f32>(xy, -cos(theta));
+  } else {
+    let longitude = ndc.x * PI;
+    let latitude = ndc.y * PI * 0.5;
+    dir = vec3<f32>(cos(latitude) * sin(longitude), sin(latitude), -cos(latitude) * cos(longitude));
+  }
+  return normalize((params.inv_view * vec4<f32>(dir, 0.0)).xyz);
+}
+
+fn gradient(dir: vec3<f32>) -> vec3<f32> {
+  if (dir.y >= 0.0) {
+    return mix(HORIZON, ZENITH, sqrt(dir.y));
+  }
+  return mix(HORIZON, GROUND, min(-dir.y * 4.0, 1.0));
+}
+
+// Longitude wraps around, latitude clamps at the poles
+fn texel(xy: vec2<i32>) -> vec3<f32> {
+  let width = i32(params.environment_width);
+  let height = i32(params.environment_height);
+  let x = ((xy.x % width) + width) % width;
+  let y = clamp(xy.y, 0, height - 1);
+  return unpack_color(environment.texels[u32(x + y * width)]);
+}
+
+fn environment_map(dir: vec3<f32>) -> vec3<f32> {
+  let uv = vec2<f32>(atan2(dir.x, -dir.z) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+  let p = uv * vec2<f32>(f32(params.environment_width), f32(params.environment_height)) - 0.5;
+  let base = vec2<i32>(floor(p));
+  let t = p - floor(p);
+  let top = mix(texel(base), texel(base + vec2<i32>(1, 0)), t.x);
+  let bottom = mix(texel(base + vec2<i32>(0, 1)), texel(base + vec2<i32>(1, 1)), t.x);
+  return mix(top, bottom, t.y);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1)
+fn sky(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let width = u32(screen_dims.width);
+  let index = global_id.x;
+  if (index >= width * u32(screen_dims.height) || depth_buffer.values[index] != 0xffffffffu) {
+    return;
+  }
+
+  let dir = view_direction(vec2<u32>(index % width, index / width));
+  var color = gradient(dir);
+  if (params.mode == SKY_ENVIRONMENT) {
+    color = environment_map(dir);
+  }
+  color_buffer.values[index] = pack_color(color);
+}
@@ -0,0 +1,283 @@
+use std::path::Path;
+
+use bytemuck::{Pod, Zeroable};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::{
+    occupancy::PassOccupancy,
+    render_graph::{Frame, Node, Pass},
+    shaders::Shaders,
+    util::{pack_color, Labels, WorkgroupSize},
+};
+use crate::camera::Camera;
+
+const LABELS: Labels = Labels::new("Sky");
+
+/// What the pixels without any fragment show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sky {
+    /// The background color, not affected by the exposure
+    #[default]
+    Solid,
+    /// From the horizon up to the zenith and down to the ground
+    Gradient,
+    /// Equirectangular map loaded by `Renderer::load_environment`
+    Environment,
+}
+
+impl Sky {
+    /// The environment is skipped until one is loaded.
+    pub fn next(self, environment: bool) -> Self {
+        match self {
+            Sky::Solid => Sky::Gradient,
+            Sky::Gradient if environment => Sky::Environment,
+            Sky::Gradient | Sky::Environment => Sky::Solid,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct SkyUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    inv_view: [[f32; 4]; 4],
+    mode: u32,
+    projection: u32,
+    fov: f32,
+    environment_width: u32,
+    environment_height: u32,
+    _padding: [u32; 3],
+}
+
+impl SkyUniform {
+    /// The sky is seen from the camera in world space, it doesn't turn with the mesh.
+    pub fn new(sky: Sky, camera: &Camera, environment: &Environment) -> Self {
+        Self {
+            inv_view_proj: camera
+                .build_view_projection_matrix()
+                .inverse()
+                .to_cols_array_2d(),
+            inv_view: camera.build_view_matrix().inverse().to_cols_array_2d(),
+            mode: sky as u32,
+            projection: camera.projection as u32,
+            fov: camera.fov(),
+            environment_width: environment.width,
+            environment_height: environment.height,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Equirectangular environment map, its texels packed row by row from the
+/// zenith down.
+pub struct Environment {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+}
+
+impl Environment {
+    /// Black texel bound until a map is loaded.
+    pub fn empty(device: &wgpu::Device) -> Self {
+        Self::new(device, 1, 1, &[0])
+    }
+
+    /// Reads a Radiance HDR image, or any other format `image` supports as linear.
+    pub fn load(device: &wgpu::Device, path: &Path) -> Result<Self> {
+        let context = || format!("Failed to load environment `{}`", path.display());
+        let image = image::open(path).wrap_err_with(context)?.into_rgb32f();
+        let (width, height) = image.dimensions();
+        let size = std::mem::size_of::<u32>() as u64 * width as u64 * height as u64;
+        let max_size = device.limits().max_storage_buffer_binding_size as u64;
+        if size > max_size {
+            return Err(eyre!(
+                "Environment `{}` of {}x{} is larger than the {} bytes a buffer can bind",
+                path.display(),
+                width,
+                height,
+                max_size
+            ));
+        }
+        let texels: Vec<u32> = image.pixels().map(|pixel| pack_color(pixel.0)).collect();
+        Ok(Self::new(device, width, height, &texels))
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn new(device: &wgpu::Device, width: u32, height: u32, texels: &[u32]) -> Self {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&LABELS.of("Environment Buffer")),
+            contents: bytemuck::cast_slice(texels),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        Self {
+            buffer,
+            width,
+            height,
+        }
+    }
+}
+
+/// Writes the sky into the pixels of the color buffer left uncovered, the
+/// passes after it treat it as any other color.
+pub struct SkyPass {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl SkyPass {
+    pub fn new(device: &wgpu::Device, shaders: &Shaders, workgroup_size: WorkgroupSize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let buffers_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Buffers Bind Group Layout")),
+                entries: &[
+                    storage_entry(0, false),
+                    storage_entry(1, true),
+                    storage_entry(2, true),
+                ],
+            });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&LABELS.of("Uniform Bind Group Layout")),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&LABELS.pipeline_layout()),
+            bind_group_layouts: &[&buffers_bind_group_layout, &uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader =
+            shaders.compute_module(device, "sky.wgsl", include_str!("sky.wgsl"), workgroup_size);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&LABELS.pipeline()),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "sky",
+        });
+        Self { pipeline }
+    }
+}
+
+pub struct SkyBindings {
+    buffers: wgpu::BindGroup,
+    uniform: wgpu::BindGroup,
+}
+
+impl SkyBindings {
+    /// Rebuilt along with the color buffer and when the environment changes.
+    pub fn new(
+        device: &wgpu::Device,
+        SkyPass { pipeline }: &SkyPass,
+        color_buffer: &wgpu::Buffer,
+        depth_buffer: &wgpu::Buffer,
+        environment: &Environment,
+        uniform: &wgpu::Buffer,
+        sky_uniform: &wgpu::Buffer,
+    ) -> Self {
+        let buffers = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Buffers Bind Group")),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: environment.buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let uniform = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&LABELS.of("Uniform Bind Group")),
+            layout: &pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: sky_uniform.as_entire_binding(),
+                },
+            ],
+        });
+        Self { buffers, uniform }
+    }
+}
+
+impl<'a> SkyPass {
+    pub fn record<'pass>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'pass>,
+        bindings: &'a SkyBindings,
+        dispatch_size: u32,
+    ) where
+        'a: 'pass,
+    {
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bindings.buffers, &[]);
+        cpass.set_bind_group(1, &bindings.uniform, &[]);
+        cpass.dispatch_workgroups(dispatch_size, 1, 1);
+    }
+}
+
+impl Pass for Node<'_, SkyPass, SkyBindings> {
+    fn name(&self) -> &'static str {
+        LABELS.name()
+    }
+
+    fn reads(&self, _: &Frame) -> Vec<&'static str> {
+        vec!["Depth Buffer", "Environment", "Camera"]
+    }
+
+    fn writes(&self) -> Vec<&'static str> {
+        vec!["Color Buffer"]
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, frame: &Frame) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&LABELS.pass()),
+        });
+        self.pass
+            .record(&mut cpass, self.bindings, frame.pixel_dispatch());
+    }
+
+    fn occupancy(&self, frame: &Frame) -> Vec<PassOccupancy> {
+        vec![frame.occupancy(self.name(), frame.pixels())]
+    }
+}
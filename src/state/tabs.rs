@@ -0,0 +1,320 @@
+use glam::{Mat4, Vec3};
+
+use super::{
+    billboard_pass::{Billboard, BillboardBindings},
+    bvh::Bvh,
+    cull_pass::CullBindings,
+    default_chunk_size,
+    history::History,
+    line_pass::{debug_lines, DebugDraw},
+    minimap_pass::{overview_bindings, MinimapBindings, Overview},
+    raster_pass::{RasterBindings, RasterChunks, TileBins},
+    reference_pass::ReferenceBindings,
+    shadow_map::ShadowBindings,
+    sun,
+    util::{create_buffer_streamed, Mesh},
+    Renderer, SceneState,
+};
+use crate::camera::Camera;
+
+/// Mesh of a tab with everything built from it, along with its camera and
+/// arrangement. The tab on screen lives in the fields of the renderer, the
+/// others are swapped in when switched to.
+pub(super) struct Tab {
+    camera: Camera,
+    transform: Mat4,
+    mesh_center: Vec3,
+    triangle_count: u32,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    material_buffer: wgpu::Buffer,
+    triangle_buffer: wgpu::Buffer,
+    cull_bindings: CullBindings,
+    tile_bins: TileBins,
+    raster_chunks: RasterChunks,
+    raster_bindings: RasterBindings,
+    shadow_bindings: ShadowBindings,
+    reference_bindings: ReferenceBindings,
+    overview: Overview,
+    overview_bindings: ReferenceBindings,
+    minimap_bindings: MinimapBindings,
+    debug_draw: DebugDraw,
+    bvh: Bvh,
+    billboards: Vec<Billboard>,
+    billboard_bindings: BillboardBindings,
+    history: History<SceneState>,
+}
+
+impl Renderer {
+    /// Uploads `mesh` into buffers of its own, bound to the passes of the
+    /// renderer at its current size.
+    pub(super) fn create_tab(&self, mesh: Mesh, camera: Camera) -> Tab {
+        let triangle_count = mesh.triangle_count();
+        let debug_draw = debug_lines(&mesh);
+        let bvh = Bvh::new(&mesh);
+        let overview = Overview::new(&mesh);
+        let mesh_center = mesh.center();
+        let bounds = mesh.bounds();
+        let Mesh {
+            vertices,
+            indices,
+            materials,
+            triangles,
+            meshlets,
+        } = mesh;
+        let vertex_buffer = create_buffer_streamed(
+            &self.device,
+            &self.queue,
+            "Vertex Buffer",
+            bytemuck::cast_slice(&vertices),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+        );
+        let index_buffer = create_buffer_streamed(
+            &self.device,
+            &self.queue,
+            "Index Buffer",
+            bytemuck::cast_slice(&indices),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDEX,
+        );
+        let material_buffer = create_buffer_streamed(
+            &self.device,
+            &self.queue,
+            "Material Buffer",
+            bytemuck::cast_slice(&materials),
+            wgpu::BufferUsages::STORAGE,
+        );
+        let triangle_buffer = create_buffer_streamed(
+            &self.device,
+            &self.queue,
+            "Triangle Info Buffer",
+            bytemuck::cast_slice(&triangles),
+            wgpu::BufferUsages::STORAGE,
+        );
+
+        let cull_bindings = CullBindings::new(
+            &self.device,
+            &self.cull_pass,
+            &vertex_buffer,
+            &index_buffer,
+            self.hiz_bindings.pyramid(),
+            &self.camera_buffer,
+            &self.screen_uniform,
+            &self.cull_uniform,
+            &self.occlusion_uniform,
+            &meshlets,
+            triangle_count,
+        );
+        let reference_bindings = ReferenceBindings::new(
+            &self.device,
+            &self.reference_pass,
+            &materials,
+            &triangles,
+            &self.camera_buffer,
+            &self.screen_uniform,
+            &self.raster_uniform,
+            &self.light_buffer,
+            self.width,
+            self.height,
+        );
+        let overview_bindings = overview_bindings(
+            &self.device,
+            &self.reference_pass,
+            &materials,
+            &triangles,
+            &overview,
+            sun(),
+        );
+        let minimap_bindings = MinimapBindings::new(
+            &self.device,
+            &self.minimap_pass,
+            &self.present_uniform,
+            &self.minimap_uniform,
+            &overview_bindings,
+        );
+        let tile_bins = TileBins::new(
+            &self.device,
+            &self.raster_pass,
+            self.width,
+            self.height,
+            triangle_count,
+        );
+        let raster_chunks = RasterChunks::new(
+            &self.device,
+            triangle_count,
+            default_chunk_size(triangle_count),
+        );
+        let raster_bindings = RasterBindings::new(
+            &self.device,
+            &self.raster_pass,
+            &self.output_buffer,
+            &self.depth_buffer,
+            &vertex_buffer,
+            &index_buffer,
+            &material_buffer,
+            &triangle_buffer,
+            &self.work_queue,
+            &cull_bindings,
+            &self.screen_uniform,
+            &self.raster_uniform,
+            &tile_bins,
+            &raster_chunks,
+            &self.camera_buffer,
+            &self.light_buffer,
+            self.shadow_map.depth_buffer(),
+            self.shadow_map.camera_buffer(),
+            self.ibl_pass.buffer(),
+            &self.ibl_uniform,
+        );
+        let shadow_bindings = ShadowBindings::new(
+            &self.device,
+            &self.cull_pass,
+            &self.raster_pass,
+            &self.shadow_map,
+            &vertex_buffer,
+            &index_buffer,
+            &material_buffer,
+            &triangle_buffer,
+            &self.work_queue,
+            &self.light_buffer,
+            &meshlets,
+            triangle_count,
+            bounds,
+        );
+        let billboard_bindings = BillboardBindings::new(
+            &self.device,
+            &self.billboard_pass,
+            &self.output_buffer,
+            &self.depth_buffer,
+            &self.camera_buffer,
+            &self.screen_uniform,
+            &[],
+        );
+
+        Tab {
+            camera,
+            transform: Mat4::IDENTITY,
+            mesh_center,
+            triangle_count,
+            vertex_buffer,
+            index_buffer,
+            material_buffer,
+            triangle_buffer,
+            cull_bindings,
+            tile_bins,
+            raster_chunks,
+            raster_bindings,
+            shadow_bindings,
+            reference_bindings,
+            overview,
+            overview_bindings,
+            minimap_bindings,
+            debug_draw,
+            bvh,
+            billboards: Vec::new(),
+            billboard_bindings,
+            history: History::default(),
+        }
+    }
+
+    /// Puts `tab` on screen, handing back the one that was.
+    pub(super) fn swap_tab(&mut self, tab: &mut Tab) {
+        use std::mem::swap;
+        swap(&mut self.camera, &mut tab.camera);
+        swap(&mut self.transform, &mut tab.transform);
+        swap(&mut self.mesh_center, &mut tab.mesh_center);
+        swap(&mut self.triangle_count, &mut tab.triangle_count);
+        swap(&mut self.vertex_buffer, &mut tab.vertex_buffer);
+        swap(&mut self.index_buffer, &mut tab.index_buffer);
+        swap(&mut self.material_buffer, &mut tab.material_buffer);
+        swap(&mut self.triangle_buffer, &mut tab.triangle_buffer);
+        swap(&mut self.cull_bindings, &mut tab.cull_bindings);
+        swap(&mut self.tile_bins, &mut tab.tile_bins);
+        swap(&mut self.raster_chunks, &mut tab.raster_chunks);
+        swap(&mut self.raster_bindings, &mut tab.raster_bindings);
+        swap(&mut self.shadow_bindings, &mut tab.shadow_bindings);
+        swap(&mut self.reference_bindings, &mut tab.reference_bindings);
+        swap(&mut self.overview, &mut tab.overview);
+        swap(&mut self.overview_bindings, &mut tab.overview_bindings);
+        swap(&mut self.minimap_bindings, &mut tab.minimap_bindings);
+        swap(&mut self.debug_draw, &mut tab.debug_draw);
+        swap(&mut self.bvh, &mut tab.bvh);
+        swap(&mut self.billboards, &mut tab.billboards);
+        swap(&mut self.billboard_bindings, &mut tab.billboard_bindings);
+        swap(&mut self.history, &mut tab.history);
+
+        self.bvh_level = self.bvh_level.map(|level| level.min(self.bvh.depth()));
+        self.shadow_map
+            .write_camera(&self.queue, &self.lights, &self.shadow_bindings);
+        // The frame buffers may have been resized while the tab was hidden
+        self.resize_buffers();
+        if self.debug_lines {
+            self.update_debug_lines();
+        }
+    }
+
+    /// Replaces the mesh of the current tab, the settings and the camera are kept.
+    pub fn load_mesh(&mut self, mesh: Mesh) {
+        let mut tab = self.create_tab(mesh, self.camera);
+        tab.transform = self.transform;
+        self.swap_tab(&mut tab);
+        // The snapshots arrange the previous mesh, the markers stay
+        self.set_billboards(&tab.billboards);
+        self.render_overview();
+    }
+
+    /// Camera and arrangement of every tab, in order.
+    pub(super) fn tab_states(&self) -> Vec<(Camera, SceneState)> {
+        self.tabs
+            .iter()
+            .map(|tab| match tab {
+                Some(tab) => (
+                    tab.camera,
+                    SceneState {
+                        transform: tab.transform,
+                        billboards: tab.billboards.clone(),
+                    },
+                ),
+                None => (self.camera, self.scene_state()),
+            })
+            .collect()
+    }
+
+    /// Opens `mesh` in a new tab seen from `camera` and switches to it.
+    /// Returns the index of the tab.
+    pub fn open_tab(&mut self, mesh: Mesh, camera: Camera) -> usize {
+        let mut tab = self.create_tab(mesh, camera);
+        self.swap_tab(&mut tab);
+        self.render_overview();
+        self.tabs[self.tab] = Some(tab);
+        self.tab = self.tabs.len();
+        self.tabs.push(None);
+        self.tab
+    }
+
+    /// Switches to the tab at `index`, the tabs keep their own camera.
+    pub fn switch_tab(&mut self, index: usize) {
+        if index == self.tab {
+            return;
+        }
+        if let Some(mut tab) = self.tabs.get_mut(index).and_then(Option::take) {
+            self.swap_tab(&mut tab);
+            self.tabs[self.tab] = Some(tab);
+            self.tab = index;
+        }
+    }
+
+    /// Switches to the tab after the current one, wrapping around. Returns
+    /// its index.
+    pub fn next_tab(&mut self) -> usize {
+        self.switch_tab((self.tab + 1) % self.tabs.len());
+        self.tab
+    }
+
+    pub fn current_tab(&self) -> usize {
+        self.tab
+    }
+
+    pub fn tab_count(&self) -> usize {
+        self.tabs.len()
+    }
+}
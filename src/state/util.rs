@@ -1,43 +1,491 @@
-use std::io::Read;
+use std::{
+    borrow::Cow,
+    fs::File,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
 
 use bytemuck::{Pod, Zeroable};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use glam::{Mat3, Mat4, Vec3, Vec4};
+use gltf::mesh::Mode;
+use memmap2::Mmap;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
-#[allow(clippy::iter_nth_zero)]
-pub fn process_gltf_model() -> Vec<Vertex> {
-    let (model, buffers, _) = {
-        let bytes = include_bytes!("../../models/suzanne.glb");
-        gltf::import_slice(bytes).unwrap()
+use super::repair::{RepairOptions, RepairReport};
+
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    /// Triangle list indexing into `vertices`
+    pub indices: Vec<u32>,
+    pub materials: Vec<Material>,
+    /// One entry for every triangle of `indices`
+    pub triangles: Vec<TriangleInfo>,
+    /// Consecutive runs of triangles covering the whole list
+    pub meshlets: Vec<Meshlet>,
+}
+
+/// Per-triangle data that isn't interpolated across the vertices.
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, Pod, Zeroable)]
+pub struct TriangleInfo {
+    /// Index into the materials of the mesh
+    pub material: u32,
+    /// glTF primitive instance the triangle comes from
+    pub object: u32,
+}
+
+/// Most triangles in a meshlet, the culling handles a meshlet per workgroup.
+pub const MESHLET_TRIANGLES: u32 = 124;
+
+/// Cutoff of the meshlets whose normal cone can't tell if they face away.
+const NO_CONE_CUTOFF: f32 = 2.0;
+
+/// Cluster of consecutive triangles culled as a whole before its triangles.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+pub struct Meshlet {
+    /// Bounding sphere
+    center: [f32; 3],
+    radius: f32,
+    /// All the triangles face away from an eye where
+    /// `dot(center - eye, cone_axis) >= cone_cutoff * |center - eye| + radius`
+    cone_axis: [f32; 3],
+    cone_cutoff: f32,
+    triangle_offset: u32,
+    triangle_count: u32,
+    _padding: [u32; 2],
+}
+
+impl Mesh {
+    /// Non-indexed triangle list sharing a single material.
+    pub fn with_material(vertices: Vec<Vertex>, material: Material) -> Self {
+        let indices = (0..vertices.len() as u32).collect();
+        let triangles = vec![TriangleInfo::default(); vertices.len() / 3];
+        let mut mesh = Self {
+            vertices,
+            indices,
+            materials: vec![material],
+            triangles,
+            meshlets: Vec::new(),
+        };
+        mesh.build_meshlets();
+        mesh
+    }
+
+    pub fn triangle_count(&self) -> u32 {
+        self.indices.len() as u32 / 3
+    }
+
+    /// Corners of the axis aligned box around the vertices.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.vertices.iter().map(|v| Vec3::from(v.v)).fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        )
+    }
+
+    /// Middle of the bounds.
+    pub fn center(&self) -> Vec3 {
+        let (min, max) = self.bounds();
+        (min + max) * 0.5
+    }
+
+    /// Splits the triangle list into meshlets of up to `MESHLET_TRIANGLES`
+    /// triangles in their order, which loaders already keep spatially coherent.
+    pub(super) fn build_meshlets(&mut self) {
+        let triangle_count = self.triangle_count();
+        self.meshlets = (0..triangle_count)
+            .step_by(MESHLET_TRIANGLES as usize)
+            .map(|offset| self.meshlet(offset, MESHLET_TRIANGLES.min(triangle_count - offset)))
+            .collect();
+    }
+
+    fn meshlet(&self, triangle_offset: u32, triangle_count: u32) -> Meshlet {
+        let triangles = triangle_offset..triangle_offset + triangle_count;
+        let corners = |triangle: u32| {
+            [0, 1, 2].map(|i| {
+                let index = self.indices[(triangle * 3 + i) as usize];
+                Vec3::from(self.vertices[index as usize].v)
+            })
+        };
+        let points = || triangles.clone().flat_map(corners);
+
+        let (min, max) = points().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        );
+        let center = (min + max) * 0.5;
+        let radius = points().map(|p| p.distance(center)).fold(0.0, f32::max);
+
+        // Degenerate triangles are never drawn, they don't widen the cone
+        let normals: Vec<_> = triangles
+            .clone()
+            .map(|triangle| {
+                let [a, b, c] = corners(triangle);
+                (b - a).cross(c - a).normalize_or_zero()
+            })
+            .filter(|normal| *normal != Vec3::ZERO)
+            .collect();
+        let cone_axis = normals.iter().sum::<Vec3>().normalize_or_zero();
+        let min_dot = normals
+            .iter()
+            .map(|normal| normal.dot(cone_axis))
+            .fold(1.0, f32::min);
+        // Double sided triangles are drawn from both sides, and a cone
+        // wider than a hemisphere always has a triangle facing the eye
+        let double_sided = triangles.clone().any(|triangle| {
+            let material = self.triangles[triangle as usize].material;
+            self.materials[material as usize].double_sided != 0
+        });
+        let cone_cutoff = if double_sided || cone_axis == Vec3::ZERO || min_dot <= 0.0 {
+            NO_CONE_CUTOFF
+        } else {
+            (1.0 - min_dot * min_dot).sqrt()
+        };
+
+        Meshlet {
+            center: center.to_array(),
+            radius,
+            cone_axis: cone_axis.to_array(),
+            cone_cutoff,
+            triangle_offset,
+            triangle_count,
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub fn process_gltf_model() -> Mesh {
+    let bytes = include_bytes!("../../models/suzanne.glb");
+    let (document, buffers) = parse_glb(bytes).unwrap().unwrap();
+    process_gltf_scene(&document, &buffers)
+}
+
+/// Loads an OBJ or a glTF model, told apart by the extension, and fixes
+/// what `options` allow. The fixes are reported on stderr.
+pub fn load_model(path: impl AsRef<Path>, options: RepairOptions) -> Result<Mesh> {
+    let path = path.as_ref();
+    let mut report = RepairReport::default();
+    let is_obj = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("obj"));
+    let mut mesh = match is_obj {
+        true => load_obj_model(path, &mut report)?,
+        false => load_gltf_model(path)?,
     };
-    let mesh = model.meshes().nth(0).unwrap();
-    let primitives = mesh.primitives().nth(0).unwrap();
-    let reader = primitives.reader(|buffer| Some(&buffers[buffer.index()]));
-    let positions = reader.read_positions().unwrap().collect::<Vec<_>>();
-    reader
-        .read_indices()
-        .unwrap()
-        .into_u32()
-        .map(|i| Vertex::from(positions[i as usize]))
-        .collect()
+    mesh.repair(options, &mut report);
+    if !report.is_clean() {
+        eprintln!("Repaired `{}`: {}", path.display(), report);
+    }
+    Ok(mesh)
 }
 
-#[allow(dead_code)]
-pub fn process_obj_model(file: impl Read) -> Vec<Vertex> {
-    obj::ObjData::load_buf(file)
-        .unwrap()
-        .position
-        .iter()
-        .cloned()
-        .map(Vertex::from)
-        .collect()
+/// Binary glTF files are memory-mapped and their buffers read in place, the
+/// file isn't copied into memory next to the mesh built from it.
+fn load_gltf_model(path: impl AsRef<Path>) -> Result<Mesh> {
+    let path = path.as_ref();
+    let context = || format!("Failed to load model `{}`", path.display());
+    let file = File::open(path).wrap_err_with(context)?;
+    // Safety: the file is only read, it's not expected to change while loading
+    let map = unsafe { Mmap::map(&file) }.wrap_err_with(context)?;
+    if let Some((document, buffers)) = parse_glb(&map).wrap_err_with(context)? {
+        return Ok(process_gltf_scene(&document, &buffers));
+    }
+
+    let (document, buffers, _) = gltf::import(path).wrap_err_with(context)?;
+    let buffers: Vec<&[u8]> = buffers.iter().map(|data| &data[..]).collect();
+    Ok(process_gltf_scene(&document, &buffers))
+}
+
+/// Document of a glTF along with the contents of its buffers.
+type Gltf<'a> = (gltf::Document, Vec<&'a [u8]>);
+
+/// The document of a binary glTF and its buffers borrowed from `bytes`.
+/// `None` for a text glTF or when a buffer refers to another file.
+fn parse_glb(bytes: &[u8]) -> Result<Option<Gltf<'_>>> {
+    if !bytes.starts_with(b"glTF") {
+        return Ok(None);
+    }
+    let glb = gltf::Glb::from_slice(bytes)?;
+    let document = gltf::Document::from_json(gltf::json::Root::from_slice(&glb.json)?)?;
+    let bin = match glb.bin {
+        Some(Cow::Borrowed(bin)) => Some(bin),
+        _ => None,
+    };
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        match buffer.source() {
+            gltf::buffer::Source::Bin => {
+                let bin = bin.ok_or_else(|| eyre!("Missing the binary chunk"))?;
+                buffers.push(&bin[..buffer.length().min(bin.len())]);
+            }
+            gltf::buffer::Source::Uri(_) => return Ok(None),
+        }
+    }
+    Ok(Some((document, buffers)))
+}
+
+/// Flattens every primitive reachable from the default scene into a single
+/// triangle list, with node transforms baked into the vertices.
+fn process_gltf_scene(document: &gltf::Document, buffers: &[&[u8]]) -> Mesh {
+    // Primitives without a material use the default one stored last
+    let mut materials: Vec<_> = document.materials().map(Material::from).collect();
+    materials.push(Material::default());
+    let mut mesh = Mesh {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        materials,
+        triangles: Vec::new(),
+        meshlets: Vec::new(),
+    };
+
+    let roots: Vec<_> = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().collect(),
+        None => document.nodes().collect(),
+    };
+    for node in roots {
+        process_gltf_node(&node, Mat4::IDENTITY, buffers, &mut mesh);
+    }
+    mesh.build_meshlets();
+    mesh
+}
+
+fn process_gltf_node(node: &gltf::Node, parent: Mat4, buffers: &[&[u8]], out: &mut Mesh) {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            let material = primitive
+                .material()
+                .index()
+                .unwrap_or(out.materials.len() - 1);
+            let info = TriangleInfo {
+                material: material as u32,
+                object: out.triangles.last().map_or(0, |info| info.object + 1),
+            };
+            let (vertices, indices) = process_gltf_primitive(&primitive, transform, buffers);
+            let base = out.vertices.len() as u32;
+            out.triangles
+                .extend(std::iter::repeat_n(info, indices.len() / 3));
+            out.indices.extend(indices.into_iter().map(|i| base + i));
+            out.vertices.extend(vertices);
+        }
+    }
+    for child in node.children() {
+        process_gltf_node(&child, transform, buffers, out);
+    }
+}
+
+/// Returns the vertices of the primitive in world space along with its triangle list.
+fn process_gltf_primitive(
+    primitive: &gltf::Primitive,
+    transform: Mat4,
+    buffers: &[&[u8]],
+) -> (Vec<Vertex>, Vec<u32>) {
+    let reader = primitive.reader(|buffer| Some(buffers[buffer.index()]));
+    let positions = match reader.read_positions() {
+        Some(positions) => positions.collect::<Vec<_>>(),
+        None => return Default::default(),
+    };
+    let normals = reader
+        .read_normals()
+        .map(|normals| normals.collect::<Vec<_>>());
+    let tex_coords = match reader.read_tex_coords(0) {
+        Some(tex_coords) => tex_coords.into_f32().collect(),
+        None => vec![[0.0; 2]; positions.len()],
+    };
+    let colors: Vec<_> = match reader.read_colors(0) {
+        Some(colors) => colors.into_rgba_f32().collect(),
+        None => Vec::new(),
+    };
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect::<Vec<_>>(),
+    };
+
+    let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+    let mut vertices: Vec<_> = (0..positions.len())
+        .map(|i| {
+            let position = transform.transform_point3(Vec3::from(positions[i]));
+            let uv = tex_coords.get(i).copied().unwrap_or_default();
+            let color = colors.get(i).copied().unwrap_or([1.0; 4]);
+            let vertex = Vertex::from(position.to_array())
+                .with_uv(uv)
+                .with_color(color);
+            match &normals {
+                Some(normals) => {
+                    let normal = normals.get(i).copied().unwrap_or_default();
+                    let normal = normal_matrix * Vec3::from(normal);
+                    vertex.with_normal(normal.normalize_or_zero().to_array())
+                }
+                None => vertex,
+            }
+        })
+        .collect();
+    let mut indices = triangle_list(primitive.mode(), &indices);
+    // Mirroring transforms flip the winding
+    if transform.determinant() < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+    // Flat normals can't be shared between triangles, unroll the list. Out of
+    // range indices become NaN vertices, dropped by the repair.
+    if normals.is_none() {
+        let invalid = Vertex::new(f32::NAN, f32::NAN, f32::NAN);
+        vertices = indices
+            .iter()
+            .map(|&i| vertices.get(i as usize).copied().unwrap_or(invalid))
+            .collect();
+        flat_normals(&mut vertices);
+        indices = (0..vertices.len() as u32).collect();
+    }
+    (vertices, indices)
 }
 
-pub(crate) const WORKGROUP_SIZE: u32 = 256;
-pub(crate) const fn dispatch_size(len: u32) -> u32 {
-    let subgroup_size = WORKGROUP_SIZE;
-    let padded_size = (subgroup_size - len % subgroup_size) % subgroup_size;
-    (len + padded_size) / subgroup_size
+/// Assigns every triangle of a list its face normal, counter-clockwise winding is front.
+pub fn flat_normals(vertices: &mut [Vertex]) {
+    for triangle in vertices.chunks_exact_mut(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(triangle[i].v));
+        let normal = (b - a).cross(c - a).normalize_or_zero().to_array();
+        for vertex in triangle {
+            vertex.normal = normal;
+        }
+    }
 }
 
+/// Converts the index stream of a primitive into a plain triangle list.
+/// Strips and fans are unrolled following the winding rules of the glTF spec.
+fn triangle_list(mode: Mode, indices: &[u32]) -> Vec<u32> {
+    match mode {
+        Mode::Triangles => indices.to_vec(),
+        Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+            .flat_map(|i| match i % 2 {
+                0 => [indices[i], indices[i + 1], indices[i + 2]],
+                _ => [indices[i], indices[i + 2], indices[i + 1]],
+            })
+            .collect(),
+        Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+            .flat_map(|i| [indices[i], indices[i + 1], indices[0]])
+            .collect(),
+        mode => {
+            eprintln!("Skipping primitive with unsupported mode: {:?}", mode);
+            Vec::new()
+        }
+    }
+}
+
+/// Faces of more than 3 vertices are split into fans, counted in `report`.
+/// Every group is an object, the faces without normals are shaded flat.
+fn load_obj_model(path: &Path, report: &mut RepairReport) -> Result<Mesh> {
+    let context = || format!("Failed to load model `{}`", path.display());
+    let file = File::open(path).wrap_err_with(context)?;
+    let obj = obj::ObjData::load_buf(io::BufReader::new(file)).wrap_err_with(context)?;
+    // Out of range indices become NaN vertices, dropped by the repair
+    let corner = |obj::IndexTuple(position, uv, normal)| {
+        let vertex = Vertex::from(obj.position.get(position).copied().unwrap_or([f32::NAN; 3]));
+        let vertex = match uv {
+            Some(uv) => vertex.with_uv(obj.texture.get(uv).copied().unwrap_or([f32::NAN; 2])),
+            None => vertex,
+        };
+        match normal {
+            Some(normal) => {
+                vertex.with_normal(obj.normal.get(normal).copied().unwrap_or([f32::NAN; 3]))
+            }
+            None => vertex,
+        }
+    };
+
+    let mut mesh = Mesh {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        materials: vec![Material::default()],
+        triangles: Vec::new(),
+        meshlets: Vec::new(),
+    };
+    let groups = obj.objects.iter().flat_map(|object| &object.groups);
+    for (object, group) in groups.enumerate() {
+        let info = TriangleInfo {
+            material: 0,
+            object: object as u32,
+        };
+        for obj::SimplePolygon(polygon) in &group.polys {
+            if polygon.len() < 3 {
+                continue;
+            }
+            if polygon.len() > 3 {
+                report.triangulated_polygons += 1;
+            }
+            let flat = polygon.iter().any(|index| index.2.is_none());
+            for i in 1..polygon.len() - 1 {
+                let mut triangle = [polygon[0], polygon[i], polygon[i + 1]].map(corner);
+                if flat {
+                    flat_normals(&mut triangle);
+                }
+                mesh.indices
+                    .extend(mesh.vertices.len() as u32..mesh.vertices.len() as u32 + 3);
+                mesh.vertices.extend(triangle);
+                mesh.triangles.push(info);
+            }
+        }
+    }
+    mesh.build_meshlets();
+    Ok(mesh)
+}
+
+/// Invocations per workgroup of the passes over flat buffers, negotiated with
+/// the adapter and injected into their shaders as `WORKGROUP_SIZE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkgroupSize(u32);
+
+impl WorkgroupSize {
+    /// Used unless the adapter can't run workgroups that large.
+    pub const PREFERRED: u32 = 256;
+
+    /// The largest power of two up to `requested`, `PREFERRED` by default,
+    /// within the adapter limits. The scans only work on powers of two.
+    pub fn new(limits: &wgpu::Limits, requested: Option<u32>) -> Self {
+        let max = requested
+            .unwrap_or(Self::PREFERRED)
+            .min(limits.max_compute_workgroup_size_x)
+            .min(limits.max_compute_invocations_per_workgroup)
+            // scan.wgsl keeps a u32 per invocation in workgroup memory
+            .min(limits.max_compute_workgroup_storage_size / 4)
+            .max(1);
+        Self(1 << (u32::BITS - 1 - max.leading_zeros()))
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// The WGSL literal `WORKGROUP_SIZE` is defined to.
+    pub fn literal(self) -> String {
+        format!("{}u", self.0)
+    }
+
+    /// Workgroups covering `len` invocations.
+    pub const fn dispatch_size(self, len: u32) -> u32 {
+        let subgroup_size = self.0;
+        let padded_size = (subgroup_size - len % subgroup_size) % subgroup_size;
+        (len + padded_size) / subgroup_size
+    }
+}
+
+/// Workgroups launched by the persistent threads raster, enough to fill
+/// a desktop GPU without knowing its actual number of compute units.
+pub(crate) const PERSISTENT_WORKGROUPS: u32 = 128;
+
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub(crate) struct Uniform {
@@ -54,41 +502,442 @@ impl Uniform {
     }
 }
 
-pub fn create_color_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
-    use std::mem::size_of;
-    #[repr(C)]
-    struct Pixel {
-        r: f32,
-        g: f32,
-        b: f32,
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct Material {
+    double_sided: u32,
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+    alpha: f32,
+    /// Linear RGB of `pbrMetallicRoughness.baseColorFactor`, `alpha` is its
+    /// fourth component
+    base_color: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    _padding: [u32; 3],
+}
+
+impl Material {
+    pub const ALPHA_OPAQUE: u32 = 0;
+    pub const ALPHA_MASK: u32 = 1;
+    pub const ALPHA_BLEND: u32 = 2;
+
+    pub fn two_sided() -> Self {
+        Self {
+            double_sided: 1,
+            ..Default::default()
+        }
     }
-    assert!(size_of::<Pixel>() == size_of::<[f32; 3]>());
 
-    let pixel_size = size_of::<Pixel>() as u64;
-    let (width, height) = (width as u64, height as u64);
-    let size = pixel_size * width * height;
+    pub fn is_double_sided(&self) -> bool {
+        self.double_sided != 0
+    }
+
+    pub fn base_color(&self) -> Vec3 {
+        Vec3::from(self.base_color)
+    }
+
+    /// Alpha cutout of a fragment with the vertex `alpha`, as `is_masked` of raster.wgsl.
+    pub fn is_masked(&self, alpha: f32) -> bool {
+        self.alpha_mode == Self::ALPHA_MASK && self.alpha * alpha < self.alpha_cutoff
+    }
+}
+
+/// A rough dielectric, the default material of glTF is fully metallic and
+/// comes out dark without an environment to reflect.
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            double_sided: 0,
+            alpha_mode: Self::ALPHA_OPAQUE,
+            alpha_cutoff: 0.5,
+            alpha: 1.0,
+            base_color: [1.0; 3],
+            metallic: 0.0,
+            roughness: 1.0,
+            _padding: [0; 3],
+        }
+    }
+}
+
+impl From<gltf::Material<'_>> for Material {
+    fn from(material: gltf::Material) -> Self {
+        let alpha_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => Self::ALPHA_OPAQUE,
+            gltf::material::AlphaMode::Mask => Self::ALPHA_MASK,
+            gltf::material::AlphaMode::Blend => Self::ALPHA_BLEND,
+        };
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, alpha] = pbr.base_color_factor();
+        Self {
+            double_sided: material.double_sided() as u32,
+            alpha_mode,
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            alpha,
+            base_color: [r, g, b],
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Contents larger than this are uploaded a chunk at a time.
+const UPLOAD_CHUNK_SIZE: usize = 64 << 20;
+
+/// Same as `create_buffer_init`, except that large contents are streamed
+/// through the queue in chunks, waiting for each one to be copied. The staging
+/// memory then stays at a chunk instead of the size of the buffer.
+pub fn create_buffer_streamed(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    contents: &[u8],
+    usage: wgpu::BufferUsages,
+) -> wgpu::Buffer {
+    if contents.len() <= UPLOAD_CHUNK_SIZE {
+        return device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage,
+        });
+    }
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: contents.len() as u64,
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    for (i, chunk) in contents.chunks(UPLOAD_CHUNK_SIZE).enumerate() {
+        queue.write_buffer(&buffer, (i * UPLOAD_CHUNK_SIZE) as u64, chunk);
+        queue.submit(None);
+        device.poll(wgpu::Maintain::Wait);
+    }
+    buffer
+}
+
+/// Labels of the objects of a pass, all named after it so a RenderDoc or
+/// Xcode capture lists them next to each other.
+#[derive(Clone, Copy)]
+pub struct Labels(&'static str);
+
+impl Labels {
+    pub const fn new(pass: &'static str) -> Self {
+        Self(pass)
+    }
+
+    /// Name of the pass, also of the debug group it's recorded in.
+    pub fn name(self) -> &'static str {
+        self.0
+    }
+
+    /// One of the buffers, bind groups or layouts of the pass, `"Ibl: Levels
+    /// Buffer"` for `of("Levels Buffer")`.
+    pub fn of(self, name: &str) -> String {
+        format!("{}: {}", self.0, name)
+    }
+
+    pub fn pipeline(self) -> String {
+        format!("{} Pipeline", self.0)
+    }
+
+    pub fn pipeline_layout(self) -> String {
+        format!("{} Pipeline Layout", self.0)
+    }
+
+    pub fn pass(self) -> String {
+        format!("{} Pass", self.0)
+    }
+}
+
+/// What debug groups can be pushed on, the groups nest in a capture.
+pub trait DebugGroups {
+    fn push_group(&mut self, label: &str);
+    fn pop_group(&mut self);
+}
+
+impl DebugGroups for wgpu::CommandEncoder {
+    fn push_group(&mut self, label: &str) {
+        self.push_debug_group(label);
+    }
+
+    fn pop_group(&mut self) {
+        self.pop_debug_group();
+    }
+}
+
+impl DebugGroups for wgpu::ComputePass<'_> {
+    fn push_group(&mut self, label: &str) {
+        self.push_debug_group(label);
+    }
+
+    fn pop_group(&mut self) {
+        self.pop_debug_group();
+    }
+}
+
+impl DebugGroups for wgpu::RenderPass<'_> {
+    fn push_group(&mut self, label: &str) {
+        self.push_debug_group(label);
+    }
+
+    fn pop_group(&mut self) {
+        self.pop_debug_group();
+    }
+}
+
+/// Records `f` inside a debug group named `label`.
+pub fn debug_group<T: DebugGroups, R>(
+    target: &mut T,
+    label: &str,
+    f: impl FnOnce(&mut T) -> R,
+) -> R {
+    target.push_group(label);
+    let result = f(target);
+    target.pop_group();
+    result
+}
+
+/// Linear HDR packed as RGB9E5 into a u32 per pixel, see color.wgsl.
+pub fn create_color_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+    let size = std::mem::size_of::<u32>() as u64 * width as u64 * height as u64;
 
     device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("Output Buffer"),
         size,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Linear color packed as RGB9E5 like the color buffer, see color.wgsl.
+pub fn pack_color(color: [f32; 3]) -> u32 {
+    const EXPONENT_BIAS: i32 = 15;
+    const MANTISSA_BITS: i32 = 9;
+    const MAX_COLOR: f32 = 65408.0;
+    let c = color.map(|channel| channel.clamp(0.0, MAX_COLOR));
+    let m = c[0].max(c[1]).max(c[2]).max(2f32.powi(-16));
+    let mut exponent = (m.log2().floor() as i32).max(-EXPONENT_BIAS - 1) + 1 + EXPONENT_BIAS;
+    let mut scale = 2f32.powi(exponent - EXPONENT_BIAS - MANTISSA_BITS);
+    // Rounding the largest channel up can overflow its mantissa
+    if (m / scale).round() as u32 == 512 {
+        exponent += 1;
+        scale *= 2.0;
+    }
+    let [r, g, b] = c.map(|channel| ((channel / scale).round() as u32).min(511));
+    r | (g << 9) | (b << 18) | ((exponent as u32) << 27)
+}
+
+/// Running average of the accumulated frames, one vec4 per pixel.
+pub fn create_accumulation_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+    let size = std::mem::size_of::<[f32; 4]>() as u64 * width as u64 * height as u64;
+
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Accumulation Buffer"),
+        size,
         usage: wgpu::BufferUsages::STORAGE,
         mapped_at_creation: false,
     })
 }
 
+/// Element `index` of the Halton low discrepancy sequence in [0, 1).
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// One atomic u32 per pixel holding the bits of the NDC depth,
+/// positive floats keep their ordering when compared as integers.
+pub fn create_depth_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+    let size = std::mem::size_of::<u32>() as u64 * width as u64 * height as u64;
+
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Depth Buffer"),
+        size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+/// Resolves once the mapping requested by [`map_read`] completed, the device
+/// has to be polled meanwhile.
+pub struct MapFuture {
+    state: Arc<Mutex<MapState>>,
+}
+
+impl Future for MapFuture {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Maps `slice` for reading.
+pub fn map_read(slice: wgpu::BufferSlice) -> MapFuture {
+    let state = Arc::new(Mutex::new(MapState::default()));
+    let callback_state = state.clone();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let mut state = callback_state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    MapFuture { state }
+}
+
+/// Resolves with `future`, such as a [`MapFuture`], polling `device` without
+/// blocking every time it is polled itself. It asks to be polled again right
+/// away, the callbacks of wgpu only run within `Device::poll`.
+pub async fn drive<F: Future + Unpin>(device: &wgpu::Device, mut future: F) -> F::Output {
+    std::future::poll_fn(|cx| {
+        device.poll(wgpu::Maintain::Poll);
+        let poll = Pin::new(&mut future).poll(cx);
+        if poll.is_pending() {
+            cx.waker().wake_by_ref();
+        }
+        poll
+    })
+    .await
+}
+
+/// Depth of the present pass, filled from the depth buffer for the debug lines.
+pub const DEPTH_TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Depth attachment of the present pass, multisampled like its color target.
+pub fn create_depth_target(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_TARGET_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    texture.create_view(&Default::default())
+}
+
+/// Multisampled color target the present pass resolves into the surface,
+/// `None` without multisampling.
+pub fn create_msaa_target(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count == 1 {
+        return None;
+    }
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    Some(texture.create_view(&Default::default()))
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 pub struct Vertex {
     v: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
+    /// Linear RGBA, multiplied with the base color of the material
+    color: [f32; 4],
 }
 
 #[allow(dead_code)]
 impl Vertex {
     pub const SIZE: u64 = std::mem::size_of::<Self>() as _;
-    pub const ATTR: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+    pub const ATTR: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+        3 => Float32x4
+    ];
 
     pub const fn new(x: f32, y: f32, z: f32) -> Self {
-        Self { v: [x, y, z] }
+        Self {
+            v: [x, y, z],
+            normal: [0.0; 3],
+            uv: [0.0; 2],
+            color: [1.0; 4],
+        }
+    }
+
+    pub const fn with_normal(self, normal: [f32; 3]) -> Self {
+        Self { normal, ..self }
+    }
+
+    pub const fn with_uv(self, uv: [f32; 2]) -> Self {
+        Self { uv, ..self }
+    }
+
+    pub const fn with_color(self, color: [f32; 4]) -> Self {
+        Self { color, ..self }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::from(self.v)
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        Vec3::from(self.normal)
+    }
+
+    pub fn color(&self) -> Vec4 {
+        Vec4::from(self.color)
+    }
+
+    /// No NaN or infinite attribute.
+    pub fn is_finite(&self) -> bool {
+        self.v
+            .iter()
+            .chain(&self.normal)
+            .chain(&self.uv)
+            .chain(&self.color)
+            .all(|x| x.is_finite())
     }
 }
 
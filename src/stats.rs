@@ -0,0 +1,128 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::session::config_dir;
+
+/// Summary of a run written at exit, one file per run so that runs of
+/// different builds can be aggregated.
+#[derive(Debug, Serialize)]
+struct SessionStats<'a> {
+    /// Seconds since the Unix epoch
+    started_at: u64,
+    duration_secs: f32,
+    frames: usize,
+    /// Between consecutive redraws, including the wait for the frame rate cap
+    frame_time: Timings,
+    /// Of `Renderer::update`, uniform uploads and shader reloads
+    update_time: Timings,
+    /// Of `Renderer::render_to_surface`, waits on the GPU to acquire the surface
+    render_time: Timings,
+    models: &'a [String],
+    /// Sizes of the color buffer, in the order they were first used
+    resolutions: &'a [(u32, u32)],
+}
+
+/// Milliseconds over the frames of a run.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Timings {
+    pub average: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl Timings {
+    pub fn new(times: &[f32]) -> Self {
+        if times.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = times.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        Self {
+            average: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// Collects the frame times of the run, in milliseconds.
+pub struct StatsRecorder {
+    started_at: SystemTime,
+    start: Instant,
+    frame_times: Vec<f32>,
+    update_times: Vec<f32>,
+    render_times: Vec<f32>,
+    models: Vec<String>,
+    resolutions: Vec<(u32, u32)>,
+}
+
+impl StatsRecorder {
+    pub fn new() -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            start: Instant::now(),
+            frame_times: Vec::new(),
+            update_times: Vec::new(),
+            render_times: Vec::new(),
+            models: Vec::new(),
+            resolutions: Vec::new(),
+        }
+    }
+
+    /// Path of the model or name of the scene that was loaded.
+    pub fn model_loaded(&mut self, name: String) {
+        self.models.push(name);
+    }
+
+    pub fn record(
+        &mut self,
+        frame_time: Duration,
+        update_time: Duration,
+        render_time: Duration,
+        resolution: (u32, u32),
+    ) {
+        let ms = |time: Duration| time.as_secs_f32() * 1000.0;
+        self.frame_times.push(ms(frame_time));
+        self.update_times.push(ms(update_time));
+        self.render_times.push(ms(render_time));
+        if !self.resolutions.contains(&resolution) {
+            self.resolutions.push(resolution);
+        }
+    }
+
+    /// Writes the summary into the stats directory next to the session,
+    /// named after the start of the run. Returns its path.
+    pub fn save(&self) -> Result<PathBuf> {
+        let started_at = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let stats = SessionStats {
+            started_at,
+            duration_secs: self.start.elapsed().as_secs_f32(),
+            frames: self.frame_times.len(),
+            frame_time: Timings::new(&self.frame_times),
+            update_time: Timings::new(&self.update_times),
+            render_time: Timings::new(&self.render_times),
+            models: &self.models,
+            resolutions: &self.resolutions,
+        };
+        let dir = config_dir()?.join("stats");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.json", started_at));
+        fs::write(&path, serde_json::to_string_pretty(&stats)?)?;
+        Ok(path)
+    }
+}
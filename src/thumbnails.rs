@@ -0,0 +1,86 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+use color_eyre::Result;
+use glam::Vec3;
+
+use compaster::{load_model, Camera, Mesh, Projection, Renderer, RendererOptions, RepairOptions};
+
+use crate::session::config_dir;
+
+/// Width and height of the thumbnails in pixels.
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// Cached PNG of `model`, named after a hash of its path.
+pub fn thumbnail_path(model: &Path) -> Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    model.hash(&mut hasher);
+    Ok(config_dir()?
+        .join("thumbnails")
+        .join(format!("{:016x}.png", hasher.finish())))
+}
+
+/// Renders the thumbnails of `models` that are missing or older than their
+/// model on a background thread, with a headless renderer of its own so that
+/// the window keeps its device and queue to itself.
+pub fn spawn(models: Vec<PathBuf>, options: RendererOptions) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for model in models {
+            if let Err(err) = update_thumbnail(&model, &options) {
+                eprintln!(
+                    "Failed to render the thumbnail of `{}`: {}",
+                    model.display(),
+                    err
+                );
+            }
+        }
+    })
+}
+
+fn update_thumbnail(model: &Path, options: &RendererOptions) -> Result<()> {
+    let path = thumbnail_path(model)?;
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    if let (Some(model), Some(thumbnail)) = (modified(model), modified(&path)) {
+        if thumbnail >= model {
+            return Ok(());
+        }
+    }
+
+    let mesh = load_model(model, RepairOptions::default())?;
+    let camera = framing_camera(&mesh)?;
+    let mut renderer = pollster::block_on(Renderer::new_headless(
+        THUMBNAIL_SIZE,
+        THUMBNAIL_SIZE,
+        camera,
+        mesh,
+        options,
+    ))?;
+    renderer.turntable = false;
+    renderer.update(0.0);
+    let image = renderer.render_to_image(THUMBNAIL_SIZE, THUMBNAIL_SIZE)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Renamed once complete, the app may exit while it's being written
+    let partial = path.with_extension("partial");
+    image.save_with_format(&partial, image::ImageFormat::Png)?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// Looks at the bounding sphere of the mesh from the front right and above,
+/// at the distance it fills the field of view from.
+fn framing_camera(mesh: &Mesh) -> Result<Camera> {
+    let (min, max) = mesh.bounds();
+    let center = (min + max) * 0.5;
+    let radius = ((max - min).length() * 0.5).max(1e-3);
+    let distance = radius / (Projection::Perspective.fov() * 0.5).sin();
+    let eye = center + Vec3::new(1.0, 0.6, 1.0).normalize() * distance;
+    Camera::look_at(eye, center, 1.0)
+}
@@ -0,0 +1,26 @@
+//! Shared by the tests rendering with a GPU adapter, each uses a part of it.
+#![allow(dead_code)]
+
+use compaster::{Camera, RendererOptions};
+use glam::Vec3;
+
+pub const WIDTH: u32 = 320;
+pub const HEIGHT: u32 = 240;
+
+/// The backends of `WGPU_BACKEND` when it's set, `WGPU_BACKEND=gl` runs the
+/// tests on a software GL driver such as llvmpipe.
+pub fn options() -> RendererOptions {
+    RendererOptions {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+        ..Default::default()
+    }
+}
+
+/// Orbit camera panned so that `target` lands in the middle of the image,
+/// the projection of the raster puts it in a corner otherwise.
+pub fn centered_camera(zoom: f32, pitch: f32, yaw: f32, target: Vec3) -> Camera {
+    let aspect = WIDTH as f32 / HEIGHT as f32;
+    let mut camera = Camera::new(zoom, pitch, yaw, target, aspect);
+    camera.pan(0.5 * aspect, -0.5);
+    camera
+}
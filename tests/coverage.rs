@@ -0,0 +1,51 @@
+//! Fill rule of the compute raster, checked on headless renders with the
+//! overdraw view. They need a GPU adapter, run with `cargo test -- --ignored`.
+
+mod common;
+
+use color_eyre::{eyre::eyre, Result};
+use common::{options, HEIGHT, WIDTH};
+use compaster::{Camera, Renderer, TestScene};
+use glam::Vec3;
+/// Yaws of the orbit the scenes are checked from, the edges cross the pixel
+/// grid at a different slope from each.
+const YAWS: [f32; 4] = [0.3, 1.25, 2.2, 4.0];
+
+/// Renders `scene` from every yaw and fails unless each covered pixel is
+/// written exactly once, with no crack between two triangles.
+fn check_watertight(scene: TestScene) -> Result<()> {
+    let aspect = WIDTH as f32 / HEIGHT as f32;
+    let camera = Camera::new(3.0, 0.7, YAWS[0], Vec3::ZERO, aspect);
+    let mut renderer = pollster::block_on(Renderer::new_headless(
+        WIDTH,
+        HEIGHT,
+        camera,
+        scene.mesh(),
+        &options(),
+    ))?;
+    renderer.turntable = false;
+    for yaw in YAWS {
+        renderer.set_camera(Camera::new(3.0, 0.7, yaw, Vec3::ZERO, aspect));
+        renderer.update(0.0);
+        let coverage = renderer.check_coverage()?;
+        if coverage.covered == 0 {
+            return Err(eyre!("The {} scene is out of view at yaw {}", scene, yaw));
+        }
+        if !coverage.is_watertight() {
+            return Err(eyre!("The {} scene at yaw {}: {}", scene, yaw, coverage));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn shared_edge_is_watertight() -> Result<()> {
+    check_watertight(TestScene::SharedEdge)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn fan_is_watertight() -> Result<()> {
+    check_watertight(TestScene::Fan)
+}
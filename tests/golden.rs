@@ -0,0 +1,150 @@
+//! Headless renders of the test scenes compared with the images under
+//! `tests/golden`. They need a GPU adapter, run with `cargo test -- --ignored`,
+//! and `UPDATE_GOLDENS=1` to write the images again after an intended change.
+
+mod common;
+
+use std::{env, fs, path::PathBuf};
+
+use color_eyre::{eyre::eyre, Result};
+use common::{centered_camera, options, HEIGHT, WIDTH};
+use compaster::{Camera, DebugView, RasterMode, Renderer, RgbaImage, Settings, TestScene};
+use glam::Vec3;
+
+/// Channels may differ by this much between adapters, from the rounding of
+/// the shading and the sRGB encoding.
+const TOLERANCE: u8 = 2;
+/// Share of the pixels allowed to differ by more than `TOLERANCE`, edges
+/// landing exactly on a sample may be covered by either triangle.
+const MAX_MISMATCH: f32 = 0.001;
+
+fn camera(scene: TestScene) -> Camera {
+    match scene {
+        // Far enough that some triangles miss every sample
+        TestScene::Tiny => centered_camera(20.0, 0.9, 0.4, Vec3::new(0.0, -20.0, 0.0)),
+        _ => centered_camera(3.0, 0.7, 0.4, Vec3::ZERO),
+    }
+}
+
+fn golden_path(scene: TestScene) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", scene))
+}
+
+fn render(scene: TestScene) -> Result<RgbaImage> {
+    let mut renderer = pollster::block_on(Renderer::new_headless(
+        WIDTH,
+        HEIGHT,
+        camera(scene),
+        scene.mesh(),
+        &options(),
+    ))?;
+    // Every triangle in its own color on black, cracks and overlaps stand out
+    let settings = Settings {
+        debug_view: DebugView::Triangle,
+        // The per triangle modes lose the color of the last columns of big
+        // triangles on llvmpipe, their depth is written but no fragment
+        raster_mode: RasterMode::Tiled,
+        background: [0.0; 3],
+        turntable: false,
+        ..renderer.settings()
+    };
+    renderer.apply_settings(&settings);
+    renderer.update(0.0);
+    renderer.render_to_image(WIDTH, HEIGHT)
+}
+
+/// Pixels with a channel differing by more than `TOLERANCE`.
+fn mismatches(image: &RgbaImage, golden: &RgbaImage) -> usize {
+    image
+        .pixels()
+        .zip(golden.pixels())
+        .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > TOLERANCE))
+        .count()
+}
+
+/// Renders `scene` and fails when it differs from its golden image, the
+/// render is then saved next to the test binaries to be looked at.
+fn check_golden(scene: TestScene) -> Result<()> {
+    let image = render(scene)?;
+    let path = golden_path(scene);
+    if env::var_os("UPDATE_GOLDENS").is_some() {
+        image.save(&path)?;
+        return Ok(());
+    }
+    let golden = image::open(&path)
+        .map_err(|err| {
+            eyre!(
+                "Can't open `{}`, write it with UPDATE_GOLDENS=1: {}",
+                path.display(),
+                err
+            )
+        })?
+        .to_rgba8();
+    if golden.dimensions() != image.dimensions() {
+        return Err(eyre!(
+            "`{}` is {:?}, the render {:?}",
+            path.display(),
+            golden.dimensions(),
+            image.dimensions()
+        ));
+    }
+
+    let mismatches = mismatches(&image, &golden);
+    if mismatches as f32 > MAX_MISMATCH * (WIDTH * HEIGHT) as f32 {
+        let actual = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", scene));
+        fs::create_dir_all(actual.parent().unwrap())?;
+        image.save(&actual)?;
+        return Err(eyre!(
+            "The {} scene differs from `{}` in {} pixels, the render is saved to `{}`",
+            scene,
+            path.display(),
+            mismatches,
+            actual.display()
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn thin_matches_golden() -> Result<()> {
+    check_golden(TestScene::Thin)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn huge_matches_golden() -> Result<()> {
+    check_golden(TestScene::Huge)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn coplanar_matches_golden() -> Result<()> {
+    check_golden(TestScene::Coplanar)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn tiny_matches_golden() -> Result<()> {
+    check_golden(TestScene::Tiny)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn t_junction_matches_golden() -> Result<()> {
+    check_golden(TestScene::TJunction)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn shared_edge_matches_golden() -> Result<()> {
+    check_golden(TestScene::SharedEdge)
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn fan_matches_golden() -> Result<()> {
+    check_golden(TestScene::Fan)
+}